@@ -0,0 +1,175 @@
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation3};
+
+use crate::camera::Camera;
+use crate::channels::ChannelRange;
+
+// time between a keyframe added by hand and the previous one, in seconds
+pub(crate) const KEYFRAME_SPACING: f32 = 2.0;
+
+/// Continuous spin of the camera around the vertical axis of the cube
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Turntable {
+    /// Angular speed in degrees per second, negative to spin the other way
+    pub speed: f32,
+}
+
+impl Default for Turntable {
+    fn default() -> Self {
+        Self { speed: 30.0 }
+    }
+}
+
+impl Turntable {
+    /// Spin the camera by the rotation done in `t` seconds
+    pub(crate) fn spin(&self, camera: &mut Camera, t: f32) {
+        let rotation = Quaternion::from_angle_y(Rad(self.speed.to_radians() * t));
+        camera.orientation = (rotation * camera.orientation).normalize();
+    }
+}
+
+impl std::str::FromStr for Turntable {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let speed = s.trim().parse().map_err(|_| "Invalid turntable speed")?;
+        Ok(Self { speed })
+    }
+}
+
+/// State of the view at a time of a camera path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    /// Time from the start of the path, in seconds
+    pub time: f32,
+    pub camera: Camera,
+    /// Contrast cuts (scale, offset) applied to the normalized values
+    pub cuts: [f32; 2],
+    /// Window of channels rendered
+    pub channels: ChannelRange,
+}
+
+impl Keyframe {
+    /// Interpolation between `self` at `s = 0` and `other` at `s = 1`
+    fn lerp(&self, other: &Keyframe, s: f32) -> Keyframe {
+        let mix = |a: f32, b: f32| a + (b - a) * s;
+        let mix_channel = |a: u32, b: u32| mix(a as f32, b as f32).round() as u32;
+
+        let (a, b) = (&self.camera, &other.camera);
+        let camera = Camera {
+            target: [
+                mix(a.target[0], b.target[0]),
+                mix(a.target[1], b.target[1]),
+                mix(a.target[2], b.target[2]),
+            ],
+            distance: mix(a.distance, b.distance),
+            orientation: a.orientation.slerp(b.orientation, s).normalize(),
+            fov: mix(a.fov, b.fov),
+            ortho_scale: mix(a.ortho_scale, b.ortho_scale),
+            ..*a
+        };
+
+        Keyframe {
+            time: mix(self.time, other.time),
+            camera,
+            cuts: [
+                mix(self.cuts[0], other.cuts[0]),
+                mix(self.cuts[1], other.cuts[1]),
+            ],
+            channels: ChannelRange {
+                first: mix_channel(self.channels.first, other.channels.first),
+                last: mix_channel(self.channels.last, other.channels.last),
+            },
+        }
+    }
+}
+
+/// Keyframes of the view interpolated over time
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraPath {
+    // sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.0)
+    }
+
+    pub fn push(&mut self, keyframe: Keyframe) {
+        let i = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(i, keyframe);
+    }
+
+    /// View at time `t`, held still before the first and after the last keyframe
+    pub fn at(&self, t: f32) -> Option<Keyframe> {
+        let i = self.keyframes.partition_point(|k| k.time <= t);
+        match (
+            i.checked_sub(1).map(|i| &self.keyframes[i]),
+            self.keyframes.get(i),
+        ) {
+            (Some(a), Some(b)) => Some(a.lerp(b, (t - a.time) / (b.time - a.time))),
+            (Some(k), None) | (None, Some(k)) => Some(*k),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Playback of the turntable and the camera path
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub turntable: Option<Turntable>,
+    pub path: CameraPath,
+    /// Start the path over once its end is reached
+    pub looping: bool,
+    pub playing: bool,
+    /// Position of the playback on the path, in seconds
+    pub time: f32,
+}
+
+impl Animation {
+    /// Move the playback by `dt` seconds, returning the view of the path at the new time
+    pub(crate) fn advance(&mut self, dt: f32) -> Option<Keyframe> {
+        self.time += dt;
+
+        let duration = self.path.duration();
+        if !self.path.is_empty() && self.time > duration {
+            if self.looping && duration > 0.0 {
+                self.time = self.time.rem_euclid(duration);
+            } else {
+                self.time = duration;
+                // keep spinning at the end of the path
+                self.playing = self.turntable.is_some();
+            }
+        }
+
+        self.path.at(self.time)
+    }
+}
+
+/// Control of the animation, from the keyboard or the web API
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AnimationCommand {
+    Play,
+    Pause,
+    /// Go back to the start of the path
+    Rewind,
+    Turntable(Option<Turntable>),
+    Loop(bool),
+    /// Record the current view at a time, after the last keyframe if `None`
+    AddKeyframe(Option<f32>),
+    ClearKeyframes,
+}
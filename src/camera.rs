@@ -0,0 +1,288 @@
+use cgmath::{InnerSpace, Matrix3, Quaternion, Rad, Rotation, Rotation3, SquareMatrix};
+
+use crate::math::{Mat4, Vec3, Vec4};
+use crate::slice::SliceAxis;
+
+const DEFAULT_DISTANCE: f32 = 1.5;
+const DEFAULT_ORTHO_SCALE: f32 = 0.75;
+// clip planes of the projections, the orthographic ones being centered on the camera
+const NEAR: f32 = 0.01;
+const FAR: f32 = 100.0;
+// duration of the animated snapping to an axis, in seconds
+const TRANSITION_DURATION: f32 = 0.4;
+
+/// Camera looking at the cube
+///
+/// World coordinates are centered on the cube, which spans [-0.5, 0.5]^3.
+/// The camera looks along its -z axis with its y axis up, and sits at
+/// `distance` from `target` on its +z axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    /// Point looked at and rotated around
+    pub target: [f32; 3],
+    /// Distance to the target, changed by the zoom in perspective
+    pub distance: f32,
+    /// Rotation from the camera frame to the world frame
+    pub orientation: Quaternion<f32>,
+    /// Vertical field of view in radians
+    pub fov: f32,
+    pub perspective: bool,
+    /// Half of the height of the view in orthographic, changed by the zoom
+    pub ortho_scale: f32,
+    /// Rotate freely with a virtual trackball instead of orbiting around the
+    /// vertical axis, the latter being stopped at the poles
+    pub arcball: bool,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            target: [0.0; 3],
+            distance: DEFAULT_DISTANCE,
+            orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            fov: std::f32::consts::FRAC_PI_3,
+            perspective: false,
+            ortho_scale: DEFAULT_ORTHO_SCALE,
+            arcball: false,
+        }
+    }
+}
+
+/// std140 layout of the `Camera` uniform block of cube.frag
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct CameraUniform {
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+impl Camera {
+    /// Go back to the default view, keeping the projection and the rotation mode
+    pub fn reset(&mut self) {
+        *self = Camera {
+            fov: self.fov,
+            perspective: self.perspective,
+            arcball: self.arcball,
+            ..Default::default()
+        };
+    }
+
+    pub fn position(&self) -> Vec3<f32> {
+        Vec3::from(self.target)
+            + self
+                .orientation
+                .rotate_vector(Vec3::unit_z() * self.distance)
+    }
+
+    /// Viewing direction, in world coordinates
+    pub fn forward(&self) -> Vec3<f32> {
+        self.orientation.rotate_vector(-Vec3::unit_z())
+    }
+
+    /// Matrix from the world to the camera frame
+    pub fn view(&self) -> Mat4<f32> {
+        Mat4::from(self.orientation.conjugate()) * Mat4::from_translation(-self.position())
+    }
+
+    /// Projection of a panel of width / height `aspect`
+    pub fn proj(&self, aspect: f32) -> Mat4<f32> {
+        if self.perspective {
+            cgmath::perspective(Rad(self.fov), aspect, NEAR, FAR)
+        } else {
+            let (w, h) = (self.ortho_scale * aspect, self.ortho_scale);
+            cgmath::ortho(-w, w, -h, h, -FAR, FAR)
+        }
+    }
+
+    pub(crate) fn uniform(&self, aspect: f32) -> CameraUniform {
+        let view = self.view();
+        let proj = self.proj(aspect);
+        let inv_view_proj = (proj * view).invert().unwrap_or(Mat4::identity());
+        CameraUniform {
+            view: view.into(),
+            proj: proj.into(),
+            inv_view_proj: inv_view_proj.into(),
+        }
+    }
+
+    /// Ray of the pixel at `ndc` (in [-1, 1]^2, y up) in world coordinates
+    ///
+    /// This mirrors the ray setup of cube.frag, the ray starting on the near plane.
+    pub(crate) fn ray(&self, ndc: [f32; 2], aspect: f32) -> (Vec3<f32>, Vec3<f32>) {
+        let inv = (self.proj(aspect) * self.view())
+            .invert()
+            .unwrap_or(Mat4::identity());
+        let unproject = |z: f32| {
+            let p = inv * Vec4::new(ndc[0], ndc[1], z, 1.0);
+            p.truncate() / p.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        (near, (far - near).normalize())
+    }
+
+    /// Rotate the view by a mouse motion of (dx, dy) in units of the panel height
+    ///
+    /// `from` is the position the motion starts at, in the same units and
+    /// relative to the center of the panel, for the trackball.
+    pub(crate) fn rotate(&mut self, from: [f32; 2], dx: f32, dy: f32) {
+        if self.arcball {
+            // map both positions on a unit sphere, the object following the cursor
+            let on_ball = |x: f32, y: f32| {
+                let d = x * x + y * y;
+                if d < 1.0 {
+                    Vec3::new(x, y, (1.0 - d).sqrt())
+                } else {
+                    Vec3::new(x, y, 0.0).normalize()
+                }
+            };
+            let p0 = on_ball(2.0 * from[0], -2.0 * from[1]);
+            let p1 = on_ball(2.0 * (from[0] + dx), -2.0 * (from[1] + dy));
+            let axis = p0.cross(p1);
+            if axis.magnitude2() > 1e-12 {
+                let angle = p0.dot(p1).clamp(-1.0, 1.0).acos();
+                // the camera turns the opposite way
+                self.orientation = (self.orientation
+                    * Quaternion::from_axis_angle(axis.normalize(), Rad(-angle)))
+                .normalize();
+            }
+        } else {
+            let yaw = Quaternion::from_angle_y(Rad(-std::f32::consts::PI * dx));
+
+            // elevation of the viewing direction, kept short of the poles
+            let forward = self.forward();
+            let max_pitch = std::f32::consts::FRAC_PI_2 - 1e-3;
+            let pitch = forward.y.clamp(-1.0, 1.0).asin();
+            let dpitch =
+                (pitch - std::f32::consts::FRAC_PI_2 * dy).clamp(-max_pitch, max_pitch) - pitch;
+            // horizontal axis, so that a roll does not tilt the motion
+            let side = forward.cross(Vec3::unit_y());
+            let tilt = if side.magnitude2() > 1e-12 {
+                Quaternion::from_axis_angle(side.normalize(), Rad(dpitch))
+            } else {
+                Quaternion::new(1.0, 0.0, 0.0, 0.0)
+            };
+
+            self.orientation = (yaw * tilt * self.orientation).normalize();
+        }
+    }
+
+    /// Rotate the camera around its viewing direction by an angle in radians
+    pub fn roll(&mut self, angle: f32) {
+        self.orientation = (self.orientation * Quaternion::from_angle_z(Rad(angle))).normalize();
+    }
+
+    /// Move the target by (dx, dy) in units of the panel height, the content following the cursor
+    pub(crate) fn pan(&mut self, dx: f32, dy: f32) {
+        let height = if self.perspective {
+            2.0 * self.distance * (0.5 * self.fov).tan()
+        } else {
+            2.0 * self.ortho_scale
+        };
+        let right = self.orientation.rotate_vector(Vec3::unit_x());
+        let up = self.orientation.rotate_vector(Vec3::unit_y());
+        let target = Vec3::from(self.target) - (right * dx - up * dy) * height;
+        self.target = target.into();
+    }
+
+    /// Zoom in for a factor below 1, out for a factor above 1
+    pub fn zoom(&mut self, factor: f32) {
+        if self.perspective {
+            self.distance = (self.distance * factor).clamp(0.05, 20.0);
+        } else {
+            self.ortho_scale = (self.ortho_scale * factor).clamp(0.01, 10.0);
+        }
+    }
+
+    /// Cube axes (0, 1 or 2) going to the right, up and toward the viewer with
+    /// their sign, if the camera looks along one of them
+    pub(crate) fn aligned_axes(&self) -> Option<[(usize, f32); 3]> {
+        let closest_axis = |v: Vec3<f32>| {
+            let v: [f32; 3] = v.into();
+            let axis = (0..3)
+                .max_by(|&a, &b| v[a].abs().total_cmp(&v[b].abs()))
+                .unwrap();
+            // within a degree of the axis
+            if v[axis].abs() > 0.9998 {
+                Some((axis, v[axis].signum()))
+            } else {
+                None
+            }
+        };
+
+        Some([
+            closest_axis(self.orientation.rotate_vector(Vec3::unit_x()))?,
+            closest_axis(self.orientation.rotate_vector(Vec3::unit_y()))?,
+            closest_axis(self.orientation.rotate_vector(Vec3::unit_z()))?,
+        ])
+    }
+}
+
+/// View along one of the axes of the cube
+///
+/// The two other axes are laid out as in the slice panel of the axis, the
+/// first one going to the right and the second one up. A reversed view looks
+/// at the cube from the other side, mirroring the horizontal axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisView {
+    pub axis: SliceAxis,
+    pub reversed: bool,
+}
+
+impl AxisView {
+    /// Orientation of a camera looking along the axis
+    pub(crate) fn orientation(self) -> Quaternion<f32> {
+        let (right, up) = match self.axis {
+            SliceAxis::Z => (Vec3::unit_x(), Vec3::unit_y()),
+            SliceAxis::Y => (Vec3::unit_x(), Vec3::unit_z()),
+            SliceAxis::X => (Vec3::unit_y(), Vec3::unit_z()),
+        };
+        let right = if self.reversed { -right } else { right };
+        // the camera looks along its -z axis
+        let back = right.cross(up);
+        Quaternion::from(Matrix3::from_cols(right, up, back)).normalize()
+    }
+}
+
+impl std::str::FromStr for AxisView {
+    type Err = &'static str;
+
+    /// `x`, `y` or `z`, preceded by a `-` for a reversed view
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (reversed, axis) = match s.strip_prefix('-') {
+            Some(axis) => (true, axis),
+            None => (false, s),
+        };
+        let axis = match axis {
+            "x" => SliceAxis::X,
+            "y" => SliceAxis::Y,
+            "z" => SliceAxis::Z,
+            _ => {
+                return Err("Unknown view, expected 'x', 'y' or 'z', preceded by '-' to reverse it")
+            }
+        };
+        Ok(Self { axis, reversed })
+    }
+}
+
+/// Animated rotation of the camera toward a new orientation
+pub(crate) struct CameraTransition {
+    from: Quaternion<f32>,
+    to: Quaternion<f32>,
+    start: f32,
+}
+
+impl CameraTransition {
+    pub(crate) fn new(from: Quaternion<f32>, to: Quaternion<f32>, start: f32) -> Self {
+        Self { from, to, start }
+    }
+
+    /// Orientation at time `t`, and whether the transition is over
+    pub(crate) fn at(&self, t: f32) -> (Quaternion<f32>, bool) {
+        let s = ((t - self.start) / TRANSITION_DURATION).clamp(0.0, 1.0);
+        // ease in and out
+        let eased = s * s * (3.0 - 2.0 * s);
+        (self.from.slerp(self.to, eased).normalize(), s >= 1.0)
+    }
+}
@@ -0,0 +1,138 @@
+//! Read back of the frames drawn offscreen
+
+/// Texture a frame is drawn into before being read back
+pub(crate) fn create_frame_texture(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("frame"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Frame copied to a buffer, waiting to be mapped on the CPU
+pub(crate) struct FrameReadback {
+    // polled to complete the mapping on native
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    device: wgpu::Device,
+    buffer: wgpu::Buffer,
+    size: (u32, u32),
+    padded_row_bytes: u32,
+    bgra: bool,
+    mapped: futures::channel::oneshot::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+/// Copy a texture of 8 bits per channel to a buffer mapped for reading
+///
+/// The commands drawing into the texture are recorded in `encoder`, which is
+/// submitted with the copy.
+pub(crate) fn read_back(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mut encoder: wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+) -> Result<FrameReadback, &'static str> {
+    let bgra = match texture.format() {
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        _ => return Err("Frames cannot be read back from this surface format"),
+    };
+
+    let (width, height) = (texture.width(), texture.height());
+    // rows of the buffer are aligned on 256 bytes
+    let padded_row_bytes = (4 * width).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("frame read back"),
+        size: (padded_row_bytes * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let (sender, mapped) = futures::channel::oneshot::channel();
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+    Ok(FrameReadback {
+        device: device.clone(),
+        buffer,
+        size: (width, height),
+        padded_row_bytes,
+        bgra,
+        mapped,
+    })
+}
+
+impl FrameReadback {
+    /// Wait for the copy and return the frame as tightly packed RGBA rows
+    ///
+    /// This blocks until the GPU is done on native. On the web the mapping
+    /// completes with the next frames submitted.
+    pub(crate) async fn rgba(self) -> Result<Vec<u8>, &'static str> {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.device
+            .poll(wgpu::PollType::Wait)
+            .map_err(|_| "The GPU did not finish drawing the frame")?;
+        self.mapped
+            .await
+            .map_err(|_| "The frame could not be read back")?
+            .map_err(|_| "The frame could not be read back")?;
+
+        let (width, height) = self.size;
+        let row_bytes = 4 * width as usize;
+        let mut rgba = Vec::with_capacity(row_bytes * height as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks(self.padded_row_bytes as usize) {
+                rgba.extend_from_slice(&row[..row_bytes]);
+            }
+        }
+        self.buffer.unmap();
+
+        if self.bgra {
+            for pixel in rgba.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        Ok(rgba)
+    }
+}
@@ -0,0 +1,552 @@
+//! Catalogs of the objects found in the cube, with their position, size and flux
+
+use crate::cube::Cube;
+use crate::finder::FinderSettings;
+use crate::overlay::format_value;
+use crate::spectrum::format_coord;
+
+/// File format of a catalog
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CatalogFormat {
+    Csv,
+    /// VOTable of the IVOA, as read by TOPCAT or Aladin
+    VoTable,
+}
+
+impl CatalogFormat {
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        ext.parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CatalogFormat::Csv => "csv",
+            CatalogFormat::VoTable => "vot",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            CatalogFormat::Csv => "text/csv",
+            CatalogFormat::VoTable => "application/x-votable+xml",
+        }
+    }
+}
+
+impl std::str::FromStr for CatalogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(CatalogFormat::Csv),
+            "vot" | "votable" | "xml" => Ok(CatalogFormat::VoTable),
+            _ => Err("Unknown catalog format, expected 'csv' or 'votable'"),
+        }
+    }
+}
+
+/// Object found in the cube, measured from the finite values of its voxels
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatalogObject {
+    /// ID of the object in the labels
+    pub id: u32,
+    /// Center of the object weighted by the positive values, in 0-based voxels
+    pub centroid: [f64; 3],
+    /// World coordinates of the centroid, the spectral one being e.g. the velocity,
+    /// if the cube has a WCS
+    pub world: Option<[f64; 3]>,
+    /// First and last voxels of the object along x, y and z
+    pub bounds: [[u32; 2]; 3],
+    pub num_voxels: u32,
+    /// Sum of the values, multiplied by the channel width in the unit of CUNIT3 (or the
+    /// default one of CTYPE3), or in channels for a cube without WCS, as the moment 0
+    pub flux: f64,
+    /// Maximum of the values
+    pub peak: f32,
+}
+
+impl CatalogObject {
+    /// Number of voxels spanned along x, y and z
+    pub fn extent(&self) -> [u32; 3] {
+        self.bounds.map(|[first, last]| last - first + 1)
+    }
+}
+
+/// Objects found in the cube, by increasing ID
+#[derive(Clone, Debug, PartialEq)]
+pub struct Catalog {
+    pub objects: Vec<CatalogObject>,
+    /// Type and unit of the axes, given by CTYPEn and CUNITn, if the cube has a WCS
+    pub axes: Option<[(String, String); 3]>,
+    /// Unit of the values, the one of the cube
+    pub bunit: Option<String>,
+    /// Name of the file of the cube, and the way the objects were found
+    pub(crate) source: Option<String>,
+    pub(crate) settings: FinderSettings,
+}
+
+// column of the CSV files and of the VOTables
+struct Column {
+    name: String,
+    unit: String,
+    ucd: &'static str,
+    /// Type of the values in the VOTables
+    datatype: &'static str,
+    description: &'static str,
+}
+
+// accumulated over the voxels of an object
+struct Sums {
+    weighted: [f64; 3],
+    weight: f64,
+    sum: f64,
+    min: [u32; 3],
+    max: [u32; 3],
+    num_voxels: u32,
+    peak: f32,
+}
+
+impl Catalog {
+    /// Measure the objects labeled from 1 in `ids`, of the dimensions of the cube
+    pub(crate) fn measure(cube: &Cube, ids: &[u32], settings: &FinderSettings) -> Self {
+        let (w, h, _) = cube.dim;
+        let num_objects = ids.iter().copied().max().unwrap_or(0) as usize;
+        let mut sums: Vec<Sums> = (0..num_objects)
+            .map(|_| Sums {
+                weighted: [0.0; 3],
+                weight: 0.0,
+                sum: 0.0,
+                min: [u32::MAX; 3],
+                max: [0; 3],
+                num_voxels: 0,
+                peak: f32::NAN,
+            })
+            .collect();
+        for (i, (&id, &v)) in ids.iter().zip(&cube.data).enumerate() {
+            if id == 0 {
+                continue;
+            }
+            let i = i as u32;
+            let voxel = [i % w, i / w % h, i / (w * h)];
+            let s = &mut sums[id as usize - 1];
+            for ((min, max), v) in s.min.iter_mut().zip(s.max.iter_mut()).zip(voxel) {
+                *min = (*min).min(v);
+                *max = (*max).max(v);
+            }
+            s.num_voxels += 1;
+            if v.is_finite() {
+                let weight = v.max(0.0) as f64;
+                for (weighted, v) in s.weighted.iter_mut().zip(voxel) {
+                    *weighted += weight * v as f64;
+                }
+                s.weight += weight;
+                s.sum += v as f64;
+                // NaN is replaced by the first finite value
+                s.peak = s.peak.max(v);
+            }
+        }
+
+        let wcs = cube.wcs();
+        let channel_width = wcs.map_or(1.0, |wcs| wcs.axes[2].cdelt.abs());
+        let objects = sums
+            .iter()
+            .enumerate()
+            .map(|(index, s)| {
+                // the middle of the bounds for the objects without positive value
+                let centroid = if s.weight > 0.0 {
+                    s.weighted.map(|c| c / s.weight)
+                } else {
+                    [0, 1, 2].map(|axis| (s.min[axis] + s.max[axis]) as f64 / 2.0)
+                };
+                CatalogObject {
+                    id: index as u32 + 1,
+                    centroid,
+                    world: wcs.map(|wcs| wcs.pix2world(centroid)),
+                    bounds: [0, 1, 2].map(|axis| [s.min[axis], s.max[axis]]),
+                    num_voxels: s.num_voxels,
+                    flux: s.sum * channel_width,
+                    peak: s.peak,
+                }
+            })
+            .collect();
+
+        Self {
+            objects,
+            axes: wcs.map(|wcs| wcs.axes.clone().map(|axis| (axis.ctype, axis.cunit))),
+            bunit: cube.bunit.clone(),
+            source: cube.name.clone(),
+            settings: settings.clone(),
+        }
+    }
+
+    /// Cube and settings the objects were found with
+    pub(crate) fn description(&self) -> String {
+        let source = match &self.source {
+            Some(name) => format!("{} objects found in {}", self.objects.len(), name),
+            None => format!("{} objects found", self.objects.len()),
+        };
+        format!("{}. {}", source, self.settings.description())
+    }
+
+    /// Columns of the tables, the world coordinates being given with a WCS only
+    fn columns(&self) -> Vec<Column> {
+        // the unit of the channel width is not known without CUNIT3 for the axes of
+        // the types without a default unit
+        let flux_unit = match (&self.bunit, &self.axes) {
+            (Some(bunit), Some([.., (ctype, cunit)])) => match axis_unit(ctype, cunit) {
+                "" => String::new(),
+                unit => format!("{}.{}", bunit, unit),
+            },
+            (Some(bunit), None) => format!("{}.channel", bunit),
+            (None, _) => String::new(),
+        };
+        let voxels = |name: &str, description| Column {
+            name: name.to_string(),
+            unit: String::new(),
+            ucd: "",
+            datatype: "int",
+            description,
+        };
+        let centroid = |name: &str, description| Column {
+            datatype: "double",
+            ..voxels(name, description)
+        };
+
+        let mut columns = vec![
+            Column {
+                ucd: "meta.id;meta.main",
+                ..voxels("id", "ID of the object in the labels")
+            },
+            centroid("x", "Centroid along x, in 0-based voxels"),
+            centroid("y", "Centroid along y, in 0-based voxels"),
+            centroid("z", "Centroid along z, in 0-based voxels"),
+        ];
+        if let Some(axes) = &self.axes {
+            for (axis, (ctype, cunit)) in axes.iter().enumerate() {
+                columns.push(Column {
+                    unit: axis_unit(ctype, cunit).to_string(),
+                    ucd: world_ucd(ctype),
+                    ..centroid(&coord_name(ctype, axis), "World coordinate of the centroid")
+                });
+            }
+        }
+        columns.extend([
+            voxels("x_min", "First voxel along x"),
+            voxels("x_max", "Last voxel along x"),
+            voxels("y_min", "First voxel along y"),
+            voxels("y_max", "Last voxel along y"),
+            voxels("z_min", "First channel"),
+            voxels("z_max", "Last channel"),
+            voxels("n_voxels", "Number of voxels of the object"),
+            Column {
+                unit: flux_unit,
+                ucd: "phot.flux",
+                ..centroid("flux", "Sum of the values times the channel width")
+            },
+            Column {
+                unit: self.bunit.clone().unwrap_or_default(),
+                ucd: "phot.flux.density;stat.max",
+                datatype: "float",
+                ..voxels("peak", "Maximum of the values")
+            },
+        ]);
+        columns
+    }
+
+    /// Values of the row of an object, in the order of the columns
+    fn row(&self, object: &CatalogObject) -> Vec<String> {
+        let mut row = vec![object.id.to_string()];
+        row.extend(object.centroid.map(format_coord));
+        if let Some(world) = object.world {
+            // enough decimals for the positions on the sky in degrees
+            row.extend(world.map(|w| {
+                let s = format!("{:.7}", w);
+                s.trim_end_matches('0').trim_end_matches('.').to_string()
+            }));
+        }
+        for [first, last] in object.bounds {
+            row.push(first.to_string());
+            row.push(last.to_string());
+        }
+        row.push(object.num_voxels.to_string());
+        row.push(object.flux.to_string());
+        row.push(object.peak.to_string());
+        row
+    }
+
+    /// Comma separated values, one row per object, after comment lines starting with '#'
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("# {}\n", self.description());
+        let header: Vec<String> = self
+            .columns()
+            .into_iter()
+            .map(|column| {
+                if column.unit.is_empty() {
+                    column.name
+                } else {
+                    format!("{} [{}]", column.name, column.unit)
+                }
+            })
+            .collect();
+        csv.push_str(&header.join(","));
+        csv.push('\n');
+        for object in &self.objects {
+            csv.push_str(&self.row(object).join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// VOTable 1.3 with the rows written inline
+    pub fn to_votable(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <VOTABLE version=\"1.3\" xmlns=\"http://www.ivoa.net/xml/VOTable/v1.3\">\n\
+             <RESOURCE>\n\
+             <TABLE name=\"objects\">\n",
+        );
+        xml.push_str(&format!(
+            "<DESCRIPTION>{}</DESCRIPTION>\n",
+            escape_xml(&self.description())
+        ));
+        for column in self.columns() {
+            xml.push_str(&format!(
+                "<FIELD name=\"{}\" datatype=\"{}\"",
+                escape_xml(&column.name),
+                column.datatype
+            ));
+            if !column.unit.is_empty() {
+                xml.push_str(&format!(" unit=\"{}\"", escape_xml(&column.unit)));
+            }
+            if !column.ucd.is_empty() {
+                xml.push_str(&format!(" ucd=\"{}\"", column.ucd));
+            }
+            xml.push_str(&format!(
+                "><DESCRIPTION>{}</DESCRIPTION></FIELD>\n",
+                column.description
+            ));
+        }
+        xml.push_str("<DATA>\n<TABLEDATA>\n");
+        for object in &self.objects {
+            xml.push_str("<TR>");
+            for value in self.row(object) {
+                // NaN is written as an empty cell
+                let value = if value == "NaN" { String::new() } else { value };
+                xml.push_str(&format!("<TD>{}</TD>", value));
+            }
+            xml.push_str("</TR>\n");
+        }
+        xml.push_str("</TABLEDATA>\n</DATA>\n</TABLE>\n</RESOURCE>\n</VOTABLE>\n");
+        xml
+    }
+
+    /// Summary of the objects, one line each after a header, in the characters of
+    /// the overlay font, `max_rows` objects at most being listed
+    ///
+    /// The spectral position is the world coordinate of the centroid with a WCS, e.g.
+    /// the velocity, and its channel otherwise.
+    pub(crate) fn summary(&self, max_rows: usize) -> Vec<String> {
+        let spectral = match &self.axes {
+            Some(axes) => coord_name(&axes[2].0, 2),
+            None => "z".to_string(),
+        };
+        let mut lines = vec![format!(
+            "{:>4} {:>6} {:>6} {:>9} {:>6} {:>9} {:>9}",
+            "id", "x", "y", spectral, "voxels", "peak", "flux"
+        )];
+        for object in self.objects.iter().take(max_rows) {
+            let [x, y, z] = object.centroid;
+            let spectral = match object.world {
+                Some(world) => format_value(world[2] as f32),
+                None => format!("{:.1}", z),
+            };
+            lines.push(format!(
+                "{:>4} {:>6.1} {:>6.1} {:>9} {:>6} {:>9} {:>9}",
+                object.id,
+                x,
+                y,
+                spectral,
+                object.num_voxels,
+                format_value(object.peak),
+                format_value(object.flux as f32)
+            ));
+        }
+        if self.objects.len() > max_rows {
+            lines.push(format!("... {} more", self.objects.len() - max_rows));
+        }
+        lines
+    }
+}
+
+/// Name of the column of a world coordinate, the type of the axis without its
+/// projection in lower case, e.g. ra for RA---SIN
+fn coord_name(ctype: &str, axis: usize) -> String {
+    match ctype.split('-').next().unwrap_or_default().trim() {
+        "" => format!("world{}", axis + 1),
+        name => name.to_lowercase(),
+    }
+}
+
+/// UCD of the world coordinate of an axis, empty for the types not known
+fn world_ucd(ctype: &str) -> &'static str {
+    match ctype.split('-').next().unwrap_or_default().trim() {
+        "RA" => "pos.eq.ra",
+        "DEC" => "pos.eq.dec",
+        "GLON" => "pos.galactic.lon",
+        "GLAT" => "pos.galactic.lat",
+        "VRAD" | "VOPT" | "VELO" | "VELO_LSR" | "FELO" => "spect.dopplerVeloc",
+        "FREQ" => "em.freq",
+        "WAVE" | "AWAV" => "em.wl",
+        _ => "",
+    }
+}
+
+/// Unit of the world coordinates of an axis, CUNITn or else the default of the FITS
+/// standard for its type, empty for the types not known
+fn axis_unit<'a>(ctype: &str, cunit: &'a str) -> &'a str {
+    if !cunit.is_empty() {
+        return cunit;
+    }
+    match ctype.split('-').next().unwrap_or_default().trim() {
+        "RA" | "DEC" | "GLON" | "GLAT" => "deg",
+        "VRAD" | "VOPT" | "VELO" | "VELO_LSR" | "FELO" => "m/s",
+        "FREQ" => "Hz",
+        "WAVE" | "AWAV" => "m",
+        _ => "",
+    }
+}
+
+// text with the characters of the XML markup escaped
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wcs::{Wcs, WcsAxis, WcsFrame};
+
+    // cube of 4x3x2 voxels with three objects: a positive one, a negative one with a
+    // blank voxel and a blank one
+    fn found(wcs: Option<Wcs>) -> Catalog {
+        let index = |x: usize, y: usize, z: usize| (z * 3 + y) * 4 + x;
+        let mut data = vec![0.0; 24];
+        let mut ids = vec![0; 24];
+        for (voxel, v, id) in [
+            ((1, 1, 0), 1.0, 1),
+            ((2, 1, 0), 3.0, 1),
+            ((0, 0, 1), -1.0, 2),
+            ((1, 0, 1), f32::NAN, 2),
+            ((3, 2, 1), f32::NAN, 3),
+        ] {
+            let i = index(voxel.0, voxel.1, voxel.2);
+            data[i] = v;
+            ids[i] = id;
+        }
+        let cube = Cube {
+            data,
+            dim: (4, 3, 2),
+            datamin: None,
+            datamax: None,
+            bunit: Some("Jy".to_string()),
+            wcs,
+            name: Some("a&b.fits".to_string()),
+        };
+        Catalog::measure(&cube, &ids, &FinderSettings::default())
+    }
+
+    // linear celestial axes and a velocity axis in km/s
+    fn wcs() -> Wcs {
+        let axis = |ctype: &str, cunit: &str, crval: f64, cdelt: f64| WcsAxis {
+            ctype: ctype.to_string(),
+            cunit: cunit.to_string(),
+            crval,
+            crpix: 1.0,
+            cdelt,
+        };
+        Wcs {
+            axes: [
+                axis("RA", "", 10.0, -0.5),
+                axis("DEC", "", 20.0, 0.5),
+                axis("VRAD", "km/s", 100.0, -2.0),
+            ],
+            pc: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            frame: WcsFrame::default(),
+        }
+    }
+
+    #[test]
+    fn objects_are_measured() {
+        let catalog = found(None);
+        let objects = &catalog.objects;
+        assert_eq!(objects.len(), 3);
+
+        assert_eq!(objects[0].centroid, [1.75, 1.0, 0.0]);
+        assert_eq!(objects[0].bounds, [[1, 2], [1, 1], [0, 0]]);
+        assert_eq!(objects[0].extent(), [2, 1, 1]);
+        assert_eq!((objects[0].num_voxels, objects[0].flux), (2, 4.0));
+        assert_eq!(objects[0].peak, 3.0);
+        assert_eq!(objects[0].world, None);
+
+        // the middle of the bounds without positive value
+        assert_eq!(objects[1].centroid, [0.5, 0.0, 1.0]);
+        assert_eq!((objects[1].flux, objects[1].peak), (-1.0, -1.0));
+        assert!(objects[2].peak.is_nan());
+
+        // the flux is multiplied by the channel width
+        let catalog = found(Some(wcs()));
+        assert_eq!(catalog.objects[0].flux, 8.0);
+        assert_eq!(catalog.objects[0].world, Some([9.125, 20.5, 100.0]));
+    }
+
+    #[test]
+    fn csv_columns_and_rows() {
+        let csv = found(None).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("# 3 objects found in a&b.fits. Smoothed with"));
+        assert_eq!(
+            lines[1..],
+            [
+                "id,x,y,z,x_min,x_max,y_min,y_max,z_min,z_max,n_voxels,flux [Jy.channel],peak [Jy]",
+                "1,1.75,1,0,1,2,1,1,0,0,2,4,3",
+                "2,0.5,0,1,0,1,0,0,1,1,2,-1,-1",
+                "3,3,2,1,3,3,2,2,1,1,1,0,NaN",
+            ]
+        );
+
+        // the world coordinates in the default units of their axes
+        let csv = found(Some(wcs())).to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[1],
+            "id,x,y,z,ra [deg],dec [deg],vrad [km/s],x_min,x_max,y_min,y_max,z_min,z_max,\
+             n_voxels,flux [Jy.km/s],peak [Jy]"
+        );
+        assert_eq!(lines[2], "1,1.75,1,0,9.125,20.5,100,1,2,1,1,0,0,2,8,3");
+    }
+
+    #[test]
+    fn votable_fields_and_rows() {
+        let xml = found(Some(wcs())).to_votable();
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<VOTABLE"));
+        assert!(xml.ends_with("</TABLE>\n</RESOURCE>\n</VOTABLE>\n"));
+        assert!(xml.contains("<DESCRIPTION>3 objects found in a&amp;b.fits. "));
+        assert_eq!(xml.matches("<FIELD ").count(), 16);
+        assert!(xml.contains(
+            "<FIELD name=\"id\" datatype=\"int\" ucd=\"meta.id;meta.main\"><DESCRIPTION>"
+        ));
+        assert!(
+            xml.contains("<FIELD name=\"ra\" datatype=\"double\" unit=\"deg\" ucd=\"pos.eq.ra\">")
+        );
+        assert!(xml.contains("<FIELD name=\"flux\" datatype=\"double\" unit=\"Jy.km/s\""));
+
+        assert_eq!(xml.matches("<TR>").count(), 3);
+        assert!(xml.contains("<TR><TD>1</TD><TD>1.75</TD><TD>1</TD><TD>0</TD><TD>9.125</TD>"));
+        // the blank peak is an empty cell
+        assert!(xml.contains("<TD>1</TD><TD>0</TD><TD></TD></TR>\n"));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::wcs::Wcs;
+
+/// Range of spectral channels rendered, bounds included
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl ChannelRange {
+    /// Every channel of a cube of `depth` channels
+    pub(crate) fn all(depth: u32) -> Self {
+        Self {
+            first: 0,
+            last: depth.max(1) - 1,
+        }
+    }
+
+    pub(crate) fn clamp(self, depth: u32) -> Self {
+        let last_channel = depth.max(1) - 1;
+        let first = self.first.min(self.last).min(last_channel);
+        let last = self.first.max(self.last).min(last_channel);
+        Self { first, last }
+    }
+
+    pub(crate) fn width(self) -> u32 {
+        self.last - self.first + 1
+    }
+
+    /// Move the range by `delta` channels, keeping its width
+    pub(crate) fn shift(self, delta: i32, depth: u32) -> Self {
+        let max_first = (depth.max(1) - self.width().min(depth.max(1))) as i64;
+        let first = (self.first as i64 + delta as i64).clamp(0, max_first) as u32;
+        Self {
+            first,
+            last: first + self.width() - 1,
+        }
+    }
+
+    /// Widen (or narrow if negative) the range by `delta` channels on each side
+    pub(crate) fn widen(self, delta: i32, depth: u32) -> Self {
+        let first = self.first as i64 - delta as i64;
+        let last = self.last as i64 + delta as i64;
+        if first > last {
+            // keep at least the middle channel
+            let middle = (self.first + self.last) / 2;
+            return Self {
+                first: middle,
+                last: middle,
+            };
+        }
+
+        let last_channel = depth.max(1) as i64 - 1;
+        Self {
+            first: first.clamp(0, last_channel) as u32,
+            last: last.clamp(0, last_channel) as u32,
+        }
+    }
+
+    /// Bounds of the range in texture coordinates, as given to the shaders
+    pub(crate) fn uniform(self, depth: u32) -> [f32; 4] {
+        let depth = depth.max(1) as f32;
+        [
+            self.first as f32 / depth,
+            (self.last + 1) as f32 / depth,
+            0.0,
+            0.0,
+        ]
+    }
+}
+
+impl std::str::FromStr for ChannelRange {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = parse_bounds::<u32>(s)?;
+        Ok(Self { first, last })
+    }
+}
+
+/// Spectral range, in channels or in the world unit of the spectral axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpectralRange {
+    Channels(ChannelRange),
+    /// Bounds expressed in the unit of CUNIT3 (e.g. a velocity in m/s)
+    World(f64, f64),
+}
+
+impl SpectralRange {
+    /// Channels of a cube of `depth` channels covered by the range
+    pub fn to_channels(&self, depth: u32, wcs: Option<&Wcs>) -> Result<ChannelRange, &'static str> {
+        match *self {
+            SpectralRange::Channels(range) => Ok(range.clamp(depth)),
+            SpectralRange::World(w0, w1) => {
+                let axis = &wcs.ok_or("The cube has no WCS for its spectral axis")?.axes[2];
+                let p0 = axis.world2pix(w0);
+                let p1 = axis.world2pix(w1);
+                // CDELT3 may be negative
+                let (lo, hi) = (p0.min(p1).round(), p0.max(p1).round());
+                if hi < 0.0 || lo > depth as f64 - 1.0 {
+                    return Err("The spectral range is outside of the cube");
+                }
+
+                Ok(ChannelRange {
+                    first: lo.max(0.0) as u32,
+                    last: hi as u32,
+                }
+                .clamp(depth))
+            }
+        }
+    }
+}
+
+/// Narrow window of channels moving back and forth through the cube
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelSweep {
+    /// Number of channels of the window
+    pub width: u32,
+    /// Channels travelled per second
+    pub speed: f32,
+}
+
+impl Default for ChannelSweep {
+    fn default() -> Self {
+        Self {
+            width: 8,
+            speed: 10.0,
+        }
+    }
+}
+
+impl ChannelSweep {
+    /// Window of the sweep `t` seconds after its start
+    pub(crate) fn at(&self, t: f32, depth: u32) -> ChannelRange {
+        let width = self.width.clamp(1, depth.max(1));
+        let span = (depth.max(1) - width) as f32;
+        if span == 0.0 {
+            return ChannelRange::all(depth);
+        }
+
+        // ping-pong between both ends of the cube
+        let phase = (t * self.speed).rem_euclid(2.0 * span);
+        let first = if phase < span {
+            phase
+        } else {
+            2.0 * span - phase
+        };
+        let first = first.floor() as u32;
+        ChannelRange {
+            first,
+            last: first + width - 1,
+        }
+    }
+}
+
+impl std::str::FromStr for ChannelSweep {
+    type Err = &'static str;
+
+    /// `width` or `width:speed`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sweep = ChannelSweep::default();
+        match s.split_once(':') {
+            Some((width, speed)) => {
+                sweep.width = width.trim().parse().map_err(|_| "Invalid sweep width")?;
+                sweep.speed = speed.trim().parse().map_err(|_| "Invalid sweep speed")?;
+            }
+            None => sweep.width = s.trim().parse().map_err(|_| "Invalid sweep width")?,
+        }
+
+        if sweep.width == 0 {
+            Err("The sweep width must be at least one channel")
+        } else {
+            Ok(sweep)
+        }
+    }
+}
+
+/// Parse a `min:max` couple of bounds
+pub(crate) fn parse_bounds<T: std::str::FromStr>(s: &str) -> Result<(T, T), &'static str> {
+    let (min, max) = s
+        .split_once(':')
+        .ok_or("Expected a range written as min:max")?;
+    let min = min.trim().parse().map_err(|_| "Invalid lower bound")?;
+    let max = max.trim().parse().map_err(|_| "Invalid upper bound")?;
+    Ok((min, max))
+}
@@ -0,0 +1,1216 @@
+//! Command line interface of the native build
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use cgmath::{Deg, Quaternion, Rotation3};
+
+use crate::animation::Turntable;
+use crate::camera::{AxisView, Camera};
+use crate::catalog::CatalogFormat;
+use crate::channels::{self, ChannelRange, ChannelSweep, SpectralRange};
+use crate::colormap::Colormap;
+use crate::cube::Cube;
+use crate::export::{self, FrameExport, Screenshot};
+use crate::finder::FinderSettings;
+use crate::fits_writer::{FitsImage, FitsLabels, FitsTable, Projection};
+use crate::labels::LabelDisplay;
+use crate::layers::{Blend, Layer, LAYER_COLORMAPS, MAX_LAYERS};
+use crate::map;
+use crate::mesh::{Mesh, MeshFormat, MeshUnits};
+use crate::moments::{Moment, MomentSettings};
+use crate::overlay;
+use crate::pv::PvPath;
+use crate::render_mode::{self, Quality, RenderMode};
+use crate::renderer::{RenderSettings, Renderer};
+use crate::slice::SliceAxis;
+use crate::spectrum::{Aperture, Spectrum, SpectrumFormat};
+use crate::ViewerOptions;
+
+pub const USAGE: &str = "Usage:
+    fits3 [cube.fits] [options]             open the viewer
+        --channels <first>:<last>           render only a range of channels
+        --velocity <min>:<max>              render only a range of the spectral axis, in its unit (CUNIT3)
+        --sweep <width>[:<speed>]           sweep a window of channels through the cube (speed in channels/s)
+        --quality draft|normal|high         sampling of the rays (default: normal)
+        --view [-]x|y|z                     look along an axis of the cube, from the other side with '-'
+        --turntable <degrees/s>             spin the cube around its vertical axis
+        --export <output>                   render the animation to a .gif, .png (APNG) or a directory of PNG files, and quit
+        --frames <count>                    frames of the export (default: the camera path or a turn of the turntable)
+        --fps <rate>                        frame rate of the export (default: 30)
+        --size <width>x<height>             size of the exported frames (default: 1280x720)
+        --samples <count>                   jittered renderings averaged per exported frame (default: 16)
+        --export-quality draft|normal|high  sampling of the rays in the export (default: high)
+        --screenshot-size <width>x<height>  size of the screenshots taken with F12 (default: the window size)
+        --overlays colorbar,axes|none       annotations of the screenshots (default: colorbar,axes)
+        --aperture <aperture>               spaxels summed into the spectra extracted with a click (default: spaxel)
+        --slit-width <pixels>               width of the slit of the position-velocity diagrams (default: 1)
+        --threshold <value>                 leave out the samples below a value from the moment maps
+        --layers <cube.fits>,...            cubes overlaid, aligned by their WCS or else by the voxel grid (3 at most)
+        --layer-colormaps <colormap>,...    colormaps of the cubes overlaid (default: grey, viridis, turbo)
+        --layer-opacity <opacity>           weight of the colors of the cubes overlaid, between 0 and 1 (default: 1)
+        --blend additive|max|alpha          combination of the colors of the cubes overlaid with the cube (default: additive)
+        --labels <mask.fits>                integer cube of the objects found in the cube, e.g. a SoFiA mask
+        --label-display off|outlines|tint   drawing of the objects labeled (default: outlines)
+        --unlabeled show|hide               data outside of the objects labeled (default: show)
+    fits3 mesh <cube.fits> --threshold <value> [options]
+        --smooth <iterations>               smoothing iterations (default: 0)
+        --units voxel|wcs                   unit of the vertices (default: voxel)
+        --format ply|obj|glb                format of the output (default: from the output extension)
+        --output <file>                     output file (default: <cube>.ply)
+    fits3 moments <cube.fits> [options]     save a moment map of a cube as a PNG image or a FITS file
+        --moment 0|1|2|peak                 integrated intensity, velocity, dispersion or peak (default: 0)
+        --channels <first>:<last>           channels integrated (default: all)
+        --velocity <min>:<max>              range of the spectral axis integrated, in its unit (CUNIT3)
+        --threshold <value>                 leave out the samples below a value
+        --output <file>                     .png or .fits output file (default: <cube>_mom0.png)
+    fits3 subcube <cube.fits> [options]     save a part of a cube, or its projection, as a FITS file
+        --region <x0>:<x1>,<y0>:<y1>        voxels kept in the spatial plane, bounds included (default: all)
+        --channels <first>:<last>           channels kept (default: all)
+        --velocity <min>:<max>              range of the spectral axis kept, in its unit (CUNIT3)
+        --bin <x>,<y>,<z>                   voxels averaged along each axis (default: 1,1,1)
+        --project x|y|z                     collapse the part kept along an axis instead of binning it
+        --mode mean|max                     value of the projection (default: mean)
+        --output <file>                     output file (default: <cube>_subcube.fits)
+    fits3 spectrum <cube.fits> [options]    save the spectrum of a position as a CSV file, a FITS table or a PNG plot
+        --position <x>,<y>                  center of the aperture, in 0-based pixels
+        --world <a>,<b>                     center of the aperture, in the world coordinates of the spatial axes
+        --aperture <aperture>               spaxel, circle:<radius> or box:<width>x<height>, in pixels (default: spaxel)
+        --output <file>                     .csv, .fits or .png output file (default: <cube>_spectrum.csv)
+    fits3 pv <cube.fits> --path <x0>,<y0>:<x1>,<y1>... [options]
+                                            save the position-velocity diagram along a path as a FITS file or a PNG image
+        --width <pixels>                    width of the slit across the path, the values across it being averaged (default: 1)
+        --output <file>                     .fits or .png output file (default: <cube>_pv.fits)
+    fits3 find <cube.fits> [options]        find the objects of a cube and save their catalog as a CSV file or a VOTable
+        --threshold <sigma>                 detection threshold, in multiples of the noise (default: 5)
+        --spatial <fwhm>,...                FWHM of the Gaussian spatial kernels in pixels, 0 for none (default: 0,3,6)
+        --spectral <width>,...              width of the boxcar spectral kernels in channels, 0 for none (default: 0,3,7)
+        --min-size <voxels>                 objects of fewer voxels are dropped (default: 8)
+        --output <file>                     .csv or .vot output file (default: <cube>_cat.csv)
+        --mask <file>                       FITS cube of the labels of the objects (default: <cube>_mask.fits)
+    fits3 render <cube.fits|directory>... [options]
+                                            render PNG thumbnails of cubes without any window
+        --output <directory>                directory of the thumbnails (default: thumbnails)
+        --views <view>,...                  [-]x, [-]y, [-]z projections and 3d oblique view (default: x,y,z,3d)
+        --mode average|iso|velocity         combination of the samples along the rays (default: average)
+        --stretch linear|sqrt|log|asinh     stretch of the values between the cuts (default: linear)
+        --colormap jet|grey|viridis|turbo   colormap (default: jet)
+        --cuts <low>:<high>                 percentiles of the values at the ends of the colormap (default: 0.5:99.5)
+        --size <width>x<height>             size of the thumbnails (default: 256x256)
+        --samples <count>                   jittered renderings averaged per thumbnail (default: 4)
+        --quality draft|normal|high         sampling of the rays (default: high)
+        --below-cut show|hide               average the values after the cuts, the ones below the lower cut giving nothing (default: show)
+        --layers <cube.fits>,...            cubes overlaid on each cube, with the same stretch and cuts
+        --layer-colormaps <colormap>,...    colormaps of the cubes overlaid (default: grey, viridis, turbo)
+        --layer-opacity <opacity>           weight of the colors of the cubes overlaid (default: 1)
+        --blend additive|max|alpha          combination of the colors of the cubes overlaid (default: additive)
+        --summary <file>                    JSON statistics of the cuts of each cube (default: <output>/summary.json)";
+
+/// Positional values and `--key value` (or `--key=value`) options, the options not
+/// known by the command being refused
+pub(crate) struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    pub(crate) fn parse(args: &[String], known: &[&str]) -> Result<Self, String> {
+        let mut positional = vec![];
+        let mut options = HashMap::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(key) = arg.strip_prefix("--") {
+                let name = key.split_once('=').map_or(key, |(name, _)| name);
+                if !known.contains(&name) {
+                    return Err(format!(
+                        "Unknown option --{}, see fits3 --help for the options",
+                        name
+                    ));
+                }
+                if let Some((key, value)) = key.split_once('=') {
+                    options.insert(key.to_string(), value.to_string());
+                } else {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("Missing value for option --{}", key))?;
+                    options.insert(key.to_string(), value.clone());
+                }
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    pub(crate) fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Get and parse the value of an option
+    pub(crate) fn get<T>(&self, key: &str) -> Result<Option<T>, String>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.options
+            .get(key)
+            .map(|value| {
+                value
+                    .parse::<T>()
+                    .map_err(|e| format!("Invalid value '{}' for --{}: {}", value, key, e))
+            })
+            .transpose()
+    }
+}
+
+/// `fits3 [cube.fits]`: options of the viewer
+pub fn viewer_options(args: &[String]) -> Result<ViewerOptions, String> {
+    let args = Args::parse(
+        args,
+        &[
+            "channels",
+            "velocity",
+            "sweep",
+            "quality",
+            "view",
+            "turntable",
+            "export",
+            "frames",
+            "fps",
+            "size",
+            "samples",
+            "export-quality",
+            "screenshot-size",
+            "overlays",
+            "aperture",
+            "slit-width",
+            "threshold",
+            "layers",
+            "layer-colormaps",
+            "layer-opacity",
+            "blend",
+            "labels",
+            "label-display",
+            "unlabeled",
+        ],
+    )?;
+
+    let path = match args.positional() {
+        [] => None,
+        [path] => Some(PathBuf::from(path)),
+        _ => return Err(USAGE.to_string()),
+    };
+    if let Some(path) = &path {
+        if !path.is_file() {
+            return Err(format!("{}: no such file", path.display()));
+        }
+    }
+
+    let channels = get_spectral_range(&args)?;
+    let sweep = args.get::<ChannelSweep>("sweep")?;
+    let quality = args.get::<Quality>("quality")?;
+    let view = args.get::<AxisView>("view")?;
+    let turntable = args.get::<Turntable>("turntable")?;
+
+    let export = args.get::<PathBuf>("export")?;
+    let defaults = FrameExport::default();
+    let size = get_size(&args, "size")?;
+    let frame_export = FrameExport {
+        size: size.unwrap_or(defaults.size),
+        frames: args.get("frames")?,
+        fps: args.get("fps")?.unwrap_or(defaults.fps),
+        quality: args.get("export-quality")?.unwrap_or(defaults.quality),
+        samples: args.get("samples")?.unwrap_or(defaults.samples),
+    };
+
+    let mut screenshot = Screenshot {
+        size: get_size(&args, "screenshot-size")?,
+        ..Default::default()
+    };
+    if let Some(overlays) = args.get::<String>("overlays")? {
+        screenshot.colorbar = false;
+        screenshot.axes = false;
+        for overlay in overlays.split(',').filter(|o| *o != "none") {
+            match overlay.trim() {
+                "colorbar" => screenshot.colorbar = true,
+                "axes" => screenshot.axes = true,
+                _ => {
+                    return Err(format!(
+                        "Invalid value '{}' for --overlays: expected 'colorbar', 'axes' or 'none'",
+                        overlays
+                    ))
+                }
+            }
+        }
+    }
+
+    let aperture = args.get::<Aperture>("aperture")?;
+    let slit_width = args.get::<f32>("slit-width")?;
+    let moment_threshold = args.get::<f32>("threshold")?;
+    let layers = get_layers(&args)?;
+    let layer_opacity = args.get::<f32>("layer-opacity")?;
+    let blend = args.get::<Blend>("blend")?;
+    let labels = args.get::<PathBuf>("labels")?;
+    let label_display = args.get::<LabelDisplay>("label-display")?;
+    let hide_unlabeled = match args.get::<String>("unlabeled")?.as_deref() {
+        None | Some("show") => false,
+        Some("hide") => true,
+        Some(unlabeled) => {
+            return Err(format!(
+                "Invalid value '{}' for --unlabeled: expected 'show' or 'hide'",
+                unlabeled
+            ))
+        }
+    };
+
+    Ok(ViewerOptions {
+        path,
+        channels,
+        sweep,
+        quality,
+        view,
+        turntable,
+        export,
+        frame_export,
+        screenshot,
+        aperture,
+        slit_width,
+        moment_threshold,
+        layers,
+        layer_opacity,
+        blend,
+        labels,
+        label_display,
+        hide_unlabeled,
+    })
+}
+
+/// Get and parse the `--channels` or `--velocity` option
+fn get_spectral_range(args: &Args) -> Result<Option<SpectralRange>, String> {
+    let channels = args.get::<ChannelRange>("channels")?;
+    let velocity = args
+        .get::<String>("velocity")?
+        .map(|range| {
+            channels::parse_bounds::<f64>(&range)
+                .map_err(|e| format!("Invalid value '{}' for --velocity: {}", range, e))
+        })
+        .transpose()?;
+    match (channels, velocity) {
+        (Some(_), Some(_)) => Err("--channels and --velocity cannot be given together".to_string()),
+        (Some(range), None) => Ok(Some(SpectralRange::Channels(range))),
+        (None, Some((min, max))) => Ok(Some(SpectralRange::World(min, max))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Get and parse the `--layers` option, with the colormaps of `--layer-colormaps`
+fn get_layers(args: &Args) -> Result<Vec<(PathBuf, Option<Colormap>)>, String> {
+    let paths: Vec<PathBuf> = match args.get::<String>("layers")? {
+        Some(layers) => layers
+            .split(',')
+            .map(|path| PathBuf::from(path.trim()))
+            .collect(),
+        None => vec![],
+    };
+    if paths.len() > MAX_LAYERS {
+        return Err(format!("{} cubes can be overlaid at most", MAX_LAYERS));
+    }
+    if let Some(path) = paths.iter().find(|path| !path.is_file()) {
+        return Err(format!("{}: no such file", path.display()));
+    }
+
+    let colormaps = match args.get::<String>("layer-colormaps")? {
+        Some(colormaps) => colormaps
+            .split(',')
+            .map(|colormap| {
+                colormap.trim().parse().map_err(|e| {
+                    format!("Invalid value '{}' for --layer-colormaps: {}", colormaps, e)
+                })
+            })
+            .collect::<Result<Vec<Colormap>, _>>()?,
+        None => vec![],
+    };
+    if colormaps.len() > paths.len() {
+        return Err("--layer-colormaps has more colormaps than cubes in --layers".to_string());
+    }
+
+    let colormaps = colormaps
+        .into_iter()
+        .map(Some)
+        .chain(std::iter::repeat(None));
+    Ok(paths.into_iter().zip(colormaps).collect())
+}
+
+/// Get and parse a `<width>x<height>` option
+fn get_size(args: &Args, key: &str) -> Result<Option<(u32, u32)>, String> {
+    args.get::<String>(key)?
+        .map(|size| {
+            export::parse_size(&size)
+                .map_err(|e| format!("Invalid value '{}' for --{}: {}", size, key, e))
+        })
+        .transpose()
+}
+
+/// `fits3 mesh`: extract an isosurface of a cube and save it to a file
+pub fn mesh(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["threshold", "smooth", "units", "format", "output"])?;
+
+    let path = match args.positional() {
+        [path] => PathBuf::from(path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let threshold: f32 = args
+        .get("threshold")?
+        .ok_or("The --threshold option is mandatory")?;
+    let smooth: usize = args.get("smooth")?.unwrap_or(0);
+    let units: MeshUnits = args.get("units")?.unwrap_or(MeshUnits::Voxel);
+    let output: PathBuf = args
+        .get("output")?
+        .unwrap_or_else(|| path.with_extension("ply"));
+    let format: MeshFormat = match args.get("format")? {
+        Some(format) => format,
+        None => MeshFormat::from_path(&output)
+            .ok_or("Cannot guess the mesh format from the output, use --format")?,
+    };
+
+    let cube = Cube::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut mesh = Mesh::marching_cubes(&cube, threshold);
+    mesh.smooth(smooth);
+    if units == MeshUnits::Wcs {
+        let wcs = cube
+            .wcs()
+            .ok_or_else(|| format!("{}: the cube has no WCS", path.display()))?;
+        mesh.to_world(wcs);
+    }
+
+    let bytes = mesh
+        .to_bytes(format)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    write_file(&output, |file| std::io::Write::write_all(file, &bytes))?;
+    println!(
+        "{} triangles written to {}",
+        mesh.num_triangles(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// `fits3 moments`: compute a moment map of a cube and save it as an image
+pub fn moments(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(
+        args,
+        &["moment", "channels", "velocity", "threshold", "output"],
+    )?;
+
+    let path = match args.positional() {
+        [path] => PathBuf::from(path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let moment: Moment = args.get("moment")?.unwrap_or(Moment::Integrated);
+    let spectral_range = get_spectral_range(&args)?;
+    let threshold: Option<f32> = args.get("threshold")?;
+    let output: PathBuf = args.get("output")?.unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_{}.png", stem, moment.name()))
+    });
+
+    let cube = Cube::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let channels = spectral_range
+        .map(|range| range.to_channels(cube.dim().2, cube.wcs()))
+        .transpose()?;
+
+    let mut renderer = pollster::block_on(Renderer::headless())?;
+    renderer.load_cube(&cube)?;
+    let map = renderer.moment_map(
+        &cube,
+        &MomentSettings {
+            moment,
+            channels,
+            threshold,
+        },
+    )?;
+    let range = map
+        .value_range()
+        .ok_or("No sample of the cube is kept, check the threshold")?;
+
+    let is_fits = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("fits"));
+    if is_fits {
+        let image = FitsImage::moment_map(&cube, &map)?;
+        write_file(&output, |file| image.write(file))?;
+    } else {
+        let png = export::encode_png(&map::colorize(map.size, &map.data, &range), map.size)?;
+        std::fs::write(&output, png).map_err(|e| format!("{}: {}", output.display(), e))?;
+    }
+    println!(
+        "{} of the channels {} to {} written to {}, from {} to {}",
+        moment.name(),
+        map.channels.first,
+        map.channels.last,
+        output.display(),
+        range.start,
+        range.end
+    );
+
+    Ok(())
+}
+
+/// `fits3 subcube`: save a part of a cube, binned or projected along an axis, as a FITS file
+pub fn subcube(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(
+        args,
+        &[
+            "region", "channels", "velocity", "bin", "project", "mode", "output",
+        ],
+    )?;
+
+    let path = match args.positional() {
+        [path] => PathBuf::from(path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let region = args
+        .get::<String>("region")?
+        .map(|region| {
+            parse_region(&region)
+                .map_err(|e| format!("Invalid value '{}' for --region: {}", region, e))
+        })
+        .transpose()?;
+    let spectral_range = get_spectral_range(&args)?;
+    let bin = args
+        .get::<String>("bin")?
+        .map(|bin| parse_bin(&bin).map_err(|e| format!("Invalid value '{}' for --bin: {}", bin, e)))
+        .transpose()?
+        .unwrap_or([1, 1, 1]);
+    let axis = match args.get::<String>("project")?.as_deref() {
+        None => None,
+        Some("x") => Some(0),
+        Some("y") => Some(1),
+        Some("z") => Some(2),
+        Some(axis) => {
+            return Err(format!(
+                "Invalid value '{}' for --project: expected 'x', 'y' or 'z'",
+                axis
+            ))
+        }
+    };
+    if axis.is_some() && bin != [1, 1, 1] {
+        return Err("--bin and --project cannot be given together".to_string());
+    }
+    let projection: Projection = args.get("mode")?.unwrap_or(Projection::Mean);
+    let output: PathBuf = args.get("output")?.unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let suffix = match axis {
+            Some(axis) => format!("{}_{}", projection.name(), ["x", "y", "z"][axis]),
+            None => "subcube".to_string(),
+        };
+        path.with_file_name(format!("{}_{}.fits", stem, suffix))
+    });
+
+    let cube = Cube::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (w, h, d) = cube.dim();
+    let [x, y] = region.unwrap_or([0..w, 0..h]);
+    let channels = match spectral_range {
+        Some(range) => range.to_channels(d, cube.wcs())?,
+        None => ChannelRange::all(d),
+    };
+    let region = [x, y, channels.first..channels.last + 1];
+
+    let image = match axis {
+        Some(axis) => FitsImage::projection(&cube, &region, axis, projection),
+        None => FitsImage::subcube(&cube, &region, bin),
+    }
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+    write_file(&output, |file| image.write(file))?;
+    let dim: Vec<String> = image.dim.iter().map(u32::to_string).collect();
+    println!("{} image written to {}", dim.join("x"), output.display());
+
+    Ok(())
+}
+
+/// `fits3 spectrum`: extract the spectrum of a spaxel or of an aperture and save it
+pub fn spectrum(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["position", "world", "aperture", "output"])?;
+
+    let path = match args.positional() {
+        [path] => PathBuf::from(path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let position = args
+        .get::<String>("position")?
+        .map(|position| {
+            parse_position(&position)
+                .map_err(|e| format!("Invalid value '{}' for --position: {}", position, e))
+        })
+        .transpose()?;
+    let world = args
+        .get::<String>("world")?
+        .map(|world| {
+            parse_position(&world)
+                .map_err(|e| format!("Invalid value '{}' for --world: {}", world, e))
+        })
+        .transpose()?;
+    let aperture: Aperture = args.get("aperture")?.unwrap_or(Aperture::Spaxel);
+    let output: PathBuf = args.get("output")?.unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_spectrum.csv", stem))
+    });
+
+    let cube = Cube::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let spectrum = match (position, world) {
+        (Some(position), None) => Spectrum::extract(&cube, position, aperture),
+        (None, Some(world)) => Spectrum::extract_world(&cube, world, aperture),
+        _ => return Err("Give either --position or --world".to_string()),
+    }
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let is_png = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if is_png {
+        let size = (800, 400);
+        let x_label = format!("{} {}", spectrum.coord_name(), spectrum.cunit);
+        let rgba = overlay::line_plot(
+            &spectrum.title(),
+            x_label.trim(),
+            spectrum.bunit.as_deref().unwrap_or_default(),
+            &spectrum.coords_or_channels(),
+            &spectrum.values,
+            size,
+            2,
+        );
+        let png = export::encode_png(&rgba, size)?;
+        std::fs::write(&output, png).map_err(|e| format!("{}: {}", output.display(), e))?;
+    } else {
+        match SpectrumFormat::from_path(&output).unwrap_or(SpectrumFormat::Csv) {
+            SpectrumFormat::Csv => {
+                std::fs::write(&output, spectrum.to_csv())
+                    .map_err(|e| format!("{}: {}", output.display(), e))?;
+            }
+            SpectrumFormat::Fits => {
+                let table = FitsTable::spectrum(&cube, &spectrum);
+                write_file(&output, |file| table.write(file))?;
+            }
+        }
+    }
+    match spectrum.aperture {
+        Aperture::Spaxel => println!(
+            "{} channels written to {}",
+            spectrum.values.len(),
+            output.display()
+        ),
+        _ => println!(
+            "{} channels summed over {} spaxels written to {}",
+            spectrum.values.len(),
+            spectrum.num_spaxels,
+            output.display()
+        ),
+    }
+
+    Ok(())
+}
+
+/// `fits3 pv`: compute the position-velocity diagram along a path and save it
+pub fn pv(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["path", "width", "output"])?;
+
+    let path = match args.positional() {
+        [path] => PathBuf::from(path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let vertices = match args.get::<String>("path")? {
+        Some(vertices) => vertices
+            .split(':')
+            .map(parse_position)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid value '{}' for --path: {}", vertices, e))?,
+        None => return Err("Give the vertices of the path with --path".to_string()),
+    };
+    let width: f32 = args.get("width")?.unwrap_or(1.0);
+    let output: PathBuf = args.get("output")?.unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_pv.fits", stem))
+    });
+
+    let cube = Cube::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut renderer = pollster::block_on(Renderer::headless())?;
+    renderer.load_cube(&cube)?;
+    let pv = renderer.pv_diagram(&cube, &PvPath { vertices, width })?;
+    let range = pv
+        .value_range()
+        .ok_or("The path does not cross any finite value of the cube")?;
+
+    let is_png = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if is_png {
+        let png = export::encode_png(&map::colorize(pv.size, &pv.data, &range), pv.size)?;
+        std::fs::write(&output, png).map_err(|e| format!("{}: {}", output.display(), e))?;
+    } else {
+        let image = FitsImage::pv_diagram(&cube, &pv)?;
+        write_file(&output, |file| image.write(file))?;
+    }
+    println!(
+        "{} positions along a path of {:.1} pixels by {} channels written to {}, from {} to {}",
+        pv.size.0,
+        pv.path.length(),
+        pv.size.1,
+        output.display(),
+        range.start,
+        range.end
+    );
+
+    Ok(())
+}
+
+/// `fits3 find`: find the objects of a cube, save their catalog and their labels
+pub fn find(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(
+        args,
+        &[
+            "threshold",
+            "spatial",
+            "spectral",
+            "min-size",
+            "output",
+            "mask",
+        ],
+    )?;
+
+    let path = match args.positional() {
+        [path] => PathBuf::from(path),
+        _ => return Err(USAGE.to_string()),
+    };
+    let defaults = FinderSettings::default();
+    let settings = FinderSettings {
+        threshold: args.get("threshold")?.unwrap_or(defaults.threshold),
+        spatial_kernels: get_list(&args, "spatial")?.unwrap_or(defaults.spatial_kernels),
+        spectral_kernels: get_list(&args, "spectral")?.unwrap_or(defaults.spectral_kernels),
+        min_size: args.get("min-size")?.unwrap_or(defaults.min_size),
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let output: PathBuf = args
+        .get("output")?
+        .unwrap_or_else(|| path.with_file_name(format!("{}_cat.csv", stem)));
+    let mask: PathBuf = args
+        .get("mask")?
+        .unwrap_or_else(|| path.with_file_name(format!("{}_mask.fits", stem)));
+
+    let cube = Cube::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (labels, catalog) = settings
+        .find_sources(&cube)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let text = match CatalogFormat::from_path(&output).unwrap_or(CatalogFormat::Csv) {
+        CatalogFormat::Csv => catalog.to_csv(),
+        CatalogFormat::VoTable => catalog.to_votable(),
+    };
+    std::fs::write(&output, text).map_err(|e| format!("{}: {}", output.display(), e))?;
+    let image = FitsLabels::new(&cube, &labels, &catalog);
+    write_file(&mask, |file| image.write(file))?;
+    println!(
+        "{} objects written to {}, labeled in {}",
+        catalog.objects.len(),
+        output.display(),
+        mask.display()
+    );
+
+    Ok(())
+}
+
+/// Get and parse the comma separated values of an option
+fn get_list<T>(args: &Args, key: &str) -> Result<Option<Vec<T>>, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    args.get::<String>(key)?
+        .map(|values| {
+            values
+                .split(',')
+                .map(|value| value.trim().parse::<T>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Invalid value '{}' for --{}: {}", values, key, e))
+        })
+        .transpose()
+}
+
+/// Parse the `<x>,<y>` center of the aperture of `fits3 spectrum`, or a vertex of
+/// the path of `fits3 pv`
+fn parse_position(s: &str) -> Result<[f64; 2], &'static str> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or("Expected a position written as <x>,<y>")?;
+    match (x.trim().parse::<f64>(), y.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) if x.is_finite() && y.is_finite() => Ok([x, y]),
+        _ => Err("Invalid coordinate"),
+    }
+}
+
+/// Parse the `<x0>:<x1>,<y0>:<y1>` spatial region of `fits3 subcube` into voxel ranges
+fn parse_region(s: &str) -> Result<[std::ops::Range<u32>; 2], &'static str> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or("Expected a region written as <x0>:<x1>,<y0>:<y1>")?;
+    let (x0, x1) = channels::parse_bounds::<u32>(x)?;
+    let (y0, y1) = channels::parse_bounds::<u32>(y)?;
+    if x0 > x1 || y0 > y1 {
+        return Err("The lower bounds must not be above the upper ones");
+    }
+    Ok([x0..x1 + 1, y0..y1 + 1])
+}
+
+/// Parse the `<x>,<y>,<z>` binning factors of `fits3 subcube`
+fn parse_bin(s: &str) -> Result<[u32; 3], &'static str> {
+    let factors = s
+        .split(',')
+        .map(|factor| factor.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "Invalid binning factor")?;
+    match factors[..] {
+        [x, y, z] if x > 0 && y > 0 && z > 0 => Ok([x, y, z]),
+        [_, _, _] => Err("The binning factors must be positive"),
+        _ => Err("Expected three binning factors written as <x>,<y>,<z>"),
+    }
+}
+
+/// View rendered by `fits3 render`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Thumbnail {
+    /// Orthographic projection along an axis of the cube
+    Axis(AxisView),
+    /// Perspective view from above a corner of the cube
+    Oblique,
+}
+
+impl Thumbnail {
+    /// Suffix of the file name
+    fn name(self) -> String {
+        match self {
+            Thumbnail::Axis(view) => {
+                let axis = match view.axis {
+                    SliceAxis::X => "x",
+                    SliceAxis::Y => "y",
+                    SliceAxis::Z => "z",
+                };
+                if view.reversed {
+                    format!("-{}", axis)
+                } else {
+                    axis.to_string()
+                }
+            }
+            Thumbnail::Oblique => "3d".to_string(),
+        }
+    }
+
+    fn camera(self) -> Camera {
+        match self {
+            Thumbnail::Axis(view) => Camera {
+                orientation: view.orientation(),
+                // the cube fills the height with a small margin
+                ortho_scale: 0.55,
+                ..Default::default()
+            },
+            Thumbnail::Oblique => Camera {
+                orientation: Quaternion::from_angle_y(Deg(35.0))
+                    * Quaternion::from_angle_x(Deg(-25.0)),
+                distance: 1.7,
+                perspective: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl FromStr for Thumbnail {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "3d" => Ok(Thumbnail::Oblique),
+            _ => s
+                .parse()
+                .map(Thumbnail::Axis)
+                .map_err(|_| "Unknown view, expected [-]x, [-]y, [-]z or 3d"),
+        }
+    }
+}
+
+/// `fits3 render`: render thumbnails of many cubes, going on after the
+/// cubes that fail
+pub fn render(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(
+        args,
+        &[
+            "output",
+            "views",
+            "mode",
+            "stretch",
+            "colormap",
+            "cuts",
+            "size",
+            "samples",
+            "quality",
+            "below-cut",
+            "layers",
+            "layer-colormaps",
+            "layer-opacity",
+            "blend",
+            "summary",
+        ],
+    )?;
+
+    if args.positional().is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let paths = fits_files(args.positional())?;
+
+    let output: PathBuf = args
+        .get("output")?
+        .unwrap_or_else(|| PathBuf::from("thumbnails"));
+    let views = match args.get::<String>("views")? {
+        Some(views) => views
+            .split(',')
+            .map(|view| {
+                view.trim()
+                    .parse()
+                    .map_err(|e| format!("Invalid value '{}' for --views: {}", views, e))
+            })
+            .collect::<Result<Vec<Thumbnail>, _>>()?,
+        None => [SliceAxis::X, SliceAxis::Y, SliceAxis::Z]
+            .iter()
+            .map(|&axis| {
+                Thumbnail::Axis(AxisView {
+                    axis,
+                    reversed: false,
+                })
+            })
+            .chain(Some(Thumbnail::Oblique))
+            .collect(),
+    };
+    let percents = match args.get::<String>("cuts")? {
+        Some(cuts) => channels::parse_bounds::<f32>(&cuts)
+            .map_err(|e| format!("Invalid value '{}' for --cuts: {}", cuts, e))?,
+        None => (0.5, 99.5),
+    };
+    if !(0.0..=100.0).contains(&percents.0)
+        || !(0.0..=100.0).contains(&percents.1)
+        || percents.0 >= percents.1
+    {
+        return Err("The --cuts percentiles must be increasing between 0 and 100".to_string());
+    }
+    let size = get_size(&args, "size")?.unwrap_or((256, 256));
+    let summary: PathBuf = args
+        .get("summary")?
+        .unwrap_or_else(|| output.join("summary.json"));
+    let settings = RenderSettings {
+        stretch: args.get("stretch")?.unwrap_or_default(),
+        colormap: args.get("colormap")?.unwrap_or_default(),
+        render_mode: args.get("mode")?.unwrap_or(RenderMode::Average),
+        quality: args.get("quality")?.unwrap_or(Quality::HIGH),
+        samples: args.get("samples")?.unwrap_or(4),
+        blend: args.get("blend")?.unwrap_or_default(),
+        hide_below_cut: match args.get::<String>("below-cut")?.as_deref() {
+            None | Some("show") => false,
+            Some("hide") => true,
+            Some(below_cut) => {
+                return Err(format!(
+                    "Invalid value '{}' for --below-cut: expected 'show' or 'hide'",
+                    below_cut
+                ))
+            }
+        },
+        ..Default::default()
+    };
+    let opacity = args.get::<f32>("layer-opacity")?.unwrap_or(1.0);
+    let layers = get_layers(&args)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, (path, colormap))| {
+            let cube = Cube::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let (_, minmax) = percentile_cuts(&cube, percents)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(LayerCube {
+                cube,
+                colormap: colormap.unwrap_or(LAYER_COLORMAPS[i]),
+                minmax,
+                opacity,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let thumbnails = Thumbnails {
+        views,
+        percents,
+        settings,
+        layers,
+        size,
+    };
+
+    std::fs::create_dir_all(&output).map_err(|e| format!("{}: {}", output.display(), e))?;
+    let mut renderer = pollster::block_on(Renderer::headless())?;
+
+    let mut entries = vec![];
+    let mut failed = 0;
+    for (path, stem) in paths.iter().zip(thumbnail_stems(&paths)) {
+        let entry = match render_thumbnails(&mut renderer, path, &output, &stem, &thumbnails) {
+            Ok(entry) => {
+                println!("{}: {} thumbnails", path.display(), thumbnails.views.len());
+                entry
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                failed += 1;
+                format!(
+                    r#"{{"file":{},"error":{}}}"#,
+                    json_string(&path.display().to_string()),
+                    json_string(&e)
+                )
+            }
+        };
+        entries.push(entry);
+    }
+
+    let json = format!(
+        r#"{{"stretch":{},"colormap":{},"percentiles":[{},{}],"cubes":[{}]}}"#,
+        json_string(thumbnails.settings.stretch.name()),
+        json_string(thumbnails.settings.colormap.name()),
+        percents.0,
+        percents.1,
+        entries.join(",\n")
+    );
+    write_file(&summary, |file| {
+        std::io::Write::write_all(file, json.as_bytes())
+    })?;
+    println!(
+        "{} cubes rendered to {}, statistics written to {}",
+        paths.len() - failed,
+        output.display(),
+        summary.display()
+    );
+
+    if failed > 0 {
+        Err(format!(
+            "{} of {} cubes could not be rendered",
+            failed,
+            paths.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Files given and FITS files of the directories given
+fn fits_files(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files = vec![];
+    for path in paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            let entries =
+                std::fs::read_dir(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let mut cubes: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.is_file()
+                        && path
+                            .extension()
+                            .and_then(|ext| ext.to_str())
+                            .map(|ext| {
+                                ["fits", "fit", "fts"].contains(&ext.to_lowercase().as_str())
+                            })
+                            .unwrap_or(false)
+                })
+                .collect();
+            cubes.sort();
+            files.extend(cubes);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Names of the thumbnails of each cube, the stem of its file followed by a counter
+/// when cubes of the same name are found in several directories
+fn thumbnail_stems(paths: &[PathBuf]) -> Vec<String> {
+    let mut used = std::collections::HashSet::new();
+    paths
+        .iter()
+        .map(|path| {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "cube".to_string());
+            let mut name = stem.clone();
+            let mut count = 1;
+            while !used.insert(name.clone()) {
+                count += 1;
+                name = format!("{}_{}", stem, count);
+            }
+            name
+        })
+        .collect()
+}
+
+/// What `fits3 render` draws of each cube
+struct Thumbnails {
+    views: Vec<Thumbnail>,
+    /// Percentiles of the values at the ends of the colormaps
+    percents: (f32, f32),
+    settings: RenderSettings,
+    layers: Vec<LayerCube>,
+    size: (u32, u32),
+}
+
+/// Cube overlaid on the thumbnails, with its colors
+struct LayerCube {
+    cube: Cube,
+    colormap: Colormap,
+    minmax: std::ops::Range<f32>,
+    opacity: f32,
+}
+
+/// Values of a cube at the percentiles given, and the range mapped to the colormap
+fn percentile_cuts(
+    cube: &Cube,
+    percents: (f32, f32),
+) -> Result<([f32; 2], std::ops::Range<f32>), &'static str> {
+    let cuts = cube
+        .percentiles(&[percents.0, percents.1])
+        .ok_or("The cube has no finite value")?;
+    // a flat cube is drawn with the low end of the colormap
+    let minmax = if cuts[1] > cuts[0] {
+        cuts[0]..cuts[1]
+    } else {
+        cuts[0]..cuts[0] + 1.0
+    };
+    Ok(([cuts[0], cuts[1]], minmax))
+}
+
+/// Render the views of a cube into `<output>/<stem>_<view>.png`, returning
+/// the entry of the cube in the summary
+fn render_thumbnails(
+    renderer: &mut Renderer,
+    path: &Path,
+    output: &Path,
+    stem: &str,
+    thumbnails: &Thumbnails,
+) -> Result<String, String> {
+    let &Thumbnails {
+        ref views,
+        percents,
+        ref settings,
+        ref layers,
+        size,
+    } = thumbnails;
+    let cube = Cube::open(path)?;
+    let range = cube.value_range().ok_or("The cube has no finite value")?;
+    let (cuts, minmax) = percentile_cuts(&cube, percents)?;
+
+    renderer.load_cube(&cube)?;
+    for layer in layers {
+        renderer.add_layer(&layer.cube)?;
+    }
+    let mut settings = RenderSettings {
+        iso_levels: render_mode::spread_iso_levels(1, minmax.start, minmax.end),
+        minmax,
+        layers: layers
+            .iter()
+            .map(|layer| Layer {
+                minmax: layer.minmax.clone(),
+                stretch: settings.stretch,
+                opacity: layer.opacity,
+                ..Layer::new(&cube, &layer.cube, layer.colormap)
+            })
+            .collect(),
+        ..settings.clone()
+    };
+
+    let mut files = vec![];
+    for view in views {
+        settings.camera = view.camera();
+        let rgba = pollster::block_on(renderer.render_rgba(&settings, size))?;
+        let png = export::encode_png(&rgba, size)?;
+        let thumbnail = output.join(format!("{}_{}.png", stem, view.name()));
+        std::fs::write(&thumbnail, png).map_err(|e| format!("{}: {}", thumbnail.display(), e))?;
+        files.push(json_string(&thumbnail.display().to_string()));
+    }
+
+    let (w, h, d) = cube.dim();
+    Ok(format!(
+        r#"{{"file":{},"dim":[{},{},{}],"min":{},"max":{},"cuts":[{},{}],"thumbnails":[{}]}}"#,
+        json_string(&path.display().to_string()),
+        w,
+        h,
+        d,
+        range.start,
+        range.end,
+        cuts[0],
+        cuts[1],
+        files.join(",")
+    ))
+}
+
+/// JSON string literal of `s`
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+pub(crate) fn write_file<F>(path: &Path, write: F) -> Result<(), String>
+where
+    F: FnOnce(&mut std::io::BufWriter<std::fs::File>) -> std::io::Result<()>,
+{
+    let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut writer = std::io::BufWriter::new(file);
+    write(&mut writer)
+        .and_then(|_| std::io::Write::flush(&mut writer))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Args::parse(&args, &["threshold", "output"])
+    }
+
+    #[test]
+    fn options_and_positional_values() {
+        let args = parse(&["cube.fits", "--threshold", "0.5", "--output=a.ply"]).unwrap();
+        assert_eq!(args.positional(), ["cube.fits"]);
+        assert_eq!(args.get::<f32>("threshold"), Ok(Some(0.5)));
+        assert_eq!(args.get::<String>("output"), Ok(Some("a.ply".to_string())));
+        assert!(args.get::<f32>("output").is_err());
+
+        assert!(parse(&["cube.fits", "--threshold"]).is_err());
+    }
+
+    #[test]
+    fn unknown_options_are_refused() {
+        for args in [
+            &["cube.fits", "--treshold", "0.5"][..],
+            &["--treshold=0.5", "cube.fits"],
+        ] {
+            let error = parse(args).err().unwrap();
+            assert!(error.starts_with("Unknown option --treshold"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use cgmath::InnerSpace;
+
+use crate::math::Vec3;
+
+// must match the size of the planes array in cube.frag
+pub(crate) const MAX_CLIP_PLANES: usize = 4;
+
+/// Oblique plane cutting the volume
+///
+/// Coordinates are texture coordinates, i.e. the cube spans [0, 1]^3.
+/// The plane keeps the points `p` verifying `dot(normal, p) <= offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipPlane {
+    pub normal: [f32; 3],
+    pub offset: f32,
+}
+
+impl ClipPlane {
+    /// Plane passing through the center of the cube, removing the side `normal` points to
+    pub fn through_center(normal: [f32; 3]) -> Self {
+        let n = Vec3::from(normal).normalize();
+        Self {
+            normal: n.into(),
+            offset: n.dot(Vec3::new(0.5, 0.5, 0.5)),
+        }
+    }
+
+    /// Rotate the normal around the center of the cube by angles in radians
+    pub(crate) fn rotate(&mut self, dlon: f32, dlat: f32) {
+        let center = Vec3::new(0.5, 0.5, 0.5);
+        let n = Vec3::from(self.normal);
+        // signed distance of the plane to the center is kept
+        let d = self.offset - n.dot(center);
+
+        let lon = n.x.atan2(n.z) + dlon;
+        let lat = (n.y.clamp(-1.0, 1.0).asin() + dlat)
+            .clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+        let n = Vec3::new(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos());
+
+        self.normal = n.into();
+        self.offset = d + n.dot(center);
+    }
+}
+
+/// Crop box and clipping planes limiting the marched part of the cube
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clipping {
+    /// Corners of the crop box, in texture coordinates
+    pub box_min: [f32; 3],
+    pub box_max: [f32; 3],
+    pub planes: Vec<ClipPlane>,
+    /// Render the faces of the cuts as textured slices
+    pub show_cut_faces: bool,
+
+    // face of the crop box under the cursor, in the order -x, +x, -y, +y, -z, +z
+    pub(crate) hovered_face: Option<usize>,
+    pub(crate) show_handles: bool,
+}
+
+impl Default for Clipping {
+    fn default() -> Self {
+        Self {
+            box_min: [0.0; 3],
+            box_max: [1.0; 3],
+            planes: vec![],
+            show_cut_faces: false,
+            hovered_face: None,
+            show_handles: false,
+        }
+    }
+}
+
+/// std140 layout of the `Clip` uniform block of cube.frag
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct ClipUniform {
+    box_min: [f32; 4],
+    box_max: [f32; 4],
+    handles: [f32; 4],
+    planes: [[f32; 4]; MAX_CLIP_PLANES],
+}
+
+impl Clipping {
+    pub(crate) fn is_cropped(&self) -> bool {
+        self.box_min != [0.0; 3] || self.box_max != [1.0; 3]
+    }
+
+    pub(crate) fn uniform(&self) -> ClipUniform {
+        let mut planes = [[0.0; 4]; MAX_CLIP_PLANES];
+        let num_planes = self.planes.len().min(MAX_CLIP_PLANES);
+        for (p, plane) in planes.iter_mut().zip(self.planes.iter()) {
+            *p = [
+                plane.normal[0],
+                plane.normal[1],
+                plane.normal[2],
+                plane.offset,
+            ];
+        }
+
+        let [x0, y0, z0] = self.box_min;
+        let [x1, y1, z1] = self.box_max;
+        ClipUniform {
+            box_min: [x0, y0, z0, if self.show_cut_faces { 1.0 } else { 0.0 }],
+            box_max: [x1, y1, z1, num_planes as f32],
+            handles: [
+                self.hovered_face.map(|f| f as f32).unwrap_or(-1.0),
+                if self.show_handles || self.is_cropped() {
+                    1.0
+                } else {
+                    0.0
+                },
+                0.0,
+                0.0,
+            ],
+            planes,
+        }
+    }
+
+    /// Face of the crop box hit by a ray, with the hit position
+    pub(crate) fn pick_face(
+        &self,
+        origin: Vec3<f32>,
+        dir: Vec3<f32>,
+    ) -> Option<(usize, Vec3<f32>)> {
+        let (lo, hi) = (Vec3::from(self.box_min), Vec3::from(self.box_max));
+
+        let mut t_near = f32::MIN;
+        let mut t_far = f32::MAX;
+        let mut face = 0;
+        for axis in 0..3 {
+            let t_lo = (lo[axis] - origin[axis]) / dir[axis];
+            let t_hi = (hi[axis] - origin[axis]) / dir[axis];
+            let (t_close, t_away, close_face) = if t_lo < t_hi {
+                (t_lo, t_hi, 2 * axis)
+            } else {
+                (t_hi, t_lo, 2 * axis + 1)
+            };
+
+            if t_close > t_near {
+                t_near = t_close;
+                face = close_face;
+            }
+            t_far = t_far.min(t_away);
+        }
+
+        if t_near <= t_far {
+            Some((face, origin + dir * t_near))
+        } else {
+            None
+        }
+    }
+
+    /// Move a face of the crop box so that it follows a ray
+    ///
+    /// `hit` is the point the face was grabbed at
+    pub(crate) fn drag_face(
+        &mut self,
+        face: usize,
+        hit: Vec3<f32>,
+        origin: Vec3<f32>,
+        dir: Vec3<f32>,
+    ) {
+        let axis = face / 2;
+        let mut e = Vec3::new(0.0, 0.0, 0.0);
+        e[axis] = 1.0;
+
+        // closest point to the ray on the line going through hit along the axis
+        let w0 = hit - origin;
+        let b = e.dot(dir);
+        let denom = dir.dot(dir) - b * b;
+        if denom.abs() < 1e-6 {
+            // the ray is parallel to the axis
+            return;
+        }
+        let s = (b * dir.dot(w0) - dir.dot(dir) * e.dot(w0)) / denom;
+        let pos = hit[axis] + s;
+
+        // keep at least 1% of the cube along the axis
+        const MIN_SIZE: f32 = 0.01;
+        if face % 2 == 0 {
+            self.box_min[axis] = pos.clamp(0.0, self.box_max[axis] - MIN_SIZE);
+        } else {
+            self.box_max[axis] = pos.clamp(self.box_min[axis] + MIN_SIZE, 1.0);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Mapping of the normalized data values to colors
+
+/// Function applied to the values normalized to [0, 1] before the cuts
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Stretch {
+    #[default]
+    Linear,
+    Sqrt,
+    /// Logarithm, raising the faint values a thousand times more than the bright ones
+    Log,
+    /// Inverse hyperbolic sine, linear on the faint values and logarithmic on the bright ones
+    Asinh,
+}
+
+impl Stretch {
+    pub(crate) fn as_f32(self) -> f32 {
+        match self {
+            Stretch::Linear => 0.0,
+            Stretch::Sqrt => 1.0,
+            Stretch::Log => 2.0,
+            Stretch::Asinh => 3.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stretch::Linear => "linear",
+            Stretch::Sqrt => "sqrt",
+            Stretch::Log => "log",
+            Stretch::Asinh => "asinh",
+        }
+    }
+}
+
+impl std::str::FromStr for Stretch {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Stretch::Linear),
+            "sqrt" => Ok(Stretch::Sqrt),
+            "log" => Ok(Stretch::Log),
+            "asinh" => Ok(Stretch::Asinh),
+            _ => Err("Unknown stretch, expected 'linear', 'sqrt', 'log' or 'asinh'"),
+        }
+    }
+}
+
+/// Colormaps of the shaders
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Colormap {
+    /// Blue to red through cyan, green and yellow, the one of the viewer
+    #[default]
+    Jet,
+    Grey,
+    Viridis,
+    Turbo,
+}
+
+impl Colormap {
+    pub(crate) fn as_f32(self) -> f32 {
+        match self {
+            Colormap::Jet => 0.0,
+            Colormap::Grey => 1.0,
+            Colormap::Viridis => 2.0,
+            Colormap::Turbo => 3.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Jet => "jet",
+            Colormap::Grey => "grey",
+            Colormap::Viridis => "viridis",
+            Colormap::Turbo => "turbo",
+        }
+    }
+}
+
+impl std::str::FromStr for Colormap {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jet" => Ok(Colormap::Jet),
+            "grey" | "gray" => Ok(Colormap::Grey),
+            "viridis" => Ok(Colormap::Viridis),
+            "turbo" => Ok(Colormap::Turbo),
+            _ => Err("Unknown colormap, expected 'jet', 'grey', 'viridis' or 'turbo'"),
+        }
+    }
+}
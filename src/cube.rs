@@ -0,0 +1,209 @@
+use fitsrs::card::Value;
+use fitsrs::Fits;
+use fitsrs::HDU;
+use std::io::Cursor;
+
+use crate::wcs::Wcs;
+
+// number of values the percentiles are estimated from
+const PERCENTILE_SAMPLES: usize = 1 << 20;
+
+/// Data cube as stored in the FITS file, i.e. big endian values of the type
+/// given by BITPIX, f32 for the data cubes
+pub(crate) struct RawCube<'a> {
+    pub data: &'a [u8],
+    pub bitpix: i64,
+    pub dim: (u32, u32, u32),
+    pub datamin: Option<f32>,
+    pub datamax: Option<f32>,
+    pub bunit: Option<String>,
+    pub wcs: Option<Wcs>,
+}
+
+pub(crate) fn parse_fits_data_cube<'a, R>(
+    fits: &'a mut Fits<Cursor<R>>,
+) -> Result<RawCube<'a>, &'static str>
+where
+    R: AsRef<[u8]> + std::fmt::Debug + 'a,
+{
+    if let Some(Ok(hdu)) = fits.next() {
+        match hdu {
+            HDU::Primary(hdu) => {
+                let header = hdu.get_header();
+
+                if let (
+                    Some(Value::Integer { value: w, .. }),
+                    Some(Value::Integer { value: h, .. }),
+                    Some(Value::Integer { value: d, .. }),
+                ) = (
+                    header.get("NAXIS1"),
+                    header.get("NAXIS2"),
+                    header.get("NAXIS3"),
+                ) {
+                    let image = fits.get_data(&hdu);
+                    let bitpix = match header.get("BITPIX") {
+                        Some(Value::Integer { value, .. }) => *value,
+                        _ => -32,
+                    };
+
+                    let d1 = *w as u32;
+                    let d2 = *h as u32;
+                    let mut d3 = *d as u32;
+                    let mut z_axis = 3;
+
+                    if d3 == 1 {
+                        // parse NAXIS4 instead it there is
+                        if let Some(Value::Integer { value, .. }) = header.get("NAXIS4") {
+                            d3 = *value as u32;
+                            z_axis = 4;
+                        }
+                    }
+
+                    let datamin = if let Some(Value::Float { value, .. }) = header.get("DATAMIN") {
+                        Some(*value as f32)
+                    } else {
+                        None
+                    };
+                    let datamax = if let Some(Value::Float { value, .. }) = header.get("DATAMAX") {
+                        Some(*value as f32)
+                    } else {
+                        None
+                    };
+
+                    let bunit = if let Some(Value::String { value, .. }) = header.get("BUNIT") {
+                        Some(value.trim().to_string()).filter(|unit| !unit.is_empty())
+                    } else {
+                        None
+                    };
+
+                    let wcs = Wcs::parse(|key| header.get(key), [1, 2, z_axis]);
+
+                    Ok(RawCube {
+                        data: image.raw_bytes(),
+                        bitpix,
+                        dim: (d1, d2, d3),
+                        datamin,
+                        datamax,
+                        bunit,
+                        wcs,
+                    })
+                } else {
+                    Err("FITS image extension not found")
+                }
+            }
+            _ => Err("FITS image extension not found"),
+        }
+    } else {
+        Err("Is not a FITS file")
+    }
+}
+
+/// CPU copy of a data cube, decoded in the native endianness
+///
+/// Values are stored with x varying the fastest, then y, then z
+pub struct Cube {
+    pub(crate) data: Vec<f32>,
+    pub(crate) dim: (u32, u32, u32),
+    pub(crate) datamin: Option<f32>,
+    pub(crate) datamax: Option<f32>,
+    /// Unit of the values, from BUNIT
+    pub(crate) bunit: Option<String>,
+    pub(crate) wcs: Option<Wcs>,
+    /// Name of the file the cube was read from, recorded in the files derived from it
+    pub(crate) name: Option<String>,
+}
+
+impl Cube {
+    pub(crate) fn from_raw(raw: &RawCube) -> Result<Self, &'static str> {
+        let (w, h, d) = raw.dim;
+        let num_values = (w as usize) * (h as usize) * (d as usize);
+        if raw.data.len() < num_values * 4 {
+            return Err("FITS data is smaller than expected from NAXISn");
+        }
+
+        let data = raw.data[..num_values * 4]
+            .chunks_exact(4)
+            .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        Ok(Self {
+            data,
+            dim: raw.dim,
+            datamin: raw.datamin,
+            datamax: raw.datamax,
+            bunit: raw.bunit.clone(),
+            wcs: raw.wcs.clone(),
+            name: None,
+        })
+    }
+
+    pub fn from_reader<R: AsRef<[u8]> + std::fmt::Debug>(
+        reader: Cursor<R>,
+    ) -> Result<Self, &'static str> {
+        let mut fits = Fits::from_reader(reader);
+        let raw = parse_fits_data_cube(&mut fits)?;
+
+        Self::from_raw(&raw)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, &'static str> {
+        let file = std::fs::File::open(&path).map_err(|_| "Cannot open the FITS file")?;
+        let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(|_| "Cannot map the FITS file")?;
+
+        let mut cube = Self::from_reader(Cursor::new(mmap))?;
+        cube.name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        Ok(cube)
+    }
+
+    pub fn dim(&self) -> (u32, u32, u32) {
+        self.dim
+    }
+
+    pub fn wcs(&self) -> Option<&Wcs> {
+        self.wcs.as_ref()
+    }
+
+    /// Minimum and maximum of the values, the blank ones being ignored
+    pub fn value_range(&self) -> Option<std::ops::Range<f32>> {
+        let mut values = self.data.iter().copied().filter(|v| v.is_finite());
+        let first = values.next()?;
+        Some(values.fold(first..first, |range, v| {
+            range.start.min(v)..range.end.max(v)
+        }))
+    }
+
+    /// Values at the percentiles given (between 0 and 100) of the finite values,
+    /// estimated from an even subsample of the large cubes
+    pub fn percentiles(&self, percents: &[f32]) -> Option<Vec<f32>> {
+        let step = (self.data.len() / PERCENTILE_SAMPLES).max(1);
+        let mut values: Vec<f32> = self
+            .data
+            .iter()
+            .step_by(step)
+            .copied()
+            .filter(|v| v.is_finite())
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable_by(f32::total_cmp);
+
+        let last = (values.len() - 1) as f32;
+        Some(
+            percents
+                .iter()
+                .map(|p| values[((p / 100.0).clamp(0.0, 1.0) * last).round() as usize])
+                .collect(),
+        )
+    }
+
+    #[inline]
+    pub(crate) fn value(&self, x: u32, y: u32, z: u32) -> f32 {
+        let (w, h, _) = self.dim;
+        self.data[((z as usize) * (h as usize) + (y as usize)) * (w as usize) + (x as usize)]
+    }
+}
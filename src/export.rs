@@ -0,0 +1,197 @@
+//! Offline rendering of the view and the animations to image files
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufWriter;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+use crate::render_mode::Quality;
+
+/// Settings of a rendering of the animation frame by frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameExport {
+    /// Width and height of the frames in pixels
+    pub size: (u32, u32),
+    /// Number of frames, the whole camera path or one turn of the turntable if `None`
+    pub frames: Option<u32>,
+    /// Frames per second of the animation
+    pub fps: f32,
+    pub quality: Quality,
+    /// Number of jittered renderings averaged in each frame
+    pub samples: u32,
+}
+
+impl Default for FrameExport {
+    fn default() -> Self {
+        Self {
+            size: (1280, 720),
+            frames: None,
+            fps: 30.0,
+            quality: Quality::HIGH,
+            samples: 16,
+        }
+    }
+}
+
+/// Settings of a still image of the view
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Screenshot {
+    /// Width and height in pixels, the ones of the window if `None`
+    pub size: Option<(u32, u32)>,
+    /// Draw the colormap with the data values of its ends
+    pub colorbar: bool,
+    /// Draw the axes of the cube as seen by the camera
+    pub axes: bool,
+    /// Number of jittered renderings averaged
+    pub samples: u32,
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Self {
+            size: None,
+            colorbar: true,
+            axes: true,
+            samples: 16,
+        }
+    }
+}
+
+/// First of `<stem>_001.<extension>`, `<stem>_002.<extension>`... not taken in the
+/// working directory
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn numbered_path(stem: &str, extension: &str) -> PathBuf {
+    (1..)
+        .map(|i| PathBuf::from(format!("{}_{:03}.{}", stem, i, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// Parse a `<width>x<height>` size in pixels
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn parse_size(s: &str) -> Result<(u32, u32), &'static str> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or("Expected a size written as <width>x<height>")?;
+    let width = width.trim().parse().map_err(|_| "Invalid width")?;
+    let height = height.trim().parse().map_err(|_| "Invalid height")?;
+    if width == 0 || height == 0 {
+        Err("The size must not be empty")
+    } else {
+        Ok((width, height))
+    }
+}
+
+/// Destination of the rendered frames
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) enum FrameSink {
+    /// One PNG file per frame in a directory
+    Sequence(PathBuf),
+    Gif(gif::Encoder<BufWriter<File>>),
+    /// Animated PNG
+    Apng(png::Writer<BufWriter<File>>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FrameSink {
+    /// Sink chosen from the extension of `path`, a directory of PNG files if it has none
+    pub(crate) fn create(
+        path: &Path,
+        size: (u32, u32),
+        frames: u32,
+        fps: f32,
+    ) -> Result<Self, String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+
+        match extension.as_deref() {
+            None => {
+                std::fs::create_dir_all(path).map_err(|e| error(&e))?;
+                Ok(FrameSink::Sequence(path.to_path_buf()))
+            }
+            Some("gif") => {
+                if size.0 > u16::MAX as u32 || size.1 > u16::MAX as u32 {
+                    return Err(error(&"too large for a GIF"));
+                }
+                let file = File::create(path).map_err(|e| error(&e))?;
+                let mut encoder =
+                    gif::Encoder::new(BufWriter::new(file), size.0 as u16, size.1 as u16, &[])
+                        .map_err(|e| error(&e))?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(|e| error(&e))?;
+                Ok(FrameSink::Gif(encoder))
+            }
+            Some("png") | Some("apng") => {
+                let file = File::create(path).map_err(|e| error(&e))?;
+                let mut encoder = png::Encoder::new(BufWriter::new(file), size.0, size.1);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder
+                    .set_animated(frames.max(1), 0)
+                    .and_then(|_| encoder.set_frame_delay(100, (fps * 100.0).round() as u16))
+                    .map_err(|e| error(&e))?;
+                let writer = encoder.write_header().map_err(|e| error(&e))?;
+                Ok(FrameSink::Apng(writer))
+            }
+            Some(_) => Err(error(
+                &"unknown animation format, expected a .gif, .png or .apng file, or a directory",
+            )),
+        }
+    }
+
+    /// Append the frame `index` of the animation
+    pub(crate) fn write(
+        &mut self,
+        index: u32,
+        size: (u32, u32),
+        fps: f32,
+        rgba: &mut [u8],
+    ) -> Result<(), String> {
+        match self {
+            FrameSink::Sequence(directory) => {
+                let path = directory.join(format!("frame_{:05}.png", index));
+                let png =
+                    encode_png(rgba, size).map_err(|e| format!("{}: {}", path.display(), e))?;
+                std::fs::write(&path, png).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            FrameSink::Gif(encoder) => {
+                let mut frame = gif::Frame::from_rgba_speed(size.0 as u16, size.1 as u16, rgba, 10);
+                // in hundredths of a second
+                frame.delay = (100.0 / fps).round() as u16;
+                encoder.write_frame(&frame).map_err(|e| e.to_string())
+            }
+            FrameSink::Apng(writer) => writer.write_image_data(rgba).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<(), String> {
+        match self {
+            FrameSink::Sequence(_) => Ok(()),
+            // writes the trailer of the file
+            FrameSink::Gif(encoder) => encoder
+                .into_inner()
+                .and_then(|mut writer| std::io::Write::flush(&mut writer))
+                .map_err(|e| e.to_string()),
+            FrameSink::Apng(writer) => writer.finish().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Encode an RGBA image of 8 bits per channel as a PNG file
+pub(crate) fn encode_png(rgba: &[u8], size: (u32, u32)) -> Result<Vec<u8>, &'static str> {
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, size.0, size.1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba))
+        .map_err(|_| "Cannot encode the PNG image")?;
+    Ok(png)
+}
@@ -0,0 +1,622 @@
+//! Source finding in the cube by smoothing and clipping, in the manner of the S+C
+//! finder of SoFiA: the cube is smoothed with kernels of several sizes, the voxels
+//! above a multiple of the noise of any of the smoothed cubes are kept, and the
+//! groups of voxels kept touching each other make the objects
+//!
+//! Unlike the moment maps and the position-velocity diagrams, the finder runs on the
+//! CPU only. The noise of a channel is a median, which needs a selection over its
+//! values, and the objects grow voxel by voxel from a stack: neither maps onto a
+//! compute shader, and WebGL has none. Smoothing alone on the GPU would mean reading
+//! the smoothed cube back for each pair of kernels to estimate its noise, as much
+//! data as the cube, so the whole work is spread over the frames instead.
+
+use crate::catalog::Catalog;
+use crate::cube::Cube;
+use crate::labels::Labels;
+
+// factor from the median absolute deviation to the standard deviation of a Gaussian noise
+const MAD_TO_SIGMA: f32 = 1.4826;
+// the voxels already detected are clipped to this many times the noise before smoothing
+// with the next kernels, so that the bright objects do not spread over the larger ones
+const REPLACEMENT: f32 = 2.0;
+
+/// Kernels and threshold of the source finder
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinderSettings {
+    /// Voxels above this many times the noise of a smoothed cube are detected
+    pub threshold: f32,
+    /// FWHM in pixels of the Gaussian kernels smoothing the spatial plane, 0 for none
+    pub spatial_kernels: Vec<f32>,
+    /// Width in channels of the boxcar kernels smoothing the spectral axis, 0 or 1 for
+    /// none, an even width spanning one channel more
+    pub spectral_kernels: Vec<u32>,
+    /// Objects of fewer voxels are dropped as noise
+    pub min_size: u32,
+}
+
+impl Default for FinderSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 5.0,
+            spatial_kernels: vec![0.0, 3.0, 6.0],
+            spectral_kernels: vec![0, 3, 7],
+            min_size: 8,
+        }
+    }
+}
+
+impl FinderSettings {
+    /// Kernels and threshold, as written in the catalogs
+    pub(crate) fn description(&self) -> String {
+        let spatial: Vec<String> = self.spatial_kernels.iter().map(f32::to_string).collect();
+        let spectral: Vec<String> = self.spectral_kernels.iter().map(u32::to_string).collect();
+        format!(
+            "Smoothed with spatial kernels of {} pixels FWHM and spectral kernels of {} \
+             channels, clipped at {} sigma, objects of less than {} voxels dropped",
+            spatial.join(", "),
+            spectral.join(", "),
+            self.threshold,
+            self.min_size
+        )
+    }
+
+    /// Find the objects of the cube, labeled in a cube of its dimensions and measured
+    /// in a catalog, the IDs of both going from 1 in the order of the voxels
+    ///
+    /// The whole work is done at once, `SourceFinder` doing it a step at a time.
+    pub fn find_sources(&self, cube: &Cube) -> Result<(Labels, Catalog), &'static str> {
+        let mut finder = SourceFinder::new(self.clone(), cube)?;
+        loop {
+            if let Some(result) = finder.step(cube) {
+                return result;
+            }
+        }
+    }
+}
+
+/// Source finding in progress, done a channel or a row of spectra at a time by `step`
+/// so that the viewer keeps drawing in between
+///
+/// The noise is estimated channel by channel from the median absolute deviation of
+/// the finite values, which the objects hardly move, and the blank voxels are taken
+/// as 0 when smoothing. Every spatial kernel is combined with every spectral kernel,
+/// the cube being smoothed with each pair in turn in the same buffer. All of it runs
+/// on the CPU, for the reasons given in the documentation of the module.
+pub struct SourceFinder {
+    settings: FinderSettings,
+    dim: (u32, u32, u32),
+    /// Pairs of spatial and spectral kernels
+    kernels: Vec<(f32, u32)>,
+    kernel: usize,
+    stage: Stage,
+    /// Channel or row of spectra of the stage done next
+    position: usize,
+    /// Noise of the channels of the cube
+    noise: Vec<Option<f32>>,
+    /// Gaussian of the spatial kernel, empty for none
+    weights: Vec<f32>,
+    /// Cube in units of the noise of its channels, smoothed with the current kernels
+    smoothed: Vec<f32>,
+    detected: Vec<bool>,
+    // values of a channel, a line or a spectrum being worked on
+    scratch: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Noise,
+    SpatialSmoothing,
+    SpectralSmoothing,
+    Clipping,
+    Labeling,
+}
+
+impl SourceFinder {
+    pub fn new(settings: FinderSettings, cube: &Cube) -> Result<Self, &'static str> {
+        if !(settings.threshold.is_finite() && settings.threshold > 0.0) {
+            return Err("The threshold must be positive");
+        }
+        if settings
+            .spatial_kernels
+            .iter()
+            .any(|fwhm| !(fwhm.is_finite() && *fwhm >= 0.0))
+        {
+            return Err("The spatial kernels must be positive");
+        }
+        let spatial = non_empty_or_none(&settings.spatial_kernels, 0.0);
+        let spectral = non_empty_or_none(&settings.spectral_kernels, 0);
+        let kernels = spatial
+            .iter()
+            .flat_map(|&fwhm| spectral.iter().map(move |&width| (fwhm, width)))
+            .collect();
+        let len = cube.data.len();
+        Ok(Self {
+            settings,
+            dim: cube.dim,
+            kernels,
+            kernel: 0,
+            stage: Stage::Noise,
+            position: 0,
+            noise: Vec::with_capacity(cube.dim.2 as usize),
+            weights: vec![],
+            smoothed: vec![0.0; len],
+            detected: vec![false; len],
+            scratch: vec![],
+        })
+    }
+
+    /// Fraction of the work done, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        let (_, h, d) = self.dim;
+        let (h, d) = (h as usize, d as usize);
+        // the channels smoothed spatially, the rows of spectra and the channels clipped
+        let per_kernel = 2 * d + h;
+        let before = d + self.kernel * per_kernel;
+        let done = match self.stage {
+            Stage::Noise => self.position,
+            Stage::SpatialSmoothing => before + self.position,
+            Stage::SpectralSmoothing => before + d + self.position,
+            Stage::Clipping => before + d + h + self.position,
+            Stage::Labeling => before,
+        };
+        done as f32 / (d + self.kernels.len() * per_kernel + 1) as f32
+    }
+
+    /// Do the next part of the work on `cube`, the one given to `new`, returning the
+    /// objects once the last step has labeled and measured them
+    pub fn step(&mut self, cube: &Cube) -> Option<Result<(Labels, Catalog), &'static str>> {
+        if cube.dim != self.dim {
+            return Some(Err("The cube changed while finding its objects"));
+        }
+        let (w, h, d) = self.dim;
+        let plane = (w as usize) * (h as usize);
+        let (fwhm, width) = self.kernels[self.kernel.min(self.kernels.len() - 1)];
+        let channel = self.position * plane..(self.position + 1) * plane;
+        match self.stage {
+            Stage::Noise => {
+                let noise = channel_noise(&cube.data[channel], &mut self.scratch);
+                self.noise.push(noise);
+                self.advance(d as usize, Stage::SpatialSmoothing);
+            }
+            Stage::SpatialSmoothing => {
+                if self.position == 0 {
+                    self.weights = gaussian(fwhm);
+                }
+                // the voxels already detected clipped, in units of the noise
+                let sigma = self.noise[self.position];
+                let values = cube.data[channel.clone()].iter();
+                let smoothed = &mut self.smoothed[channel.clone()];
+                for ((smoothed, &v), &detected) in
+                    smoothed.iter_mut().zip(values).zip(&self.detected[channel])
+                {
+                    let v = match sigma {
+                        Some(sigma) if v.is_finite() => v / sigma,
+                        _ => 0.0,
+                    };
+                    *smoothed = if detected {
+                        v.clamp(-REPLACEMENT, REPLACEMENT)
+                    } else {
+                        v
+                    };
+                }
+                smooth_spatial(smoothed, (w, h), &self.weights, &mut self.scratch);
+                let next = if width > 1 {
+                    Stage::SpectralSmoothing
+                } else {
+                    Stage::Clipping
+                };
+                self.advance(d as usize, next);
+            }
+            Stage::SpectralSmoothing => {
+                let y = self.position as u32;
+                smooth_spectral(&mut self.smoothed, self.dim, y, width, &mut self.scratch);
+                self.advance(h as usize, Stage::Clipping);
+            }
+            Stage::Clipping => {
+                let smoothed = &self.smoothed[channel.clone()];
+                if let Some(sigma) = channel_noise(smoothed, &mut self.scratch) {
+                    let threshold = self.settings.threshold * sigma;
+                    for (v, detected) in smoothed.iter().zip(&mut self.detected[channel]) {
+                        *detected |= *v > threshold;
+                    }
+                }
+                let next = if self.kernel + 1 < self.kernels.len() {
+                    Stage::SpatialSmoothing
+                } else {
+                    Stage::Labeling
+                };
+                self.advance(d as usize, next);
+                if self.position == 0 {
+                    self.kernel += 1;
+                }
+            }
+            Stage::Labeling => {
+                let ids = connected_components(&self.detected, self.dim, self.settings.min_size);
+                let catalog = Catalog::measure(cube, &ids, &self.settings);
+                return Some(Labels::from_ids(ids, cube.dim).map(|mut labels| {
+                    labels.name = Some("source finder".to_string());
+                    (labels, catalog)
+                }));
+            }
+        }
+        None
+    }
+
+    // move to the next channel or row of spectra, or to the next stage after the
+    // `count` ones of the current stage
+    fn advance(&mut self, count: usize, next: Stage) {
+        self.position += 1;
+        if self.position == count {
+            self.position = 0;
+            self.stage = next;
+        }
+    }
+}
+
+// the values given, or the one meaning no smoothing if there is none
+fn non_empty_or_none<T: Copy>(values: &[T], none: T) -> Vec<T> {
+    if values.is_empty() {
+        vec![none]
+    } else {
+        values.to_vec()
+    }
+}
+
+/// Standard deviation of the noise of the finite values of a channel, estimated from
+/// their median absolute deviation, `None` without any finite value or any spread
+fn channel_noise(channel: &[f32], values: &mut Vec<f32>) -> Option<f32> {
+    values.clear();
+    values.extend(channel.iter().copied().filter(|v| v.is_finite()));
+    if values.is_empty() {
+        return None;
+    }
+    let middle = values.len() / 2;
+    let median = *values.select_nth_unstable_by(middle, f32::total_cmp).1;
+    for v in values.iter_mut() {
+        *v = (*v - median).abs();
+    }
+    let mad = *values.select_nth_unstable_by(middle, f32::total_cmp).1;
+    let sigma = MAD_TO_SIGMA * mad;
+    (sigma > 0.0).then_some(sigma)
+}
+
+/// Normalized weights of a Gaussian of `fwhm` pixels, out to 3 sigma, empty for a
+/// null width
+fn gaussian(fwhm: f32) -> Vec<f32> {
+    if fwhm <= 0.0 {
+        return vec![];
+    }
+    let sigma = fwhm / (8.0 * std::f32::consts::LN_2).sqrt();
+    let radius = (3.0 * sigma).ceil() as i64;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|i| (-0.5 * (i as f32 / sigma).powi(2)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|weight| weight / total).collect()
+}
+
+/// Convolve a channel with the Gaussian `weights`, along x then y, the values outside
+/// of the cube being taken as 0
+fn smooth_spatial(channel: &mut [f32], (w, h): (u32, u32), weights: &[f32], line: &mut Vec<f32>) {
+    if weights.len() <= 1 {
+        return;
+    }
+    let radius = (weights.len() / 2) as i64;
+    let (w, h) = (w as usize, h as usize);
+    for y in 0..h {
+        line.clear();
+        line.extend((0..w).map(|x| channel[y * w + x]));
+        for x in 0..w {
+            channel[y * w + x] = convolve(line, x, weights, radius);
+        }
+    }
+    for x in 0..w {
+        line.clear();
+        line.extend((0..h).map(|y| channel[y * w + x]));
+        for y in 0..h {
+            channel[y * w + x] = convolve(line, y, weights, radius);
+        }
+    }
+}
+
+// value of the convolution of a line with a kernel centered on `i`
+fn convolve(line: &[f32], i: usize, weights: &[f32], radius: i64) -> f32 {
+    let first = (i as i64 - radius).max(0) as usize;
+    let last = (i as i64 + radius).min(line.len() as i64 - 1) as usize;
+    (first..=last)
+        .map(|j| line[j] * weights[(j as i64 - i as i64 + radius) as usize])
+        .sum()
+}
+
+/// Average the spaxels of the row `y` over a window of `width` channels centered on
+/// each channel, the channels outside of the cube being taken as 0
+fn smooth_spectral(
+    data: &mut [f32],
+    (w, h, d): (u32, u32, u32),
+    y: u32,
+    width: u32,
+    spectrum: &mut Vec<f32>,
+) {
+    if width <= 1 {
+        return;
+    }
+    let radius = (width / 2) as usize;
+    let norm = 1.0 / (2 * radius + 1) as f32;
+    let plane = (w as usize) * (h as usize);
+    let d = d as usize;
+    let row = (y as usize) * (w as usize);
+    for i in row..row + w as usize {
+        spectrum.clear();
+        spectrum.extend((0..d).map(|z| data[z * plane + i]));
+        // running sum over the window
+        let mut sum: f32 = spectrum[..radius.min(d)].iter().sum();
+        for z in 0..d {
+            if z + radius < d {
+                sum += spectrum[z + radius];
+            }
+            if z > radius {
+                sum -= spectrum[z - radius - 1];
+            }
+            data[z * plane + i] = sum * norm;
+        }
+    }
+}
+
+/// Label the groups of voxels detected touching each other by a face, an edge or a
+/// corner, from 1 in the order of their first voxel, the groups of less than
+/// `min_size` voxels being left out with 0
+fn connected_components(detected: &[bool], (w, h, d): (u32, u32, u32), min_size: u32) -> Vec<u32> {
+    let (w, h, d) = (w as i64, h as i64, d as i64);
+    let index = |x: i64, y: i64, z: i64| ((z * h + y) * w + x) as usize;
+
+    let mut labels = vec![0_u32; detected.len()];
+    let mut num_labels = 0;
+    let mut kept = vec![];
+    let mut stack = vec![];
+    let mut group = vec![];
+    for start in 0..detected.len() {
+        if !detected[start] || labels[start] != 0 {
+            continue;
+        }
+        // the label is given when the voxel is pushed, so that it is pushed once
+        let label = kept.len() as u32 + 1;
+        labels[start] = label;
+        stack.push(start);
+        group.clear();
+        while let Some(i) = stack.pop() {
+            group.push(i);
+            let i = i as i64;
+            let (x, y, z) = (i % w, i / w % h, i / (w * h));
+            for dz in -1..=1 {
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                        if nx < 0 || ny < 0 || nz < 0 || nx >= w || ny >= h || nz >= d {
+                            continue;
+                        }
+                        let j = index(nx, ny, nz);
+                        if detected[j] && labels[j] == 0 {
+                            labels[j] = label;
+                            stack.push(j);
+                        }
+                    }
+                }
+            }
+        }
+        let is_kept = group.len() >= min_size as usize;
+        if is_kept {
+            num_labels += 1;
+        }
+        kept.push(is_kept.then_some(num_labels));
+    }
+
+    // groups renumbered without the ones left out
+    for label in labels.iter_mut() {
+        if *label != 0 {
+            *label = kept[*label as usize - 1].unwrap_or(0);
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(data: Vec<f32>, dim: (u32, u32, u32)) -> Cube {
+        Cube {
+            data,
+            dim,
+            datamin: None,
+            datamax: None,
+            bunit: None,
+            wcs: None,
+            name: None,
+        }
+    }
+
+    // noise spread evenly between -1 and 1, the same at each run
+    fn noise(len: usize) -> Vec<f32> {
+        let mut state = 12345_u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) as f32 / (1 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn noise_from_the_median_absolute_deviation() {
+        let mut values = vec![];
+        let channel = [0.0, 1.0, -1.0, f32::NAN, 2.0, -2.0];
+        assert_eq!(channel_noise(&channel, &mut values), Some(MAD_TO_SIGMA));
+        // a bright outlier hardly moves it
+        let channel = [0.0, 1.0, -1.0, 1000.0, -2.0];
+        assert_eq!(channel_noise(&channel, &mut values), Some(MAD_TO_SIGMA));
+
+        assert_eq!(channel_noise(&[3.0; 4], &mut values), None);
+        assert_eq!(channel_noise(&[f32::NAN; 4], &mut values), None);
+    }
+
+    #[test]
+    fn gaussian_kernels() {
+        assert!(gaussian(0.0).is_empty());
+        let weights = gaussian(4.0);
+        let radius = weights.len() / 2;
+        assert_eq!(weights.len() % 2, 1);
+        assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        // half of the maximum at half of the FWHM
+        assert!((weights[radius + 2] / weights[radius] - 0.5).abs() < 1e-6);
+        assert_eq!(weights[radius - 1], weights[radius + 1]);
+    }
+
+    #[test]
+    fn spatial_smoothing_spreads_the_values() {
+        let (w, h) = (15, 15);
+        let mut channel = vec![0.0; w * h];
+        channel[7 * w + 7] = 1.0;
+        let weights = gaussian(3.0);
+        smooth_spatial(&mut channel, (w as u32, h as u32), &weights, &mut vec![]);
+        assert!((channel.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert_eq!(channel[7 * w + 7], weights[weights.len() / 2].powi(2));
+        assert_eq!(channel[7 * w + 6], channel[6 * w + 7]);
+
+        // what is spread outside of the channel is lost
+        let mut corner = vec![0.0; w * h];
+        corner[0] = 1.0;
+        smooth_spatial(&mut corner, (w as u32, h as u32), &weights, &mut vec![]);
+        assert!(corner.iter().sum::<f32>() < 0.5);
+    }
+
+    #[test]
+    fn spectral_smoothing_averages_the_channels() {
+        let spectra = |width: u32, spectrum: &[f32]| {
+            // the same spectrum in both spaxels of the row 1 out of 2
+            let mut data = vec![0.0; 4 * spectrum.len()];
+            for (z, &v) in spectrum.iter().enumerate() {
+                data[4 * z + 2] = v;
+                data[4 * z + 3] = v;
+            }
+            smooth_spectral(
+                &mut data,
+                (2, 2, spectrum.len() as u32),
+                1,
+                width,
+                &mut vec![],
+            );
+            assert_eq!(
+                data.iter().skip(2).step_by(4).collect::<Vec<_>>(),
+                data.iter().skip(3).step_by(4).collect::<Vec<_>>()
+            );
+            // the row 0 is left alone
+            assert!(data.chunks(4).all(|plane| plane[..2] == [0.0, 0.0]));
+            data.iter()
+                .skip(2)
+                .step_by(4)
+                .copied()
+                .collect::<Vec<f32>>()
+        };
+        let line = [0.0, 0.0, 3.0, 0.0, 0.0];
+        assert_eq!(spectra(3, &line), [0.0, 1.0, 1.0, 1.0, 0.0]);
+        // an even width spans one channel more
+        assert_eq!(spectra(2, &line), [0.0, 1.0, 1.0, 1.0, 0.0]);
+        assert_eq!(spectra(1, &line), line);
+        assert_eq!(spectra(3, &[3.0, 0.0, 0.0]), [1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn components_touch_by_corners_and_small_ones_are_dropped() {
+        let dim = (4, 4, 2);
+        let index = |x: usize, y: usize, z: usize| (z * 4 + y) * 4 + x;
+        let mut detected = vec![false; 32];
+        // touching by a corner only
+        detected[index(0, 0, 0)] = true;
+        detected[index(1, 1, 1)] = true;
+        // alone
+        detected[index(3, 0, 0)] = true;
+        // a line across the channels
+        detected[index(1, 3, 0)] = true;
+        detected[index(2, 3, 0)] = true;
+        detected[index(3, 3, 1)] = true;
+
+        let ids = connected_components(&detected, dim, 2);
+        assert_eq!(ids[index(0, 0, 0)], 1);
+        assert_eq!(ids[index(1, 1, 1)], 1);
+        assert_eq!(ids[index(3, 0, 0)], 0);
+        for voxel in [index(1, 3, 0), index(2, 3, 0), index(3, 3, 1)] {
+            assert_eq!(ids[voxel], 2);
+        }
+        assert_eq!(ids.iter().filter(|&&id| id != 0).count(), 5);
+
+        let ids = connected_components(&detected, dim, 3);
+        assert_eq!(ids[index(0, 0, 0)], 0);
+        assert_eq!(ids[index(3, 3, 1)], 1);
+    }
+
+    #[test]
+    fn finds_a_source_in_the_noise() {
+        let (w, h, d) = (16, 16, 12);
+        let mut data = noise((w * h * d) as usize);
+        for z in 5..8 {
+            for y in 6..9 {
+                for x in 9..12 {
+                    data[((z * h + y) * w + x) as usize] += 8.0;
+                }
+            }
+        }
+        let cube = cube(data, (w, h, d));
+        let settings = FinderSettings {
+            spatial_kernels: vec![0.0, 3.0],
+            spectral_kernels: vec![0, 3],
+            ..Default::default()
+        };
+
+        let mut finder = SourceFinder::new(settings, &cube).unwrap();
+        let mut progress = 0.0;
+        let (labels, catalog) = loop {
+            if let Some(result) = finder.step(&cube) {
+                break result.unwrap();
+            }
+            assert!(finder.progress() >= progress && finder.progress() < 1.0);
+            progress = finder.progress();
+        };
+        assert_eq!(catalog.objects.len(), 1);
+        let object = &catalog.objects[0];
+        // the smoothed object spreading a little around the voxels of the source
+        for ([first, last], [source_first, source_last]) in
+            object.bounds.iter().zip([[9, 11], [6, 8], [5, 7]])
+        {
+            assert!(*first <= source_first && source_first <= first + 1);
+            assert!(*last >= source_last && *last <= source_last + 1);
+        }
+        for (c, expected) in object.centroid.iter().zip([10.0, 7.0, 6.0]) {
+            assert!((c - expected).abs() < 0.1, "{:?}", object.centroid);
+        }
+        assert_eq!(labels.id_at([10, 7, 6]), Some(1));
+        assert_eq!(labels.id_at([0, 0, 0]), None);
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        let (small, other) = (
+            cube(vec![0.0; 8], (2, 2, 2)),
+            cube(vec![0.0; 27], (3, 3, 3)),
+        );
+        for settings in [
+            FinderSettings {
+                threshold: 0.0,
+                ..Default::default()
+            },
+            FinderSettings {
+                spatial_kernels: vec![-1.0],
+                ..Default::default()
+            },
+        ] {
+            assert!(SourceFinder::new(settings, &small).is_err());
+        }
+        // the cube must not change in between
+        let mut finder = SourceFinder::new(FinderSettings::default(), &small).unwrap();
+        assert!(matches!(finder.step(&other), Some(Err(_))));
+    }
+}
@@ -0,0 +1,1068 @@
+//! Writing of parts of the cube, and of the maps, diagrams, spectra and labels computed
+//! from it, as FITS files
+
+use std::io::Write;
+use std::ops::Range;
+
+use crate::catalog::Catalog;
+use crate::cube::Cube;
+use crate::labels::Labels;
+use crate::moments::{Moment, MomentMap};
+use crate::pv::PvDiagram;
+use crate::spectrum::Spectrum;
+use crate::wcs::{Wcs, WcsAxis, WcsFrame};
+
+// a FITS file is made of blocks of 36 cards of 80 characters
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+// characters of a HISTORY card after its keyword
+const HISTORY_SIZE: usize = 72;
+// characters of a string value between its quotes, from the 12th column
+const STRING_SIZE: usize = 68;
+
+const AXIS_NAMES: [&str; 3] = ["x", "y", "z"];
+
+/// Collapse of the cube along one of its axes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Mean of the finite values
+    Mean,
+    /// Maximum of the finite values
+    Max,
+}
+
+impl Projection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Projection::Mean => "mean",
+            Projection::Max => "max",
+        }
+    }
+}
+
+impl std::str::FromStr for Projection {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Projection::Mean),
+            "max" => Ok(Projection::Max),
+            _ => Err("Unknown projection, expected 'mean' or 'max'"),
+        }
+    }
+}
+
+/// Linear WCS written with an image, its axes being the ones of the image
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitsWcs {
+    pub axes: Vec<WcsAxis>,
+    /// PCi_j matrix of the axes, written unless it is the identity
+    pub pc: Vec<Vec<f64>>,
+    pub frame: WcsFrame,
+}
+
+impl FitsWcs {
+    /// WCS of the axes of the cube kept in an image, in their order
+    ///
+    /// It is refused when the world coordinates of an axis kept depend on an axis
+    /// dropped, i.e. when the cube is rotated out of the image.
+    fn keep(wcs: &Wcs, kept: &[usize]) -> Result<Self, &'static str> {
+        let mixed = kept
+            .iter()
+            .any(|&i| (0..3).any(|j| !kept.contains(&j) && wcs.pc[i][j] != 0.0));
+        if mixed {
+            return Err("The axes of the cube are rotated, the world coordinates of the axes kept depending on the ones dropped");
+        }
+        Ok(Self {
+            axes: kept.iter().map(|&i| wcs.axes[i].clone()).collect(),
+            pc: kept
+                .iter()
+                .map(|&i| kept.iter().map(|&j| wcs.pc[i][j]).collect())
+                .collect(),
+            frame: wcs.frame.clone(),
+        })
+    }
+
+    /// Axes of the pixels from `offsets` on, binned by blocks of `bins` pixels
+    fn crop(mut self, offsets: &[u32], bins: &[u32]) -> Self {
+        for (i, axis) in self.axes.iter_mut().enumerate() {
+            *axis = crop_axis(axis, offsets[i], bins[i]);
+        }
+        // CDELT being scaled by the bin of its axis, PCi_j is by the ratio of the bins
+        for (i, row) in self.pc.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v *= bins[j] as f64 / bins[i] as f64;
+            }
+        }
+        self
+    }
+
+    /// Write a celestial axis left alone, e.g. the declination of a projection along
+    /// x, as a linear one since its projection needs both celestial axes, returning
+    /// the projection dropped
+    fn drop_lone_projection(&mut self) -> Option<String> {
+        let celestial: Vec<usize> = (0..self.axes.len())
+            .filter(|&i| celestial_name(&self.axes[i].ctype).is_some())
+            .collect();
+        match celestial[..] {
+            [i] => {
+                let ctype = self.axes[i].ctype.clone();
+                self.axes[i].ctype = celestial_name(&ctype)?.to_string();
+                Some(ctype)
+            }
+            _ => None,
+        }
+    }
+}
+
+// name of a celestial axis with a projection, e.g. "DEC" for "DEC--SIN"
+fn celestial_name(ctype: &str) -> Option<&str> {
+    if ctype.len() < 8 || ctype.as_bytes()[4] != b'-' {
+        return None;
+    }
+    let name = ctype[..4].trim_end_matches('-');
+    (name == "RA" || name == "DEC" || name.ends_with("LON") || name.ends_with("LAT"))
+        .then_some(name)
+}
+
+// WCS of the axes of the cube kept in an image, if the cube has one
+fn image_wcs(cube: &Cube, kept: &[usize]) -> Result<Option<FitsWcs>, &'static str> {
+    cube.wcs().map(|wcs| FitsWcs::keep(wcs, kept)).transpose()
+}
+
+/// Image written as the primary HDU of a FITS file, in 32 bits floats
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitsImage {
+    /// Length of the axes, the first one varying the fastest in `data`
+    pub dim: Vec<u32>,
+    /// Values, NaN for the blank ones
+    pub data: Vec<f32>,
+    /// Linear WCS of the axes, none being written if `None`
+    pub wcs: Option<FitsWcs>,
+    /// Unit of the values
+    pub bunit: Option<String>,
+    /// Text of the HISTORY cards, the lines longer than a card being split
+    pub history: Vec<String>,
+}
+
+impl FitsImage {
+    /// Voxels of the cube in `region`, the 0-based voxel ranges along x, y and z,
+    /// averaged by blocks of `bin` voxels along each axis
+    ///
+    /// The voxels at the end of an axis not filling a whole block are dropped, and
+    /// a block without any finite value is blank.
+    pub fn subcube(
+        cube: &Cube,
+        region: &[Range<u32>; 3],
+        bin: [u32; 3],
+    ) -> Result<Self, &'static str> {
+        let region = clamp_region(cube, region)?;
+        if bin.contains(&0) {
+            return Err("The binning factors must be positive");
+        }
+        let dim: Vec<u32> = (0..3).map(|i| region[i].len() as u32 / bin[i]).collect();
+        if dim.contains(&0) {
+            return Err("The region is smaller than a bin");
+        }
+
+        // first voxel of a block along an axis
+        let block = |axis: usize, i: u32| region[axis].start + i * bin[axis];
+
+        let mut data = Vec::with_capacity(dim.iter().map(|&n| n as usize).product());
+        for k in 0..dim[2] {
+            for j in 0..dim[1] {
+                for i in 0..dim[0] {
+                    let (mut sum, mut num_values) = (0.0_f64, 0);
+                    for z in block(2, k)..block(2, k) + bin[2] {
+                        for y in block(1, j)..block(1, j) + bin[1] {
+                            for x in block(0, i)..block(0, i) + bin[0] {
+                                let v = cube.value(x, y, z);
+                                if v.is_finite() {
+                                    sum += v as f64;
+                                    num_values += 1;
+                                }
+                            }
+                        }
+                    }
+                    data.push(if num_values == 0 {
+                        f32::NAN
+                    } else {
+                        (sum / num_values as f64) as f32
+                    });
+                }
+            }
+        }
+
+        let offsets = region.clone().map(|range| range.start);
+        let wcs = image_wcs(cube, &[0, 1, 2])?.map(|wcs| wcs.crop(&offsets, &bin));
+
+        let mut history = source_history(cube);
+        history.push(format!(
+            "Subcube of the voxels {}",
+            describe_region(&region)
+        ));
+        if bin != [1, 1, 1] {
+            history.push(format!(
+                "Binned by {}x{}x{} voxels, the mean of the finite values being kept",
+                bin[0], bin[1], bin[2]
+            ));
+        }
+
+        Ok(Self {
+            dim,
+            data,
+            wcs,
+            bunit: cube.bunit.clone(),
+            history,
+        })
+    }
+
+    /// Projection of the voxels of the cube in `region` along `axis` (0 for x, 1 for y
+    /// and 2 for z), the image keeping the two other axes in their order
+    ///
+    /// A pixel without any finite value along the axis is blank.
+    pub fn projection(
+        cube: &Cube,
+        region: &[Range<u32>; 3],
+        axis: usize,
+        projection: Projection,
+    ) -> Result<Self, &'static str> {
+        if axis > 2 {
+            return Err("The axis must be 0, 1 or 2");
+        }
+        let region = clamp_region(cube, region)?;
+        // axes kept, the first one varying the fastest
+        let (a, b) = match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+
+        let mut data = Vec::with_capacity(region[a].len() * region[b].len());
+        let mut voxel = [0; 3];
+        for vb in region[b].clone() {
+            voxel[b] = vb;
+            for va in region[a].clone() {
+                voxel[a] = va;
+                let (mut sum, mut max, mut num_values) = (0.0_f64, f32::MIN, 0);
+                for t in region[axis].clone() {
+                    voxel[axis] = t;
+                    let v = cube.value(voxel[0], voxel[1], voxel[2]);
+                    if v.is_finite() {
+                        sum += v as f64;
+                        max = max.max(v);
+                        num_values += 1;
+                    }
+                }
+                data.push(match projection {
+                    _ if num_values == 0 => f32::NAN,
+                    Projection::Mean => (sum / num_values as f64) as f32,
+                    Projection::Max => max,
+                });
+            }
+        }
+
+        let mut wcs = image_wcs(cube, &[a, b])?
+            .map(|wcs| wcs.crop(&[region[a].start, region[b].start], &[1, 1]));
+
+        let mut history = source_history(cube);
+        history.push(format!(
+            "{} along the {} axis of the voxels {}",
+            match projection {
+                Projection::Mean => "Mean",
+                Projection::Max => "Maximum",
+            },
+            AXIS_NAMES[axis],
+            describe_region(&region)
+        ));
+        if let Some(ctype) = wcs.as_mut().and_then(FitsWcs::drop_lone_projection) {
+            history.push(format!(
+                "Celestial axis {} written as linear without its projection, the other celestial axis being projected out",
+                ctype
+            ));
+        }
+
+        Ok(Self {
+            dim: vec![region[a].len() as u32, region[b].len() as u32],
+            data,
+            wcs,
+            bunit: cube.bunit.clone(),
+            history,
+        })
+    }
+
+    /// Moment map computed from the cube, its spectral axis being dropped
+    pub fn moment_map(cube: &Cube, map: &MomentMap) -> Result<Self, &'static str> {
+        let spectral_unit = cube
+            .wcs()
+            .map(|wcs| wcs.axes[2].cunit.clone())
+            .filter(|unit| !unit.is_empty());
+        let bunit = match map.moment {
+            Moment::Integrated => cube
+                .bunit
+                .as_ref()
+                .zip(spectral_unit)
+                .map(|(bunit, unit)| format!("{}.{}", bunit, unit)),
+            Moment::Velocity | Moment::Dispersion => spectral_unit,
+            Moment::Peak => cube.bunit.clone(),
+        };
+
+        let mut history = source_history(cube);
+        history.push(format!(
+            "{} map of the channels {} to {}",
+            map.moment.name(),
+            map.channels.first,
+            map.channels.last
+        ));
+        if let Some(threshold) = map.threshold {
+            history.push(format!("Values below {} left out", threshold));
+        }
+
+        Ok(Self {
+            dim: vec![map.size.0, map.size.1],
+            data: map.data.clone(),
+            wcs: image_wcs(cube, &[0, 1])?,
+            bunit,
+            history,
+        })
+    }
+
+    /// Position-velocity diagram, the first axis being the offset along the path
+    /// from its first vertex and the second the spectral axis of the cube
+    ///
+    /// The offset is in the unit of the first spatial axis, its pixels being
+    /// taken as square.
+    pub fn pv_diagram(cube: &Cube, pv: &PvDiagram) -> Result<Self, &'static str> {
+        let mut history = source_history(cube);
+        history.push(pv.path.description());
+
+        let wcs = match cube.wcs() {
+            Some(wcs) => {
+                let spectral = FitsWcs::keep(wcs, &[2])?;
+                Some(FitsWcs {
+                    axes: vec![
+                        WcsAxis {
+                            ctype: "OFFSET".to_string(),
+                            cunit: wcs.axes[0].cunit.clone(),
+                            crval: 0.0,
+                            crpix: 1.0,
+                            cdelt: wcs.axes[0].cdelt.abs(),
+                        },
+                        spectral.axes[0].clone(),
+                    ],
+                    pc: vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+                    frame: spectral.frame,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            dim: vec![pv.size.0, pv.size.1],
+            data: pv.data.clone(),
+            wcs,
+            bunit: cube.bunit.clone(),
+            history,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut header = Vec::with_capacity(BLOCK_SIZE);
+        push_value(&mut header, "SIMPLE", "T", "conforms to the FITS standard");
+        push_value(&mut header, "BITPIX", "-32", "32 bits floats");
+        push_value(&mut header, "NAXIS", &self.dim.len().to_string(), "");
+        for (i, n) in self.dim.iter().enumerate() {
+            push_value(&mut header, &format!("NAXIS{}", i + 1), &n.to_string(), "");
+        }
+        if let Some(wcs) = &self.wcs {
+            push_wcs(&mut header, wcs);
+        }
+        if let Some(bunit) = &self.bunit {
+            push_string(&mut header, "BUNIT", bunit);
+        }
+        let mut values = self.data.iter().filter(|v| v.is_finite());
+        if let Some(&first) = values.next() {
+            let (min, max) = values.fold((first, first), |(min, max), &v| (min.min(v), max.max(v)));
+            push_value(&mut header, "DATAMIN", &real(min), "");
+            push_value(&mut header, "DATAMAX", &real(max), "");
+        }
+        push_history(&mut header, &self.history);
+        push_end(&mut header);
+        writer.write_all(&header)?;
+
+        let mut data = Vec::with_capacity((self.data.len() * 4).next_multiple_of(BLOCK_SIZE));
+        for v in &self.data {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.resize(data.len().next_multiple_of(BLOCK_SIZE), 0);
+        writer.write_all(&data)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        // writing into a vec cannot fail
+        self.write(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+/// Labels of the objects found in the cube, written as the primary HDU of a FITS file
+/// in 32 bits integers, as the masks of SoFiA
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitsLabels {
+    /// Length of the axes, the ones of the cube
+    pub dim: (u32, u32, u32),
+    /// ID of the object of each voxel, 0 outside of the objects
+    pub data: Vec<i32>,
+    /// Linear WCS of the axes, the one of the cube
+    pub wcs: Option<FitsWcs>,
+    pub history: Vec<String>,
+}
+
+impl FitsLabels {
+    /// Labels of the objects of the cube, found by the settings of the catalog
+    pub fn new(cube: &Cube, labels: &Labels, catalog: &Catalog) -> Self {
+        let mut history = source_history(cube);
+        history.push(catalog.description());
+
+        Self {
+            dim: labels.dim,
+            data: labels.voxel_ids().map(|id| id as i32).collect(),
+            // keeping every axis cannot fail
+            wcs: image_wcs(cube, &[0, 1, 2]).ok().flatten(),
+            history,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let (w, h, d) = self.dim;
+        let mut header = Vec::with_capacity(BLOCK_SIZE);
+        push_value(&mut header, "SIMPLE", "T", "conforms to the FITS standard");
+        push_value(&mut header, "BITPIX", "32", "32 bits integers");
+        push_value(&mut header, "NAXIS", "3", "");
+        push_value(&mut header, "NAXIS1", &w.to_string(), "");
+        push_value(&mut header, "NAXIS2", &h.to_string(), "");
+        push_value(&mut header, "NAXIS3", &d.to_string(), "");
+        if let Some(wcs) = &self.wcs {
+            push_wcs(&mut header, wcs);
+        }
+        let max = self.data.iter().copied().max().unwrap_or(0);
+        push_value(&mut header, "DATAMIN", "0", "");
+        push_value(&mut header, "DATAMAX", &max.to_string(), "");
+        push_history(&mut header, &self.history);
+        push_end(&mut header);
+        writer.write_all(&header)?;
+
+        let mut data = Vec::with_capacity((self.data.len() * 4).next_multiple_of(BLOCK_SIZE));
+        for v in &self.data {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.resize(data.len().next_multiple_of(BLOCK_SIZE), 0);
+        writer.write_all(&data)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        // writing into a vec cannot fail
+        self.write(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+/// Values of a column of a binary table
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColumnData {
+    /// 32 bits integers
+    Int(Vec<i32>),
+    /// 32 bits floats
+    Float(Vec<f32>),
+    /// 64 bits floats
+    Double(Vec<f64>),
+}
+
+impl ColumnData {
+    fn len(&self) -> usize {
+        match self {
+            ColumnData::Int(values) => values.len(),
+            ColumnData::Float(values) => values.len(),
+            ColumnData::Double(values) => values.len(),
+        }
+    }
+
+    // TFORM code and size in bytes of a value
+    fn format(&self) -> (&'static str, usize) {
+        match self {
+            ColumnData::Int(_) => ("1J", 4),
+            ColumnData::Float(_) => ("1E", 4),
+            ColumnData::Double(_) => ("1D", 8),
+        }
+    }
+
+    fn push_be_bytes(&self, row: usize, data: &mut Vec<u8>) {
+        match self {
+            ColumnData::Int(values) => data.extend_from_slice(&values[row].to_be_bytes()),
+            ColumnData::Float(values) => data.extend_from_slice(&values[row].to_be_bytes()),
+            ColumnData::Double(values) => data.extend_from_slice(&values[row].to_be_bytes()),
+        }
+    }
+}
+
+/// Column of a binary table, all the columns having the same number of rows
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitsColumn {
+    pub name: String,
+    pub unit: Option<String>,
+    pub data: ColumnData,
+}
+
+/// Binary table written as the extension of a FITS file with an empty primary HDU
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitsTable {
+    /// Name of the extension
+    pub name: String,
+    pub columns: Vec<FitsColumn>,
+    /// Text of the HISTORY cards of the extension
+    pub history: Vec<String>,
+}
+
+impl FitsTable {
+    /// Spectrum extracted from the cube, with the channel index, the spectral
+    /// coordinate if the cube has a WCS and the value of each channel as columns
+    pub fn spectrum(cube: &Cube, spectrum: &Spectrum) -> Self {
+        let mut history = source_history(cube);
+        history.push(spectrum.description());
+
+        let mut columns = vec![FitsColumn {
+            name: "CHANNEL".to_string(),
+            unit: None,
+            data: ColumnData::Int((0..spectrum.values.len() as i32).collect()),
+        }];
+        if let Some(coords) = &spectrum.coords {
+            columns.push(FitsColumn {
+                name: spectrum.coord_name().to_uppercase(),
+                unit: Some(spectrum.cunit.clone()).filter(|unit| !unit.is_empty()),
+                data: ColumnData::Double(coords.clone()),
+            });
+        }
+        columns.push(FitsColumn {
+            name: "VALUE".to_string(),
+            unit: spectrum.bunit.clone(),
+            data: ColumnData::Float(spectrum.values.clone()),
+        });
+
+        Self {
+            name: "SPECTRUM".to_string(),
+            columns,
+            history,
+        }
+    }
+
+    fn num_rows(&self) -> usize {
+        self.columns.first().map_or(0, |column| column.data.len())
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let num_rows = self.num_rows();
+        if self
+            .columns
+            .iter()
+            .any(|column| column.data.len() != num_rows)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The columns of the table do not have the same length",
+            ));
+        }
+
+        let mut header = Vec::with_capacity(2 * BLOCK_SIZE);
+        push_value(&mut header, "SIMPLE", "T", "conforms to the FITS standard");
+        push_value(&mut header, "BITPIX", "8", "");
+        push_value(&mut header, "NAXIS", "0", "no data in the primary HDU");
+        push_value(&mut header, "EXTEND", "T", "the table follows");
+        push_end(&mut header);
+
+        let row_size: usize = self.columns.iter().map(|c| c.data.format().1).sum();
+        push_string(&mut header, "XTENSION", "BINTABLE");
+        push_value(&mut header, "BITPIX", "8", "");
+        push_value(&mut header, "NAXIS", "2", "");
+        push_value(
+            &mut header,
+            "NAXIS1",
+            &row_size.to_string(),
+            "bytes per row",
+        );
+        push_value(&mut header, "NAXIS2", &num_rows.to_string(), "rows");
+        push_value(&mut header, "PCOUNT", "0", "");
+        push_value(&mut header, "GCOUNT", "1", "");
+        push_value(&mut header, "TFIELDS", &self.columns.len().to_string(), "");
+        for (i, column) in self.columns.iter().enumerate() {
+            let n = i + 1;
+            push_string(&mut header, &format!("TTYPE{}", n), &column.name);
+            push_string(&mut header, &format!("TFORM{}", n), column.data.format().0);
+            if let Some(unit) = &column.unit {
+                push_string(&mut header, &format!("TUNIT{}", n), unit);
+            }
+        }
+        push_string(&mut header, "EXTNAME", &self.name);
+        push_history(&mut header, &self.history);
+        push_end(&mut header);
+        writer.write_all(&header)?;
+
+        let mut data = Vec::with_capacity((num_rows * row_size).next_multiple_of(BLOCK_SIZE));
+        for row in 0..num_rows {
+            for column in &self.columns {
+                column.data.push_be_bytes(row, &mut data);
+            }
+        }
+        data.resize(data.len().next_multiple_of(BLOCK_SIZE), 0);
+        writer.write_all(&data)
+    }
+
+    /// Bytes of the FITS file, an error being returned if the columns do not have
+    /// the same length
+    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+// region clamped to the cube, none of its ranges being empty
+fn clamp_region(cube: &Cube, region: &[Range<u32>; 3]) -> Result<[Range<u32>; 3], &'static str> {
+    let (w, h, d) = cube.dim;
+    let clamp = |range: &Range<u32>, n: u32| range.start.min(n)..range.end.min(n);
+    let region = [
+        clamp(&region[0], w),
+        clamp(&region[1], h),
+        clamp(&region[2], d),
+    ];
+    if region.iter().any(|range| range.is_empty()) {
+        Err("The region does not contain any voxel of the cube")
+    } else {
+        Ok(region)
+    }
+}
+
+// voxel ranges written as in the HISTORY cards, bounds included
+fn describe_region(region: &[Range<u32>; 3]) -> String {
+    let ranges: Vec<String> = (0..3)
+        .map(|i| {
+            format!(
+                "{} {} to {}",
+                AXIS_NAMES[i],
+                region[i].start,
+                region[i].end - 1
+            )
+        })
+        .collect();
+    format!("{} (0-based, bounds included)", ranges.join(", "))
+}
+
+fn source_history(cube: &Cube) -> Vec<String> {
+    match &cube.name {
+        Some(name) => vec![format!("Written by fits3 from {}", name)],
+        None => vec!["Written by fits3".to_string()],
+    }
+}
+
+// axis of the pixels from `offset` on, binned by blocks of `bin` pixels
+fn crop_axis(axis: &WcsAxis, offset: u32, bin: u32) -> WcsAxis {
+    let bin = bin as f64;
+    WcsAxis {
+        // the first binned pixel is centered on the middle of the first block
+        crpix: (axis.crpix - offset as f64 - 0.5) / bin + 0.5,
+        cdelt: axis.cdelt * bin,
+        ..axis.clone()
+    }
+}
+
+// WCS cards of the axes, numbered from 1, and of their frames
+fn push_wcs(header: &mut Vec<u8>, wcs: &FitsWcs) {
+    for (i, axis) in wcs.axes.iter().enumerate() {
+        let n = i + 1;
+        push_string(header, &format!("CTYPE{}", n), &axis.ctype);
+        if !axis.cunit.is_empty() {
+            push_string(header, &format!("CUNIT{}", n), &axis.cunit);
+        }
+        push_value(header, &format!("CRVAL{}", n), &real(axis.crval), "");
+        push_value(header, &format!("CRPIX{}", n), &real(axis.crpix), "");
+        push_value(header, &format!("CDELT{}", n), &real(axis.cdelt), "");
+    }
+    // the matrix defaults to the identity, only the terms differing are written
+    for (i, row) in wcs.pc.iter().enumerate() {
+        for (j, &v) in row.iter().enumerate() {
+            if v != if i == j { 1.0 } else { 0.0 } {
+                push_value(header, &format!("PC{}_{}", i + 1, j + 1), &real(v), "");
+            }
+        }
+    }
+
+    let frame = &wcs.frame;
+    if let Some(restfrq) = frame.restfrq {
+        push_value(header, "RESTFRQ", &real(restfrq), "rest frequency (Hz)");
+    }
+    if let Some(specsys) = &frame.specsys {
+        push_string(header, "SPECSYS", specsys);
+    }
+    if let Some(radesys) = &frame.radesys {
+        push_string(header, "RADESYS", radesys);
+    }
+    if let Some(equinox) = frame.equinox {
+        push_value(header, "EQUINOX", &real(equinox), "");
+    }
+}
+
+// fixed format real, with a decimal point and an exponent
+fn real<T: std::fmt::UpperExp>(v: T) -> String {
+    let s = format!("{:E}", v);
+    if s.contains('.') {
+        s
+    } else {
+        s.replacen('E', ".0E", 1)
+    }
+}
+
+// lines of at most `width` characters, split between words where there are some
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut rest: Vec<char> = text.chars().collect();
+    while rest.len() > width {
+        let cut = rest[..=width]
+            .iter()
+            .rposition(|&c| c == ' ')
+            .filter(|&i| i > 0)
+            .unwrap_or(width);
+        lines.push(rest[..cut].iter().collect());
+        rest.drain(..cut);
+        if rest.first() == Some(&' ') {
+            rest.remove(0);
+        }
+    }
+    lines.push(rest.into_iter().collect());
+    lines
+}
+
+// HISTORY cards of the lines of text, split between words to fit in the cards
+fn push_history(header: &mut Vec<u8>, history: &[String]) {
+    for line in history {
+        for part in wrap(&ascii(line), HISTORY_SIZE) {
+            push_card(header, &format!("HISTORY {}", part));
+        }
+    }
+}
+
+// END card, the header being padded with spaces to a whole block
+fn push_end(header: &mut Vec<u8>) {
+    push_card(header, "END");
+    header.resize(header.len().next_multiple_of(BLOCK_SIZE), b' ');
+}
+
+// card of a number or a logical, right aligned to the 30th column
+fn push_value(header: &mut Vec<u8>, key: &str, value: &str, comment: &str) {
+    let mut card = format!("{:<8}= {:>20}", key, value);
+    if !comment.is_empty() {
+        card.push_str(" / ");
+        card.push_str(comment);
+    }
+    push_card(header, &card);
+}
+
+// card of a quoted string, the quotes inside being doubled and the string cut
+// between characters so that it fits in the columns left for it
+fn push_string(header: &mut Vec<u8>, key: &str, value: &str) {
+    let mut escaped = String::new();
+    for c in ascii(value).chars() {
+        let len = if c == '\'' { 2 } else { 1 };
+        if escaped.len() + len > STRING_SIZE {
+            break;
+        }
+        escaped.push(c);
+        if c == '\'' {
+            escaped.push(c);
+        }
+    }
+    let quoted = format!("'{:<8}'", escaped);
+    push_card(header, &format!("{:<8}= {:<20}", key, quoted));
+}
+
+// text with the characters outside of printable ASCII replaced, one byte each
+fn ascii(text: &str) -> String {
+    text.chars()
+        .map(|c| if (' '..='~').contains(&c) { c } else { '?' })
+        .collect()
+}
+
+// card padded with spaces, the bytes outside of printable ASCII being replaced
+fn push_card(header: &mut Vec<u8>, card: &str) {
+    let start = header.len();
+    header.extend(
+        card.bytes()
+            .map(|b| if (0x20..0x7f).contains(&b) { b } else { b'?' })
+            .take(CARD_SIZE),
+    );
+    header.resize(start + CARD_SIZE, b' ');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cards of a header, without their trailing spaces
+    fn cards(header: &[u8]) -> Vec<String> {
+        header
+            .chunks_exact(CARD_SIZE)
+            .map(|card| {
+                String::from_utf8(card.to_vec())
+                    .unwrap()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn strings_are_escaped_then_cut() {
+        let mut header = vec![];
+        push_string(&mut header, "OBJECT", "M31");
+        push_string(&mut header, "OBJECT", "it's");
+        push_string(&mut header, "OBJECT", "cube_é.fits");
+        assert_eq!(header.len(), 3 * CARD_SIZE);
+        assert_eq!(
+            cards(&header),
+            [
+                "OBJECT  = 'M31     '",
+                "OBJECT  = 'it''s   '",
+                "OBJECT  = 'cube_?.fits'",
+            ]
+        );
+
+        // the closing quote ends the card, a doubled quote being never split
+        for value in [
+            "x".repeat(100),
+            "'".repeat(100),
+            format!("x{}", "'".repeat(100)),
+        ] {
+            let mut header = vec![];
+            push_string(&mut header, "OBJECT", &value);
+            let card = &cards(&header)[0];
+            let quoted = &card[10..];
+            assert!(quoted.len() <= 70);
+            assert!(quoted.ends_with('\''));
+            let inner = &quoted[1..quoted.len() - 1];
+            assert_eq!(inner.replace("''", "").find('\''), None, "{}", card);
+        }
+    }
+
+    #[test]
+    fn history_is_split_between_words() {
+        let line = "données ".repeat(20);
+        let mut header = vec![];
+        push_history(&mut header, &[line.trim_end().to_string()]);
+        let cards = cards(&header);
+        assert_eq!(cards.len(), 3);
+        for card in &cards {
+            assert!(card.starts_with("HISTORY "));
+            assert!(card.len() <= CARD_SIZE);
+            assert!(card.ends_with("donn?es"), "{}", card);
+        }
+        let words: usize = cards
+            .iter()
+            .map(|card| card.matches("donn?es").count())
+            .sum();
+        assert_eq!(words, 20);
+    }
+
+    fn axis(ctype: &str, crpix: f64, cdelt: f64) -> WcsAxis {
+        WcsAxis {
+            ctype: ctype.to_string(),
+            cunit: String::new(),
+            crval: 100.0,
+            crpix,
+            cdelt,
+        }
+    }
+
+    // WCS of a cube whose celestial axes are rotated by `pc`
+    fn wcs(pc: [[f64; 3]; 3]) -> Wcs {
+        Wcs {
+            axes: [
+                axis("RA---SIN", 10.0, -0.5),
+                axis("DEC--SIN", 20.0, 0.5),
+                axis("FREQ", 1.0, 1e6),
+            ],
+            pc,
+            frame: WcsFrame {
+                restfrq: Some(1.4204e9),
+                ..Default::default()
+            },
+        }
+    }
+
+    const ROTATED: [[f64; 3]; 3] = [[0.8, -0.6, 0.0], [0.6, 0.8, 0.0], [0.0, 0.0, 1.0]];
+    const IDENTITY_PC: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    #[test]
+    fn image_header_and_data() {
+        let image = FitsImage {
+            dim: vec![2, 3],
+            data: vec![1.0, f32::NAN, -2.5, 4.0, 0.0, 3.0],
+            wcs: Some(FitsWcs::keep(&wcs(ROTATED), &[0, 1]).unwrap()),
+            bunit: Some("Jy/beam".to_string()),
+            history: vec!["Written by fits3".to_string()],
+        };
+        let bytes = image.to_bytes();
+        assert_eq!(bytes.len(), 2 * BLOCK_SIZE);
+
+        let cards = cards(&bytes[..BLOCK_SIZE]);
+        let end = cards.iter().position(|card| card == "END").unwrap();
+        assert!(cards[end + 1..].iter().all(|card| card.is_empty()));
+        assert_eq!(
+            cards[..end],
+            [
+                "SIMPLE  =                    T / conforms to the FITS standard",
+                "BITPIX  =                  -32 / 32 bits floats",
+                "NAXIS   =                    2",
+                "NAXIS1  =                    2",
+                "NAXIS2  =                    3",
+                "CTYPE1  = 'RA---SIN'",
+                "CRVAL1  =                1.0E2",
+                "CRPIX1  =                1.0E1",
+                "CDELT1  =              -5.0E-1",
+                "CTYPE2  = 'DEC--SIN'",
+                "CRVAL2  =                1.0E2",
+                "CRPIX2  =                2.0E1",
+                "CDELT2  =               5.0E-1",
+                "PC1_1   =               8.0E-1",
+                "PC1_2   =              -6.0E-1",
+                "PC2_1   =               6.0E-1",
+                "PC2_2   =               8.0E-1",
+                "RESTFRQ =             1.4204E9 / rest frequency (Hz)",
+                "BUNIT   = 'Jy/beam '",
+                "DATAMIN =               -2.5E0",
+                "DATAMAX =                4.0E0",
+                "HISTORY Written by fits3",
+            ]
+        );
+
+        // big endian floats, the blank value being a NaN
+        let value = |i: usize| {
+            let offset = BLOCK_SIZE + 4 * i;
+            f32::from_be_bytes([0, 1, 2, 3].map(|k| bytes[offset + k]))
+        };
+        assert_eq!(value(0), 1.0);
+        assert!(value(1).is_nan());
+        assert_eq!(value(2), -2.5);
+        assert!(bytes[BLOCK_SIZE + 24..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn cropped_axes_keep_their_world_coordinates() {
+        let original = axis("VRAD", 10.0, 0.5);
+        for (offset, bin) in [(0, 1), (4, 1), (4, 2), (3, 5)] {
+            let cropped = crop_axis(&original, offset, bin);
+            assert_eq!(cropped.cdelt, 0.5 * bin as f64);
+            // the first binned pixel is centered between the pixels of its block
+            let center = offset as f64 + (bin - 1) as f64 / 2.0;
+            let world = original.pix2world(center);
+            assert!((cropped.pix2world(0.0) - world).abs() < 1e-12);
+            assert!((cropped.pix2world(1.0) - world - cropped.cdelt).abs() < 1e-12);
+        }
+        assert_eq!(crop_axis(&original, 4, 2).crpix, 3.25);
+    }
+
+    #[test]
+    fn rotated_axes_are_binned_without_moving() {
+        let wcs = wcs(ROTATED);
+        let (offsets, bins) = ([3, 2, 0], [2, 4, 1]);
+        let cropped = FitsWcs::keep(&wcs, &[0, 1, 2])
+            .unwrap()
+            .crop(&offsets, &bins);
+        let cropped = Wcs {
+            axes: [0, 1, 2].map(|i| cropped.axes[i].clone()),
+            pc: [0, 1, 2].map(|i| [0, 1, 2].map(|j| cropped.pc[i][j])),
+            frame: cropped.frame,
+        };
+        for p in [[0.0, 0.0, 0.0], [2.0, 1.0, 3.0]] {
+            let center = [0, 1, 2]
+                .map(|i| (offsets[i] + bins[i] * p[i] as u32) as f64 + (bins[i] - 1) as f64 / 2.0);
+            let (a, b) = (cropped.pix2world(p), wcs.pix2world(center));
+            for i in 0..3 {
+                assert!((a[i] - b[i]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn axes_depending_on_the_dropped_ones_are_refused() {
+        let rotated = wcs(ROTATED);
+        assert!(FitsWcs::keep(&rotated, &[0, 1, 2]).is_ok());
+        assert!(FitsWcs::keep(&rotated, &[0, 1]).is_ok());
+        assert!(FitsWcs::keep(&rotated, &[0, 2]).is_err());
+        assert!(FitsWcs::keep(&rotated, &[1, 2]).is_err());
+
+        let kept = FitsWcs::keep(&wcs(IDENTITY_PC), &[0, 2]).unwrap();
+        assert_eq!(kept.axes[1].ctype, "FREQ");
+        assert_eq!(kept.pc, [[1.0, 0.0], [0.0, 1.0]]);
+    }
+
+    #[test]
+    fn binary_table_layout() {
+        let table = FitsTable {
+            name: "SPECTRUM".to_string(),
+            columns: vec![
+                FitsColumn {
+                    name: "CHANNEL".to_string(),
+                    unit: None,
+                    data: ColumnData::Int(vec![0, 1, 2]),
+                },
+                FitsColumn {
+                    name: "VRAD".to_string(),
+                    unit: Some("m/s".to_string()),
+                    data: ColumnData::Double(vec![-1.5, 0.0, 1.5]),
+                },
+            ],
+            history: vec![],
+        };
+        let bytes = table.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 3 * BLOCK_SIZE);
+
+        let primary = cards(&bytes[..BLOCK_SIZE]);
+        assert!(primary[2].starts_with("NAXIS   =                    0"));
+        assert!(primary[3].starts_with("EXTEND  =                    T"));
+        assert_eq!(primary[4], "END");
+
+        let extension = cards(&bytes[BLOCK_SIZE..2 * BLOCK_SIZE]);
+        let end = extension.iter().position(|card| card == "END").unwrap();
+        assert_eq!(
+            extension[..end],
+            [
+                "XTENSION= 'BINTABLE'",
+                "BITPIX  =                    8",
+                "NAXIS   =                    2",
+                "NAXIS1  =                   12 / bytes per row",
+                "NAXIS2  =                    3 / rows",
+                "PCOUNT  =                    0",
+                "GCOUNT  =                    1",
+                "TFIELDS =                    2",
+                "TTYPE1  = 'CHANNEL '",
+                "TFORM1  = '1J      '",
+                "TTYPE2  = 'VRAD    '",
+                "TFORM2  = '1D      '",
+                "TUNIT2  = 'm/s     '",
+                "EXTNAME = 'SPECTRUM'",
+            ]
+        );
+
+        // rows of a big endian integer then a double
+        let data = &bytes[2 * BLOCK_SIZE..];
+        assert_eq!(data[12..16], 1_i32.to_be_bytes());
+        assert_eq!(data[16..24], 0.0_f64.to_be_bytes());
+        assert_eq!(data[28..36], 1.5_f64.to_be_bytes());
+        assert!(data[36..].iter().all(|&b| b == 0));
+
+        // columns of different lengths are refused
+        let mut table = table;
+        table.columns[0].data = ColumnData::Int(vec![0, 1]);
+        assert!(table.to_bytes().is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::cube::Cube;
+use crate::texture::Texture;
+
+// edge of a macro cell in voxels, must match GRID_CELL in cube.frag
+pub(crate) const CELL_SIZE: u32 = 8;
+
+/// Min and max values of the cube over macro cells of `CELL_SIZE`^3 voxels
+///
+/// The ray marcher looks it up to jump over the cells that cannot contribute
+/// to the pixel. The values are the raw data ones, the transfer function being
+/// applied in the shader, so that the grid only depends on the cube.
+pub(crate) struct MinMaxGrid {
+    dim: (u32, u32, u32),
+    cells: Vec<[f32; 2]>,
+}
+
+impl MinMaxGrid {
+    /// Grid of a single cell that can never be skipped, used before any cube is loaded
+    pub(crate) fn empty() -> Self {
+        Self {
+            dim: (1, 1, 1),
+            cells: vec![[f32::NEG_INFINITY, f32::INFINITY]],
+        }
+    }
+
+    pub(crate) fn from_cube(cube: &Cube) -> Self {
+        let (w, h, d) = cube.dim;
+        let dim = (
+            w.div_ceil(CELL_SIZE).max(1),
+            h.div_ceil(CELL_SIZE).max(1),
+            d.div_ceil(CELL_SIZE).max(1),
+        );
+        let mut cells = vec![[f32::INFINITY, f32::NEG_INFINITY]; (dim.0 * dim.1 * dim.2) as usize];
+
+        // cells touched by a voxel, including the ones next to it so that
+        // a sample rounded to a voxel of a neighbouring cell is still covered
+        let cell_range = |x: u32, len: u32| {
+            (x.saturating_sub(1) / CELL_SIZE)..=((x + 1).min(len - 1) / CELL_SIZE)
+        };
+
+        for z in 0..d {
+            for y in 0..h {
+                for x in 0..w {
+                    let v = cube.value(x, y, z);
+                    for cz in cell_range(z, d) {
+                        for cy in cell_range(y, h) {
+                            for cx in cell_range(x, w) {
+                                let cell = &mut cells[((cz * dim.1 + cy) * dim.0 + cx) as usize];
+                                if v.is_nan() {
+                                    // blank voxels are never skipped
+                                    *cell = [f32::NEG_INFINITY, f32::INFINITY];
+                                } else {
+                                    cell[0] = cell[0].min(v);
+                                    cell[1] = cell[1].max(v);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Self { dim, cells }
+    }
+
+    pub(crate) fn texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Texture, &'static str> {
+        Texture::from_raw_bytes::<[f32; 2]>(
+            device,
+            queue,
+            Some(bytemuck::cast_slice(&self.cells)),
+            self.dim,
+            8,
+            "minmax grid",
+        )
+    }
+}
@@ -0,0 +1,259 @@
+//! Objects found in the cube by a source finder, given as a cube of integer labels
+//! like the masks of SoFiA: each voxel holds the ID of the object it belongs to,
+//! 0 outside of the objects
+
+use std::io::Cursor;
+
+use fitsrs::Fits;
+
+use crate::cube::{parse_fits_data_cube, RawCube};
+
+/// Width of the table of the colors of the objects, must match cube.frag
+pub(crate) const TABLE_WIDTH: u32 = 256;
+
+/// How the objects are drawn over the volume
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum LabelDisplay {
+    /// Not drawn, though the data outside of them can still be hidden
+    Off,
+    /// Silhouettes of the objects in their colors
+    #[default]
+    Outlines,
+    /// Objects tinted with their colors
+    Tint,
+}
+
+impl LabelDisplay {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            LabelDisplay::Off => LabelDisplay::Outlines,
+            LabelDisplay::Outlines => LabelDisplay::Tint,
+            LabelDisplay::Tint => LabelDisplay::Off,
+        }
+    }
+
+    fn as_f32(self) -> f32 {
+        match self {
+            LabelDisplay::Off => 0.0,
+            LabelDisplay::Outlines => 1.0,
+            LabelDisplay::Tint => 2.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LabelDisplay::Off => "off",
+            LabelDisplay::Outlines => "outlines",
+            LabelDisplay::Tint => "tint",
+        }
+    }
+}
+
+impl std::str::FromStr for LabelDisplay {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" | "none" => Ok(LabelDisplay::Off),
+            "outlines" | "outline" => Ok(LabelDisplay::Outlines),
+            "tint" => Ok(LabelDisplay::Tint),
+            _ => Err("Unknown label display, expected 'off', 'outlines' or 'tint'"),
+        }
+    }
+}
+
+/// Labels of the objects of a cube, of the same dimensions as the cube
+pub struct Labels {
+    // index of the object of each voxel in `ids` plus one, 0 outside of the objects,
+    // x varying the fastest, then y, then z
+    pub(crate) indices: Vec<u32>,
+    pub(crate) dim: (u32, u32, u32),
+    // IDs of the objects in increasing order
+    ids: Vec<u32>,
+    // whether each object is hidden, by index
+    hidden: Vec<bool>,
+    /// Name of the file the labels were read from
+    pub(crate) name: Option<String>,
+}
+
+impl Labels {
+    /// Labels from the values of any BITPIX, the ones below 1 or blank being
+    /// outside of the objects
+    pub(crate) fn from_raw(raw: &RawCube) -> Result<Self, &'static str> {
+        let (w, h, d) = raw.dim;
+        let num_values = (w as usize) * (h as usize) * (d as usize);
+        let size = match raw.bitpix {
+            8 | 16 | 32 | 64 | -32 | -64 => raw.bitpix.unsigned_abs() as usize / 8,
+            _ => return Err("Unsupported BITPIX"),
+        };
+        if raw.data.len() < num_values * size {
+            return Err("FITS data is smaller than expected from NAXISn");
+        }
+
+        let labels: Vec<u32> = raw.data[..num_values * size]
+            .chunks_exact(size)
+            .map(|b| {
+                let v = match raw.bitpix {
+                    8 => b[0] as f64,
+                    16 => i16::from_be_bytes([b[0], b[1]]) as f64,
+                    32 => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    64 => {
+                        i64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f64
+                    }
+                    -32 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+                };
+                // NaN is cast to 0
+                if v >= 1.0 {
+                    v as u32
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        Self::from_ids(labels, raw.dim)
+    }
+
+    /// Labels from the ID of the object of each voxel, 0 outside of the objects
+    pub(crate) fn from_ids(labels: Vec<u32>, dim: (u32, u32, u32)) -> Result<Self, &'static str> {
+        let mut ids: Vec<u32> = labels.iter().copied().filter(|&id| id != 0).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.len() > (TABLE_WIDTH * TABLE_WIDTH) as usize {
+            return Err("Too many objects labeled");
+        }
+        let indices = labels
+            .iter()
+            .map(|&id| match id {
+                0 => 0,
+                id => ids.binary_search(&id).map_or(0, |index| index as u32 + 1),
+            })
+            .collect();
+
+        Ok(Self {
+            indices,
+            dim,
+            hidden: vec![false; ids.len()],
+            ids,
+            name: None,
+        })
+    }
+
+    pub fn from_reader<R: AsRef<[u8]> + std::fmt::Debug>(
+        reader: Cursor<R>,
+    ) -> Result<Self, &'static str> {
+        let mut fits = Fits::from_reader(reader);
+        let raw = parse_fits_data_cube(&mut fits)?;
+
+        Self::from_raw(&raw)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, &'static str> {
+        let file = std::fs::File::open(&path).map_err(|_| "Cannot open the FITS file")?;
+        let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(|_| "Cannot map the FITS file")?;
+
+        let mut labels = Self::from_reader(Cursor::new(mmap))?;
+        labels.name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        Ok(labels)
+    }
+
+    pub fn dim(&self) -> (u32, u32, u32) {
+        self.dim
+    }
+
+    /// IDs of the objects, in increasing order
+    pub fn ids(&self) -> &[u32] {
+        &self.ids
+    }
+
+    /// ID of the object of each voxel, 0 outside of the objects
+    pub(crate) fn voxel_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.indices.iter().map(move |&index| match index {
+            0 => 0,
+            index => self.ids[index as usize - 1],
+        })
+    }
+
+    // index of the object of a voxel plus one, 0 outside of the objects
+    fn index_at(&self, [x, y, z]: [u32; 3]) -> usize {
+        let (w, h, _) = self.dim;
+        self.indices[((z as usize) * (h as usize) + (y as usize)) * (w as usize) + (x as usize)]
+            as usize
+    }
+
+    /// ID of the object a voxel belongs to, `None` outside of the objects
+    pub fn id_at(&self, voxel: [u32; 3]) -> Option<u32> {
+        match self.index_at(voxel) {
+            0 => None,
+            index => Some(self.ids[index - 1]),
+        }
+    }
+
+    /// Whether a voxel belongs to an object which is not hidden
+    pub(crate) fn shown_at(&self, voxel: [u32; 3]) -> bool {
+        match self.index_at(voxel) {
+            0 => false,
+            index => !self.hidden[index - 1],
+        }
+    }
+
+    pub fn is_hidden(&self, id: u32) -> bool {
+        self.ids
+            .binary_search(&id)
+            .is_ok_and(|index| self.hidden[index])
+    }
+
+    /// Hide or show an object, by its ID
+    pub fn set_hidden(&mut self, id: u32, hidden: bool) -> Result<(), &'static str> {
+        let index = self
+            .ids
+            .binary_search(&id)
+            .map_err(|_| "No object of this ID")?;
+        self.hidden[index] = hidden;
+        Ok(())
+    }
+
+    pub fn show_all(&mut self) {
+        self.hidden.fill(false);
+    }
+
+    /// Colors of the objects by index, transparent for the hidden ones, as rows
+    /// of RGBA texels of at most `TABLE_WIDTH` texels
+    pub(crate) fn table(&self) -> (Vec<u8>, (u32, u32, u32)) {
+        let n = self.ids.len().max(1) as u32;
+        let size = (n.min(TABLE_WIDTH), n.div_ceil(TABLE_WIDTH), 1);
+        let mut rgba = vec![0; (size.0 * size.1) as usize * 4];
+        for (index, (texel, hidden)) in rgba.chunks_exact_mut(4).zip(&self.hidden).enumerate() {
+            let [r, g, b] = object_color(index);
+            texel.copy_from_slice(&[r, g, b, if *hidden { 0 } else { 255 }]);
+        }
+        (rgba, size)
+    }
+}
+
+/// Color of the object of an index, the hues of consecutive objects being far apart
+fn object_color(index: usize) -> [u8; 3] {
+    // the hue turns by the golden ratio, in sixths of a turn
+    let h = (index as f32 * 0.618_034).fract() * 6.0;
+    [0.0, 4.0, 2.0].map(|offset: f32| {
+        let c = (((h + offset) % 6.0 - 3.0).abs() - 1.0).clamp(0.0, 1.0);
+        // pastel enough to stand out on the dark colors of the colormaps
+        ((0.35 + 0.65 * c) * 255.0).round() as u8
+    })
+}
+
+/// Display of the objects and whether the data outside of the ones shown is hidden,
+/// as the `Labels` uniform of cube.frag
+pub(crate) fn labels_uniform(display: LabelDisplay, hide_unlabeled: bool) -> [f32; 4] {
+    [
+        display.as_f32(),
+        if hide_unlabeled { 1.0 } else { 0.0 },
+        0.0,
+        0.0,
+    ]
+}
@@ -0,0 +1,371 @@
+//! Cubes overlaid on the one loaded and combined with it along the rays, e.g. the
+//! CO emission of a galaxy over its HI
+
+use std::ops::Range;
+
+use crate::colormap::{Colormap, Stretch};
+use crate::cube::Cube;
+use crate::wcs::WcsAxis;
+
+/// Number of cubes that can be overlaid on the one loaded, must match cube.frag
+pub(crate) const MAX_LAYERS: usize = 3;
+
+/// Colormaps given to the layers by default, in the order they are added
+pub(crate) const LAYER_COLORMAPS: [Colormap; MAX_LAYERS] =
+    [Colormap::Grey, Colormap::Viridis, Colormap::Turbo];
+
+/// The way the colors of the layers are combined with the one of the cube
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Blend {
+    /// Colors added, the bright parts of both cubes standing out
+    #[default]
+    Additive,
+    /// Brightest of the colors on each channel
+    Max,
+    /// Colors of the layers over the one of the cube, the faint values being transparent
+    Alpha,
+}
+
+impl Blend {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Blend::Additive => Blend::Max,
+            Blend::Max => Blend::Alpha,
+            Blend::Alpha => Blend::Additive,
+        }
+    }
+
+    fn as_f32(self) -> f32 {
+        match self {
+            Blend::Additive => 0.0,
+            Blend::Max => 1.0,
+            Blend::Alpha => 2.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Blend::Additive => "additive",
+            Blend::Max => "max",
+            Blend::Alpha => "alpha",
+        }
+    }
+}
+
+impl std::str::FromStr for Blend {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "additive" | "add" => Ok(Blend::Additive),
+            "max" => Ok(Blend::Max),
+            "alpha" => Ok(Blend::Alpha),
+            _ => Err("Unknown blend mode, expected 'additive', 'max' or 'alpha'"),
+        }
+    }
+}
+
+/// Affine map from the texture coordinates of the cube loaded to those of a layer,
+/// axis by axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Alignment {
+    pub scale: [f32; 3],
+    pub offset: [f32; 3],
+    /// Axes aligned by their world coordinates, the others by the voxel grid
+    pub world: [bool; 3],
+}
+
+impl Alignment {
+    /// Voxel `(i, j, k)` of the cube over voxel `(i, j, k)` of the layer
+    pub fn grid(cube: (u32, u32, u32), layer: (u32, u32, u32)) -> Self {
+        let cube = [cube.0, cube.1, cube.2];
+        let layer = [layer.0, layer.1, layer.2];
+        Self {
+            scale: [0, 1, 2].map(|i| cube[i] as f32 / layer[i].max(1) as f32),
+            offset: [0.0; 3],
+            world: [false; 3],
+        }
+    }
+
+    /// Alignment by the WCS of the axes of the same type in both headers, and by
+    /// the voxel grid for the other axes
+    ///
+    /// The spatial axes are taken as linear around the reference points, which
+    /// holds over the small fields of the cubes of a same object.
+    pub fn between(cube: &Cube, layer: &Cube) -> Self {
+        let mut alignment = Self::grid(cube.dim, layer.dim);
+        let (wcs, layer_wcs) = match (cube.wcs(), layer.wcs()) {
+            (Some(wcs), Some(layer_wcs)) => (wcs, layer_wcs),
+            _ => return alignment,
+        };
+
+        // the differences of longitude are offsets on the sky once multiplied
+        // by the cosine of the latitude
+        let cos_lat = wcs
+            .axes
+            .iter()
+            .find(|axis| is_latitude(axis))
+            .map(|axis| axis.crval.to_radians().cos())
+            .unwrap_or(1.0);
+
+        let dims = [cube.dim.0, cube.dim.1, cube.dim.2];
+        let layer_dims = [layer.dim.0, layer.dim.1, layer.dim.2];
+        for i in 0..3 {
+            let (a, b) = (&wcs.axes[i], &layer_wcs.axes[i]);
+            let (unit_a, unit_b) = match (unit_scale(a, b), unit_scale(b, a)) {
+                (Some(unit_a), Some(unit_b)) => (unit_a, unit_b),
+                _ => continue,
+            };
+            if axis_type(a) != axis_type(b) || a.cdelt == 0.0 || b.cdelt == 0.0 {
+                continue;
+            }
+            let shift =
+                (a.crval * unit_a - b.crval * unit_b) * if is_longitude(a) { cos_lat } else { 1.0 };
+
+            // texture coordinate of the layer from the one of the cube
+            let (n_a, n_b) = (dims[i] as f64, layer_dims[i].max(1) as f64);
+            let to_layer = |u: f64| {
+                let offset = (u * n_a - 0.5 + 1.0 - a.crpix) * a.cdelt * unit_a;
+                let p = (offset + shift) / (b.cdelt * unit_b) + b.crpix - 1.0;
+                (p + 0.5) / n_b
+            };
+            alignment.offset[i] = to_layer(0.0) as f32;
+            alignment.scale[i] = (to_layer(1.0) - to_layer(0.0)) as f32;
+            alignment.world[i] = true;
+        }
+        alignment
+    }
+
+    /// Axes aligned by their world coordinates, as a list of their names
+    pub(crate) fn description(&self) -> String {
+        let axes: Vec<&str> = ["x", "y", "z"]
+            .iter()
+            .zip(self.world)
+            .filter(|(_, world)| *world)
+            .map(|(name, _)| *name)
+            .collect();
+        match axes.len() {
+            0 => "aligned by the voxel grid".to_string(),
+            3 => "aligned by the WCS".to_string(),
+            _ => format!(
+                "aligned by the WCS along {}, by the voxel grid otherwise",
+                axes.join(", ")
+            ),
+        }
+    }
+}
+
+// type of an axis without its projection, e.g. RA for RA---SIN
+fn axis_type(axis: &WcsAxis) -> &str {
+    axis.ctype.split('-').next().unwrap_or_default().trim()
+}
+
+fn is_longitude(axis: &WcsAxis) -> bool {
+    let ctype = axis_type(axis);
+    ctype == "RA" || ctype.ends_with("LON")
+}
+
+fn is_latitude(axis: &WcsAxis) -> bool {
+    let ctype = axis_type(axis);
+    ctype == "DEC" || ctype.ends_with("LAT")
+}
+
+/// Factor converting the unit of `axis` to the one of the SI, or to the degree for
+/// the angles, `None` if it cannot be compared with the unit of `other`
+fn unit_scale(axis: &WcsAxis, other: &WcsAxis) -> Option<f64> {
+    if axis.cunit.eq_ignore_ascii_case(&other.cunit) {
+        return Some(1.0);
+    }
+    let (scale, quantity) = unit(&axis.cunit)?;
+    (unit(&other.cunit)?.1 == quantity).then_some(scale)
+}
+
+// factor converting a unit to the one of the SI or to the degree, with the quantity
+// it measures
+fn unit(cunit: &str) -> Option<(f64, &'static str)> {
+    match cunit {
+        "deg" => Some((1.0, "angle")),
+        "arcmin" => Some((1.0 / 60.0, "angle")),
+        "arcsec" => Some((1.0 / 3600.0, "angle")),
+        "m/s" => Some((1.0, "velocity")),
+        "km/s" => Some((1e3, "velocity")),
+        "Hz" => Some((1.0, "frequency")),
+        "kHz" => Some((1e3, "frequency")),
+        "MHz" => Some((1e6, "frequency")),
+        "GHz" => Some((1e9, "frequency")),
+        "m" => Some((1.0, "length")),
+        "mm" => Some((1e-3, "length")),
+        "um" => Some((1e-6, "length")),
+        _ => None,
+    }
+}
+
+/// Cube overlaid on the one loaded, with its own colors
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    /// Data values mapped to 0 and 1 before the cuts
+    pub minmax: Range<f32>,
+    /// Contrast cuts (scale, offset) applied to the normalized values
+    pub cuts: [f32; 2],
+    pub stretch: Stretch,
+    pub colormap: Colormap,
+    /// Weight of the colors of the layer in the blending, between 0 and 1
+    pub opacity: f32,
+    pub alignment: Alignment,
+}
+
+impl Layer {
+    /// Layer of `layer` over `cube`, with the data range of its header if given
+    pub fn new(cube: &Cube, layer: &Cube, colormap: Colormap) -> Self {
+        let range = layer.value_range().unwrap_or(0.0..1.0);
+        Self {
+            minmax: layer.datamin.unwrap_or(range.start)..layer.datamax.unwrap_or(range.end),
+            cuts: [1.0, 0.0],
+            stretch: Stretch::Linear,
+            colormap,
+            opacity: 1.0,
+            alignment: Alignment::between(cube, layer),
+        }
+    }
+}
+
+/// std140 layout of the `Layers` uniform block of cube.frag
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LayersUniform {
+    // x: number of layers, y: blend
+    params: [f32; 4],
+    // cuts, minmax with the opacity, scale and offset of the alignment of each layer
+    layers: [[[f32; 4]; 4]; MAX_LAYERS],
+}
+
+impl LayersUniform {
+    pub(crate) fn new(layers: &[Layer], blend: Blend) -> Self {
+        let num_layers = layers.len().min(MAX_LAYERS);
+        let mut uniform = Self {
+            params: [num_layers as f32, blend.as_f32(), 0.0, 0.0],
+            layers: [[[0.0; 4]; 4]; MAX_LAYERS],
+        };
+        for (u, layer) in uniform.layers.iter_mut().zip(layers) {
+            let Alignment { scale, offset, .. } = layer.alignment;
+            *u = [
+                [
+                    layer.cuts[0],
+                    layer.cuts[1],
+                    layer.stretch.as_f32(),
+                    layer.colormap.as_f32(),
+                ],
+                [
+                    layer.minmax.start,
+                    layer.minmax.end,
+                    layer.opacity.clamp(0.0, 1.0),
+                    0.0,
+                ],
+                [scale[0], scale[1], scale[2], 0.0],
+                [offset[0], offset[1], offset[2], 0.0],
+            ];
+        }
+        uniform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wcs::{Wcs, WcsFrame};
+
+    fn axis(ctype: &str, cunit: &str, crval: f64, cdelt: f64) -> WcsAxis {
+        WcsAxis {
+            ctype: ctype.to_string(),
+            cunit: cunit.to_string(),
+            crval,
+            crpix: 1.0,
+            cdelt,
+        }
+    }
+
+    fn cube(dim: (u32, u32, u32), axes: Option<[WcsAxis; 3]>) -> Cube {
+        Cube {
+            data: vec![0.0; (dim.0 * dim.1 * dim.2) as usize],
+            dim,
+            datamin: None,
+            datamax: None,
+            bunit: None,
+            wcs: axes.map(|axes| Wcs {
+                axes,
+                pc: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+                frame: WcsFrame::default(),
+            }),
+            name: None,
+        }
+    }
+
+    // celestial axes around (150, 60) degrees and a velocity axis in m/s
+    fn sky() -> [WcsAxis; 3] {
+        [
+            axis("RA---SIN", "deg", 150.0, -0.01),
+            axis("DEC--SIN", "deg", 60.0, 0.01),
+            axis("VRAD", "m/s", 0.0, 1000.0),
+        ]
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn cubes_without_wcs_are_aligned_by_the_grid() {
+        let alignment = Alignment::between(&cube((10, 10, 4), None), &cube((5, 5, 2), Some(sky())));
+        assert_eq!(alignment, Alignment::grid((10, 10, 4), (5, 5, 2)));
+        assert_eq!(alignment.scale, [2.0; 3]);
+        assert_eq!(alignment.description(), "aligned by the voxel grid");
+    }
+
+    #[test]
+    fn same_wcs_is_the_identity() {
+        let alignment = Alignment::between(
+            &cube((10, 10, 4), Some(sky())),
+            &cube((10, 10, 4), Some(sky())),
+        );
+        assert_close(alignment.scale, [1.0; 3]);
+        assert_close(alignment.offset, [0.0; 3]);
+        assert_eq!(alignment.description(), "aligned by the WCS");
+    }
+
+    #[test]
+    fn world_coordinates_in_other_units() {
+        // one pixel to the east on the sky, pixels given in arcsec, and the channels
+        // from 5 km/s on
+        let layer_axes = [
+            axis("RA---SIN", "arcsec", (150.0 + 0.02) * 3600.0, -36.0),
+            axis("DEC--SIN", "deg", 60.0, 0.01),
+            axis("VRAD", "km/s", 5.0, 1.0),
+        ];
+        let alignment = Alignment::between(
+            &cube((10, 10, 10), Some(sky())),
+            &cube((10, 10, 5), Some(layer_axes)),
+        );
+        assert_eq!(alignment.world, [true; 3]);
+        assert_close(alignment.scale, [1.0, 1.0, 2.0]);
+        assert_close(alignment.offset, [0.1, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn axes_of_other_types_or_units_are_aligned_by_the_grid() {
+        let mut layer_axes = sky();
+        layer_axes[1].cunit = "m".to_string();
+        layer_axes[2] = axis("FREQ", "Hz", 1.4e9, 1e4);
+        let alignment = Alignment::between(
+            &cube((10, 10, 4), Some(sky())),
+            &cube((10, 5, 8), Some(layer_axes)),
+        );
+        assert_eq!(alignment.world, [true, false, false]);
+        assert_close(alignment.scale, [1.0, 2.0, 0.5]);
+        assert_eq!(
+            alignment.description(),
+            "aligned by the WCS along x, by the voxel grid otherwise"
+        );
+    }
+}
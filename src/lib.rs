@@ -1,6 +1,6 @@
 extern crate byte_slice_cast;
 
-use log::warn;
+use log::{info, warn};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use wgpu::Device;
@@ -11,7 +11,6 @@ extern crate console_error_panic_hook;
 use std::iter;
 use std::path::Path;
 
-use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalPosition;
 use winit::keyboard::KeyCode;
 use winit::keyboard::PhysicalKey;
@@ -21,18 +20,83 @@ use winit::{
     event_loop::EventLoop,
     window::{Window, WindowBuilder},
 };
+mod animation;
+mod camera;
+mod capture;
+mod catalog;
+mod channels;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
+mod clip;
+mod colormap;
+mod cube;
+mod export;
+mod finder;
+mod fits_writer;
+mod grid;
 mod gui;
+mod labels;
+mod layers;
+mod map;
 mod math;
+mod mesh;
+mod moments;
+mod overlay;
+mod pick;
+mod pv;
+mod refine;
+mod render_mode;
+mod renderer;
+mod slice;
+mod spectrum;
 mod texture;
 mod time;
 mod vertex;
-use fitsrs::card::Value;
-use fitsrs::HDU;
+mod wcs;
+#[cfg(target_arch = "wasm32")]
+mod web;
 
-use crate::math::Vec4;
+use crate::math::Vec3;
+pub use animation::{Animation, CameraPath, Keyframe, Turntable};
+use animation::{AnimationCommand, KEYFRAME_SPACING};
+use camera::CameraTransition;
+pub use camera::{AxisView, Camera};
+use capture::FrameReadback;
+pub use catalog::{Catalog, CatalogFormat, CatalogObject};
+pub use channels::{ChannelRange, ChannelSweep, SpectralRange};
+use clip::MAX_CLIP_PLANES;
+pub use clip::{ClipPlane, Clipping};
+pub use colormap::{Colormap, Stretch};
+pub use cube::Cube;
+use cube::{parse_fits_data_cube, RawCube};
+#[cfg(not(target_arch = "wasm32"))]
+use export::FrameSink;
+pub use export::{FrameExport, Screenshot};
+pub use finder::{FinderSettings, SourceFinder};
+pub use fits_writer::{
+    ColumnData, FitsColumn, FitsImage, FitsLabels, FitsTable, FitsWcs, Projection,
+};
+use grid::MinMaxGrid;
+pub use labels::{LabelDisplay, Labels};
+use layers::LAYER_COLORMAPS;
+pub use layers::{Alignment, Blend, Layer};
+use map::MapPanel;
+pub use mesh::{Mesh, MeshFormat, MeshUnits};
+pub use moments::{Moment, MomentMap, MomentSettings};
+use overlay::Overlay;
+pub use pick::{Pick, PickKind};
+use pv::MAX_PATH_VERTICES;
+pub use pv::{PvDiagram, PvPath};
+use refine::OffscreenTarget;
+use render_mode::MAX_ISO_LEVELS;
+pub use render_mode::{IsoLevel, Quality, RenderMode};
+pub use renderer::{RenderSettings, Renderer};
+use slice::{Panel, Slice};
+pub use slice::{SliceAxis, ViewLayout};
+pub use spectrum::{Aperture, Spectrum, SpectrumFormat};
 use texture::Texture;
 use time::Clock;
-use vertex::Vertex;
+pub use wcs::{Wcs, WcsAxis, WcsFrame};
 
 //use gui::EguiRenderer;
 
@@ -43,6 +107,11 @@ use memmap2::Mmap;
 use std::fs::File;
 use std::io::Cursor;
 
+// file the animation is rendered to from the viewer, when not given on the command line
+#[cfg(not(target_arch = "wasm32"))]
+const DEFAULT_EXPORT_PATH: &str = "animation.gif";
+const WINDOW_TITLE: &str = "Astronomical cube visualizer";
+
 const CUBES_PATH: &[&'static str] = &[
     "./cubes/NGC3198_cube.fits",
     "./cubes/NGC7331_cube.fits",
@@ -63,8 +132,7 @@ const MINMAX: &[Range<f32>] = &[
 
 struct State<'a> {
     surface: wgpu::Surface<'a>,
-    pub device: wgpu::Device,
-    pub queue: wgpu::Queue,
+    renderer: Renderer,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
 
@@ -75,21 +143,13 @@ struct State<'a> {
     // unsafe references to the window's resources.
     window: &'a Window,
 
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-
-    texture_bind_group_layout: wgpu::BindGroupLayout,
-    diffuse_bind_group: wgpu::BindGroup,
-
-    // uniforms
-    rot_mat_buf: wgpu::Buffer,
-    window_size_buf: wgpu::Buffer,
-    time_buf: wgpu::Buffer,
-    cam_origin_buf: wgpu::Buffer,
-    cuts_buf: wgpu::Buffer,
-    perspective_buf: wgpu::Buffer,
-    minmax_buf: wgpu::Buffer,
+    slice_pipeline: wgpu::RenderPipeline,
+    map_bind_group_layout: wgpu::BindGroupLayout,
+    map_pipeline: wgpu::RenderPipeline,
+    // the volume is drawn offscreen, at a lower resolution while the view changes
+    // and accumulated over jittered frames at rest
+    low_res_target: OffscreenTarget,
+    history: OffscreenTarget,
 
     clock: Clock,
 
@@ -97,73 +157,79 @@ struct State<'a> {
 
     scale: f32,
     offset: f32,
-    //egui: EguiRenderer,
-}
 
-struct Cube<'a> {
-    data: &'a [u8],
-    dim: (u32, u32, u32),
-    datamin: Option<f32>,
-    datamax: Option<f32>,
-}
+    minmax: Range<f32>,
+    render_mode: RenderMode,
+    iso_levels: Vec<IsoLevel>,
+    // values below the lower cut give nothing in the average and velocity coded modes
+    hide_below_cut: bool,
+    quality: Quality,
+    // samples below are left out of the moment maps, in the data unit
+    moment_threshold: Option<f32>,
 
-fn parse_fits_data_cube<'a, R>(fits: &'a mut Fits<Cursor<R>>) -> Result<Cube<'a>, &'static str>
-where
-    R: AsRef<[u8]> + std::fmt::Debug + 'a,
-{
-    if let Some(Ok(hdu)) = fits.next() {
-        match hdu {
-            HDU::Primary(hdu) => {
-                let header = hdu.get_header();
-
-                if let (
-                    Some(Value::Integer { value: w, .. }),
-                    Some(Value::Integer { value: h, .. }),
-                    Some(Value::Integer { value: d, .. }),
-                ) = (
-                    header.get("NAXIS1"),
-                    header.get("NAXIS2"),
-                    header.get("NAXIS3"),
-                ) {
-                    let image = fits.get_data(&hdu);
-
-                    let d1 = *w as u32;
-                    let d2 = *h as u32;
-                    let mut d3 = *d as u32;
-
-                    if d3 == 1 {
-                        // parse NAXIS4 instead it there is
-                        if let Some(Value::Integer { value, .. }) = header.get("NAXIS4") {
-                            d3 = *value as u32;
-                        }
-                    }
+    // CPU copy of the cube currently visualized
+    cube: Option<Cube>,
+    // cubes overlaid on it, with the way their colors are combined with its own
+    layers: Vec<Layer>,
+    blend: Blend,
+    // objects labeled in it, drawn over the volume
+    labels: Option<Labels>,
+    label_display: LabelDisplay,
+    hide_unlabeled: bool,
+    // objects found by the source finder last, listed in the lower right corner of
+    // the window on native and given to the callback of the page on the web
+    catalog: Option<Catalog>,
+    catalog_panel: Option<OffscreenTarget>,
+    // source finding in progress, advanced a little each frame
+    finder: Option<SourceFinder>,
+    #[cfg(target_arch = "wasm32")]
+    catalog_callback: Option<js_sys::Function>,
 
-                    let datamin = if let Some(Value::Float { value, .. }) = header.get("DATAMIN") {
-                        Some(*value as f32)
-                    } else {
-                        None
-                    };
-                    let datamax = if let Some(Value::Float { value, .. }) = header.get("DATAMAX") {
-                        Some(*value as f32)
-                    } else {
-                        None
-                    };
-
-                    Ok(Cube {
-                        data: image.raw_bytes(),
-                        dim: (d1, d2, d3),
-                        datamin,
-                        datamax,
-                    })
-                } else {
-                    Err("FITS image extension not found")
-                }
-            }
-            _ => Err("FITS image extension not found"),
-        }
-    } else {
-        Err("Is not a FITS file")
-    }
+    layout: ViewLayout,
+    // the three orthogonal slices, indexed by `SliceAxis::index`
+    slices: Vec<Slice>,
+    // moment map computed last, shown with the map layout
+    moment: Option<(MomentMap, MapPanel)>,
+    // path drawn over the moment map and the position-velocity diagram computed
+    // along it last, shown with the pv layout
+    pv_path: PvPath,
+    pv: Option<(PvDiagram, MapPanel)>,
+    // voxel under the cursor, drawn as a label over the volume on native
+    // and given to the callback of the page on the web
+    pick: Option<Pick>,
+    pick_label: Option<OffscreenTarget>,
+    #[cfg(target_arch = "wasm32")]
+    pick_callback: Option<js_sys::Function>,
+    // spectrum extracted last, drawn as a plot in the lower left corner of the window
+    spectrum: Option<Spectrum>,
+    spectrum_plot: Option<OffscreenTarget>,
+    // spaxels summed into the spectra extracted with a click
+    aperture: Aperture,
+    // hues of the channels in the velocity coded mode, drawn in the upper right
+    // corner of the volume
+    velocity_legend: Option<OffscreenTarget>,
+
+    clipping: Clipping,
+    camera: Camera,
+    // animated snapping of the camera to an axis
+    camera_transition: Option<CameraTransition>,
+    // axes of the cube along the view, empty if it is not aligned with them
+    view_label: String,
+    animation: Animation,
+    // time of the last update, to move the animation
+    last_update: f32,
+
+    // channels rendered, all of them if `None`
+    channels: Option<ChannelRange>,
+    // sweep of a channel window with the time it started at
+    sweep: Option<(ChannelSweep, f32)>,
+
+    // time of the last change of the view, and whether the volume is drawn at low resolution
+    last_interaction: f32,
+    interacting: bool,
+    // number of frames averaged in the history
+    accumulated: u32,
+    //egui: EguiRenderer,
 }
 
 use std::fmt::Debug;
@@ -171,23 +237,21 @@ fn read_fits<R: AsRef<[u8]> + Debug>(
     reader: Cursor<R>,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-) -> Result<(Texture, Option<f32>, Option<f32>), &'static str> {
+) -> Result<(Texture, Cube), &'static str> {
     let mut fits = Fits::from_reader(reader);
-    let Cube {
+    let raw_cube = parse_fits_data_cube(&mut fits)?;
+    let RawCube {
         data: raw_bytes,
         dim,
-        datamin,
-        datamax,
-    } = parse_fits_data_cube(&mut fits)?;
+        ..
+    } = raw_cube;
 
     Ok((
         Texture::from_raw_bytes::<f32>(&device, &queue, Some(raw_bytes), dim, 4, "cube")?,
-        datamin,
-        datamax,
+        Cube::from_raw(&raw_cube)?,
     ))
 }
 
-use crate::math::Mat4;
 impl<'a> State<'a> {
     async fn new(window: &'a Window) -> Self {
         let size = window.inner_size();
@@ -255,353 +319,28 @@ impl<'a> State<'a> {
             desired_maximum_frame_latency: 2,
         };
 
-        let cube =
-            Texture::from_raw_bytes::<f32>(&device, &queue, None, (1, 1, 1), 4, "cube").unwrap();
-        //let cube = load_fits_cube_file(&CUBES_PATH[0], &device, &queue);
-
-        // Uniform buffer
-        let rot_mat_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("rot matrix uniform"),
-            size: 64,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let time_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("time in secs since starting"),
-            size: 16,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let perspective_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("perspective"),
-            size: 16,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let minmax_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("minmax"),
-            size: 16,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let cam_origin_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Cam origin"),
-            size: 16,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let cuts_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Cuts"),
-            size: 16,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let window_size_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("window size uniform"),
-            size: 16,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D3,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        count: None,
-                    },
-                    // rot matrix uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                std::mem::size_of::<Mat4<f32>>() as _,
-                            ),
-                        },
-                        count: None,
-                    },
-                    // window size uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
-                            ),
-                        },
-                        count: None,
-                    },
-                    // time uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
-                            ),
-                        },
-                        count: None,
-                    },
-                    // cam origin uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 5,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
-                            ),
-                        },
-                        count: None,
-                    },
-                    // cuts uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 6,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
-                            ),
-                        },
-                        count: None,
-                    },
-                    // perspective uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 7,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
-                            ),
-                        },
-                        count: None,
-                    },
-                    // minmax uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 8,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress,
-                            ),
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&cube.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&cube.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &rot_mat_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(
-                            std::mem::size_of::<Mat4<f32>>() as wgpu::BufferAddress
-                        ),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &window_size_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &time_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &cam_origin_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &cuts_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &perspective_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 8,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &minmax_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
-
-        // uniform buffer
-        let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("cube vert shader"),
-            source: wgpu::ShaderSource::Glsl {
-                #[cfg(not(target_arch = "wasm32"))]
-                shader: std::str::from_utf8(&std::fs::read("src/shaders/cube.vert").unwrap())
-                    .unwrap()
-                    .into(),
-                #[cfg(target_arch = "wasm32")]
-                shader: include_str!("shaders/cube.vert").into(),
-                stage: wgpu::naga::ShaderStage::Vertex,
-                defines: Default::default(),
-            },
-        });
-        let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("cube frag shader"),
-            source: wgpu::ShaderSource::Glsl {
-                #[cfg(not(target_arch = "wasm32"))]
-                shader: std::str::from_utf8(&std::fs::read("src/shaders/cube.frag").unwrap())
-                    .unwrap()
-                    .into(),
-                #[cfg(target_arch = "wasm32")]
-                shader: include_str!("shaders/cube.frag").into(),
-                stage: wgpu::naga::ShaderStage::Fragment,
-                defines: Default::default(),
-            },
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vs_shader,
-                entry_point: Some("main"),
-                compilation_options: Default::default(),
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &fs_shader,
-                entry_point: Some("main"),
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None, // 5.
-            cache: None,     // 6.
-        });
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&[
-                Vertex { ndc: [-1.0, -1.0] },
-                Vertex { ndc: [1.0, -1.0] },
-                Vertex { ndc: [1.0, 1.0] },
-                Vertex { ndc: [-1.0, 1.0] },
-            ]),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&[0, 1, 2, 0, 2, 3]),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        //let num_indices = indices.len() as u32;
-
-        // set the initial cut values
-        queue.write_buffer(
-            &cuts_buf,
-            0,
-            bytemuck::bytes_of(&[1.0 as f32, 0.0, 0.0, 0.0]),
-        );
+        let renderer = Renderer::new(&adapter, device, queue, config.format);
+        let device = &renderer.device;
 
-        queue.write_buffer(
-            &minmax_buf,
-            0,
-            bytemuck::bytes_of(&[0.0_f32, 1.0, 0.0, 0.0]),
+        let slice_bind_group_layout = Slice::bind_group_layout(device);
+        let slice_pipeline = slice::create_pipeline(
+            device,
+            &renderer.bind_group_layout,
+            &slice_bind_group_layout,
+            config.format,
         );
+        let slices = SliceAxis::ALL
+            .iter()
+            .map(|axis| Slice::new(device, &slice_bind_group_layout, *axis))
+            .collect();
+        let map_bind_group_layout = MapPanel::bind_group_layout(device);
+        let map_pipeline = map::create_pipeline(device, &map_bind_group_layout, config.format);
+        let low_res_target = renderer.blit.create_target(device, (1, 1));
+        let history = renderer.blit.create_target(device, (1, 1));
 
+        let render_mode = RenderMode::Average;
+        let iso_levels = render_mode::spread_iso_levels(1, 0.0, 1.0);
+        let clipping = Clipping::default();
         let clock = Clock::now();
 
         /*let mut egui = EguiRenderer::new(
@@ -614,34 +353,76 @@ impl<'a> State<'a> {
 
         Self {
             surface,
-            device,
-            queue,
+            renderer,
             config,
             size,
             window,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
+            slice_pipeline,
+            map_bind_group_layout,
+            map_pipeline,
+            low_res_target,
+            history,
             is_surface_configured: false,
 
-            diffuse_bind_group,
-            texture_bind_group_layout,
-
-            // uniforms
-            window_size_buf,
-            rot_mat_buf,
-            time_buf,
-            cam_origin_buf,
-            cuts_buf,
-            minmax_buf,
-            perspective_buf,
-
             clock,
             //egui,
             i: 0,
 
             scale: 1.0,
             offset: 0.0,
+
+            minmax: 0.0..1.0,
+            render_mode,
+            iso_levels,
+            hide_below_cut: false,
+            quality: Quality::default(),
+            moment_threshold: None,
+
+            cube: None,
+            layers: vec![],
+            blend: Blend::default(),
+            labels: None,
+            label_display: LabelDisplay::default(),
+            hide_unlabeled: false,
+            catalog: None,
+            catalog_panel: None,
+            finder: None,
+            #[cfg(target_arch = "wasm32")]
+            catalog_callback: None,
+
+            layout: ViewLayout::Volume,
+            slices,
+            moment: None,
+            pv_path: PvPath {
+                vertices: vec![],
+                width: 1.0,
+            },
+            pv: None,
+            pick: None,
+            pick_label: None,
+            #[cfg(target_arch = "wasm32")]
+            pick_callback: None,
+            spectrum: None,
+            spectrum_plot: None,
+            aperture: Aperture::Spaxel,
+            velocity_legend: None,
+
+            clipping,
+            camera: Camera::default(),
+            camera_transition: None,
+            view_label: String::new(),
+            animation: Animation {
+                looping: true,
+                ..Default::default()
+            },
+            last_update: 0.0,
+
+            channels: None,
+            sweep: None,
+
+            last_interaction: 0.0,
+            interacting: false,
+            accumulated: 0,
         }
     }
 
@@ -660,14 +441,12 @@ impl<'a> State<'a> {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            self.surface.configure(&self.renderer.device, &self.config);
             self.is_surface_configured = true;
+            self.update_slices();
+            self.update_spectrum_plot();
         }
-        self.queue.write_buffer(
-            &self.window_size_buf,
-            0,
-            bytemuck::bytes_of(&[self.size.width as f32, self.size.height as f32, 0.0, 0.0]),
-        );
+        self.update_camera_uniform();
     }
 
     #[allow(unused_variables)]
@@ -677,226 +456,2570 @@ impl<'a> State<'a> {
 
     fn update(&mut self) {
         let elapsed = self.clock.elapsed_as_secs();
+        let dt = elapsed - self.last_update;
+        self.last_update = elapsed;
 
-        let rot = Mat4::from_angle_y(cgmath::Rad(elapsed));
-        let rot: &[[f32; 4]; 4] = rot.as_ref();
+        // the index of the accumulated frame drives the jitter of the rays
+        self.renderer.write_time(elapsed, self.accumulated);
 
-        self.queue
-            .write_buffer(&self.rot_mat_buf, 0, bytemuck::bytes_of(rot));
-        self.queue.write_buffer(
-            &self.time_buf,
-            0,
-            bytemuck::bytes_of(&[elapsed, 0.0, 0.0, 0.0]),
-        );
+        if let Some(transition) = &self.camera_transition {
+            let (orientation, done) = transition.at(elapsed);
+            self.camera.orientation = orientation;
+            if done {
+                self.camera_transition = None;
+            }
+            self.update_camera_uniform();
+        }
+
+        if self.animation.playing {
+            self.animate(dt);
+        }
+
+        self.step_finder();
+
+        if let Some((sweep, start)) = self.sweep {
+            let range = sweep.at(elapsed - start, self.cube_dim().2);
+            if self.channels != Some(range) {
+                self.channels = Some(range);
+                self.update_channels_uniform();
+            }
+        }
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let size = self.window.inner_size();
-        if size.width == 0 || size.height == 0 {
-            return Ok(());
+    fn set_minmax(&mut self, minmax: Range<f32>) {
+        self.reset_accumulation();
+        self.renderer.write_minmax(&minmax);
+        self.minmax = minmax;
+    }
+
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_mode = mode;
+        self.update_render_uniform();
+        self.update_velocity_legend();
+    }
+
+    /// Name of the spectral axis, with its unit, and its coordinates at the first and
+    /// last channels rendered, the channel indices without WCS
+    fn velocity_axis(&self) -> (String, [f64; 2]) {
+        let channels = self.channel_range();
+        let ends = [channels.first as f64, channels.last as f64];
+        match self.cube.as_ref().and_then(|cube| cube.wcs()) {
+            Some(wcs) => {
+                let axis = &wcs.axes[2];
+                let name = match axis.ctype.split('-').next().unwrap_or_default() {
+                    "" => "spectral",
+                    name => name,
+                };
+                let label = format!("{} {}", name, axis.cunit);
+                (label.trim().to_string(), ends.map(|z| axis.pix2world(z)))
+            }
+            None => ("channel".to_string(), ends),
         }
+    }
 
-        if !self.is_surface_configured {
-            return Ok(());
+    // draw the legend of the velocity coded mode for the channels rendered
+    fn update_velocity_legend(&mut self) {
+        self.velocity_legend = (self.render_mode == RenderMode::Velocity).then(|| {
+            let scale = (2.0 * self.window.scale_factor()).round().max(1.0) as i32;
+            let (label, ends) = self.velocity_axis();
+            // the colors are sRGB encoded in the image, decoded when it is sampled
+            let (rgba, size) = overlay::velocity_legend(&label, ends, scale, true);
+            self.renderer.blit.create_image(
+                &self.renderer.device,
+                &self.renderer.queue,
+                &rgba,
+                size,
+            )
+        });
+    }
+
+    fn set_iso_levels(&mut self, mut levels: Vec<IsoLevel>) {
+        if levels.len() > MAX_ISO_LEVELS {
+            warn!("Only the first {} iso levels are rendered", MAX_ISO_LEVELS);
+            levels.truncate(MAX_ISO_LEVELS);
         }
+        self.iso_levels = levels;
+        self.update_render_uniform();
+    }
 
-        if let Ok(frame) = self.surface.get_current_texture() {
-            let view = frame.texture.create_view(&wgpu::TextureViewDescriptor {
-                format: Some(self.config.format.add_srgb_suffix()),
-                ..Default::default()
-            });
+    fn set_quality(&mut self, quality: Quality) {
+        self.quality = quality;
+        self.update_render_uniform();
+    }
 
-            let mut encoder = self
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Render Encoder"),
-                });
+    /// Average the values after the transfer function in the average and velocity
+    /// coded modes, the ones below the lower cut giving nothing, letting the ray marcher
+    /// skip the parts of the cube below it
+    fn set_hide_below_cut(&mut self, hide: bool) {
+        self.hide_below_cut = hide;
+        self.update_render_uniform();
+    }
 
-            {
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("Render Pass"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.01,
-                                g: 0.01,
-                                b: 0.01,
-                                a: 1.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                    occlusion_query_set: None,
-                    timestamp_writes: None,
-                });
+    // shift all the iso levels by a fraction of the data range
+    fn shift_iso_levels(&mut self, frac: f32) {
+        let delta = frac * (self.minmax.end - self.minmax.start);
+        for level in self.iso_levels.iter_mut() {
+            level.value += delta;
+        }
+        self.update_render_uniform();
+    }
 
-                render_pass.set_pipeline(&self.render_pipeline);
-                render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-                render_pass
-                    .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                render_pass.draw_indexed(0..6, 0, 0..1);
-            }
+    fn cube_dim(&self) -> (u32, u32, u32) {
+        self.cube.as_ref().map(|cube| cube.dim).unwrap_or((1, 1, 1))
+    }
 
-            self.queue.submit(iter::once(encoder.finish()));
-            frame.present();
+    fn set_layout(&mut self, layout: ViewLayout) {
+        self.layout = layout;
+        self.update_slices();
+        self.update_camera_uniform();
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    fn set_slice(&mut self, axis: SliceAxis, index: u32) {
+        let dim = self.cube_dim();
+        let slice = &mut self.slices[axis.index()];
+        slice.index = 0;
+        slice.step(index as i32, dim);
+        self.update_slices();
+    }
+
+    fn step_slice(&mut self, axis: SliceAxis, delta: i32) {
+        let dim = self.cube_dim();
+        self.slices[axis.index()].step(delta, dim);
+        self.update_slices();
+    }
+
+    /// Slice axis targeted by the keyboard, i.e. the slice displayed or the
+    /// one under the cursor in the four-panel layout
+    fn slice_axis_at(&self, cursor_pos: PhysicalPosition<f64>) -> Option<SliceAxis> {
+        match self.layout.panel_at(cursor_pos, self.size) {
+            Some((Panel::Slice(axis), _)) => Some(axis),
+            _ => match self.layout {
+                ViewLayout::Slice(axis) => Some(axis),
+                _ => None,
+            },
+        }
+    }
+
+    // write the uniforms of the slices and of the map visible with the current layout
+    fn update_slices(&self) {
+        let dim = self.cube_dim();
+        for (panel, viewport) in self.layout.panels(self.size) {
+            match panel {
+                Panel::Slice(axis) => {
+                    self.slices[axis.index()].update(&self.renderer.queue, dim, viewport)
+                }
+                Panel::Map => {
+                    if let Some((_, map)) = &self.moment {
+                        let path = (!self.pv_path.vertices.is_empty()).then_some(&self.pv_path);
+                        map.update(&self.renderer.queue, viewport, path);
+                    }
+                }
+                Panel::Pv => {
+                    if let Some((_, diagram)) = &self.pv {
+                        diagram.update(&self.renderer.queue, viewport, None);
+                    }
+                }
+                Panel::Volume => (),
+            }
         }
+    }
 
+    /// Compute a moment map of the cube and show it with the map layout
+    fn show_moment(&mut self, settings: &MomentSettings) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        let moment = self.renderer.moment_map(cube, settings)?;
+        // the next maps stepped through with "G" keep the threshold
+        self.moment_threshold = settings.threshold;
+        let range = moment.value_range().unwrap_or(0.0..1.0);
+        let panel = MapPanel::new(
+            &self.renderer.device,
+            &self.renderer.queue,
+            &self.map_bind_group_layout,
+            moment.size,
+            &moment.data,
+            range,
+        )?;
+        self.moment = Some((moment, panel));
+        self.set_layout(ViewLayout::Map);
         Ok(())
     }
 
-    fn visualize_cube<R: AsRef<[u8]> + std::fmt::Debug>(
+    /// Add a vertex to the path of the position-velocity diagram at the pixel of
+    /// the moment map under the cursor
+    fn add_pv_vertex(&mut self, cursor_pos: PhysicalPosition<f64>) {
+        if !matches!(
+            self.layout.panel_at(cursor_pos, self.size),
+            Some((Panel::Map, _))
+        ) {
+            return;
+        }
+        if let Some(position) = self.spaxel_at(cursor_pos) {
+            if self.pv_path.vertices.len() < MAX_PATH_VERTICES {
+                self.pv_path.vertices.push(position);
+                self.update_slices();
+            } else {
+                warn!("The path has {} vertices at most", MAX_PATH_VERTICES);
+            }
+        }
+    }
+
+    /// Replace the path of the position-velocity diagram, keeping the slit width
+    /// when none is given
+    fn set_pv_path(
         &mut self,
-        reader: Cursor<R>,
-        // override the minmax value
-        min: Option<f32>,
-        max: Option<f32>,
+        vertices: Vec<[f64; 2]>,
+        width: Option<f32>,
     ) -> Result<(), &'static str> {
-        let (new_cube, datamin, datamax) = read_fits(reader, &self.device, &self.queue)?;
+        if vertices.len() > MAX_PATH_VERTICES {
+            return Err("The path has too many vertices");
+        }
+        self.pv_path.vertices = vertices;
+        if let Some(width) = width {
+            self.pv_path.width = width;
+        }
+        self.update_slices();
+        Ok(())
+    }
 
-        // set the new datamin/datamax if there is some
-        let datamin = min.or(datamin).unwrap_or(0.0);
-        let datamax = max.or(datamax).unwrap_or(1.0);
-        self.queue.write_buffer(
-            &self.minmax_buf,
-            0,
-            bytemuck::bytes_of(&[datamin, datamax, 0.0_f32, 0.0_f32]),
-        );
+    /// Compute the position-velocity diagram along the path and show it with the pv layout
+    fn show_pv(&mut self) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        let pv = self.renderer.pv_diagram(cube, &self.pv_path)?;
+        let range = pv.value_range().unwrap_or(0.0..1.0);
+        let panel = MapPanel::new(
+            &self.renderer.device,
+            &self.renderer.queue,
+            &self.map_bind_group_layout,
+            pv.size,
+            &pv.data,
+            range,
+        )?;
+        self.pv = Some((pv, panel));
+        self.set_layout(ViewLayout::Pv);
+        Ok(())
+    }
 
-        // reset the cutoff values
-        self.queue.write_buffer(
-            &self.cuts_buf,
-            0,
-            bytemuck::bytes_of(&[1.0 as f32, 0.0, 0.0, 0.0]),
-        );
+    /// Settings of the next moment map shown by the viewer, computed over the
+    /// channels rendered and above the threshold of the moment maps
+    fn next_moment(&self) -> Option<MomentSettings> {
+        let moment = match (&self.moment, self.layout) {
+            (Some((map, _)), ViewLayout::Map) => map.moment.next()?,
+            _ => Moment::Integrated,
+        };
+        Some(MomentSettings {
+            moment,
+            channels: self.channels,
+            threshold: self.moment_threshold,
+        })
+    }
 
-        self.scale = 1.0;
-        self.offset = 0.0;
+    /// Mark the view as changing, drawing the volume at a low resolution until it rests
+    fn interact(&mut self) {
+        self.last_interaction = self.clock.elapsed_as_secs();
+        self.reset_accumulation();
+    }
 
-        self.diffuse_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&new_cube.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&new_cube.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &self.rot_mat_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(
-                            std::mem::size_of::<Mat4<f32>>() as wgpu::BufferAddress
-                        ),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &self.window_size_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &self.time_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &self.cam_origin_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &self.cuts_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &self.perspective_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 8,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &self.minmax_buf,
-                        offset: 0,
-                        size: wgpu::BufferSize::new(16),
-                    }),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
+    /// Restart the averaging of the frames, the volume having changed
+    fn reset_accumulation(&mut self) {
+        self.accumulated = 0;
+    }
 
-        Ok(())
+    fn set_camera(&mut self, camera: Camera) {
+        // a move of the user stops the snapping to an axis
+        self.camera_transition = None;
+        self.camera = camera;
+        self.update_camera_uniform();
     }
-}
 
-use std::ops::Range;
-#[derive(Debug, Default)]
-struct Params {
-    perspective: Option<bool>,
-    minmax: Option<Range<f32>>,
-}
+    fn set_cuts(&mut self, scale: f32, offset: f32) {
+        self.reset_accumulation();
+        self.scale = scale;
+        self.offset = offset;
+        self.renderer.write_cuts(scale, offset);
+    }
 
-#[cfg(target_arch = "wasm32")]
-use lazy_static::lazy_static;
-#[cfg(target_arch = "wasm32")]
-lazy_static! {
-    static ref CHANNEL_PARAMS: (
-        async_channel::Sender<Params>,
-        async_channel::Receiver<Params>,
-    ) = async_channel::unbounded::<Params>();
-}
+    /// Current view, as a keyframe at `time`
+    fn keyframe(&self, time: f32) -> Keyframe {
+        Keyframe {
+            time,
+            camera: self.camera,
+            cuts: [self.scale, self.offset],
+            channels: self.channel_range(),
+        }
+    }
 
-static mut PARAMS: Params = Params {
-    perspective: None,
-    minmax: None,
-};
+    fn animation_command(&mut self, command: AnimationCommand) {
+        let animation = &mut self.animation;
+        match command {
+            AnimationCommand::Play => {
+                // spin the cube if there is nothing else to play
+                if animation.turntable.is_none() && animation.path.is_empty() {
+                    animation.turntable = Some(Turntable::default());
+                }
+                if !animation.path.is_empty() && animation.time >= animation.path.duration() {
+                    animation.time = 0.0;
+                }
+                animation.playing = true;
+            }
+            AnimationCommand::Pause => animation.playing = false,
+            AnimationCommand::Rewind => animation.time = 0.0,
+            AnimationCommand::Turntable(turntable) => {
+                animation.turntable = turntable;
+                animation.playing = turntable.is_some() || !animation.path.is_empty();
+            }
+            AnimationCommand::Loop(looping) => animation.looping = looping,
+            AnimationCommand::AddKeyframe(time) => {
+                let time = time.unwrap_or(if animation.path.is_empty() {
+                    0.0
+                } else {
+                    animation.path.duration() + KEYFRAME_SPACING
+                });
+                let keyframe = self.keyframe(time);
+                self.animation.path.push(keyframe);
+            }
+            AnimationCommand::ClearKeyframes => {
+                animation.path = CameraPath::default();
+                animation.time = 0.0;
+            }
+        }
+    }
 
+    /// Move the animation by `dt` seconds
+    fn animate(&mut self, dt: f32) {
+        let mut camera = self.camera;
+        let keyframe = self.animation.advance(dt);
+        if let Some(keyframe) = keyframe {
+            camera = keyframe.camera;
+            if [self.scale, self.offset] != keyframe.cuts {
+                self.set_cuts(keyframe.cuts[0], keyframe.cuts[1]);
+            }
+            let channels = keyframe.channels.clamp(self.cube_dim().2);
+            if self.channel_range() != channels {
+                self.set_channel_range(Some(channels));
+            }
+        }
+
+        if let Some(turntable) = self.animation.turntable {
+            // on a path the spin is added to its views, else it goes on from the current one
+            let t = if keyframe.is_some() {
+                self.animation.time
+            } else {
+                dt
+            };
+            turntable.spin(&mut camera, t);
+        }
+
+        self.camera = camera;
+        self.update_camera_uniform();
+    }
+
+    /// Rotate the camera smoothly to look along an axis of the cube
+    fn snap_camera(&mut self, view: AxisView) {
+        self.camera_transition = Some(CameraTransition::new(
+            self.camera.orientation,
+            view.orientation(),
+            self.clock.elapsed_as_secs(),
+        ));
+    }
+
+    /// Names of the axes of the cube, given by the CTYPEn keywords
+    fn axis_names(&self) -> [String; 3] {
+        let mut names = [String::from("x"), String::from("y"), String::from("z")];
+        if let Some(wcs) = self.cube.as_ref().and_then(|cube| cube.wcs()) {
+            for (name, axis) in names.iter_mut().zip(wcs.axes.iter()) {
+                let ctype = axis.ctype.trim();
+                if !ctype.is_empty() {
+                    *name = ctype.to_string();
+                }
+            }
+        }
+        names
+    }
+
+    /// Label the view with the axes of the cube it is aligned with
+    fn update_view_label(&mut self) {
+        let label = match self.camera.aligned_axes() {
+            Some([right, up, back]) => {
+                let names = self.axis_names();
+                let name = |(axis, sign): (usize, f32)| {
+                    if sign < 0.0 {
+                        format!("-{}", names[axis])
+                    } else {
+                        names[axis].clone()
+                    }
+                };
+                format!(
+                    "{} right, {} up, {} toward the viewer",
+                    name(right),
+                    name(up),
+                    name(back)
+                )
+            }
+            None => String::new(),
+        };
+        if label == self.view_label {
+            return;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if label.is_empty() {
+            self.window.set_title(WINDOW_TITLE);
+        } else {
+            self.window
+                .set_title(&format!("{} - {}", WINDOW_TITLE, label));
+        }
+        #[cfg(target_arch = "wasm32")]
+        if let Some(element) = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id("view-label"))
+        {
+            element.set_text_content(Some(&label));
+        }
+
+        self.view_label = label;
+    }
+
+    /// Width / height of the volume panel, the one of the window if it is hidden
+    fn volume_aspect(&self) -> f32 {
+        let [_, _, w, h] = self.volume_viewport().unwrap_or([
+            0.0,
+            0.0,
+            self.size.width as f32,
+            self.size.height as f32,
+        ]);
+        w.max(1.0) / h.max(1.0)
+    }
+
+    /// Viewport of the volume panel, if it is shown
+    fn volume_viewport(&self) -> Option<[f32; 4]> {
+        self.layout
+            .panels(self.size)
+            .into_iter()
+            .find(|(panel, _)| *panel == Panel::Volume)
+            .map(|(_, viewport)| viewport)
+    }
+
+    fn update_camera_uniform(&mut self) {
+        self.interact();
+        self.renderer
+            .write_camera(&self.camera, self.volume_aspect());
+        self.update_view_label();
+    }
+
+    /// Ray going through the cursor in the volume panel, in texture coordinates
+    fn volume_ray(&self, cursor_pos: PhysicalPosition<f64>) -> Option<(Vec3<f32>, Vec3<f32>)> {
+        match self.layout.panel_at(cursor_pos, self.size) {
+            Some((Panel::Volume, [x, y, w, h])) => {
+                let ndc = [
+                    2.0 * (cursor_pos.x as f32 - x) / w - 1.0,
+                    1.0 - 2.0 * (cursor_pos.y as f32 - y) / h,
+                ];
+                // from world to texture coordinates
+                let (origin, dir) = self.camera.ray(ndc, w / h);
+                Some((origin + Vec3::new(0.5, 0.5, 0.5), dir))
+            }
+            _ => None,
+        }
+    }
+
+    /// Pick the voxel under the cursor, none once it has left the volume
+    fn update_pick(&mut self, cursor_pos: Option<PhysicalPosition<f64>>) {
+        let (kind, iso_values) = match self.render_mode {
+            RenderMode::Isosurface => (
+                PickKind::FirstHit,
+                self.iso_levels.iter().map(|level| level.value).collect(),
+            ),
+            _ => (PickKind::Maximum, vec![]),
+        };
+        let labels = self
+            .labels
+            .as_ref()
+            .map(|labels| (labels, self.hide_unlabeled));
+        let pick = cursor_pos
+            .and_then(|cursor_pos| self.volume_ray(cursor_pos))
+            .zip(self.cube.as_ref())
+            .and_then(|(ray, cube)| {
+                pick::pick(
+                    cube,
+                    labels,
+                    &self.clipping,
+                    self.channel_range(),
+                    kind,
+                    &iso_values,
+                    ray,
+                )
+            });
+        if pick == self.pick {
+            return;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.pick_label = pick.as_ref().zip(self.cube.as_ref()).map(|(pick, cube)| {
+                let scale = (2.0 * self.window.scale_factor()).round().max(1.0) as i32;
+                let (rgba, size) = overlay::text_box(&pick.readout(cube), scale);
+                self.renderer.blit.create_image(
+                    &self.renderer.device,
+                    &self.renderer.queue,
+                    &rgba,
+                    size,
+                )
+            });
+        }
+        #[cfg(target_arch = "wasm32")]
+        if let Some(callback) = &self.pick_callback {
+            let _ = callback.call1(&JsValue::NULL, &web::pick_to_js(pick.as_ref()));
+        }
+
+        self.pick = pick;
+    }
+
+    /// Position in the spatial plane of the cube under the cursor, in 0-based pixels:
+    /// the voxel picked in the volume or the pixel of the map
+    fn spaxel_at(&self, cursor_pos: PhysicalPosition<f64>) -> Option<[f64; 2]> {
+        match self.layout.panel_at(cursor_pos, self.size)? {
+            (Panel::Volume, _) => self
+                .pick
+                .as_ref()
+                .map(|pick| [pick.voxel[0] as f64, pick.voxel[1] as f64]),
+            (Panel::Map, viewport @ [x, y, w, h]) => {
+                let (map, _) = self.moment.as_ref()?;
+                // texture coordinates of the map, as in map.vert
+                let (sx, sy) = slice::letterbox(map.size, viewport);
+                let u = (2.0 * (cursor_pos.x as f32 - x) / w - 1.0) / sx * 0.5 + 0.5;
+                let v = (1.0 - 2.0 * (cursor_pos.y as f32 - y) / h) / sy * 0.5 + 0.5;
+                if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                    return None;
+                }
+                Some([
+                    (u * map.size.0 as f32).floor() as f64,
+                    (v * map.size.1 as f32).floor() as f64,
+                ])
+            }
+            _ => None,
+        }
+    }
+
+    /// Extract the spectrum of the aperture centered on `position`, given in 0-based
+    /// pixels or in world coordinates, and plot it
+    fn extract_spectrum(&mut self, position: [f64; 2], world: bool) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        let spectrum = if world {
+            Spectrum::extract_world(cube, position, self.aperture)?
+        } else {
+            Spectrum::extract(cube, position, self.aperture)?
+        };
+        self.spectrum = Some(spectrum);
+        self.update_spectrum_plot();
+        Ok(())
+    }
+
+    /// Extract the spectrum under the cursor, hiding the plot when clicking outside of the cube
+    fn click(&mut self, cursor_pos: PhysicalPosition<f64>) {
+        match self.spaxel_at(cursor_pos) {
+            Some(position) => {
+                if let Err(error) = self.extract_spectrum(position, false) {
+                    warn!("{}", error);
+                }
+            }
+            None => {
+                self.spectrum = None;
+                self.update_spectrum_plot();
+            }
+        }
+    }
+
+    // draw the plot of the spectrum at a size relative to the one of the window
+    fn update_spectrum_plot(&mut self) {
+        self.spectrum_plot = self.spectrum.as_ref().map(|spectrum| {
+            let scale = (2.0 * self.window.scale_factor()).round().max(1.0) as i32;
+            let size = (
+                ((0.45 * self.size.width as f32) as u32).max(1),
+                ((0.3 * self.size.height as f32) as u32).max(1),
+            );
+            let x_label = format!("{} {}", spectrum.coord_name(), spectrum.cunit);
+            let rgba = overlay::line_plot(
+                &spectrum.title(),
+                x_label.trim(),
+                spectrum.bunit.as_deref().unwrap_or_default(),
+                &spectrum.coords_or_channels(),
+                &spectrum.values,
+                size,
+                scale,
+            );
+            self.renderer.blit.create_image(
+                &self.renderer.device,
+                &self.renderer.queue,
+                &rgba,
+                size,
+            )
+        });
+    }
+
+    /// Save the spectrum extracted last in the working directory, or download it on the web
+    fn save_spectrum(&self, format: SpectrumFormat) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        let spectrum = self
+            .spectrum
+            .as_ref()
+            .ok_or("No spectrum extracted, click on the cube first")?;
+        let bytes = match format {
+            SpectrumFormat::Csv => spectrum.to_csv().into_bytes(),
+            SpectrumFormat::Fits => FitsTable::spectrum(cube, spectrum)
+                .to_bytes()
+                .map_err(|_| "The spectrum cannot be written as a FITS table")?,
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = export::numbered_path("spectrum", format.extension());
+            match std::fs::write(&path, bytes) {
+                Ok(()) => info!("Spectrum written to {}", path.display()),
+                Err(error) => warn!("{}: {}", path.display(), error),
+            }
+            Ok(())
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            web::download(
+                &bytes,
+                &format!("spectrum.{}", format.extension()),
+                format.mime_type(),
+            )
+        }
+    }
+
+    fn set_clipping(&mut self, mut clipping: Clipping) {
+        if clipping.planes.len() > MAX_CLIP_PLANES {
+            warn!(
+                "Only the first {} clipping planes are used",
+                MAX_CLIP_PLANES
+            );
+            clipping.planes.truncate(MAX_CLIP_PLANES);
+        }
+        self.clipping = clipping;
+        self.update_clip_uniform();
+    }
+
+    fn update_clip_uniform(&mut self) {
+        self.interact();
+        self.renderer.write_clipping(&self.clipping);
+    }
+
+    /// Restrict the rendering to a range of channels, `None` renders all of them
+    fn set_channel_range(&mut self, range: Option<ChannelRange>) {
+        let depth = self.cube_dim().2;
+        self.channels = range.map(|range| range.clamp(depth));
+        // a range given by hand stops the sweep
+        self.sweep = None;
+        self.update_channels_uniform();
+    }
+
+    fn set_spectral_range(&mut self, range: SpectralRange) -> Result<(), &'static str> {
+        let depth = self.cube_dim().2;
+        let wcs = self.cube.as_ref().and_then(Cube::wcs);
+        let range = range.to_channels(depth, wcs)?;
+        self.set_channel_range(Some(range));
+        Ok(())
+    }
+
+    fn channel_range(&self) -> ChannelRange {
+        self.channels
+            .unwrap_or_else(|| ChannelRange::all(self.cube_dim().2))
+    }
+
+    /// Start sweeping a window of channels through the cube, or stop it with `None`.
+    /// The last window of the sweep stays rendered once stopped.
+    fn set_sweep(&mut self, sweep: Option<ChannelSweep>) {
+        self.sweep = sweep.map(|sweep| (sweep, self.clock.elapsed_as_secs()));
+    }
+
+    fn update_channels_uniform(&mut self) {
+        self.reset_accumulation();
+        self.renderer.write_channels(self.channel_range());
+        self.update_velocity_legend();
+    }
+
+    fn update_render_uniform(&mut self) {
+        self.reset_accumulation();
+        self.renderer.write_render(
+            self.render_mode,
+            &self.iso_levels,
+            self.hide_below_cut,
+            // coarse sampling while the view changes
+            if self.interacting {
+                Quality::DRAFT
+            } else {
+                self.quality
+            },
+        );
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let size = self.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return Ok(());
+        }
+
+        if !self.is_surface_configured {
+            return Ok(());
+        }
+
+        let panels = self.layout.panels(self.size);
+
+        // draw the volume at a low resolution with a coarse sampling while the view
+        // changes, then refine it at full resolution once at rest
+        let interacting = self.clock.elapsed_as_secs() - self.last_interaction < refine::REST_DELAY;
+        if interacting != self.interacting {
+            self.interacting = interacting;
+            self.update_render_uniform();
+        }
+        let volume_size = panels
+            .iter()
+            .find(|(panel, _)| *panel == Panel::Volume)
+            .map(|(_, [_, _, w, h])| (*w, *h));
+        if let Some((w, h)) = volume_size {
+            if interacting {
+                let size = (
+                    (w * refine::INTERACTIVE_SCALE).ceil() as u32,
+                    (h * refine::INTERACTIVE_SCALE).ceil() as u32,
+                );
+                self.low_res_target
+                    .resize(&self.renderer.device, &self.renderer.blit, size);
+            } else if self.history.resize(
+                &self.renderer.device,
+                &self.renderer.blit,
+                (w as u32, h as u32),
+            ) {
+                self.reset_accumulation();
+            }
+        }
+        // once the history has converged, it is only copied to the panel
+        let draw_volume = volume_size.is_some()
+            && (interacting || self.accumulated < refine::MAX_ACCUMULATED_FRAMES);
+
+        if let Ok(frame) = self.surface.get_current_texture() {
+            let view = frame.texture.create_view(&wgpu::TextureViewDescriptor {
+                format: Some(self.config.format.add_srgb_suffix()),
+                ..Default::default()
+            });
+
+            let mut encoder =
+                self.renderer
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Render Encoder"),
+                    });
+
+            let volume_target = if interacting {
+                &self.low_res_target
+            } else {
+                &self.history
+            };
+
+            if draw_volume {
+                // weight of the new frame in the average
+                let weight = if interacting {
+                    1.0
+                } else {
+                    1.0 / (self.accumulated + 1) as f64
+                };
+
+                self.renderer
+                    .draw_volume(&mut encoder, volume_target.view(), weight);
+            }
+
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 0.01,
+                                g: 0.01,
+                                b: 0.01,
+                                a: 1.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+
+                render_pass.set_vertex_buffer(0, self.renderer.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(
+                    self.renderer.index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint32,
+                );
+
+                for (panel, [x, y, w, h]) in panels {
+                    render_pass.set_viewport(x, y, w, h, 0.0, 1.0);
+                    match panel {
+                        Panel::Volume => {
+                            // copy the volume drawn offscreen, upscaling it while interacting
+                            self.renderer.blit.bind(&mut render_pass, volume_target);
+                        }
+                        Panel::Slice(axis) => {
+                            render_pass.set_pipeline(&self.slice_pipeline);
+                            render_pass.set_bind_group(0, &self.renderer.bind_group, &[]);
+                            render_pass.set_bind_group(
+                                1,
+                                &self.slices[axis.index()].bind_group,
+                                &[],
+                            );
+                        }
+                        Panel::Map | Panel::Pv => {
+                            let map = match panel {
+                                Panel::Map => self.moment.as_ref().map(|(_, map)| map),
+                                _ => self.pv.as_ref().map(|(_, diagram)| diagram),
+                            };
+                            match map {
+                                Some(map) => {
+                                    render_pass.set_pipeline(&self.map_pipeline);
+                                    render_pass.set_bind_group(0, &map.bind_group, &[]);
+                                }
+                                None => continue,
+                            }
+                        }
+                    }
+                    render_pass.draw_indexed(0..6, 0, 0..1);
+                }
+
+                const MARGIN: f32 = 8.0;
+                // readout of the voxel under the cursor, in the upper left corner of the volume
+                if let (Some(label), Some([x, y, w, h])) =
+                    (&self.pick_label, self.volume_viewport())
+                {
+                    let (width, height) = label.size();
+                    if width as f32 + MARGIN <= w && height as f32 + MARGIN <= h {
+                        render_pass.set_viewport(
+                            x + MARGIN,
+                            y + MARGIN,
+                            width as f32,
+                            height as f32,
+                            0.0,
+                            1.0,
+                        );
+                        self.renderer.blit.bind(&mut render_pass, label);
+                        render_pass.draw_indexed(0..6, 0, 0..1);
+                    }
+                }
+                // legend of the velocity coded mode, in the upper right corner of the volume
+                if let (Some(legend), Some([x, y, w, h])) =
+                    (&self.velocity_legend, self.volume_viewport())
+                {
+                    let (width, height) = legend.size();
+                    if width as f32 + MARGIN <= w && height as f32 + MARGIN <= h {
+                        render_pass.set_viewport(
+                            x + w - width as f32 - MARGIN,
+                            y + MARGIN,
+                            width as f32,
+                            height as f32,
+                            0.0,
+                            1.0,
+                        );
+                        self.renderer.blit.bind(&mut render_pass, legend);
+                        render_pass.draw_indexed(0..6, 0, 0..1);
+                    }
+                }
+                // plot of the spectrum extracted last, in the lower left corner of the window
+                if let Some(plot) = &self.spectrum_plot {
+                    let (width, height) = plot.size();
+                    let (w, h) = (self.size.width as f32, self.size.height as f32);
+                    if width as f32 + MARGIN <= w && height as f32 + MARGIN <= h {
+                        render_pass.set_viewport(
+                            MARGIN,
+                            h - height as f32 - MARGIN,
+                            width as f32,
+                            height as f32,
+                            0.0,
+                            1.0,
+                        );
+                        self.renderer.blit.bind(&mut render_pass, plot);
+                        render_pass.draw_indexed(0..6, 0, 0..1);
+                    }
+                }
+                // catalog of the objects found last, in the lower right corner of the window
+                if let Some(panel) = &self.catalog_panel {
+                    let (width, height) = panel.size();
+                    let (w, h) = (self.size.width as f32, self.size.height as f32);
+                    if width as f32 + MARGIN <= w && height as f32 + MARGIN <= h {
+                        render_pass.set_viewport(
+                            w - width as f32 - MARGIN,
+                            h - height as f32 - MARGIN,
+                            width as f32,
+                            height as f32,
+                            0.0,
+                            1.0,
+                        );
+                        self.renderer.blit.bind(&mut render_pass, panel);
+                        render_pass.draw_indexed(0..6, 0, 0..1);
+                    }
+                }
+            }
+
+            self.renderer.queue.submit(iter::once(encoder.finish()));
+            frame.present();
+
+            if draw_volume && !interacting {
+                self.accumulated += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Render the volume offscreen at a size of its own, averaging jittered samples,
+    /// and start reading it back
+    fn render_frame(
+        &mut self,
+        size: (u32, u32),
+        samples: u32,
+    ) -> Result<FrameReadback, &'static str> {
+        // full quality whatever the state of the window
+        let interacting = self.interacting;
+        self.interacting = false;
+        self.update_render_uniform();
+        self.renderer
+            .write_camera(&self.camera, size.0 as f32 / size.1.max(1) as f32);
+
+        let frame = self
+            .renderer
+            .draw_frame(size, samples, self.clock.elapsed_as_secs());
+
+        self.interacting = interacting;
+        self.update_render_uniform();
+        self.renderer
+            .write_camera(&self.camera, self.volume_aspect());
+
+        let encoder =
+            self.renderer
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Frame Encoder"),
+                });
+        capture::read_back(
+            &self.renderer.device,
+            &self.renderer.queue,
+            encoder,
+            &frame?,
+        )
+    }
+
+    /// Data values mapped to both ends of the colormap by the cuts
+    fn colormap_range(&self) -> (f32, f32) {
+        let Range { start, end } = self.minmax;
+        let value = |x: f32| start + (x - self.offset) / self.scale.max(1e-6) * (end - start);
+        (value(0.0), value(1.0))
+    }
+
+    /// Render the current view, returning the PNG file once it has been read back
+    ///
+    /// Only the volume is rendered, at the size of the window if none is given.
+    /// With the map and pv layouts, the map or the diagram is saved instead at its
+    /// own size.
+    fn screenshot(
+        &mut self,
+        screenshot: &Screenshot,
+    ) -> Result<impl std::future::Future<Output = Result<Vec<u8>, &'static str>>, &'static str>
+    {
+        let map = match (&self.moment, &self.pv, self.layout) {
+            (Some((moment, map)), _, ViewLayout::Map) => Some((moment.size, &moment.data, map)),
+            (_, Some((pv, diagram)), ViewLayout::Pv) => Some((pv.size, &pv.data, diagram)),
+            _ => None,
+        };
+        if let Some((size, data, map)) = map {
+            let rgba = map::colorize(size, data, &map.range);
+            let png = export::encode_png(&rgba, size);
+            return Ok(futures::future::Either::Left(async move { png }));
+        }
+
+        let size = screenshot
+            .size
+            .unwrap_or((self.size.width, self.size.height));
+        let readback = self.render_frame(size, screenshot.samples)?;
+        let overlay = Overlay {
+            // the iso levels have colors of their own, and the velocity coded mode
+            // a legend of its hues
+            colorbar: (screenshot.colorbar && self.render_mode == RenderMode::Average)
+                .then(|| self.colormap_range()),
+            velocity: (screenshot.colorbar && self.render_mode == RenderMode::Velocity)
+                .then(|| self.velocity_axis()),
+            axes: screenshot
+                .axes
+                .then(|| (self.camera.orientation, self.axis_names())),
+            srgb: self.config.format.is_srgb(),
+        };
+
+        Ok(futures::future::Either::Right(async move {
+            let mut rgba = readback.rgba().await?;
+            overlay.draw(&mut rgba, size);
+            export::encode_png(&rgba, size)
+        }))
+    }
+
+    /// Save a screenshot in the working directory, or download it on the web
+    fn save_screenshot(&mut self, screenshot: &Screenshot) {
+        let png = match self.screenshot(screenshot) {
+            Ok(png) => png,
+            Err(error) => {
+                warn!("{}", error);
+                return;
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = export::numbered_path("screenshot", "png");
+            match pollster::block_on(png) {
+                Ok(png) => match std::fs::write(&path, png) {
+                    Ok(()) => info!("Screenshot written to {}", path.display()),
+                    Err(error) => warn!("{}: {}", path.display(), error),
+                },
+                Err(error) => warn!("{}", error),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            wasm_bindgen_futures::spawn_local(async move {
+                let result = match png.await {
+                    Ok(png) => web::download(&png, "screenshot.png", "image/png"),
+                    Err(error) => Err(error),
+                };
+                if let Err(error) = result {
+                    web_sys::window()
+                        .unwrap()
+                        .alert_with_message(error)
+                        .unwrap();
+                }
+            });
+            // the read back completes with the next frame submitted
+            self.window.request_redraw();
+        }
+    }
+
+    /// Number of frames of the animation played once: the camera path, else one
+    /// turn of the turntable
+    #[cfg(not(target_arch = "wasm32"))]
+    fn animation_frames(&self, fps: f32) -> u32 {
+        let animation = &self.animation;
+        let (duration, looping) = if !animation.path.is_empty() {
+            (animation.path.duration(), animation.looping)
+        } else {
+            match animation.turntable {
+                Some(turntable) if turntable.speed != 0.0 => (360.0 / turntable.speed.abs(), true),
+                _ => (0.0, false),
+            }
+        };
+        // the end of a loop is its first frame
+        let frames = (duration * fps).round() as u32 + if looping { 0 } else { 1 };
+        frames.max(1)
+    }
+
+    /// Render the animation from its start frame by frame, returning the number
+    /// of frames written. The view is restored afterwards.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_animation(&mut self, export: &FrameExport, path: &Path) -> Result<u32, String> {
+        if export.fps.is_nan() || export.fps <= 0.0 {
+            return Err("The frame rate must be positive".to_string());
+        }
+        let frames = export
+            .frames
+            .unwrap_or_else(|| self.animation_frames(export.fps));
+        let mut sink = FrameSink::create(path, export.size, frames, export.fps)?;
+
+        let camera = self.camera;
+        let cuts = (self.scale, self.offset);
+        let channels = self.channels;
+        let animation = self.animation.clone();
+        let quality = self.quality;
+
+        self.camera_transition = None;
+        self.quality = export.quality;
+        self.animation.time = 0.0;
+        let mut result = Ok(());
+        for i in 0..frames {
+            // a fixed step, however long a frame takes to render
+            self.animate(if i == 0 { 0.0 } else { 1.0 / export.fps });
+            result = self
+                .render_frame(export.size, export.samples)
+                .and_then(|readback| pollster::block_on(readback.rgba()))
+                .map_err(String::from)
+                .and_then(|mut rgba| sink.write(i, export.size, export.fps, &mut rgba));
+            if result.is_err() {
+                break;
+            }
+        }
+        let result = result.and_then(|_| sink.finish());
+
+        self.animation = animation;
+        self.set_quality(quality);
+        self.set_cuts(cuts.0, cuts.1);
+        self.channels = channels;
+        self.update_channels_uniform();
+        self.camera = camera;
+        self.update_camera_uniform();
+
+        result.map(|_| frames)
+    }
+
+    fn visualize_cube<R: AsRef<[u8]> + std::fmt::Debug>(
+        &mut self,
+        reader: Cursor<R>,
+        // name of the file, recorded in the files derived from the cube
+        name: Option<&str>,
+        // override the minmax value
+        min: Option<f32>,
+        max: Option<f32>,
+    ) -> Result<(), &'static str> {
+        let (new_cube, mut cube) = read_fits(reader, &self.renderer.device, &self.renderer.queue)?;
+        cube.name = name.map(str::to_string);
+        let grid =
+            MinMaxGrid::from_cube(&cube).texture(&self.renderer.device, &self.renderer.queue)?;
+        self.renderer.set_textures(new_cube, grid, cube.dim.2);
+
+        // set the new datamin/datamax if there is some
+        let datamin = min.or(cube.datamin).unwrap_or(0.0);
+        let datamax = max.or(cube.datamax).unwrap_or(1.0);
+        // start from the middle of the new cube
+        for slice in self.slices.iter_mut() {
+            slice.index = slice.axis.num_slices(cube.dim) / 2;
+        }
+        self.cube = Some(cube);
+        // the layers were aligned on the previous cube, whose objects were labeled
+        self.layers.clear();
+        self.update_layers_uniform();
+        self.labels = None;
+        self.update_labels_uniform();
+        self.catalog = None;
+        self.finder = None;
+        self.update_catalog();
+        // the maps, the path, the voxel picked and the spectrum belong to the previous cube
+        self.moment = None;
+        self.pv = None;
+        self.pv_path.vertices.clear();
+        self.update_pick(None);
+        self.spectrum = None;
+        self.update_spectrum_plot();
+        if matches!(self.layout, ViewLayout::Map | ViewLayout::Pv) {
+            self.set_layout(ViewLayout::Volume);
+        }
+        self.update_slices();
+
+        // the crop box, planes and channel range are relative to the previous cube
+        self.set_clipping(Clipping::default());
+        self.channels = None;
+        self.update_channels_uniform();
+        // the axis names come from the new header
+        self.update_view_label();
+        self.set_minmax(datamin..datamax);
+
+        // keep the same number of iso levels but spread them in the new data range
+        self.iso_levels = render_mode::spread_iso_levels(self.iso_levels.len(), datamin, datamax);
+        self.update_render_uniform();
+
+        // reset the cutoff values
+        self.set_cuts(1.0, 0.0);
+
+        Ok(())
+    }
+
+    /// Overlay a cube on the one visualized, aligned with it by the WCS when both have
+    /// one, with the next default colormap of the layers if none is given
+    fn add_layer(&mut self, layer: Cube, colormap: Option<Colormap>) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        let index = self.renderer.add_layer(&layer)?;
+        let settings = Layer::new(cube, &layer, colormap.unwrap_or(LAYER_COLORMAPS[index]));
+        info!(
+            "{} overlaid, {}",
+            layer.name.as_deref().unwrap_or("cube"),
+            settings.alignment.description()
+        );
+        self.layers.push(settings);
+        self.update_layers_uniform();
+        Ok(())
+    }
+
+    fn remove_layer(&mut self, index: usize) -> Result<(), &'static str> {
+        if index >= self.layers.len() {
+            return Err("No such layer");
+        }
+        self.renderer.remove_layer(index);
+        self.layers.remove(index);
+        self.update_layers_uniform();
+        Ok(())
+    }
+
+    /// Change the colors of a layer
+    fn update_layer<F: FnOnce(&mut Layer)>(
+        &mut self,
+        index: usize,
+        update: F,
+    ) -> Result<(), &'static str> {
+        update(self.layers.get_mut(index).ok_or("No such layer")?);
+        self.update_layers_uniform();
+        Ok(())
+    }
+
+    fn set_blend(&mut self, blend: Blend) {
+        self.blend = blend;
+        self.update_layers_uniform();
+    }
+
+    fn update_layers_uniform(&mut self) {
+        self.reset_accumulation();
+        self.renderer.write_layers(&self.layers, self.blend);
+    }
+
+    /// Load the labels of the objects found in the cube visualized, e.g. the mask of
+    /// a source finder, which must have the same dimensions
+    fn load_labels(&mut self, labels: Labels) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        if labels.dim != cube.dim {
+            return Err("The labels must have the dimensions of the cube");
+        }
+        self.renderer.load_labels(&labels)?;
+        info!(
+            "{}: {} objects labeled",
+            labels.name.as_deref().unwrap_or("labels"),
+            labels.ids().len()
+        );
+        self.labels = Some(labels);
+        self.update_labels_uniform();
+        // the voxel picked is labeled once the cursor moves
+        self.update_pick(None);
+        // the catalog listed the previous objects, and the ones being found would
+        // replace these labels
+        self.catalog = None;
+        self.finder = None;
+        self.update_catalog();
+        Ok(())
+    }
+
+    /// Start finding the objects of the cube visualized, labeled in the volume and
+    /// listed in a catalog once `step_finder` is done
+    fn find_sources(&mut self, settings: &FinderSettings) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        self.finder = Some(SourceFinder::new(settings.clone(), cube)?);
+        self.update_catalog();
+        Ok(())
+    }
+
+    // advance the source finder for a part of the frame, so that the window keeps
+    // responding, loading the objects found at the end
+    fn step_finder(&mut self) {
+        // time given to the source finder in each frame, in seconds
+        const BUDGET: f32 = 0.02;
+        let (finder, cube) = match (&mut self.finder, &self.cube) {
+            (Some(finder), Some(cube)) => (finder, cube),
+            _ => return,
+        };
+        let percent = (100.0 * finder.progress()) as u32;
+        let start = self.clock.elapsed_as_secs();
+        let result = loop {
+            if let Some(result) = finder.step(cube) {
+                break Some(result);
+            }
+            if self.clock.elapsed_as_secs() - start > BUDGET {
+                break None;
+            }
+        };
+        match result {
+            None => {
+                if (100.0 * finder.progress()) as u32 != percent {
+                    self.update_catalog();
+                }
+            }
+            Some(result) => {
+                self.finder = None;
+                let result = result.and_then(|(labels, catalog)| {
+                    self.load_labels(labels)?;
+                    self.catalog = Some(catalog);
+                    Ok(())
+                });
+                if let Err(error) = result {
+                    warn!("{}", error);
+                }
+                self.update_catalog();
+            }
+        }
+    }
+
+    fn update_catalog(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            // the first objects, the whole catalog being saved with Shift+F
+            const MAX_ROWS: usize = 12;
+            let lines = match (&self.finder, &self.catalog) {
+                (Some(finder), _) => Some(vec![format!(
+                    "Finding the objects: {} %",
+                    (100.0 * finder.progress()) as u32
+                )]),
+                (None, Some(catalog)) => Some(catalog.summary(MAX_ROWS)),
+                (None, None) => None,
+            };
+            self.catalog_panel = lines.map(|lines| {
+                let scale = (2.0 * self.window.scale_factor()).round().max(1.0) as i32;
+                let (rgba, size) = overlay::text_box(&lines, scale);
+                self.renderer.blit.create_image(
+                    &self.renderer.device,
+                    &self.renderer.queue,
+                    &rgba,
+                    size,
+                )
+            });
+        }
+        // the page is called once the objects are found
+        #[cfg(target_arch = "wasm32")]
+        if let (None, Some(callback)) = (&self.finder, &self.catalog_callback) {
+            let _ = callback.call1(&JsValue::NULL, &web::catalog_to_js(self.catalog.as_ref()));
+        }
+    }
+
+    /// Save the catalog of the objects found last in the working directory, or
+    /// download it on the web
+    fn save_catalog(&self, format: CatalogFormat) -> Result<(), &'static str> {
+        let catalog = self
+            .catalog
+            .as_ref()
+            .ok_or("No objects found, run the source finder first")?;
+        let bytes = match format {
+            CatalogFormat::Csv => catalog.to_csv().into_bytes(),
+            CatalogFormat::VoTable => catalog.to_votable().into_bytes(),
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = export::numbered_path("catalog", format.extension());
+            match std::fs::write(&path, bytes) {
+                Ok(()) => info!("Catalog written to {}", path.display()),
+                Err(error) => warn!("{}: {}", path.display(), error),
+            }
+            Ok(())
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            web::download(
+                &bytes,
+                &format!("catalog.{}", format.extension()),
+                format.mime_type(),
+            )
+        }
+    }
+
+    /// Save the labels of the objects found last as a FITS file in the working
+    /// directory, or download it on the web
+    fn save_labels(&self) -> Result<(), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        let (labels, catalog) = self
+            .labels
+            .as_ref()
+            .zip(self.catalog.as_ref())
+            .ok_or("No objects found, run the source finder first")?;
+        let bytes = FitsLabels::new(cube, labels, catalog).to_bytes();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = export::numbered_path("mask", "fits");
+            match std::fs::write(&path, bytes) {
+                Ok(()) => info!("Labels written to {}", path.display()),
+                Err(error) => warn!("{}: {}", path.display(), error),
+            }
+            Ok(())
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            web::download(&bytes, "mask.fits", "application/fits")
+        }
+    }
+
+    /// Hide the object of an ID if it is shown, else show it
+    fn toggle_object(&mut self, id: u32) -> Result<(), &'static str> {
+        let labels = self.labels.as_mut().ok_or("No labels loaded")?;
+        labels.set_hidden(id, !labels.is_hidden(id))?;
+        self.renderer.write_label_table(labels);
+        self.reset_accumulation();
+        Ok(())
+    }
+
+    fn show_all_objects(&mut self) {
+        if let Some(labels) = &mut self.labels {
+            labels.show_all();
+            self.renderer.write_label_table(labels);
+            self.reset_accumulation();
+        }
+    }
+
+    fn set_label_display(&mut self, display: LabelDisplay) {
+        self.label_display = display;
+        self.update_labels_uniform();
+    }
+
+    fn set_hide_unlabeled(&mut self, hide: bool) {
+        self.hide_unlabeled = hide;
+        self.update_labels_uniform();
+    }
+
+    fn update_labels_uniform(&mut self) {
+        self.reset_accumulation();
+        self.renderer
+            .write_labels(self.label_display, self.hide_unlabeled);
+    }
+
+    /// Extract the isosurface of the current cube
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    fn extract_mesh(
+        &self,
+        threshold: f32,
+        smooth: usize,
+        units: MeshUnits,
+    ) -> Result<Mesh, &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        let mut mesh = Mesh::marching_cubes(cube, threshold);
+        mesh.smooth(smooth);
+
+        if units == MeshUnits::Wcs {
+            let wcs = cube.wcs().ok_or("The cube has no WCS")?;
+            mesh.to_world(wcs);
+        }
+
+        Ok(mesh)
+    }
+
+    /// Data shown by the view, with the stem of the file it is saved to
+    ///
+    /// This is the moment map with the map layout, the position-velocity diagram with
+    /// the pv layout, the mean along the view axis of the
+    /// crop box when looking along an axis of the cube in the average mode,
+    /// and otherwise the subcube of the crop box and the channels rendered. The clip
+    /// planes are not taken into account.
+    fn fits_image(&self) -> Result<(FitsImage, String), &'static str> {
+        let cube = self.cube.as_ref().ok_or("No cube loaded")?;
+        if let (Some((moment, _)), ViewLayout::Map) = (&self.moment, self.layout) {
+            return Ok((
+                FitsImage::moment_map(cube, moment)?,
+                moment.moment.name().to_string(),
+            ));
+        }
+        if let (Some((pv, _)), ViewLayout::Pv) = (&self.pv, self.layout) {
+            return Ok((FitsImage::pv_diagram(cube, pv)?, "pv".to_string()));
+        }
+
+        let dim = [cube.dim.0, cube.dim.1, cube.dim.2];
+        let mut region = [0, 1, 2].map(|i| {
+            let start = (self.clipping.box_min[i] * dim[i] as f32).floor() as u32;
+            let end = (self.clipping.box_max[i] * dim[i] as f32).ceil() as u32;
+            start.min(dim[i])..end.min(dim[i])
+        });
+        let channels = self.channel_range();
+        region[2] = region[2].start.max(channels.first)..region[2].end.min(channels.last + 1);
+
+        let projection = match self.render_mode {
+            RenderMode::Average => Some(Projection::Mean),
+            RenderMode::Isosurface | RenderMode::Velocity => None,
+        };
+        match (self.camera.aligned_axes(), projection) {
+            (Some([_, _, (axis, _)]), Some(projection)) => Ok((
+                FitsImage::projection(cube, &region, axis, projection)?,
+                format!("{}_{}", projection.name(), ["x", "y", "z"][axis]),
+            )),
+            _ => Ok((
+                FitsImage::subcube(cube, &region, [1, 1, 1])?,
+                "subcube".to_string(),
+            )),
+        }
+    }
+
+    /// Save the data shown in the working directory, or download it on the web
+    fn save_fits(&self) -> Result<(), &'static str> {
+        let (image, stem) = self.fits_image()?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = export::numbered_path(&stem, "fits");
+            match std::fs::write(&path, image.to_bytes()) {
+                Ok(()) => info!("FITS file written to {}", path.display()),
+                Err(error) => warn!("{}: {}", path.display(), error),
+            }
+            Ok(())
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            web::download(
+                &image.to_bytes(),
+                &format!("{}.fits", stem),
+                "application/fits",
+            )
+        }
+    }
+}
+
+use std::ops::Range;
+#[derive(Debug, Default)]
+struct Params {
+    perspective: Option<bool>,
+    fov: Option<f32>,
+    arcball: Option<bool>,
+    reset_view: bool,
+    view: Option<AxisView>,
+    animation: Option<AnimationCommand>,
+    minmax: Option<Range<f32>>,
+    render_mode: Option<RenderMode>,
+    iso_levels: Option<Vec<IsoLevel>>,
+    export_mesh: Option<MeshExport>,
+    screenshot: Option<Screenshot>,
+    layout: Option<ViewLayout>,
+    slice: Option<(SliceAxis, u32)>,
+    crop_box: Option<([f32; 3], [f32; 3])>,
+    clip_planes: Option<Vec<ClipPlane>>,
+    show_cut_faces: Option<bool>,
+    // `Some(None)` renders all the channels again
+    channels: Option<Option<SpectralRange>>,
+    sweep: Option<Option<ChannelSweep>>,
+    quality: Option<Quality>,
+    hide_below_cut: Option<bool>,
+    moment: Option<MomentSettings>,
+    save_fits: bool,
+    aperture: Option<Aperture>,
+    // center of the aperture, with whether it is given in world coordinates
+    spectrum: Option<([f64; 2], bool)>,
+    save_spectrum: Option<SpectrumFormat>,
+    // vertices of the path, with the slit width if given
+    pv_path: Option<(Vec<[f64; 2]>, Option<f32>)>,
+    show_pv: bool,
+    // index of the layer changed, with its new colors
+    layer_colormap: Option<(usize, Colormap)>,
+    layer_opacity: Option<(usize, f32)>,
+    layer_minmax: Option<(usize, Range<f32>)>,
+    remove_layer: Option<usize>,
+    blend: Option<Blend>,
+    // ID of the object hidden or shown
+    toggle_object: Option<u32>,
+    show_all_objects: bool,
+    label_display: Option<LabelDisplay>,
+    hide_unlabeled: Option<bool>,
+    find_sources: Option<FinderSettings>,
+    save_catalog: Option<CatalogFormat>,
+    save_labels: bool,
+    // `Some(None)` removes the callback
+    #[cfg(target_arch = "wasm32")]
+    pick_callback: Option<Option<js_sys::Function>>,
+    #[cfg(target_arch = "wasm32")]
+    catalog_callback: Option<Option<js_sys::Function>>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+struct MeshExport {
+    threshold: f32,
+    smooth: usize,
+    units: MeshUnits,
+    format: MeshFormat,
+}
+
+#[cfg(target_arch = "wasm32")]
+use lazy_static::lazy_static;
+#[cfg(target_arch = "wasm32")]
+lazy_static! {
+    static ref CHANNEL_PARAMS: (
+        async_channel::Sender<Params>,
+        async_channel::Receiver<Params>,
+    ) = async_channel::unbounded::<Params>();
+}
+
+static mut PARAMS: Params = Params {
+    perspective: None,
+    fov: None,
+    arcball: None,
+    reset_view: false,
+    view: None,
+    animation: None,
+    minmax: None,
+    render_mode: None,
+    iso_levels: None,
+    export_mesh: None,
+    screenshot: None,
+    layout: None,
+    slice: None,
+    crop_box: None,
+    clip_planes: None,
+    show_cut_faces: None,
+    channels: None,
+    sweep: None,
+    quality: None,
+    hide_below_cut: None,
+    moment: None,
+    save_fits: false,
+    aperture: None,
+    spectrum: None,
+    save_spectrum: None,
+    pv_path: None,
+    show_pv: false,
+    layer_colormap: None,
+    layer_opacity: None,
+    layer_minmax: None,
+    remove_layer: None,
+    blend: None,
+    toggle_object: None,
+    show_all_objects: false,
+    label_display: None,
+    hide_unlabeled: None,
+    find_sources: None,
+    save_catalog: None,
+    save_labels: false,
+    #[cfg(target_arch = "wasm32")]
+    pick_callback: None,
+    #[cfg(target_arch = "wasm32")]
+    catalog_callback: None,
+};
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setPerspective")]
+pub fn set_perspective(perspective: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                perspective: Some(perspective),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setFov")]
+pub fn set_fov(degrees: f32) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                fov: Some(degrees.clamp(1.0, 170.0).to_radians()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setArcball")]
+pub fn set_arcball(arcball: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                arcball: Some(arcball),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "resetView")]
+pub fn reset_view() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                reset_view: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setView")]
+pub fn set_view(view: &str) -> Result<(), JsValue> {
+    let view = view.parse::<AxisView>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                view: Some(view),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+#[cfg(target_arch = "wasm32")]
+/// Play the turntable and the camera path
+#[wasm_bindgen(js_name = "playAnimation")]
+pub fn play_animation() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                animation: Some(AnimationCommand::Play),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "pauseAnimation")]
+pub fn pause_animation() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                animation: Some(AnimationCommand::Pause),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "rewindAnimation")]
+pub fn rewind_animation() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                animation: Some(AnimationCommand::Rewind),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+/// Spin the cube at `speed` degrees per second, or stop it if undefined
+#[wasm_bindgen(js_name = "setTurntable")]
+pub fn set_turntable(speed: Option<f32>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                animation: Some(AnimationCommand::Turntable(
+                    speed.map(|speed| Turntable { speed }),
+                )),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setAnimationLoop")]
+pub fn set_animation_loop(looping: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                animation: Some(AnimationCommand::Loop(looping)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+/// Record the current view in the camera path at `time` seconds,
+/// two seconds after the last keyframe if undefined
+#[wasm_bindgen(js_name = "addKeyframe")]
+pub fn add_keyframe(time: Option<f32>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                animation: Some(AnimationCommand::AddKeyframe(time)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "clearKeyframes")]
+pub fn clear_keyframes() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                animation: Some(AnimationCommand::ClearKeyframes),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "normalize")]
+pub fn normalize(min: f32, max: f32) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                minmax: Some(min..max),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setRenderMode")]
+pub fn set_render_mode(mode: &str) -> Result<(), JsValue> {
+    let mode = mode.parse::<RenderMode>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                render_mode: Some(mode),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+/// Set the iso levels rendered in the "iso" mode.
+///
+/// * `values` - the iso values in the data unit
+/// * `colors` - optional rgba colors in [0, 1], 4 floats per level
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setIsoLevels")]
+pub fn set_iso_levels(values: Vec<f32>, colors: Option<Vec<f32>>) -> Result<(), JsValue> {
+    let mut levels = render_mode::spread_iso_levels(values.len(), 0.0, 1.0);
+    if let Some(colors) = &colors {
+        if colors.len() != 4 * values.len() {
+            return Err(JsValue::from_str(
+                "4 color components are expected per level",
+            ));
+        }
+    }
+
+    for (i, (level, value)) in levels.iter_mut().zip(values.iter()).enumerate() {
+        level.value = *value;
+        if let Some(colors) = &colors {
+            level.color = [colors[4 * i], colors[4 * i + 1], colors[4 * i + 2]];
+            level.opacity = colors[4 * i + 3];
+        }
+    }
+
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                iso_levels: Some(levels),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Extract the isosurface of the loaded cube and download it
+///
+/// * `threshold` - the iso value in the data unit
+/// * `format` - "ply", "obj" or "glb"
+/// * `smooth` - number of smoothing iterations
+/// * `units` - "voxel" or "wcs"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "exportMesh")]
+pub fn export_mesh(threshold: f32, format: &str, smooth: u32, units: &str) -> Result<(), JsValue> {
+    let format = format.parse::<MeshFormat>().map_err(JsValue::from_str)?;
+    let units = units.parse::<MeshUnits>().map_err(JsValue::from_str)?;
+
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                export_mesh: Some(MeshExport {
+                    threshold,
+                    smooth: smooth as usize,
+                    units,
+                    format,
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Render the current view to a PNG file and download it
+///
+/// * `width`, `height` - size of the image, the one of the canvas if not given
+/// * `colorbar` - draw the colormap with the data values of its ends
+/// * `axes` - draw the axes of the cube
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "screenshot")]
+pub fn screenshot(
+    width: Option<u32>,
+    height: Option<u32>,
+    colorbar: bool,
+    axes: bool,
+) -> Result<(), JsValue> {
+    let size = match (width, height) {
+        (Some(0), _) | (_, Some(0)) => return Err(JsValue::from_str("The size must not be empty")),
+        (Some(width), Some(height)) => Some((width, height)),
+        (None, None) => None,
+        _ => return Err(JsValue::from_str("Give both the width and the height")),
+    };
+
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                screenshot: Some(Screenshot {
+                    size,
+                    colorbar,
+                    axes,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Compute a moment map of the cube and show it in place of the volume
+///
+/// * `moment` - "0", "1", "2" or "peak"
+/// * `first`, `last` - channels integrated, all of them if not given
+/// * `threshold` - samples below are left out, in the data unit
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "showMoment")]
+pub fn show_moment(
+    moment: &str,
+    first: Option<u32>,
+    last: Option<u32>,
+    threshold: Option<f32>,
+) -> Result<(), JsValue> {
+    let moment = moment.parse::<Moment>().map_err(JsValue::from_str)?;
+    let channels = match (first, last) {
+        (Some(first), Some(last)) => Some(ChannelRange { first, last }),
+        (None, None) => None,
+        _ => {
+            return Err(JsValue::from_str(
+                "Give both the first and the last channel",
+            ))
+        }
+    };
+
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                moment: Some(MomentSettings {
+                    moment,
+                    channels,
+                    threshold,
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Download what is shown as a FITS file: the moment map with the map layout, the
+/// mean along the view axis when looking along an axis of the cube,
+/// else the subcube of the crop box and the channels rendered
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "saveFits")]
+pub fn save_fits() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                save_fits: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Call `callback` with the voxel under the cursor each time it changes, or stop with
+/// no callback
+///
+/// The voxel is given as `{ kind, voxel: [x, y, z], value, world }`, `kind` being "max"
+/// for the maximum along the ray or "first hit" for the first crossing of an iso level
+/// in the isosurface mode, and `world` the world coordinates or null without WCS.
+/// `null` is given once the cursor leaves the volume.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "onPick")]
+pub fn on_pick(callback: Option<js_sys::Function>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                pick_callback: Some(callback),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Set the spaxels summed into the spectra extracted with a click or `extractSpectrum`:
+/// "spaxel", "circle:<radius>" or "box:<width>x<height>", sizes being given in pixels
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setAperture")]
+pub fn set_aperture(aperture: &str) -> Result<(), JsValue> {
+    let aperture = aperture.parse::<Aperture>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                aperture: Some(aperture),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Extract the spectrum of the aperture centered on (`x`, `y`) and plot it
+///
+/// The center is given in 0-based pixels of the spatial plane, or in the world
+/// coordinates of the spatial axes (e.g. RA and DEC) with `world`.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "extractSpectrum")]
+pub fn extract_spectrum(x: f64, y: f64, world: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                spectrum: Some(([x, y], world)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Download the spectrum extracted last: "csv" or "fits" for a FITS binary table
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "saveSpectrum")]
+pub fn save_spectrum(format: &str) -> Result<(), JsValue> {
+    let format = format
+        .parse::<SpectrumFormat>()
+        .map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                save_spectrum: Some(format),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Set the path of the position-velocity diagrams, drawn over the moment map
+///
+/// `vertices` are the x and y of each vertex in turn, in 0-based pixels of the spatial
+/// plane, and `width` the width of the slit across the path in pixels, the values
+/// across it being averaged.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setPvPath")]
+pub fn set_pv_path(vertices: Vec<f64>, width: Option<f32>) -> Result<(), JsValue> {
+    if vertices.len() % 2 != 0 {
+        return Err(JsValue::from_str("Give the x and y of each vertex"));
+    }
+    let vertices = vertices.chunks(2).map(|v| [v[0], v[1]]).collect();
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                pv_path: Some((vertices, width)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Compute the position-velocity diagram along the path and show it
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "showPv")]
+pub fn show_pv() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                show_pv: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Set the colormap of a cube overlaid: "jet", "grey", "viridis" or "turbo"
+///
+/// The cubes are overlaid with the layer file input, and indexed in the order they
+/// were added.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setLayerColormap")]
+pub fn set_layer_colormap(index: usize, colormap: &str) -> Result<(), JsValue> {
+    let colormap = colormap.parse::<Colormap>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                layer_colormap: Some((index, colormap)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Set the weight, between 0 and 1, of the colors of a cube overlaid in the blending
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setLayerOpacity")]
+pub fn set_layer_opacity(index: usize, opacity: f32) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                layer_opacity: Some((index, opacity)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Set the values of a cube overlaid mapped to the ends of its colormap
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "normalizeLayer")]
+pub fn normalize_layer(index: usize, min: f32, max: f32) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                layer_minmax: Some((index, min..max)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Remove a cube overlaid, the next ones moving down by one index
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "removeLayer")]
+pub fn remove_layer(index: usize) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                remove_layer: Some(index),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Set the way the cubes overlaid are combined with the cube: "additive", "max" or "alpha"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setBlend")]
+pub fn set_blend(blend: &str) -> Result<(), JsValue> {
+    let blend = blend.parse::<Blend>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                blend: Some(blend),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Hide the object of an ID, as given to the pick callback, or show it again
 #[cfg(target_arch = "wasm32")]
-#[wasm_bindgen(js_name = "setPerspective")]
-pub fn set_perspective(perspective: bool) {
-    wasm_bindgen_futures::spawn_local(async move {
-        CHANNEL_PARAMS
-            .0
-            .send(Params {
-                perspective: Some(perspective),
-                ..Default::default()
-            })
-            .await
-            .unwrap();
-    });
-}
-#[cfg(target_arch = "wasm32")]
-#[wasm_bindgen(js_name = "normalize")]
-pub fn normalize(min: f32, max: f32) {
+#[wasm_bindgen(js_name = "toggleObject")]
+pub fn toggle_object(id: u32) {
     wasm_bindgen_futures::spawn_local(async move {
         CHANNEL_PARAMS
             .0
             .send(Params {
-                minmax: Some(min..max),
+                toggle_object: Some(id),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "showAllObjects")]
+pub fn show_all_objects() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                show_all_objects: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Set the drawing of the objects labeled: "off", "outlines" or "tint"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setLabelDisplay")]
+pub fn set_label_display(display: &str) -> Result<(), JsValue> {
+    let display = display.parse::<LabelDisplay>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                label_display: Some(display),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Hide the data outside of the objects labeled, or show it again
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setHideUnlabeled")]
+pub fn set_hide_unlabeled(hide: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                hide_unlabeled: Some(hide),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Find the objects of the cube by smoothing and clipping, label them in the volume
+/// and give their catalog to the callback of `onCatalog`
+///
+/// The voxels above `threshold` times the noise of the cube smoothed by any of the
+/// Gaussian `spatialKernels` (FWHM in pixels) combined with any of the boxcar
+/// `spectralKernels` (in channels) are detected, and the groups of less than
+/// `minSize` voxels dropped. The settings not given keep their default: 5 sigma,
+/// 0, 3 and 6 pixels, 0, 3 and 7 channels, 8 voxels.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "findSources")]
+pub fn find_sources(
+    threshold: Option<f32>,
+    spatial_kernels: Option<Vec<f32>>,
+    spectral_kernels: Option<Vec<u32>>,
+    min_size: Option<u32>,
+) {
+    let defaults = FinderSettings::default();
+    let settings = FinderSettings {
+        threshold: threshold.unwrap_or(defaults.threshold),
+        spatial_kernels: spatial_kernels.unwrap_or(defaults.spatial_kernels),
+        spectral_kernels: spectral_kernels.unwrap_or(defaults.spectral_kernels),
+        min_size: min_size.unwrap_or(defaults.min_size),
+    };
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                find_sources: Some(settings),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Download the catalog of the objects found last: "csv" or "votable"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "saveCatalog")]
+pub fn save_catalog(format: &str) -> Result<(), JsValue> {
+    let format = format.parse::<CatalogFormat>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                save_catalog: Some(format),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Download the labels of the objects found last, as a FITS cube of 32 bits integers
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "saveLabels")]
+pub fn save_labels() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                save_labels: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Call `callback` with the catalog of the objects each time the source finder runs,
+/// or stop with no callback
+///
+/// The objects are given as an array of `{ id, centroid: [x, y, z], world, voxels,
+/// flux, peak }`, `world` being the world coordinates of the centroid or null without
+/// WCS, and `null` once the objects of the catalog are no longer labeled.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "onCatalog")]
+pub fn on_catalog(callback: Option<js_sys::Function>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                catalog_callback: Some(callback),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Set the layout of the view: "volume", "xy", "xz", "yz" or "quad"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setLayout")]
+pub fn set_layout(layout: &str) -> Result<(), JsValue> {
+    let layout = layout.parse::<ViewLayout>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                layout: Some(layout),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+/// Display the slice `index` of the plane "xy", "xz" or "yz"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setSlice")]
+pub fn set_slice(plane: &str, index: u32) -> Result<(), JsValue> {
+    let axis = plane.parse::<SliceAxis>().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                slice: Some((axis, index)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// Crop the rendered volume to a box given in voxels, e.g. `xmin` to `xmax`
+/// between 0 and NAXIS1
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setCropBox")]
+pub fn set_crop_box(xmin: f32, xmax: f32, ymin: f32, ymax: f32, zmin: f32, zmax: f32) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                crop_box: Some(([xmin, ymin, zmin], [xmax, ymax, zmax])),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+/// Set the oblique clipping planes, given as 4 floats per plane: the normal
+/// (nx, ny, nz) and the offset d. With the cube spanning [0, 1] on each axis,
+/// the points p kept verify dot(n, p) <= d.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setClipPlanes")]
+pub fn set_clip_planes(planes: Vec<f32>) -> Result<(), JsValue> {
+    if planes.len() % 4 != 0 {
+        return Err(JsValue::from_str("4 floats are expected per plane"));
+    }
+
+    let planes = planes
+        .chunks_exact(4)
+        .map(|p| ClipPlane {
+            normal: [p[0], p[1], p[2]],
+            offset: p[3],
+        })
+        .collect();
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                clip_planes: Some(planes),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+/// Render the faces of the crop box and clipping planes as textured slices
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "showCutFaces")]
+pub fn show_cut_faces(show: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                show_cut_faces: Some(show),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+
+/// Set the sampling quality of the rays: "draft", "normal" or "high"
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setQuality")]
+pub fn set_quality(preset: &str) -> Result<(), JsValue> {
+    let quality: Quality = preset.parse().map_err(JsValue::from_str)?;
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                quality: Some(quality),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+
+    Ok(())
+}
+
+/// In the average and velocity coded modes, average the values after the transfer
+/// function, the ones below the lower cut giving nothing. The parts of the cube below
+/// it are skipped, making the rendering faster on sparse cubes.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setHideBelowCut")]
+pub fn set_hide_below_cut(hide: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                hide_below_cut: Some(hide),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+/// Render only the channels from `first` to `last`, both included
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setChannelRange")]
+pub fn set_channel_range(first: u32, last: u32) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                channels: Some(Some(SpectralRange::Channels(ChannelRange { first, last }))),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+/// Render only a range of the spectral axis, given in its unit (CUNIT3), e.g. a velocity range
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "setVelocityRange")]
+pub fn set_velocity_range(min: f64, max: f64) {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                channels: Some(Some(SpectralRange::World(min, max))),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+/// Render all the channels again
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "resetChannelRange")]
+pub fn reset_channel_range() {
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                channels: Some(None),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    });
+}
+/// Sweep a window of `width` channels through the cube at `speed` channels per second.
+/// A width of 0 stops the sweep.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = "sweepChannels")]
+pub fn sweep_channels(width: u32, speed: f32) {
+    let sweep = if width > 0 {
+        Some(ChannelSweep { width, speed })
+    } else {
+        None
+    };
+    wasm_bindgen_futures::spawn_local(async move {
+        CHANNEL_PARAMS
+            .0
+            .send(Params {
+                sweep: Some(sweep),
                 ..Default::default()
             })
             .await
@@ -904,29 +3027,87 @@ pub fn normalize(min: f32, max: f32) {
     });
 }
 
+/// Options of the viewer given on the command line
+#[derive(Debug, Default)]
+pub struct ViewerOptions {
+    /// Cube opened at startup
+    pub path: Option<std::path::PathBuf>,
+    /// Spectral range rendered
+    pub channels: Option<SpectralRange>,
+    pub sweep: Option<ChannelSweep>,
+    pub quality: Option<Quality>,
+    /// Axis the camera starts looking along
+    pub view: Option<AxisView>,
+    /// Spin of the cube played at startup
+    pub turntable: Option<Turntable>,
+    /// Render the animation to this file, or directory of PNG files, and quit
+    pub export: Option<std::path::PathBuf>,
+    pub frame_export: FrameExport,
+    /// Settings of the screenshots taken from the viewer
+    pub screenshot: Screenshot,
+    /// Spaxels summed into the spectra extracted with a click
+    pub aperture: Option<Aperture>,
+    /// Width of the slit of the position-velocity diagrams, in pixels
+    pub slit_width: Option<f32>,
+    /// Samples below are left out of the moment maps, in the data unit
+    pub moment_threshold: Option<f32>,
+    /// Cubes overlaid on the one opened, with their colormap if given
+    pub layers: Vec<(std::path::PathBuf, Option<Colormap>)>,
+    /// Weight of the colors of the cubes overlaid, between 0 and 1
+    pub layer_opacity: Option<f32>,
+    pub blend: Option<Blend>,
+    /// Labels of the objects found in the cube, e.g. the mask of a source finder
+    pub labels: Option<std::path::PathBuf>,
+    pub label_display: Option<LabelDisplay>,
+    /// Hide the data outside of the objects labeled
+    pub hide_unlabeled: bool,
+}
+
+/// What the file of a file input of the page is loaded as
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone, Copy)]
+enum InputFile {
+    Cube,
+    /// Cube overlaid on the one visualized
+    Layer,
+    /// Labels of the objects of the cube visualized
+    Labels,
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    run_viewer(ViewerOptions::default()).await
+}
+
+pub async fn run_viewer(options: ViewerOptions) {
     #[cfg(target_arch = "wasm32")]
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
     #[cfg(target_arch = "wasm32")]
     console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
+    // the messages of the viewer are shown unless RUST_LOG filters them out
     #[cfg(not(target_arch = "wasm32"))]
-    env_logger::init();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("fits3=info"))
+        .init();
 
+    // content of the files chosen, with what they are loaded as
     #[cfg(target_arch = "wasm32")]
-    let (send_data, recv_data) = async_channel::unbounded::<Vec<u8>>();
+    let (send_data, recv_data) = async_channel::unbounded::<(Vec<u8>, InputFile)>();
 
     #[cfg(target_arch = "wasm32")]
-    {
+    for (id, role) in [
+        ("file-input", InputFile::Cube),
+        ("layer-input", InputFile::Layer),
+        ("labels-input", InputFile::Labels),
+    ] {
         // File reading
         let document = web_sys::window().unwrap().document().unwrap();
-        let input = document
-            .get_element_by_id("file-input")
-            .unwrap()
-            .dyn_into::<web_sys::HtmlInputElement>()
-            .unwrap();
+        let input = match document.get_element_by_id(id) {
+            Some(input) => input.dyn_into::<web_sys::HtmlInputElement>().unwrap(),
+            None => continue,
+        };
 
         let input_cloned = input.clone();
+        let send_data = send_data.clone();
         let closure = Closure::wrap(Box::new(move |_: web_sys::Event| {
             if let Some(file_list) = input_cloned.files() {
                 if let Some(file) = file_list.get(0) {
@@ -942,7 +3123,7 @@ pub async fn run() {
 
                         wasm_bindgen_futures::spawn_local(async move {
                             let mut data = array.to_vec();
-                            sd3.send(data).await.unwrap();
+                            sd3.send((data, role)).await.unwrap();
                         });
 
                         // Here you can use `data` (Vec<u8>) as you like.
@@ -960,41 +3141,134 @@ pub async fn run() {
         closure.forget(); // prevent drop
     }
 
-    let event_loop = EventLoop::new().unwrap();
-    let window = create_window(&event_loop);
-    let mut state = State::new(&window).await;
+    let event_loop = EventLoop::new().unwrap();
+    let window = create_window(&event_loop);
+    let mut state = State::new(&window).await;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = options
+            .path
+            .unwrap_or_else(|| std::path::PathBuf::from(CUBES_PATH[0]));
+        let file = File::open(&path).unwrap();
+        let mmap = unsafe { Mmap::map(&file).unwrap() };
+
+        let reader = Cursor::new(mmap);
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        let _ = state.visualize_cube(reader, name.as_deref(), None, None);
+
+        for (path, colormap) in &options.layers {
+            if let Err(error) = Cube::open(path).and_then(|layer| state.add_layer(layer, *colormap))
+            {
+                warn!("{}: {}", path.display(), error);
+            }
+        }
+        if let Some(path) = &options.labels {
+            if let Err(error) = Labels::open(path).and_then(|labels| state.load_labels(labels)) {
+                warn!("{}: {}", path.display(), error);
+            }
+        }
+    }
+    if let Some(opacity) = options.layer_opacity {
+        for index in 0..state.layers.len() {
+            let _ = state.update_layer(index, |layer| layer.opacity = opacity);
+        }
+    }
+    if let Some(blend) = options.blend {
+        state.set_blend(blend);
+    }
+    if let Some(display) = options.label_display {
+        state.set_label_display(display);
+    }
+    if options.hide_unlabeled {
+        state.set_hide_unlabeled(true);
+    }
+
+    if let Some(range) = options.channels {
+        if let Err(error) = state.set_spectral_range(range) {
+            warn!("{}", error);
+        }
+    }
+    if options.sweep.is_some() {
+        state.set_sweep(options.sweep);
+    }
+    if let Some(quality) = options.quality {
+        state.set_quality(quality);
+    }
+    if let Some(view) = options.view {
+        state.set_camera(Camera {
+            orientation: view.orientation(),
+            ..state.camera
+        });
+    }
+    if options.turntable.is_some() {
+        state.animation_command(AnimationCommand::Turntable(options.turntable));
+    }
+    if let Some(aperture) = options.aperture {
+        state.aperture = aperture;
+    }
+    if let Some(width) = options.slit_width {
+        state.pv_path.width = width;
+    }
+    state.moment_threshold = options.moment_threshold;
 
     #[cfg(not(target_arch = "wasm32"))]
-    {
-        let file = File::open(&CUBES_PATH[0]).unwrap();
-        let mmap = unsafe { Mmap::map(&file).unwrap() };
-
-        let reader = Cursor::new(mmap);
-        let _ = state.visualize_cube(reader, None, None);
+    if let Some(path) = &options.export {
+        match state.export_animation(&options.frame_export, path) {
+            Ok(frames) => info!("{} frames written to {}", frames, path.display()),
+            Err(error) => {
+                warn!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
     }
+    #[cfg(not(target_arch = "wasm32"))]
+    let screenshot = options.screenshot;
+    #[cfg(not(target_arch = "wasm32"))]
+    let (export_path, frame_export) = (
+        options
+            .export
+            .unwrap_or_else(|| std::path::PathBuf::from(DEFAULT_EXPORT_PATH)),
+        options.frame_export,
+    );
 
     //setup_event_loop(state, event_loop);
+    // camera motions
+    let mut rotating = false;
+    let mut rolling = false;
     let mut panning = false;
     let mut cuts = false;
     let mut cursor_pos = PhysicalPosition::new(0.0, 0.0);
     let mut start_cursor_pos = PhysicalPosition::new(0.0, 0.0);
-
-    // move variable
-    let mut delta = 0.0;
-    let mut theta = 0.0;
-    let mut dtheta = 0.0;
-    let mut ddelta: f64 = 0.0;
+    // position of the left button press, a release at the same place being a click
+    let mut press_pos = PhysicalPosition::new(0.0, 0.0);
 
     // cuts
     let mut dscale = 0.0;
     let mut doffset = 0.0;
+
+    // clipping handles
+    let mut modifiers = winit::keyboard::ModifiersState::empty();
+    // face of the crop box grabbed and the point it was grabbed at
+    let mut grabbed_face: Option<(usize, Vec3<f32>)> = None;
+    let mut rotating_plane = false;
     event_loop.set_control_flow(ControlFlow::Wait);
     event_loop
         .run(move |event, control_flow| {
             #[cfg(target_arch = "wasm32")]
-            if let Ok(data) = recv_data.try_recv() {
+            if let Ok((data, role)) = recv_data.try_recv() {
                 let reader = Cursor::new(data.as_slice());
-                match state.visualize_cube(reader, None, None) {
+                let loaded = match role {
+                    InputFile::Cube => state.visualize_cube(reader, None, None, None),
+                    InputFile::Layer => {
+                        Cube::from_reader(reader).and_then(|cube| state.add_layer(cube, None))
+                    }
+                    InputFile::Labels => {
+                        Labels::from_reader(reader).and_then(|labels| state.load_labels(labels))
+                    }
+                };
+                match loaded {
                     Ok(()) => {}
                     Err(error) => web_sys::window()
                         .unwrap()
@@ -1007,29 +3281,333 @@ pub async fn run() {
             if let Ok(params) = CHANNEL_PARAMS.1.try_recv() {
                 let Params {
                     perspective,
+                    fov,
+                    arcball,
+                    reset_view,
+                    view,
+                    animation,
                     minmax,
-                    ..
+                    render_mode,
+                    iso_levels,
+                    export_mesh,
+                    screenshot,
+                    layout,
+                    slice,
+                    crop_box,
+                    clip_planes,
+                    show_cut_faces,
+                    channels,
+                    sweep,
+                    quality,
+                    hide_below_cut,
+                    moment,
+                    save_fits,
+                    aperture,
+                    spectrum,
+                    save_spectrum,
+                    pv_path,
+                    show_pv,
+                    layer_colormap,
+                    layer_opacity,
+                    layer_minmax,
+                    remove_layer,
+                    blend,
+                    toggle_object,
+                    show_all_objects,
+                    label_display,
+                    hide_unlabeled,
+                    find_sources,
+                    save_catalog,
+                    save_labels,
+                    pick_callback,
+                    catalog_callback,
                 } = params;
 
                 if let Some(perspective) = perspective {
-                    state.queue.write_buffer(
-                        &state.perspective_buf,
-                        0,
-                        bytemuck::bytes_of(&[
-                            if perspective { 1.0_f32 } else { 0.0_f32 },
-                            0.0_f32,
-                            0.0_f32,
-                            0.0_f32,
-                        ]),
-                    );
+                    state.set_camera(Camera {
+                        perspective,
+                        ..state.camera
+                    });
+                }
+
+                if let Some(fov) = fov {
+                    state.set_camera(Camera {
+                        fov,
+                        ..state.camera
+                    });
+                }
+
+                if let Some(arcball) = arcball {
+                    state.set_camera(Camera {
+                        arcball,
+                        ..state.camera
+                    });
+                }
+
+                if reset_view {
+                    let mut camera = state.camera;
+                    camera.reset();
+                    state.set_camera(camera);
+                }
+
+                if let Some(view) = view {
+                    state.snap_camera(view);
+                }
+
+                if let Some(command) = animation {
+                    state.animation_command(command);
                 }
 
                 if let Some(minmax) = minmax {
-                    state.queue.write_buffer(
-                        &state.minmax_buf,
-                        0,
-                        bytemuck::bytes_of(&[minmax.start, minmax.end, 0.0_f32, 0.0_f32]),
-                    );
+                    state.set_minmax(minmax);
+                }
+
+                if let Some(render_mode) = render_mode {
+                    state.set_render_mode(render_mode);
+                }
+
+                if let Some(iso_levels) = iso_levels {
+                    state.set_iso_levels(iso_levels);
+                }
+
+                if let Some(quality) = quality {
+                    state.set_quality(quality);
+                }
+
+                if let Some(hide) = hide_below_cut {
+                    state.set_hide_below_cut(hide);
+                }
+
+                if let Some(layout) = layout {
+                    state.set_layout(layout);
+                }
+
+                if let Some((axis, index)) = slice {
+                    state.set_slice(axis, index);
+                }
+
+                if let Some((min, max)) = crop_box {
+                    let (w, h, d) = state.cube_dim();
+                    let dim = [w as f32, h as f32, d as f32];
+                    let mut clipping = state.clipping.clone();
+                    for i in 0..3 {
+                        clipping.box_min[i] = (min[i] / dim[i]).clamp(0.0, 1.0);
+                        clipping.box_max[i] = (max[i] / dim[i]).clamp(0.0, 1.0);
+                    }
+                    state.set_clipping(clipping);
+                }
+
+                if let Some(planes) = clip_planes {
+                    state.set_clipping(Clipping {
+                        planes,
+                        ..state.clipping.clone()
+                    });
+                }
+
+                if let Some(show) = show_cut_faces {
+                    state.set_clipping(Clipping {
+                        show_cut_faces: show,
+                        ..state.clipping.clone()
+                    });
+                }
+
+                match channels {
+                    Some(Some(range)) => {
+                        if let Err(error) = state.set_spectral_range(range) {
+                            web_sys::window()
+                                .unwrap()
+                                .alert_with_message(error)
+                                .unwrap();
+                        }
+                    }
+                    Some(None) => state.set_channel_range(None),
+                    None => {}
+                }
+
+                if let Some(sweep) = sweep {
+                    state.set_sweep(sweep);
+                }
+
+                if let Some(MeshExport {
+                    threshold,
+                    smooth,
+                    units,
+                    format,
+                }) = export_mesh
+                {
+                    let result = state
+                        .extract_mesh(threshold, smooth, units)
+                        .and_then(|mesh| mesh.to_bytes(format))
+                        .and_then(|bytes| {
+                            web::download(
+                                &bytes,
+                                &format!("isosurface.{}", format.extension()),
+                                format.mime_type(),
+                            )
+                        });
+
+                    if let Err(error) = result {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some(settings) = moment {
+                    if let Err(error) = state.show_moment(&settings) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some(screenshot) = screenshot {
+                    state.save_screenshot(&screenshot);
+                }
+
+                if let Some(callback) = pick_callback {
+                    state.pick_callback = callback;
+                }
+
+                if save_fits {
+                    if let Err(error) = state.save_fits() {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some(aperture) = aperture {
+                    state.aperture = aperture;
+                }
+
+                if let Some((position, world)) = spectrum {
+                    if let Err(error) = state.extract_spectrum(position, world) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some(format) = save_spectrum {
+                    if let Err(error) = state.save_spectrum(format) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some((vertices, width)) = pv_path {
+                    if let Err(error) = state.set_pv_path(vertices, width) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if show_pv {
+                    if let Err(error) = state.show_pv() {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some((index, colormap)) = layer_colormap {
+                    if let Err(error) = state.update_layer(index, |layer| layer.colormap = colormap)
+                    {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some((index, opacity)) = layer_opacity {
+                    if let Err(error) = state.update_layer(index, |layer| layer.opacity = opacity) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some((index, minmax)) = layer_minmax {
+                    if let Err(error) = state.update_layer(index, |layer| layer.minmax = minmax) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some(index) = remove_layer {
+                    if let Err(error) = state.remove_layer(index) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some(blend) = blend {
+                    state.set_blend(blend);
+                }
+
+                if let Some(id) = toggle_object {
+                    if let Err(error) = state.toggle_object(id) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+                if show_all_objects {
+                    state.show_all_objects();
+                }
+                if let Some(display) = label_display {
+                    state.set_label_display(display);
+                }
+                if let Some(hide) = hide_unlabeled {
+                    state.set_hide_unlabeled(hide);
+                }
+
+                if let Some(callback) = catalog_callback {
+                    state.catalog_callback = callback;
+                }
+
+                if let Some(settings) = find_sources {
+                    if let Err(error) = state.find_sources(&settings) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if let Some(format) = save_catalog {
+                    if let Err(error) = state.save_catalog(format) {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
+                }
+
+                if save_labels {
+                    if let Err(error) = state.save_labels() {
+                        web_sys::window()
+                            .unwrap()
+                            .alert_with_message(error)
+                            .unwrap();
+                    }
                 }
             }
 
@@ -1041,54 +3619,594 @@ pub async fn run() {
                     if !state.input(event) {
                         match event {
                             #[cfg(not(target_arch = "wasm32"))]
-                            WindowEvent::CloseRequested
-                            | WindowEvent::KeyboardInput {
+                            WindowEvent::CloseRequested
+                            | WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                                        ..
+                                    },
+                                ..
+                            } => control_flow.exit(),
+                            #[cfg(not(target_arch = "wasm32"))]
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyA),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // toggle fullscreen
+                                state.i = (state.i + 1) % CUBES_PATH.len();
+
+                                let file = File::open(&CUBES_PATH[state.i]).unwrap();
+                                let mmap = unsafe { Mmap::map(&file).unwrap() };
+
+                                let reader = Cursor::new(mmap);
+
+                                let minmax = &MINMAX[state.i];
+                                let name = std::path::Path::new(CUBES_PATH[state.i])
+                                    .file_name()
+                                    .and_then(|name| name.to_str());
+                                let _ = state.visualize_cube(
+                                    reader,
+                                    name,
+                                    Some(minmax.start),
+                                    Some(minmax.end),
+                                );
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::Enter),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // toggle fullscreen
+                                state
+                                    .window
+                                    .set_fullscreen(Some(Fullscreen::Borderless(None)));
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyM),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // cycle through the render modes
+                                state.set_render_mode(state.render_mode.next());
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyU),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                if modifiers.shift_key() {
+                                    // remove the cube overlaid last
+                                    if let Some(index) = state.layers.len().checked_sub(1) {
+                                        let _ = state.remove_layer(index);
+                                    }
+                                } else {
+                                    // cycle through the blendings of the cubes overlaid
+                                    state.set_blend(state.blend.next());
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyI),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                if modifiers.shift_key() {
+                                    // hide the data outside of the objects labeled
+                                    state.set_hide_unlabeled(!state.hide_unlabeled);
+                                } else {
+                                    // cycle through the displays of the objects labeled
+                                    state.set_label_display(state.label_display.next());
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyH),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                if modifiers.shift_key() {
+                                    state.show_all_objects();
+                                } else if let Some(id) =
+                                    state.pick.as_ref().and_then(|pick| pick.label)
+                                {
+                                    // hide or show the object under the cursor
+                                    if let Err(error) = state.toggle_object(id) {
+                                        warn!("{}", error);
+                                    }
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                let result = if modifiers.shift_key() {
+                                    // the catalog in both formats, with the labels
+                                    state
+                                        .save_catalog(CatalogFormat::Csv)
+                                        .and_then(|_| state.save_catalog(CatalogFormat::VoTable))
+                                        .and_then(|_| state.save_labels())
+                                } else {
+                                    state.find_sources(&FinderSettings::default())
+                                };
+                                if let Err(error) = result {
+                                    warn!("{}", error);
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyL),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // cycle the number of iso levels
+                                let num = state.iso_levels.len() % MAX_ISO_LEVELS + 1;
+                                let levels = render_mode::spread_iso_levels(
+                                    num,
+                                    state.minmax.start,
+                                    state.minmax.end,
+                                );
+                                state.set_iso_levels(levels);
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::PageUp),
+                                        ..
+                                    },
+                                ..
+                            } => state.shift_iso_levels(0.02),
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::PageDown),
+                                        ..
+                                    },
+                                ..
+                            } => state.shift_iso_levels(-0.02),
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // cycle through the volume, slices and four-panel layouts
+                                state.set_layout(state.layout.next());
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(code @ (KeyCode::Comma | KeyCode::Period)),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                let delta = if *code == KeyCode::Period { 1 } else { -1 };
+                                if let Some(axis) = state.slice_axis_at(cursor_pos) {
+                                    state.step_slice(axis, delta);
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(code @ (KeyCode::BracketLeft | KeyCode::BracketRight)),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // move the channel range
+                                let delta = if *code == KeyCode::BracketRight { 1 } else { -1 };
+                                let range = state.channel_range().shift(delta, state.cube_dim().2);
+                                state.set_channel_range(Some(range));
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(code @ (KeyCode::Minus | KeyCode::Equal)),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // narrow or widen the channel range
+                                let delta = if *code == KeyCode::Equal { 1 } else { -1 };
+                                let range = state.channel_range().widen(delta, state.cube_dim().2);
+                                state.set_channel_range(Some(range));
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyW),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                if state.sweep.is_some() {
+                                    state.set_sweep(None);
+                                } else {
+                                    // sweep the current range if it has been narrowed
+                                    let depth = state.cube_dim().2;
+                                    let range = state.channel_range();
+                                    let mut sweep = ChannelSweep::default();
+                                    if range.width() < depth {
+                                        sweep.width = range.width();
+                                    }
+                                    state.set_sweep(Some(sweep));
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyQ),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // cycle through the draft, normal and high quality presets
+                                let quality = state.quality.next();
+                                state.set_quality(quality);
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyN),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                state.set_hide_below_cut(!state.hide_below_cut);
+                            }
+                            WindowEvent::MouseWheel { delta, .. } => {
+                                let delta = match delta {
+                                    MouseScrollDelta::LineDelta(_, y) => y.signum() as i32,
+                                    MouseScrollDelta::PixelDelta(pos) => pos.y.signum() as i32,
+                                };
+                                if let Some(axis) = state.slice_axis_at(cursor_pos) {
+                                    state.step_slice(axis, delta);
+                                } else if modifiers.control_key() {
+                                    // move the last clipping plane along its normal
+                                    if let Some(plane) = state.clipping.planes.last_mut() {
+                                        plane.offset += 0.01 * delta as f32;
+                                    }
+                                    state.update_clip_uniform();
+                                } else if matches!(
+                                    state.layout.panel_at(cursor_pos, state.size),
+                                    Some((Panel::Volume, _))
+                                ) {
+                                    let mut camera = state.camera;
+                                    camera.zoom(0.9_f32.powi(delta));
+                                    state.set_camera(camera);
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // add a clipping plane facing the camera, removing the near half
+                                if state.clipping.planes.len() < MAX_CLIP_PLANES {
+                                    let toward_cam = -state.camera.forward();
+                                    state.clipping.planes.push(ClipPlane::through_center(toward_cam.into()));
+                                    state.update_clip_uniform();
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key:
+                                            PhysicalKey::Code(
+                                                code @ (KeyCode::Digit1
+                                                | KeyCode::Digit2
+                                                | KeyCode::Digit3),
+                                            ),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // look along x, y or z, from the other side with shift
+                                let axis = match code {
+                                    KeyCode::Digit1 => SliceAxis::X,
+                                    KeyCode::Digit2 => SliceAxis::Y,
+                                    _ => SliceAxis::Z,
+                                };
+                                state.snap_camera(AxisView {
+                                    axis,
+                                    reversed: modifiers.shift_key(),
+                                });
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key:
+                                            PhysicalKey::Code(
+                                                code @ (KeyCode::ArrowLeft
+                                                | KeyCode::ArrowRight
+                                                | KeyCode::ArrowUp
+                                                | KeyCode::ArrowDown),
+                                            ),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // orbit by steps of a twentieth of the panel height, shift + up/down zooming
+                                let mut camera = state.camera;
+                                match (code, modifiers.shift_key()) {
+                                    (KeyCode::ArrowUp, true) => camera.zoom(0.9),
+                                    (KeyCode::ArrowDown, true) => camera.zoom(1.0 / 0.9),
+                                    (KeyCode::ArrowLeft, _) => camera.rotate([0.0, 0.0], -0.05, 0.0),
+                                    (KeyCode::ArrowRight, _) => camera.rotate([0.0, 0.0], 0.05, 0.0),
+                                    (KeyCode::ArrowUp, _) => camera.rotate([0.0, 0.0], 0.0, -0.05),
+                                    _ => camera.rotate([0.0, 0.0], 0.0, 0.05),
+                                }
+                                state.set_camera(camera);
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::Space),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                let command = if modifiers.shift_key() {
+                                    AnimationCommand::Loop(!state.animation.looping)
+                                } else if state.animation.playing {
+                                    AnimationCommand::Pause
+                                } else {
+                                    AnimationCommand::Play
+                                };
+                                state.animation_command(command);
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyO),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                let turntable = if state.animation.turntable.is_some() {
+                                    None
+                                } else {
+                                    Some(Turntable::default())
+                                };
+                                state.animation_command(AnimationCommand::Turntable(turntable));
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyK),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                let command = if modifiers.shift_key() {
+                                    AnimationCommand::ClearKeyframes
+                                } else {
+                                    AnimationCommand::AddKeyframe(None)
+                                };
+                                state.animation_command(command);
+                            }
+                            // the browser keeps F12 for its developer tools
+                            #[cfg(not(target_arch = "wasm32"))]
+                            WindowEvent::KeyboardInput {
                                 event:
                                     KeyEvent {
                                         state: ElementState::Pressed,
-                                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                                        physical_key: PhysicalKey::Code(KeyCode::F12),
+                                        repeat: false,
                                         ..
                                     },
                                 ..
-                            } => control_flow.exit(),
+                            } => {
+                                state.save_screenshot(&screenshot);
+                            }
                             #[cfg(not(target_arch = "wasm32"))]
                             WindowEvent::KeyboardInput {
                                 event:
                                     KeyEvent {
                                         state: ElementState::Pressed,
-                                        physical_key: PhysicalKey::Code(KeyCode::KeyA),
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyE),
+                                        repeat: false,
                                         ..
                                     },
                                 ..
                             } => {
-                                // toggle fullscreen
-                                state.i = (state.i + 1) % CUBES_PATH.len();
-
-                                let file = File::open(&CUBES_PATH[state.i]).unwrap();
-                                let mmap = unsafe { Mmap::map(&file).unwrap() };
-
-                                let reader = Cursor::new(mmap);
-
-                                let minmax = &MINMAX[state.i];
-                                let _ = state.visualize_cube(
-                                    reader,
-                                    Some(minmax.start),
-                                    Some(minmax.end),
-                                );
+                                // render the animation offscreen, the window waiting for it
+                                match state.export_animation(&frame_export, &export_path) {
+                                    Ok(frames) => {
+                                        info!(
+                                            "{} frames written to {}",
+                                            frames,
+                                            export_path.display()
+                                        )
+                                    }
+                                    Err(error) => warn!("{}", error),
+                                }
                             }
                             WindowEvent::KeyboardInput {
                                 event:
                                     KeyEvent {
                                         state: ElementState::Pressed,
-                                        physical_key: PhysicalKey::Code(KeyCode::Enter),
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyG),
+                                        repeat: false,
                                         ..
                                     },
                                 ..
                             } => {
-                                // toggle fullscreen
-                                state
-                                    .window
-                                    .set_fullscreen(Some(Fullscreen::Borderless(None)));
+                                // moment 0, 1, 2, the peak, then back to the volume
+                                match state.next_moment() {
+                                    Some(settings) => {
+                                        if let Err(error) = state.show_moment(&settings) {
+                                            warn!("{}", error);
+                                        }
+                                    }
+                                    None => state.set_layout(ViewLayout::Volume),
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyX),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                if let Err(error) = state.save_fits() {
+                                    warn!("{}", error);
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyY),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // a FITS binary table with shift, else comma separated values
+                                let format = if modifiers.shift_key() {
+                                    SpectrumFormat::Fits
+                                } else {
+                                    SpectrumFormat::Csv
+                                };
+                                if let Err(error) = state.save_spectrum(format) {
+                                    warn!("{}", error);
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyJ),
+                                        repeat: false,
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                if modifiers.shift_key() {
+                                    // clear the path drawn over the moment map
+                                    if let Err(error) = state.set_pv_path(vec![], None) {
+                                        warn!("{}", error);
+                                    }
+                                } else if state.layout == ViewLayout::Pv {
+                                    // back to the moment map the path is drawn on
+                                    state.set_layout(ViewLayout::Map);
+                                } else if let Err(error) = state.show_pv() {
+                                    warn!("{}", error);
+                                }
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::Home),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                state.animation_command(AnimationCommand::Rewind);
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                let mut camera = state.camera;
+                                camera.reset();
+                                state.set_camera(camera);
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                state.clipping.show_cut_faces = !state.clipping.show_cut_faces;
+                                state.update_clip_uniform();
+                            }
+                            WindowEvent::KeyboardInput {
+                                event:
+                                    KeyEvent {
+                                        state: ElementState::Pressed,
+                                        physical_key: PhysicalKey::Code(KeyCode::KeyB),
+                                        ..
+                                    },
+                                ..
+                            } => {
+                                // remove the crop box and the clipping planes
+                                state.set_clipping(Clipping {
+                                    show_handles: state.clipping.show_handles,
+                                    ..Default::default()
+                                });
                             }
                             WindowEvent::Resized(physical_size) => state.resize(*physical_size),
                             WindowEvent::RedrawRequested => {
@@ -1103,9 +4221,7 @@ pub async fn run() {
                                     // The system is out of memory, we should probably quit
                                     Err(wgpu::SurfaceError::OutOfMemory) => control_flow.exit(),
                                     // All other errors (Outdated, Timeout) should be resolved by the next frame
-                                    Err(e) => {
-                                        eprintln!("{}", e);
-                                    }
+                                    Err(e) => warn!("{}", e),
                                 }
                             }
                             // Moving
@@ -1114,25 +4230,69 @@ pub async fn run() {
                                 button: MouseButton::Left,
                                 ..
                             } => {
-                                panning = true;
                                 start_cursor_pos = cursor_pos;
-                                dtheta = 0.0;
-                                ddelta = 0.0;
+                                press_pos = cursor_pos;
+
+                                if modifiers.shift_key() {
+                                    // grab a face of the crop box
+                                    grabbed_face = state.volume_ray(cursor_pos).and_then(
+                                        |(origin, dir)| state.clipping.pick_face(origin, dir),
+                                    );
+                                } else if modifiers.control_key() {
+                                    rotating_plane = !state.clipping.planes.is_empty();
+                                } else {
+                                    // only the volume can be rotated
+                                    let over_volume = matches!(
+                                        state.layout.panel_at(cursor_pos, state.size),
+                                        Some((Panel::Volume, _))
+                                    );
+                                    if modifiers.alt_key() {
+                                        rolling = over_volume;
+                                    } else {
+                                        rotating = over_volume;
+                                    }
+                                }
                             }
                             WindowEvent::MouseInput {
                                 state: ElementState::Released,
                                 button: MouseButton::Left,
                                 ..
                             } => {
-                                panning = false;
-                                theta += dtheta;
-                                delta += ddelta;
+                                grabbed_face = None;
+                                rotating_plane = false;
+                                rotating = false;
+                                rolling = false;
 
-                                delta = delta.clamp(
-                                    -std::f64::consts::PI * 0.5 + 1e-3,
-                                    std::f64::consts::PI * 0.5 - 1e-3,
+                                // a click without modifier extracts the spectrum under the cursor
+                                let moved = (cursor_pos.x - press_pos.x).abs()
+                                    + (cursor_pos.y - press_pos.y).abs();
+                                if moved < 4.0 && modifiers.is_empty() {
+                                    state.click(cursor_pos);
+                                }
+                                // and with alt adds a vertex to the path over the moment map
+                                if moved < 4.0 && modifiers == winit::keyboard::ModifiersState::ALT {
+                                    state.add_pv_vertex(cursor_pos);
+                                }
+                            }
+                            // Panning
+                            WindowEvent::MouseInput {
+                                state: ElementState::Pressed,
+                                button: MouseButton::Middle,
+                                ..
+                            } => {
+                                start_cursor_pos = cursor_pos;
+                                panning = matches!(
+                                    state.layout.panel_at(cursor_pos, state.size),
+                                    Some((Panel::Volume, _))
                                 );
                             }
+                            WindowEvent::MouseInput {
+                                state: ElementState::Released,
+                                button: MouseButton::Middle,
+                                ..
+                            } => {
+                                panning = false;
+                            }
                             // Change cuts
                             WindowEvent::MouseInput {
                                 state: ElementState::Pressed,
@@ -1156,30 +4316,43 @@ pub async fn run() {
                             WindowEvent::CursorMoved { position, .. } => {
                                 cursor_pos = *position;
 
-                                if panning {
+                                if rotating || rolling || panning {
+                                    if let Some([x, y, w, h]) = state.volume_viewport() {
+                                        // motion since the last event, in units of the panel height
+                                        let dx = (cursor_pos.x - start_cursor_pos.x) as f32 / h;
+                                        let dy = (cursor_pos.y - start_cursor_pos.y) as f32 / h;
+
+                                        let mut camera = state.camera;
+                                        if panning {
+                                            camera.pan(dx, dy);
+                                        } else if rolling {
+                                            camera.roll(std::f32::consts::PI * dx);
+                                        } else {
+                                            let from = [
+                                                (start_cursor_pos.x as f32 - x - 0.5 * w) / h,
+                                                (start_cursor_pos.y as f32 - y - 0.5 * h) / h,
+                                            ];
+                                            camera.rotate(from, dx, dy);
+                                        }
+                                        state.set_camera(camera);
+                                    }
+                                    start_cursor_pos = cursor_pos;
+                                } else if let Some((face, hit)) = grabbed_face {
+                                    if let Some((origin, dir)) = state.volume_ray(cursor_pos) {
+                                        state.clipping.drag_face(face, hit, origin, dir);
+                                        state.update_clip_uniform();
+                                    }
+                                } else if rotating_plane {
                                     let dx = (cursor_pos.x - start_cursor_pos.x)
                                         / ((state.size.width as f64) * 0.5);
                                     let dy = (cursor_pos.y - start_cursor_pos.y)
                                         / ((state.size.height as f64) * 0.5);
+                                    start_cursor_pos = cursor_pos;
 
-                                    dtheta = 2.0 * dx;
-                                    ddelta = dy;
-
-                                    let d = (delta as f32 + ddelta as f32).clamp(
-                                        -std::f32::consts::PI * 0.5 + 1e-3,
-                                        std::f32::consts::PI * 0.5 - 1e-3,
-                                    );
-
-                                    state.queue.write_buffer(
-                                        &state.cam_origin_buf,
-                                        0,
-                                        bytemuck::bytes_of(&[
-                                            theta as f32 + dtheta as f32,
-                                            d,
-                                            0.0,
-                                            0.0,
-                                        ]),
-                                    );
+                                    if let Some(plane) = state.clipping.planes.last_mut() {
+                                        plane.rotate(dx as f32, -dy as f32);
+                                    }
+                                    state.update_clip_uniform();
                                 } else if cuts {
                                     let dx = (cursor_pos.x - start_cursor_pos.x)
                                         / ((state.size.width as f64) * 0.5);
@@ -1190,17 +4363,39 @@ pub async fn run() {
                                     dscale = dy as f32;
                                     doffset = dx as f32;
 
-                                    state.queue.write_buffer(
-                                        &state.cuts_buf,
-                                        0,
-                                        bytemuck::bytes_of(&[
-                                            state.scale * (1.0 + dscale),
-                                            state.offset * (1.0 + doffset),
-                                            0.0,
-                                            0.0,
-                                        ]),
+                                    state.interact();
+                                    state.renderer.write_cuts(
+                                        state.scale * (1.0 + dscale),
+                                        state.offset * (1.0 + doffset),
                                     );
                                 }
+
+                                // highlight the face of the crop box that would be grabbed
+                                if modifiers.shift_key() && grabbed_face.is_none() {
+                                    let hovered = state
+                                        .volume_ray(cursor_pos)
+                                        .and_then(|(origin, dir)| state.clipping.pick_face(origin, dir))
+                                        .map(|(face, _)| face);
+                                    if hovered != state.clipping.hovered_face {
+                                        state.clipping.hovered_face = hovered;
+                                        state.update_clip_uniform();
+                                    }
+                                }
+
+                                state.update_pick(Some(cursor_pos));
+                            }
+                            WindowEvent::CursorLeft { .. } => {
+                                state.update_pick(None);
+                            }
+                            WindowEvent::ModifiersChanged(new_modifiers) => {
+                                modifiers = new_modifiers.state();
+
+                                // show the crop box handles while shift is held
+                                state.clipping.show_handles = modifiers.shift_key();
+                                if !modifiers.shift_key() {
+                                    state.clipping.hovered_face = None;
+                                }
+                                state.update_clip_uniform();
                             }
                             _ => {}
                         }
@@ -1237,10 +4432,7 @@ fn create_window(event_loop: &EventLoop<()>) -> Window {
         builder = builder.with_canvas(Some(canvas));
     }
 
-    let window = builder
-        .with_title("Astronomical cube visualizer")
-        .build(&event_loop)
-        .unwrap();
+    let window = builder.with_title(WINDOW_TITLE).build(&event_loop).unwrap();
 
     // Winit prevents sizing with CSS, so we have to set
     // the size manually when on web.
@@ -1,4 +1,24 @@
-use fits3::run;
+use fits3::{cli, run_viewer};
 fn main() {
-    pollster::block_on(run());
-}
\ No newline at end of file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("find") => cli::find(&args[1..]),
+        Some("mesh") => cli::mesh(&args[1..]),
+        Some("moments") => cli::moments(&args[1..]),
+        Some("pv") => cli::pv(&args[1..]),
+        Some("render") => cli::render(&args[1..]),
+        Some("spectrum") => cli::spectrum(&args[1..]),
+        Some("subcube") => cli::subcube(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        _ => cli::viewer_options(&args).map(|options| pollster::block_on(run_viewer(options))),
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
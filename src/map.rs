@@ -0,0 +1,241 @@
+//! Display of the 2D maps computed from the cube
+
+use std::ops::Range;
+
+use crate::overlay;
+use crate::pv::{PvPath, MAX_PATH_VERTICES};
+use crate::slice;
+use crate::texture::Texture;
+
+/// std140 layout of the `Map` uniform block of map.vert/map.frag
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MapUniform {
+    // xy: values at both ends of the colormap, zw: fraction of the panel covered by the map
+    params: [f32; 4],
+    // x: number of vertices of the path drawn over the map, y: half width of its slit,
+    // zw: size of the map in pixels
+    path_params: [f32; 4],
+    // vertices of the path in pixels of the map, two per vector
+    path: [[f32; 4]; MAX_PATH_VERTICES / 2],
+}
+
+/// GPU state of a map shown in a panel
+pub(crate) struct MapPanel {
+    size: (u32, u32),
+    /// Values at both ends of the colormap
+    pub range: Range<f32>,
+    // kept alive for the bind group
+    _texture: Texture,
+    buf: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl MapPanel {
+    pub(crate) fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<MapUniform>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("map_bind_group_layout"),
+        })
+    }
+
+    /// Upload a map of `size` pixels, x varying the fastest, shown with `range`
+    /// mapped to the ends of the colormap
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        size: (u32, u32),
+        data: &[f32],
+        range: Range<f32>,
+    ) -> Result<Self, &'static str> {
+        let texture = Texture::from_raw_bytes::<f32>(
+            device,
+            queue,
+            Some(bytemuck::cast_slice(data)),
+            (size.0, size.1, 1),
+            4,
+            "map",
+        )?;
+        let buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("map"),
+            size: std::mem::size_of::<MapUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buf.as_entire_binding(),
+                },
+            ],
+            label: Some("map_bind_group"),
+        });
+
+        Ok(Self {
+            size,
+            range,
+            _texture: texture,
+            buf,
+            bind_group,
+        })
+    }
+
+    /// Write the uniform for a panel of `viewport` size, with the path of a
+    /// position-velocity diagram drawn over the map
+    pub(crate) fn update(&self, queue: &wgpu::Queue, viewport: [f32; 4], path: Option<&PvPath>) {
+        let (sx, sy) = slice::letterbox(self.size, viewport);
+        let mut vertices = [[0.0; 4]; MAX_PATH_VERTICES / 2];
+        let (mut num_vertices, mut half_width) = (0, 0.0);
+        if let Some(path) = path {
+            num_vertices = path.vertices.len().min(MAX_PATH_VERTICES);
+            half_width = path.width.round().max(1.0) * 0.5;
+            // from the centers of the pixels to the coordinates of the texture in pixels
+            for (i, [x, y]) in path.vertices.iter().take(num_vertices).enumerate() {
+                vertices[i / 2][i % 2 * 2] = *x as f32 + 0.5;
+                vertices[i / 2][i % 2 * 2 + 1] = *y as f32 + 0.5;
+            }
+        }
+        queue.write_buffer(
+            &self.buf,
+            0,
+            bytemuck::bytes_of(&MapUniform {
+                params: [self.range.start, self.range.end, sx, sy],
+                path_params: [
+                    num_vertices as f32,
+                    half_width,
+                    self.size.0 as f32,
+                    self.size.1 as f32,
+                ],
+                path: vertices,
+            }),
+        );
+    }
+}
+
+/// Colors of a map as RGBA rows of 8 bits per channel, from the top one down,
+/// with `range` mapped to the ends of the colormap and the blank pixels in grey
+pub(crate) fn colorize(size: (u32, u32), data: &[f32], range: &Range<f32>) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(data.len() * 4);
+    // the y axis of the maps goes up
+    for row in data.chunks(size.0.max(1) as usize).rev() {
+        for v in row {
+            if v.is_nan() {
+                rgba.extend_from_slice(&[63, 63, 63, 255]);
+            } else {
+                let x = (v - range.start) / (range.end - range.start);
+                let [r, g, b] = overlay::colormap(x, true);
+                rgba.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+    }
+    rgba
+}
+
+/// Pipeline drawing a map with the colormap of the volume
+pub(crate) fn create_pipeline(
+    device: &wgpu::Device,
+    map_bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("map vert shader"),
+        source: wgpu::ShaderSource::Glsl {
+            shader: include_str!("shaders/map.vert").into(),
+            stage: wgpu::naga::ShaderStage::Vertex,
+            defines: Default::default(),
+        },
+    });
+    let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("map frag shader"),
+        source: wgpu::ShaderSource::Glsl {
+            shader: include_str!("shaders/map.frag").into(),
+            stage: wgpu::naga::ShaderStage::Fragment,
+            defines: Default::default(),
+        },
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Map Pipeline Layout"),
+        bind_group_layouts: &[map_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Map Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &vs_shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            buffers: &[crate::vertex::Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &fs_shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
@@ -1,3 +1,3 @@
+pub(crate) type Vec3<T> = cgmath::Vector3<T>;
 pub(crate) type Vec4<T> = cgmath::Vector4<T>;
 pub(crate) type Mat4<T> = cgmath::Matrix4<T>;
-
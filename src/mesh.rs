@@ -0,0 +1,928 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::cube::Cube;
+use crate::wcs::{self, Wcs};
+
+/// Triangle mesh of an isosurface of the cube
+///
+/// Triangles are wound counter-clockwise when seen from the side of the
+/// lower values, i.e. from outside the emission
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+/// Coordinate system of the exported vertices
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshUnits {
+    /// 0-based voxel indices
+    Voxel,
+    /// Linear world coordinates given by the WCS of the cube
+    Wcs,
+}
+
+impl std::str::FromStr for MeshUnits {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "voxel" => Ok(MeshUnits::Voxel),
+            "wcs" => Ok(MeshUnits::Wcs),
+            _ => Err("Unknown mesh units, expected 'voxel' or 'wcs'"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshFormat {
+    Ply,
+    Obj,
+    /// Binary glTF
+    Glb,
+}
+
+impl MeshFormat {
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        ext.parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MeshFormat::Ply => "ply",
+            MeshFormat::Obj => "obj",
+            MeshFormat::Glb => "glb",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            MeshFormat::Ply => "application/octet-stream",
+            MeshFormat::Obj => "text/plain",
+            MeshFormat::Glb => "model/gltf-binary",
+        }
+    }
+}
+
+impl std::str::FromStr for MeshFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ply" => Ok(MeshFormat::Ply),
+            "obj" => Ok(MeshFormat::Obj),
+            "glb" | "gltf" => Ok(MeshFormat::Glb),
+            _ => Err("Unknown mesh format, expected 'ply', 'obj' or 'glb'"),
+        }
+    }
+}
+
+// error of the writers, no format describing an empty mesh properly
+const EMPTY_MESH: &str = "The isosurface has no triangles, no voxel crossing the threshold";
+
+// Corner offsets of a cell
+const CORNERS: [(u32, u32, u32); 8] = [
+    (0, 0, 0),
+    (1, 0, 0),
+    (1, 1, 0),
+    (0, 1, 0),
+    (0, 0, 1),
+    (1, 0, 1),
+    (1, 1, 1),
+    (0, 1, 1),
+];
+
+// Corners joined by each of the 12 edges of a cell
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (1, 2),
+    (2, 3),
+    (3, 0),
+    (4, 5),
+    (5, 6),
+    (6, 7),
+    (7, 4),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+// Edges cut by the isosurface for each of the 256 corner configurations
+#[rustfmt::skip]
+const EDGE_TABLE: [u16; 256] = [
+    0x000, 0x109, 0x203, 0x30a, 0x406, 0x50f, 0x605, 0x70c,
+    0x80c, 0x905, 0xa0f, 0xb06, 0xc0a, 0xd03, 0xe09, 0xf00,
+    0x190, 0x099, 0x393, 0x29a, 0x596, 0x49f, 0x795, 0x69c,
+    0x99c, 0x895, 0xb9f, 0xa96, 0xd9a, 0xc93, 0xf99, 0xe90,
+    0x230, 0x339, 0x033, 0x13a, 0x636, 0x73f, 0x435, 0x53c,
+    0xa3c, 0xb35, 0x83f, 0x936, 0xe3a, 0xf33, 0xc39, 0xd30,
+    0x3a0, 0x2a9, 0x1a3, 0x0aa, 0x7a6, 0x6af, 0x5a5, 0x4ac,
+    0xbac, 0xaa5, 0x9af, 0x8a6, 0xfaa, 0xea3, 0xda9, 0xca0,
+    0x460, 0x569, 0x663, 0x76a, 0x066, 0x16f, 0x265, 0x36c,
+    0xc6c, 0xd65, 0xe6f, 0xf66, 0x86a, 0x963, 0xa69, 0xb60,
+    0x5f0, 0x4f9, 0x7f3, 0x6fa, 0x1f6, 0x0ff, 0x3f5, 0x2fc,
+    0xdfc, 0xcf5, 0xfff, 0xef6, 0x9fa, 0x8f3, 0xbf9, 0xaf0,
+    0x650, 0x759, 0x453, 0x55a, 0x256, 0x35f, 0x055, 0x15c,
+    0xe5c, 0xf55, 0xc5f, 0xd56, 0xa5a, 0xb53, 0x859, 0x950,
+    0x7c0, 0x6c9, 0x5c3, 0x4ca, 0x3c6, 0x2cf, 0x1c5, 0x0cc,
+    0xfcc, 0xec5, 0xdcf, 0xcc6, 0xbca, 0xac3, 0x9c9, 0x8c0,
+    0x8c0, 0x9c9, 0xac3, 0xbca, 0xcc6, 0xdcf, 0xec5, 0xfcc,
+    0x0cc, 0x1c5, 0x2cf, 0x3c6, 0x4ca, 0x5c3, 0x6c9, 0x7c0,
+    0x950, 0x859, 0xb53, 0xa5a, 0xd56, 0xc5f, 0xf55, 0xe5c,
+    0x15c, 0x055, 0x35f, 0x256, 0x55a, 0x453, 0x759, 0x650,
+    0xaf0, 0xbf9, 0x8f3, 0x9fa, 0xef6, 0xfff, 0xcf5, 0xdfc,
+    0x2fc, 0x3f5, 0x0ff, 0x1f6, 0x6fa, 0x7f3, 0x4f9, 0x5f0,
+    0xb60, 0xa69, 0x963, 0x86a, 0xf66, 0xe6f, 0xd65, 0xc6c,
+    0x36c, 0x265, 0x16f, 0x066, 0x76a, 0x663, 0x569, 0x460,
+    0xca0, 0xda9, 0xea3, 0xfaa, 0x8a6, 0x9af, 0xaa5, 0xbac,
+    0x4ac, 0x5a5, 0x6af, 0x7a6, 0x0aa, 0x1a3, 0x2a9, 0x3a0,
+    0xd30, 0xc39, 0xf33, 0xe3a, 0x936, 0x83f, 0xb35, 0xa3c,
+    0x53c, 0x435, 0x73f, 0x636, 0x13a, 0x033, 0x339, 0x230,
+    0xe90, 0xf99, 0xc93, 0xd9a, 0xa96, 0xb9f, 0x895, 0x99c,
+    0x69c, 0x795, 0x49f, 0x596, 0x29a, 0x393, 0x099, 0x190,
+    0xf00, 0xe09, 0xd03, 0xc0a, 0xb06, 0xa0f, 0x905, 0x80c,
+    0x70c, 0x605, 0x50f, 0x406, 0x30a, 0x203, 0x109, 0x000,
+];
+
+// Triangles (as triples of cut edges) for each corner configuration,
+// terminated by -1
+#[rustfmt::skip]
+const TRI_TABLE: [[i8; 16]; 256] = [
+    [-1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 1, 3, 8, 9, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 10, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 1, 10, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 10, 2, 0, 9, 10, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 10, 2, 3, 9, 10, 3, 8, 9, -1, -1, -1, -1, -1, -1, -1],
+    [2, 11, 3, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 0, 2, 11, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 2, 11, 3, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 9, 1, 2, 8, 9, 2, 11, 8, -1, -1, -1, -1, -1, -1, -1],
+    [1, 11, 3, 1, 10, 11, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 0, 1, 11, 8, 1, 10, 11, -1, -1, -1, -1, -1, -1, -1],
+    [0, 11, 3, 0, 10, 11, 0, 9, 10, -1, -1, -1, -1, -1, -1, -1],
+    [9, 11, 8, 9, 10, 11, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [4, 8, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 4, 0, 3, 7, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 4, 8, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 1, 3, 4, 9, 3, 7, 4, -1, -1, -1, -1, -1, -1, -1],
+    [1, 10, 2, 4, 8, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 4, 0, 3, 7, 4, 1, 10, 2, -1, -1, -1, -1, -1, -1, -1],
+    [0, 10, 2, 0, 9, 10, 4, 8, 7, -1, -1, -1, -1, -1, -1, -1],
+    [3, 10, 2, 3, 9, 10, 3, 4, 9, 3, 7, 4, -1, -1, -1, -1],
+    [2, 11, 3, 4, 8, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 4, 0, 2, 7, 4, 2, 11, 7, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 2, 11, 3, 4, 8, 7, -1, -1, -1, -1, -1, -1, -1],
+    [2, 9, 1, 2, 4, 9, 2, 7, 4, 2, 11, 7, -1, -1, -1, -1],
+    [1, 11, 3, 1, 10, 11, 4, 8, 7, -1, -1, -1, -1, -1, -1, -1],
+    [1, 4, 0, 1, 7, 4, 1, 11, 7, 1, 10, 11, -1, -1, -1, -1],
+    [0, 11, 3, 0, 10, 11, 0, 9, 10, 4, 8, 7, -1, -1, -1, -1],
+    [4, 11, 7, 4, 10, 11, 4, 9, 10, -1, -1, -1, -1, -1, -1, -1],
+    [5, 9, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 5, 9, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 5, 1, 0, 4, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 5, 1, 3, 4, 5, 3, 8, 4, -1, -1, -1, -1, -1, -1, -1],
+    [1, 10, 2, 5, 9, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 1, 10, 2, 5, 9, 4, -1, -1, -1, -1, -1, -1, -1],
+    [0, 10, 2, 0, 5, 10, 0, 4, 5, -1, -1, -1, -1, -1, -1, -1],
+    [3, 10, 2, 3, 5, 10, 3, 4, 5, 3, 8, 4, -1, -1, -1, -1],
+    [2, 11, 3, 5, 9, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 0, 2, 11, 8, 5, 9, 4, -1, -1, -1, -1, -1, -1, -1],
+    [0, 5, 1, 0, 4, 5, 2, 11, 3, -1, -1, -1, -1, -1, -1, -1],
+    [2, 5, 1, 2, 4, 5, 2, 8, 4, 2, 11, 8, -1, -1, -1, -1],
+    [1, 11, 3, 1, 10, 11, 5, 9, 4, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 0, 1, 11, 8, 1, 10, 11, 5, 9, 4, -1, -1, -1, -1],
+    [0, 11, 3, 0, 10, 11, 0, 5, 10, 0, 4, 5, -1, -1, -1, -1],
+    [5, 8, 4, 5, 11, 8, 5, 10, 11, -1, -1, -1, -1, -1, -1, -1],
+    [5, 8, 7, 5, 9, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 0, 3, 5, 9, 3, 7, 5, -1, -1, -1, -1, -1, -1, -1],
+    [0, 5, 1, 0, 7, 5, 0, 8, 7, -1, -1, -1, -1, -1, -1, -1],
+    [3, 5, 1, 3, 7, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 10, 2, 5, 8, 7, 5, 9, 8, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 0, 3, 5, 9, 3, 7, 5, 1, 10, 2, -1, -1, -1, -1],
+    [0, 10, 2, 0, 5, 10, 0, 7, 5, 0, 8, 7, -1, -1, -1, -1],
+    [3, 10, 2, 3, 5, 10, 3, 7, 5, -1, -1, -1, -1, -1, -1, -1],
+    [2, 11, 3, 5, 8, 7, 5, 9, 8, -1, -1, -1, -1, -1, -1, -1],
+    [2, 9, 0, 2, 5, 9, 2, 7, 5, 2, 11, 7, -1, -1, -1, -1],
+    [0, 5, 1, 0, 7, 5, 0, 8, 7, 2, 11, 3, -1, -1, -1, -1],
+    [2, 5, 1, 2, 7, 5, 2, 11, 7, -1, -1, -1, -1, -1, -1, -1],
+    [1, 11, 3, 1, 10, 11, 5, 8, 7, 5, 9, 8, -1, -1, -1, -1],
+    [1, 9, 0, 1, 5, 9, 1, 7, 5, 1, 11, 7, 1, 10, 11, -1],
+    [0, 11, 3, 0, 10, 11, 0, 5, 10, 0, 7, 5, 0, 8, 7, -1],
+    [5, 11, 7, 5, 10, 11, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [6, 10, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 1, 3, 8, 9, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [1, 6, 2, 1, 5, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 1, 6, 2, 1, 5, 6, -1, -1, -1, -1, -1, -1, -1],
+    [0, 6, 2, 0, 5, 6, 0, 9, 5, -1, -1, -1, -1, -1, -1, -1],
+    [3, 6, 2, 3, 5, 6, 3, 9, 5, 3, 8, 9, -1, -1, -1, -1],
+    [2, 11, 3, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 0, 2, 11, 8, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 2, 11, 3, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [2, 9, 1, 2, 8, 9, 2, 11, 8, 6, 10, 5, -1, -1, -1, -1],
+    [1, 11, 3, 1, 6, 11, 1, 5, 6, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 0, 1, 11, 8, 1, 6, 11, 1, 5, 6, -1, -1, -1, -1],
+    [0, 11, 3, 0, 6, 11, 0, 5, 6, 0, 9, 5, -1, -1, -1, -1],
+    [6, 9, 5, 6, 8, 9, 6, 11, 8, -1, -1, -1, -1, -1, -1, -1],
+    [4, 8, 7, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 4, 0, 3, 7, 4, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 4, 8, 7, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 1, 3, 4, 9, 3, 7, 4, 6, 10, 5, -1, -1, -1, -1],
+    [1, 6, 2, 1, 5, 6, 4, 8, 7, -1, -1, -1, -1, -1, -1, -1],
+    [3, 4, 0, 3, 7, 4, 1, 6, 2, 1, 5, 6, -1, -1, -1, -1],
+    [0, 6, 2, 0, 5, 6, 0, 9, 5, 4, 8, 7, -1, -1, -1, -1],
+    [3, 6, 2, 3, 5, 6, 3, 9, 5, 3, 4, 9, 3, 7, 4, -1],
+    [2, 11, 3, 4, 8, 7, 6, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [2, 4, 0, 2, 7, 4, 2, 11, 7, 6, 10, 5, -1, -1, -1, -1],
+    [0, 9, 1, 2, 11, 3, 4, 8, 7, 6, 10, 5, -1, -1, -1, -1],
+    [2, 9, 1, 2, 4, 9, 2, 7, 4, 2, 11, 7, 6, 10, 5, -1],
+    [1, 11, 3, 1, 6, 11, 1, 5, 6, 4, 8, 7, -1, -1, -1, -1],
+    [1, 4, 0, 1, 7, 4, 1, 11, 7, 1, 6, 11, 1, 5, 6, -1],
+    [0, 11, 3, 0, 6, 11, 0, 5, 6, 0, 9, 5, 4, 8, 7, -1],
+    [4, 11, 7, 4, 6, 11, 4, 5, 6, 4, 9, 5, -1, -1, -1, -1],
+    [6, 9, 4, 6, 10, 9, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 6, 9, 4, 6, 10, 9, -1, -1, -1, -1, -1, -1, -1],
+    [0, 10, 1, 0, 6, 10, 0, 4, 6, -1, -1, -1, -1, -1, -1, -1],
+    [3, 10, 1, 3, 6, 10, 3, 4, 6, 3, 8, 4, -1, -1, -1, -1],
+    [1, 6, 2, 1, 4, 6, 1, 9, 4, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 1, 6, 2, 1, 4, 6, 1, 9, 4, -1, -1, -1, -1],
+    [0, 6, 2, 0, 4, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 6, 2, 3, 4, 6, 3, 8, 4, -1, -1, -1, -1, -1, -1, -1],
+    [2, 11, 3, 6, 9, 4, 6, 10, 9, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 0, 2, 11, 8, 6, 9, 4, 6, 10, 9, -1, -1, -1, -1],
+    [0, 10, 1, 0, 6, 10, 0, 4, 6, 2, 11, 3, -1, -1, -1, -1],
+    [2, 10, 1, 2, 6, 10, 2, 4, 6, 2, 8, 4, 2, 11, 8, -1],
+    [1, 11, 3, 1, 6, 11, 1, 4, 6, 1, 9, 4, -1, -1, -1, -1],
+    [1, 8, 0, 1, 11, 8, 1, 6, 11, 1, 4, 6, 1, 9, 4, -1],
+    [0, 11, 3, 0, 6, 11, 0, 4, 6, -1, -1, -1, -1, -1, -1, -1],
+    [6, 8, 4, 6, 11, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [6, 8, 7, 6, 9, 8, 6, 10, 9, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 0, 3, 10, 9, 3, 6, 10, 3, 7, 6, -1, -1, -1, -1],
+    [0, 10, 1, 0, 6, 10, 0, 7, 6, 0, 8, 7, -1, -1, -1, -1],
+    [3, 10, 1, 3, 6, 10, 3, 7, 6, -1, -1, -1, -1, -1, -1, -1],
+    [1, 6, 2, 1, 7, 6, 1, 8, 7, 1, 9, 8, -1, -1, -1, -1],
+    [3, 9, 0, 3, 1, 9, 3, 2, 1, 3, 6, 2, 3, 7, 6, -1],
+    [0, 6, 2, 0, 7, 6, 0, 8, 7, -1, -1, -1, -1, -1, -1, -1],
+    [3, 6, 2, 3, 7, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 11, 3, 6, 8, 7, 6, 9, 8, 6, 10, 9, -1, -1, -1, -1],
+    [2, 9, 0, 2, 10, 9, 2, 6, 10, 2, 7, 6, 2, 11, 7, -1],
+    [0, 10, 1, 0, 6, 10, 0, 7, 6, 0, 8, 7, 2, 11, 3, -1],
+    [2, 10, 1, 2, 6, 10, 2, 7, 6, 2, 11, 7, -1, -1, -1, -1],
+    [1, 11, 3, 1, 6, 11, 1, 7, 6, 1, 8, 7, 1, 9, 8, -1],
+    [1, 9, 0, 6, 11, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 11, 3, 0, 6, 11, 0, 7, 6, 0, 8, 7, -1, -1, -1, -1],
+    [6, 11, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [7, 11, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 1, 3, 8, 9, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [1, 10, 2, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 1, 10, 2, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [0, 10, 2, 0, 9, 10, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [3, 10, 2, 3, 9, 10, 3, 8, 9, 7, 11, 6, -1, -1, -1, -1],
+    [2, 7, 3, 2, 6, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 0, 2, 7, 8, 2, 6, 7, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 2, 7, 3, 2, 6, 7, -1, -1, -1, -1, -1, -1, -1],
+    [2, 9, 1, 2, 8, 9, 2, 7, 8, 2, 6, 7, -1, -1, -1, -1],
+    [1, 7, 3, 1, 6, 7, 1, 10, 6, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 0, 1, 7, 8, 1, 6, 7, 1, 10, 6, -1, -1, -1, -1],
+    [0, 7, 3, 0, 6, 7, 0, 10, 6, 0, 9, 10, -1, -1, -1, -1],
+    [7, 10, 6, 7, 9, 10, 7, 8, 9, -1, -1, -1, -1, -1, -1, -1],
+    [4, 11, 6, 4, 8, 11, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 4, 0, 3, 6, 4, 3, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 4, 11, 6, 4, 8, 11, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 1, 3, 4, 9, 3, 6, 4, 3, 11, 6, -1, -1, -1, -1],
+    [1, 10, 2, 4, 11, 6, 4, 8, 11, -1, -1, -1, -1, -1, -1, -1],
+    [3, 4, 0, 3, 6, 4, 3, 11, 6, 1, 10, 2, -1, -1, -1, -1],
+    [0, 10, 2, 0, 9, 10, 4, 11, 6, 4, 8, 11, -1, -1, -1, -1],
+    [3, 10, 2, 3, 9, 10, 3, 4, 9, 3, 6, 4, 3, 11, 6, -1],
+    [2, 8, 3, 2, 4, 8, 2, 6, 4, -1, -1, -1, -1, -1, -1, -1],
+    [2, 4, 0, 2, 6, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 2, 8, 3, 2, 4, 8, 2, 6, 4, -1, -1, -1, -1],
+    [2, 9, 1, 2, 4, 9, 2, 6, 4, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 3, 1, 4, 8, 1, 6, 4, 1, 10, 6, -1, -1, -1, -1],
+    [1, 4, 0, 1, 6, 4, 1, 10, 6, -1, -1, -1, -1, -1, -1, -1],
+    [0, 8, 3, 0, 4, 8, 0, 6, 4, 0, 10, 6, 0, 9, 10, -1],
+    [4, 10, 6, 4, 9, 10, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [5, 9, 4, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 5, 9, 4, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [0, 5, 1, 0, 4, 5, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [3, 5, 1, 3, 4, 5, 3, 8, 4, 7, 11, 6, -1, -1, -1, -1],
+    [1, 10, 2, 5, 9, 4, 7, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 1, 10, 2, 5, 9, 4, 7, 11, 6, -1, -1, -1, -1],
+    [0, 10, 2, 0, 5, 10, 0, 4, 5, 7, 11, 6, -1, -1, -1, -1],
+    [3, 10, 2, 3, 5, 10, 3, 4, 5, 3, 8, 4, 7, 11, 6, -1],
+    [2, 7, 3, 2, 6, 7, 5, 9, 4, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 0, 2, 7, 8, 2, 6, 7, 5, 9, 4, -1, -1, -1, -1],
+    [0, 5, 1, 0, 4, 5, 2, 7, 3, 2, 6, 7, -1, -1, -1, -1],
+    [2, 5, 1, 2, 4, 5, 2, 8, 4, 2, 7, 8, 2, 6, 7, -1],
+    [1, 7, 3, 1, 6, 7, 1, 10, 6, 5, 9, 4, -1, -1, -1, -1],
+    [1, 8, 0, 1, 7, 8, 1, 6, 7, 1, 10, 6, 5, 9, 4, -1],
+    [0, 7, 3, 0, 6, 7, 0, 10, 6, 0, 5, 10, 0, 4, 5, -1],
+    [5, 8, 4, 5, 7, 8, 5, 6, 7, 5, 10, 6, -1, -1, -1, -1],
+    [5, 11, 6, 5, 8, 11, 5, 9, 8, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 0, 3, 5, 9, 3, 6, 5, 3, 11, 6, -1, -1, -1, -1],
+    [0, 5, 1, 0, 6, 5, 0, 11, 6, 0, 8, 11, -1, -1, -1, -1],
+    [3, 5, 1, 3, 6, 5, 3, 11, 6, -1, -1, -1, -1, -1, -1, -1],
+    [1, 10, 2, 5, 11, 6, 5, 8, 11, 5, 9, 8, -1, -1, -1, -1],
+    [3, 9, 0, 3, 5, 9, 3, 6, 5, 3, 11, 6, 1, 10, 2, -1],
+    [0, 10, 2, 0, 5, 10, 0, 6, 5, 0, 11, 6, 0, 8, 11, -1],
+    [3, 10, 2, 3, 5, 10, 3, 6, 5, 3, 11, 6, -1, -1, -1, -1],
+    [2, 8, 3, 2, 9, 8, 2, 5, 9, 2, 6, 5, -1, -1, -1, -1],
+    [2, 9, 0, 2, 5, 9, 2, 6, 5, -1, -1, -1, -1, -1, -1, -1],
+    [0, 5, 1, 0, 6, 5, 0, 2, 6, 0, 3, 2, 0, 8, 3, -1],
+    [2, 5, 1, 2, 6, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 3, 1, 9, 8, 1, 5, 9, 1, 6, 5, 1, 10, 6, -1],
+    [1, 9, 0, 1, 5, 9, 1, 6, 5, 1, 10, 6, -1, -1, -1, -1],
+    [0, 8, 3, 5, 10, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [5, 10, 6, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [7, 10, 5, 7, 11, 10, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 7, 10, 5, 7, 11, 10, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 7, 10, 5, 7, 11, 10, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 1, 3, 8, 9, 7, 10, 5, 7, 11, 10, -1, -1, -1, -1],
+    [1, 11, 2, 1, 7, 11, 1, 5, 7, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 1, 11, 2, 1, 7, 11, 1, 5, 7, -1, -1, -1, -1],
+    [0, 11, 2, 0, 7, 11, 0, 5, 7, 0, 9, 5, -1, -1, -1, -1],
+    [3, 11, 2, 3, 7, 11, 3, 5, 7, 3, 9, 5, 3, 8, 9, -1],
+    [2, 7, 3, 2, 5, 7, 2, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 0, 2, 7, 8, 2, 5, 7, 2, 10, 5, -1, -1, -1, -1],
+    [0, 9, 1, 2, 7, 3, 2, 5, 7, 2, 10, 5, -1, -1, -1, -1],
+    [2, 9, 1, 2, 8, 9, 2, 7, 8, 2, 5, 7, 2, 10, 5, -1],
+    [1, 7, 3, 1, 5, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 0, 1, 7, 8, 1, 5, 7, -1, -1, -1, -1, -1, -1, -1],
+    [0, 7, 3, 0, 5, 7, 0, 9, 5, -1, -1, -1, -1, -1, -1, -1],
+    [7, 9, 5, 7, 8, 9, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [4, 10, 5, 4, 11, 10, 4, 8, 11, -1, -1, -1, -1, -1, -1, -1],
+    [3, 4, 0, 3, 5, 4, 3, 10, 5, 3, 11, 10, -1, -1, -1, -1],
+    [0, 9, 1, 4, 10, 5, 4, 11, 10, 4, 8, 11, -1, -1, -1, -1],
+    [3, 9, 1, 3, 4, 9, 3, 5, 4, 3, 10, 5, 3, 11, 10, -1],
+    [1, 11, 2, 1, 8, 11, 1, 4, 8, 1, 5, 4, -1, -1, -1, -1],
+    [3, 4, 0, 3, 5, 4, 3, 1, 5, 3, 2, 1, 3, 11, 2, -1],
+    [0, 11, 2, 0, 8, 11, 0, 4, 8, 0, 5, 4, 0, 9, 5, -1],
+    [3, 11, 2, 4, 9, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 3, 2, 4, 8, 2, 5, 4, 2, 10, 5, -1, -1, -1, -1],
+    [2, 4, 0, 2, 5, 4, 2, 10, 5, -1, -1, -1, -1, -1, -1, -1],
+    [0, 9, 1, 2, 8, 3, 2, 4, 8, 2, 5, 4, 2, 10, 5, -1],
+    [2, 9, 1, 2, 4, 9, 2, 5, 4, 2, 10, 5, -1, -1, -1, -1],
+    [1, 8, 3, 1, 4, 8, 1, 5, 4, -1, -1, -1, -1, -1, -1, -1],
+    [1, 4, 0, 1, 5, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 8, 3, 0, 4, 8, 0, 5, 4, 0, 9, 5, -1, -1, -1, -1],
+    [4, 9, 5, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [7, 9, 4, 7, 10, 9, 7, 11, 10, -1, -1, -1, -1, -1, -1, -1],
+    [3, 8, 0, 7, 9, 4, 7, 10, 9, 7, 11, 10, -1, -1, -1, -1],
+    [0, 10, 1, 0, 11, 10, 0, 7, 11, 0, 4, 7, -1, -1, -1, -1],
+    [3, 10, 1, 3, 11, 10, 3, 7, 11, 3, 4, 7, 3, 8, 4, -1],
+    [1, 11, 2, 1, 7, 11, 1, 4, 7, 1, 9, 4, -1, -1, -1, -1],
+    [3, 8, 0, 1, 11, 2, 1, 7, 11, 1, 4, 7, 1, 9, 4, -1],
+    [0, 11, 2, 0, 7, 11, 0, 4, 7, -1, -1, -1, -1, -1, -1, -1],
+    [3, 11, 2, 3, 7, 11, 3, 4, 7, 3, 8, 4, -1, -1, -1, -1],
+    [2, 7, 3, 2, 4, 7, 2, 9, 4, 2, 10, 9, -1, -1, -1, -1],
+    [2, 8, 0, 2, 7, 8, 2, 4, 7, 2, 9, 4, 2, 10, 9, -1],
+    [0, 10, 1, 0, 2, 10, 0, 3, 2, 0, 7, 3, 0, 4, 7, -1],
+    [2, 10, 1, 7, 8, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 7, 3, 1, 4, 7, 1, 9, 4, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 0, 1, 7, 8, 1, 4, 7, 1, 9, 4, -1, -1, -1, -1],
+    [0, 7, 3, 0, 4, 7, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [7, 8, 4, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [8, 10, 9, 8, 11, 10, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 0, 3, 10, 9, 3, 11, 10, -1, -1, -1, -1, -1, -1, -1],
+    [0, 10, 1, 0, 11, 10, 0, 8, 11, -1, -1, -1, -1, -1, -1, -1],
+    [3, 10, 1, 3, 11, 10, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 11, 2, 1, 8, 11, 1, 9, 8, -1, -1, -1, -1, -1, -1, -1],
+    [3, 9, 0, 3, 1, 9, 3, 2, 1, 3, 11, 2, -1, -1, -1, -1],
+    [0, 11, 2, 0, 8, 11, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [3, 11, 2, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [2, 8, 3, 2, 9, 8, 2, 10, 9, -1, -1, -1, -1, -1, -1, -1],
+    [2, 9, 0, 2, 10, 9, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 10, 1, 0, 2, 10, 0, 3, 2, 0, 8, 3, -1, -1, -1, -1],
+    [2, 10, 1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 8, 3, 1, 9, 8, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [1, 9, 0, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [0, 8, 3, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+    [-1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
+];
+
+impl Mesh {
+    /// Extract the isosurface of the cube at `threshold`
+    ///
+    /// Vertices lying on the same cell edge are shared between the
+    /// neighbouring cells so that the mesh is connected. Blank (NaN) voxels
+    /// are considered below the threshold.
+    pub fn marching_cubes(cube: &Cube, threshold: f32) -> Self {
+        let (w, h, d) = cube.dim;
+        let mut mesh = Mesh::default();
+        if w < 2 || h < 2 || d < 2 {
+            return mesh;
+        }
+
+        // vertex index of each cut edge, keyed by its first voxel and its axis
+        let mut edge_vertices: HashMap<u64, u32> = HashMap::new();
+
+        for z in 0..(d - 1) {
+            for y in 0..(h - 1) {
+                for x in 0..(w - 1) {
+                    let mut values = [0.0_f32; 8];
+                    let mut config = 0;
+                    for (i, (dx, dy, dz)) in CORNERS.iter().enumerate() {
+                        values[i] = cube.value(x + dx, y + dy, z + dz);
+                        if values[i] >= threshold {
+                            config |= 1 << i;
+                        }
+                    }
+
+                    if EDGE_TABLE[config] == 0 {
+                        continue;
+                    }
+
+                    for &e in TRI_TABLE[config].iter().take_while(|&&e| e >= 0) {
+                        let (a, b) = EDGES[e as usize];
+                        let (ca, cb) = (CORNERS[a], CORNERS[b]);
+
+                        let (x0, y0, z0) =
+                            (x + ca.0.min(cb.0), y + ca.1.min(cb.1), z + ca.2.min(cb.2));
+                        let axis = if ca.0 != cb.0 {
+                            0
+                        } else if ca.1 != cb.1 {
+                            1
+                        } else {
+                            2
+                        };
+                        let key = (((z0 as u64) * (h as u64) + (y0 as u64)) * (w as u64)
+                            + (x0 as u64))
+                            * 3
+                            + axis;
+
+                        let idx = *edge_vertices.entry(key).or_insert_with(|| {
+                            let (va, vb) = (values[a], values[b]);
+                            // a blank value gives a cut in the middle of the edge
+                            let t = ((threshold - va) / (vb - va)).clamp(0.0, 1.0);
+                            let t = if t.is_nan() { 0.5 } else { t };
+
+                            let lerp = |pa: u32, pb: u32| pa as f32 + t * (pb as f32 - pa as f32);
+                            mesh.positions.push([
+                                lerp(x + ca.0, x + cb.0),
+                                lerp(y + ca.1, y + cb.1),
+                                lerp(z + ca.2, z + cb.2),
+                            ]);
+                            (mesh.positions.len() - 1) as u32
+                        });
+                        mesh.indices.push(idx);
+                    }
+                }
+            }
+        }
+
+        mesh
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Smooth the mesh with Taubin's lambda/mu algorithm
+    ///
+    /// Contrary to a plain laplacian smoothing, it does not shrink the surface
+    pub fn smooth(&mut self, iterations: usize) {
+        if iterations == 0 {
+            return;
+        }
+
+        let mut neighbours: Vec<Vec<u32>> = vec![vec![]; self.positions.len()];
+        for tri in self.indices.chunks_exact(3) {
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                if !neighbours[a as usize].contains(&b) {
+                    neighbours[a as usize].push(b);
+                    neighbours[b as usize].push(a);
+                }
+            }
+        }
+
+        const LAMBDA: f32 = 0.5;
+        const MU: f32 = -0.53;
+        for _ in 0..iterations {
+            for factor in [LAMBDA, MU] {
+                let positions = self
+                    .positions
+                    .iter()
+                    .zip(neighbours.iter())
+                    .map(|(p, n)| {
+                        if n.is_empty() {
+                            return *p;
+                        }
+
+                        let mut mean = [0.0; 3];
+                        for &i in n {
+                            let q = self.positions[i as usize];
+                            mean[0] += q[0];
+                            mean[1] += q[1];
+                            mean[2] += q[2];
+                        }
+                        let num = n.len() as f32;
+                        [
+                            p[0] + factor * (mean[0] / num - p[0]),
+                            p[1] + factor * (mean[1] / num - p[1]),
+                            p[2] + factor * (mean[2] / num - p[2]),
+                        ]
+                    })
+                    .collect();
+
+                self.positions = positions;
+            }
+        }
+    }
+
+    /// Convert the vertices from voxel indices to the linear world coordinates of `wcs`,
+    /// CRVAL plus the offsets from CRPIX through PC and CDELT, without the celestial
+    /// projection
+    pub fn to_world(&mut self, wcs: &Wcs) {
+        for p in self.positions.iter_mut() {
+            let x = wcs.intermediate(p.map(f64::from));
+            *p = [0, 1, 2].map(|i| (wcs.axes[i].crval + x[i]) as f32);
+        }
+
+        // a transformation of negative determinant (e.g. RA increasing to the left)
+        // mirrors the mesh, restore the winding of the triangles
+        if wcs::determinant(&wcs.linear_matrix()) < 0.0 {
+            for tri in self.indices.chunks_exact_mut(3) {
+                tri.swap(1, 2);
+            }
+        }
+    }
+
+    /// Area weighted normals of the vertices
+    pub fn normals(&self) -> Vec<[f32; 3]> {
+        let mut normals = vec![[0.0_f32; 3]; self.positions.len()];
+        for tri in self.indices.chunks_exact(3) {
+            let (a, b, c) = (
+                self.positions[tri[0] as usize],
+                self.positions[tri[1] as usize],
+                self.positions[tri[2] as usize],
+            );
+            let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+            let n = [
+                u[1] * v[2] - u[2] * v[1],
+                u[2] * v[0] - u[0] * v[2],
+                u[0] * v[1] - u[1] * v[0],
+            ];
+
+            for &i in tri {
+                let normal = &mut normals[i as usize];
+                normal[0] += n[0];
+                normal[1] += n[1];
+                normal[2] += n[2];
+            }
+        }
+
+        for n in normals.iter_mut() {
+            let norm = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if norm > 0.0 {
+                *n = [n[0] / norm, n[1] / norm, n[2] / norm];
+            }
+        }
+
+        normals
+    }
+
+    /// Write the mesh in `format`, refusing an empty one which no format can describe
+    /// properly (e.g. glTF requires accessors of at least one element)
+    pub fn write<W: Write>(&self, format: MeshFormat, writer: &mut W) -> std::io::Result<()> {
+        if self.num_triangles() == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                EMPTY_MESH,
+            ));
+        }
+        match format {
+            MeshFormat::Ply => self.write_ply(writer),
+            MeshFormat::Obj => self.write_obj(writer),
+            MeshFormat::Glb => self.write_glb(writer),
+        }
+    }
+
+    pub fn to_bytes(&self, format: MeshFormat) -> Result<Vec<u8>, &'static str> {
+        if self.num_triangles() == 0 {
+            return Err(EMPTY_MESH);
+        }
+        let mut bytes = vec![];
+        // writing a mesh with triangles into a vec cannot fail
+        self.write(format, &mut bytes).unwrap();
+        Ok(bytes)
+    }
+
+    fn write_ply<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let normals = self.normals();
+
+        write!(
+            w,
+            "ply\nformat binary_little_endian 1.0\ncomment generated by fits3\n\
+             element vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\n\
+             element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+            self.positions.len(),
+            self.num_triangles()
+        )?;
+
+        for (p, n) in self.positions.iter().zip(normals.iter()) {
+            for v in p.iter().chain(n.iter()) {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+
+        for tri in self.indices.chunks_exact(3) {
+            w.write_all(&[3])?;
+            for i in tri {
+                w.write_all(&i.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_obj<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let normals = self.normals();
+
+        writeln!(w, "# generated by fits3")?;
+        for p in &self.positions {
+            writeln!(w, "v {} {} {}", p[0], p[1], p[2])?;
+        }
+        for n in &normals {
+            writeln!(w, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+        // OBJ indices start at 1
+        for tri in self.indices.chunks_exact(3) {
+            let (a, b, c) = (tri[0] + 1, tri[1] + 1, tri[2] + 1);
+            writeln!(w, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_glb<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let normals = self.normals();
+
+        // binary buffer: positions, then normals, then indices
+        let mut bin: Vec<u8> = vec![];
+        for p in self.positions.iter().chain(normals.iter()) {
+            for v in p {
+                bin.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        for i in &self.indices {
+            bin.extend_from_slice(&i.to_le_bytes());
+        }
+
+        let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+        for p in &self.positions {
+            for k in 0..3 {
+                min[k] = min[k].min(p[k]);
+                max[k] = max[k].max(p[k]);
+            }
+        }
+
+        let num_vertices = self.positions.len();
+        let vertices_len = num_vertices * 12;
+        let indices_len = self.indices.len() * 4;
+        let json = format!(
+            r#"{{"asset":{{"version":"2.0","generator":"fits3"}},"scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1}},"indices":2}}]}}],"buffers":[{{"byteLength":{}}}],"bufferViews":[{{"buffer":0,"byteOffset":0,"byteLength":{},"target":34962}},{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34962}},{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34963}}],"accessors":[{{"bufferView":0,"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}},{{"bufferView":1,"componentType":5126,"count":{},"type":"VEC3"}},{{"bufferView":2,"componentType":5125,"count":{},"type":"SCALAR"}}]}}"#,
+            bin.len(),
+            vertices_len,
+            vertices_len,
+            vertices_len,
+            2 * vertices_len,
+            indices_len,
+            num_vertices,
+            min[0],
+            min[1],
+            min[2],
+            max[0],
+            max[1],
+            max[2],
+            num_vertices,
+            self.indices.len(),
+        );
+
+        // chunks must be aligned on 4 bytes
+        let mut json = json.into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+
+        let total_len = 12 + 8 + json.len() + 8 + bin.len();
+        w.write_all(b"glTF")?;
+        w.write_all(&2_u32.to_le_bytes())?;
+        w.write_all(&(total_len as u32).to_le_bytes())?;
+
+        w.write_all(&(json.len() as u32).to_le_bytes())?;
+        w.write_all(b"JSON")?;
+        w.write_all(&json)?;
+
+        w.write_all(&(bin.len() as u32).to_le_bytes())?;
+        w.write_all(b"BIN\0")?;
+        w.write_all(&bin)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    // cube of `n`^3 voxels whose values decrease away from its center, the
+    // isosurface at 0 being a sphere of `radius` voxels
+    fn sphere(n: u32, radius: f32) -> Cube {
+        let c = (n - 1) as f32 / 2.0;
+        let mut data = vec![];
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let (dx, dy, dz) = (x as f32 - c, y as f32 - c, z as f32 - c);
+                    data.push(radius - (dx * dx + dy * dy + dz * dz).sqrt());
+                }
+            }
+        }
+        Cube {
+            data,
+            dim: (n, n, n),
+            datamin: None,
+            datamax: None,
+            bunit: None,
+            wcs: None,
+            name: None,
+        }
+    }
+
+    fn triangle() -> Mesh {
+        Mesh {
+            positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            indices: vec![0, 1, 2],
+        }
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn marching_cubes_sphere() {
+        let (n, radius) = (16, 5.0);
+        let mesh = Mesh::marching_cubes(&sphere(n, radius), 0.0);
+        assert!(mesh.num_triangles() > 100);
+        assert_eq!(mesh.indices.len() % 3, 0);
+
+        // the vertices lie on the sphere
+        let c = (n - 1) as f32 / 2.0;
+        let dist =
+            |p: &[f32; 3]| ((p[0] - c).powi(2) + (p[1] - c).powi(2) + (p[2] - c).powi(2)).sqrt();
+        for p in &mesh.positions {
+            assert!((dist(p) - radius).abs() < 0.1, "vertex at {}", dist(p));
+        }
+
+        // the surface is closed, each edge being shared by two triangles
+        // running along it in opposite directions
+        let mut edges = HashMap::new();
+        for tri in mesh.indices.chunks_exact(3) {
+            for k in 0..3 {
+                *edges.entry((tri[k], tri[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+
+        // the triangles face out of the emission
+        for tri in mesh.indices.chunks_exact(3) {
+            let [a, b, c_] = [0, 1, 2].map(|k| mesh.positions[tri[k] as usize]);
+            let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let v = [c_[0] - a[0], c_[1] - a[1], c_[2] - a[2]];
+            let normal = [
+                u[1] * v[2] - u[2] * v[1],
+                u[2] * v[0] - u[0] * v[2],
+                u[0] * v[1] - u[1] * v[0],
+            ];
+            let out = [a[0] - c, a[1] - c, a[2] - c];
+            assert!(normal[0] * out[0] + normal[1] * out[1] + normal[2] * out[2] > 0.0);
+        }
+    }
+
+    #[test]
+    fn to_world_applies_pc_and_keeps_the_winding() {
+        let axis = |crval: f64, cdelt: f64| crate::wcs::WcsAxis {
+            ctype: String::new(),
+            cunit: String::new(),
+            crval,
+            crpix: 1.0,
+            cdelt,
+        };
+        // the x and y axes swapped by PC, a mirror unless an axis is flipped too
+        let swapped = |cdelt_x: f64| Wcs {
+            axes: [axis(10.0, cdelt_x), axis(20.0, 1.0), axis(30.0, 2.0)],
+            pc: [[0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            frame: Default::default(),
+        };
+
+        let mut mesh = triangle();
+        mesh.to_world(&swapped(1.0));
+        assert_eq!(
+            mesh.positions,
+            [[10.0, 20.0, 30.0], [10.0, 21.0, 30.0], [11.0, 20.0, 30.0]]
+        );
+        assert_eq!(mesh.indices, [0, 2, 1]);
+
+        let mut mesh = triangle();
+        mesh.to_world(&swapped(-1.0));
+        assert_eq!(mesh.positions[2], [9.0, 20.0, 30.0]);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn empty_mesh_is_refused() {
+        let mesh = Mesh::marching_cubes(&sphere(8, 2.0), 10.0);
+        assert_eq!(mesh.num_triangles(), 0);
+        for format in [MeshFormat::Ply, MeshFormat::Obj, MeshFormat::Glb] {
+            let mut bytes = vec![];
+            assert!(mesh.write(format, &mut bytes).is_err());
+            assert!(bytes.is_empty());
+            assert_eq!(mesh.to_bytes(format), Err(EMPTY_MESH));
+        }
+    }
+
+    #[test]
+    fn write_ply() {
+        let bytes = triangle().to_bytes(MeshFormat::Ply).unwrap();
+        let end = b"end_header\n";
+        let header_len = bytes.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = std::str::from_utf8(&bytes[..header_len]).unwrap();
+        assert!(header.starts_with("ply\nformat binary_little_endian 1.0\n"));
+        assert!(header.contains("element vertex 3\n"));
+        assert!(header.contains("element face 1\n"));
+
+        // 3 vertices of 6 floats, then 1 face of a count and 3 indices
+        let body = &bytes[header_len..];
+        assert_eq!(body.len(), 3 * 6 * 4 + 1 + 3 * 4);
+        assert_eq!(f32::from_le_bytes(body[24..28].try_into().unwrap()), 1.0);
+        assert_eq!(body[72], 3);
+        assert_eq!(read_u32(body, 73), 0);
+        assert_eq!(read_u32(body, 81), 2);
+    }
+
+    #[test]
+    fn write_obj() {
+        let bytes = triangle().to_bytes(MeshFormat::Obj).unwrap();
+        let obj = String::from_utf8(bytes).unwrap();
+        let lines: Vec<&str> = obj.lines().collect();
+        assert_eq!(lines.iter().filter(|l| l.starts_with("v ")).count(), 3);
+        assert_eq!(lines.iter().filter(|l| l.starts_with("vn ")).count(), 3);
+        assert!(lines.contains(&"v 1 0 0"));
+        // the normal of a triangle wound counter-clockwise in the xy plane
+        assert!(lines.contains(&"vn 0 0 1"));
+        // 1-based indices
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|l| l.starts_with("f "))
+                .collect::<Vec<_>>(),
+            [&"f 1//1 2//2 3//3"]
+        );
+    }
+
+    #[test]
+    fn write_glb() {
+        let bytes = triangle().to_bytes(MeshFormat::Glb).unwrap();
+        assert_eq!(&bytes[0..4], b"glTF");
+        assert_eq!(read_u32(&bytes, 4), 2);
+        assert_eq!(read_u32(&bytes, 8) as usize, bytes.len());
+
+        let json_len = read_u32(&bytes, 12) as usize;
+        assert_eq!(&bytes[16..20], b"JSON");
+        assert_eq!(json_len % 4, 0);
+        let json = std::str::from_utf8(&bytes[20..20 + json_len]).unwrap();
+        assert!(json.contains(r#""count":3,"type":"VEC3","min":[0,0,0],"max":[1,1,0]"#));
+        assert!(json.contains(r#""count":3,"type":"SCALAR""#));
+
+        // positions and normals of 3 vertices, then 3 indices
+        let bin = &bytes[20 + json_len..];
+        let bin_len = read_u32(bin, 0) as usize;
+        assert_eq!(&bin[4..8], b"BIN\0");
+        assert_eq!(bin_len, 2 * 3 * 12 + 3 * 4);
+        assert_eq!(bin.len(), 8 + bin_len);
+        assert!(json.contains(&format!(r#""buffers":[{{"byteLength":{}}}]"#, bin_len)));
+        assert_eq!(read_u32(bin, 8 + 72 + 8), 2);
+    }
+}
@@ -0,0 +1,485 @@
+//! Moment maps of the cube along its spectral axis
+
+use std::ops::Range;
+
+use crate::channels::ChannelRange;
+use crate::cube::Cube;
+use crate::texture::Texture;
+
+/// Moment computed along the spectral axis of each spaxel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Moment {
+    /// Moment 0, the intensity integrated over the spectral axis
+    Integrated,
+    /// Moment 1, the spectral coordinate weighted by the intensity (e.g. the velocity)
+    Velocity,
+    /// Moment 2, the dispersion of the spectral coordinate around the moment 1
+    Dispersion,
+    /// Maximum of the intensity
+    Peak,
+}
+
+impl Moment {
+    pub(crate) fn next(self) -> Option<Self> {
+        match self {
+            Moment::Integrated => Some(Moment::Velocity),
+            Moment::Velocity => Some(Moment::Dispersion),
+            Moment::Dispersion => Some(Moment::Peak),
+            Moment::Peak => None,
+        }
+    }
+
+    fn as_f32(self) -> f32 {
+        match self {
+            Moment::Integrated => 0.0,
+            Moment::Velocity => 1.0,
+            Moment::Dispersion => 2.0,
+            Moment::Peak => 3.0,
+        }
+    }
+
+    /// Short name, as used in the file names
+    pub fn name(self) -> &'static str {
+        match self {
+            Moment::Integrated => "mom0",
+            Moment::Velocity => "mom1",
+            Moment::Dispersion => "mom2",
+            Moment::Peak => "peak",
+        }
+    }
+}
+
+impl std::str::FromStr for Moment {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" | "mom0" => Ok(Moment::Integrated),
+            "1" | "mom1" => Ok(Moment::Velocity),
+            "2" | "mom2" => Ok(Moment::Dispersion),
+            "peak" => Ok(Moment::Peak),
+            _ => Err("Unknown moment, expected '0', '1', '2' or 'peak'"),
+        }
+    }
+}
+
+/// What a moment map is computed from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MomentSettings {
+    pub moment: Moment,
+    /// Channels integrated, all of them if `None`
+    pub channels: Option<ChannelRange>,
+    /// Samples below are left out, in the data unit
+    pub threshold: Option<f32>,
+}
+
+impl MomentSettings {
+    pub fn new(moment: Moment) -> Self {
+        Self {
+            moment,
+            channels: None,
+            threshold: None,
+        }
+    }
+}
+
+/// Map of a moment over the spatial plane of the cube
+#[derive(Clone, Debug, PartialEq)]
+pub struct MomentMap {
+    pub moment: Moment,
+    /// Width and height in pixels, the ones of the spatial plane of the cube
+    pub size: (u32, u32),
+    /// Values with x varying the fastest, NaN where no sample is kept
+    ///
+    /// The spectral coordinates are given in the unit of CUNIT3, or in channels
+    /// for a cube without WCS, the moment 0 being multiplied by the channel width.
+    pub data: Vec<f32>,
+    pub channels: ChannelRange,
+    pub threshold: Option<f32>,
+}
+
+impl MomentMap {
+    /// Minimum and maximum of the values, the blank ones being ignored
+    pub fn value_range(&self) -> Option<Range<f32>> {
+        let mut values = self.data.iter().copied().filter(|v| v.is_finite());
+        let first = values.next()?;
+        Some(values.fold(first..first, |range, v| {
+            range.start.min(v)..range.end.max(v)
+        }))
+    }
+
+    /// Compute the map on the CPU, for the devices without compute shaders
+    pub(crate) fn from_cube(cube: &Cube, settings: &MomentSettings) -> Self {
+        let (w, h, d) = cube.dim;
+        let channels = settings
+            .channels
+            .map(|range| range.clamp(d))
+            .unwrap_or_else(|| ChannelRange::all(d));
+        let (start, step) = spectral_axis(cube, channels);
+
+        let mut data = Vec::with_capacity((w as usize) * (h as usize));
+        for y in 0..h {
+            for x in 0..w {
+                let (mut sum, mut sum_k, mut sum_k2) = (0.0_f64, 0.0_f64, 0.0_f64);
+                let mut peak = f32::MIN;
+                let mut num_samples = 0;
+                for z in channels.first..=channels.last {
+                    let v = cube.value(x, y, z);
+                    if !v.is_finite() || settings.threshold.is_some_and(|t| v < t) {
+                        continue;
+                    }
+                    let k = (z - channels.first) as f64;
+                    sum += v as f64;
+                    sum_k += v as f64 * k;
+                    sum_k2 += v as f64 * k * k;
+                    peak = peak.max(v);
+                    num_samples += 1;
+                }
+
+                let value = if num_samples == 0 {
+                    f64::NAN
+                } else {
+                    match settings.moment {
+                        Moment::Integrated => sum * step.abs(),
+                        Moment::Peak => peak as f64,
+                        _ if sum <= 0.0 => f64::NAN,
+                        Moment::Velocity => start + sum_k / sum * step,
+                        Moment::Dispersion => {
+                            let mean_k = sum_k / sum;
+                            (sum_k2 / sum - mean_k * mean_k).max(0.0).sqrt() * step.abs()
+                        }
+                    }
+                };
+                data.push(value as f32);
+            }
+        }
+
+        Self {
+            moment: settings.moment,
+            size: (w, h),
+            data,
+            channels,
+            threshold: settings.threshold,
+        }
+    }
+}
+
+// spectral coordinate of the first channel and increment per channel,
+// in channels for a cube without WCS
+fn spectral_axis(cube: &Cube, channels: ChannelRange) -> (f64, f64) {
+    match cube.wcs() {
+        Some(wcs) => (
+            wcs.axes[2].pix2world(channels.first as f64),
+            wcs.axes[2].cdelt,
+        ),
+        None => (channels.first as f64, 1.0),
+    }
+}
+
+/// std140 layout of the `Moment` uniform block of moments.comp
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MomentUniform {
+    // x: first channel, y: last channel, z: moment, w: 1 if the threshold is set
+    params: [f32; 4],
+    // x: threshold, y: spectral coordinate of the first channel, z: increment per channel
+    spectral: [f32; 4],
+}
+
+// edge of the workgroups of moments.comp
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Compute pass integrating the cube texture along its spectral axis
+pub(crate) struct MomentPipeline {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buf: wgpu::Buffer,
+}
+
+impl MomentPipeline {
+    pub(crate) fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("moments comp shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/moments.comp").into(),
+                stage: wgpu::naga::ShaderStage::Compute,
+                defines: Default::default(),
+            },
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<MomentUniform>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("moments_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Moments Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Moments Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("moment"),
+            size: std::mem::size_of::<MomentUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            uniform_buf,
+        }
+    }
+
+    /// Compute the map of `cube` from its texture, waiting for the GPU
+    pub(crate) fn compute(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &Texture,
+        cube: &Cube,
+        settings: &MomentSettings,
+    ) -> Result<MomentMap, &'static str> {
+        let (w, h, d) = cube.dim;
+        let channels = settings
+            .channels
+            .map(|range| range.clamp(d))
+            .unwrap_or_else(|| ChannelRange::all(d));
+        let (start, step) = spectral_axis(cube, channels);
+
+        let size = (w as wgpu::BufferAddress) * (h as wgpu::BufferAddress) * 4;
+        if size > device.limits().max_storage_buffer_binding_size as wgpu::BufferAddress {
+            return Err("The spatial plane of the cube is too large for the GPU");
+        }
+
+        queue.write_buffer(
+            &self.uniform_buf,
+            0,
+            bytemuck::bytes_of(&MomentUniform {
+                params: [
+                    channels.first as f32,
+                    channels.last as f32,
+                    settings.moment.as_f32(),
+                    if settings.threshold.is_some() {
+                        1.0
+                    } else {
+                        0.0
+                    },
+                ],
+                spectral: [
+                    settings.threshold.unwrap_or(0.0),
+                    start as f32,
+                    step as f32,
+                    0.0,
+                ],
+            }),
+        );
+
+        let map_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("moment map"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("moment map read back"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: map_buf.as_entire_binding(),
+                },
+            ],
+            label: Some("moments_bind_group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Moments Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Moments Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(w.div_ceil(WORKGROUP_SIZE), h.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        encoder.copy_buffer_to_buffer(&map_buf, 0, &read_buf, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, mapped) = futures::channel::oneshot::channel();
+        read_buf
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        device
+            .poll(wgpu::PollType::Wait)
+            .map_err(|_| "The GPU did not finish computing the moment map")?;
+        pollster::block_on(mapped)
+            .map_err(|_| "The moment map could not be read back")?
+            .map_err(|_| "The moment map could not be read back")?;
+
+        let data = bytemuck::cast_slice(&read_buf.slice(..).get_mapped_range()).to_vec();
+        read_buf.unmap();
+
+        Ok(MomentMap {
+            moment: settings.moment,
+            size: (w, h),
+            data,
+            channels,
+            threshold: settings.threshold,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wcs::{Wcs, WcsAxis, WcsFrame};
+
+    // cube of 2x1 spaxels, a line in the first one and negative values in the second
+    fn cube(wcs: Option<Wcs>) -> Cube {
+        let line = [0.0, 1.0, 2.0, 1.0, 0.0];
+        Cube {
+            data: line.iter().flat_map(|&v| [v, -1.0]).collect(),
+            dim: (2, 1, 5),
+            datamin: None,
+            datamax: None,
+            bunit: None,
+            wcs,
+            name: None,
+        }
+    }
+
+    // WCS of a velocity axis decreasing by 10 per channel from 1000 at the first one
+    fn velocity_wcs() -> Wcs {
+        let axis = |ctype: &str, crval: f64, cdelt: f64| WcsAxis {
+            ctype: ctype.to_string(),
+            cunit: String::new(),
+            crval,
+            crpix: 1.0,
+            cdelt,
+        };
+        Wcs {
+            axes: [
+                axis("RA", 0.0, 1.0),
+                axis("DEC", 0.0, 1.0),
+                axis("VRAD", 1000.0, -10.0),
+            ],
+            pc: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            frame: WcsFrame::default(),
+        }
+    }
+
+    fn map(cube: &Cube, moment: Moment, threshold: Option<f32>) -> Vec<f32> {
+        let settings = MomentSettings {
+            threshold,
+            ..MomentSettings::new(moment)
+        };
+        MomentMap::from_cube(cube, &settings).data
+    }
+
+    #[test]
+    fn moments_of_a_line() {
+        let cube = cube(Some(velocity_wcs()));
+        assert_eq!(map(&cube, Moment::Integrated, None), [40.0, -50.0]);
+        assert_eq!(map(&cube, Moment::Peak, None), [2.0, -1.0]);
+
+        // the velocity and the dispersion need a positive integrated intensity
+        let velocity = map(&cube, Moment::Velocity, None);
+        assert_eq!(velocity[0], 980.0);
+        assert!(velocity[1].is_nan());
+        let dispersion = map(&cube, Moment::Dispersion, None);
+        assert!((dispersion[0] - 0.5_f32.sqrt() * 10.0).abs() < 1e-5);
+        assert!(dispersion[1].is_nan());
+    }
+
+    #[test]
+    fn threshold_and_channels_leave_samples_out() {
+        let cube = cube(None);
+        // in channels without WCS
+        assert_eq!(map(&cube, Moment::Integrated, None), [4.0, -5.0]);
+        assert_eq!(map(&cube, Moment::Velocity, None)[0], 2.0);
+
+        let integrated = map(&cube, Moment::Integrated, Some(0.5));
+        assert_eq!(integrated[0], 4.0);
+        assert!(integrated[1].is_nan());
+
+        let settings = MomentSettings {
+            channels: Some(ChannelRange { first: 3, last: 1 }),
+            ..MomentSettings::new(Moment::Velocity)
+        };
+        let map = MomentMap::from_cube(&cube, &settings);
+        assert_eq!(map.channels, ChannelRange { first: 1, last: 3 });
+        assert_eq!(map.size, (2, 1));
+        assert_eq!(map.data[0], 2.0);
+        assert_eq!(map.value_range(), Some(2.0..2.0));
+    }
+}
@@ -0,0 +1,607 @@
+//! Annotations drawn over the frames read back from the GPU
+
+use cgmath::{Quaternion, Rotation};
+
+use crate::math::Vec3;
+
+const WHITE: [u8; 3] = [230, 230, 230];
+const SHADOW: [u8; 3] = [0, 0, 0];
+const BACKGROUND: [u8; 3] = [20, 20, 20];
+// color of the lines of the plots
+const PLOT_COLOR: [u8; 3] = [250, 200, 80];
+// colors of the x, y and z axes of the cube
+const AXIS_COLORS: [[u8; 3]; 3] = [[240, 80, 80], [90, 220, 90], [90, 140, 250]];
+
+/// Annotations of a screenshot
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Overlay {
+    /// Data values at both ends of the colormap
+    pub(crate) colorbar: Option<(f32, f32)>,
+    /// Name of the spectral axis and its coordinates at both ends of the hues of the
+    /// velocity coded rendering
+    pub(crate) velocity: Option<(String, [f64; 2])>,
+    /// Orientation of the camera and names of the cube axes
+    pub(crate) axes: Option<(Quaternion<f32>, [String; 3])>,
+    /// The frame is sRGB encoded, the colors of the colormap being linear
+    pub(crate) srgb: bool,
+}
+
+impl Overlay {
+    /// Draw the annotations over an RGBA image
+    pub(crate) fn draw(&self, rgba: &mut [u8], size: (u32, u32)) {
+        let mut canvas = Canvas {
+            rgba,
+            width: size.0 as i32,
+            height: size.1 as i32,
+        };
+        // sized relative to the height, so that a print keeps the proportions of the screen
+        let unit = (size.1 as f32 / 100.0).max(1.0);
+        let text_scale = ((size.1 as f32 / 250.0).round() as i32).max(1);
+
+        if let Some((min, max)) = self.colorbar {
+            self.draw_colorbar(&mut canvas, unit, text_scale, min, max);
+        }
+        if let Some((label, ends)) = &self.velocity {
+            // on the right, where the colorbar goes in the other modes
+            let (legend, (width, height)) = velocity_legend(label, *ends, text_scale, self.srgb);
+            let x = canvas.width - width as i32 - (4.0 * unit) as i32;
+            let y = (canvas.height - height as i32) / 2;
+            canvas.image(x, y, &legend, (width as i32, height as i32));
+        }
+        if let Some((orientation, names)) = &self.axes {
+            draw_axes(&mut canvas, unit, text_scale, *orientation, names);
+        }
+    }
+
+    // vertical bar on the right, the maximum at the top
+    fn draw_colorbar(&self, canvas: &mut Canvas, unit: f32, text_scale: i32, min: f32, max: f32) {
+        let width = (2.5 * unit) as i32;
+        let height = (50.0 * unit) as i32;
+        let x0 = canvas.width - width - (4.0 * unit) as i32;
+        let y0 = (canvas.height - height) / 2;
+        let border = (unit / 4.0).ceil() as i32;
+
+        canvas.fill_rect(
+            x0 - border,
+            y0 - border,
+            width + 2 * border,
+            height + 2 * border,
+            WHITE,
+        );
+        for y in 0..height {
+            let x = 1.0 - y as f32 / (height - 1).max(1) as f32;
+            let color = colormap(x, self.srgb);
+            canvas.fill_rect(x0, y0 + y, width, 1, color);
+        }
+
+        // labels on the left of the bar, aligned on their right
+        let tick = (unit * 1.5) as i32;
+        let char_height = 7 * text_scale;
+        for (i, value) in [max, 0.5 * (min + max), min].iter().enumerate() {
+            let y = y0 + i as i32 * (height - 1) / 2;
+            canvas.fill_rect(x0 - border - tick, y - border / 2, tick, border, WHITE);
+            let label = format_value(*value);
+            let x = x0 - border - tick - unit as i32 - text_width(&label, text_scale);
+            canvas.text(x, y - char_height / 2, &label, text_scale, WHITE);
+        }
+    }
+}
+
+// axes of the cube as seen by the camera, in the lower left corner
+fn draw_axes(
+    canvas: &mut Canvas,
+    unit: f32,
+    text_scale: i32,
+    orientation: Quaternion<f32>,
+    names: &[String; 3],
+) {
+    let length = 8.0 * unit;
+    let origin = (
+        (6.0 * unit + length) as i32,
+        canvas.height - (6.0 * unit + length) as i32,
+    );
+    let thickness = (unit / 2.0).ceil() as i32;
+
+    // from the world to the camera frame
+    let camera = orientation.conjugate();
+    let mut axes: Vec<(usize, Vec3<f32>)> = [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()]
+        .iter()
+        .map(|axis| camera.rotate_vector(*axis))
+        .enumerate()
+        .collect();
+    // the axes pointing away from the viewer are drawn first
+    axes.sort_by(|(_, a), (_, b)| a.z.total_cmp(&b.z));
+
+    for (i, dir) in axes {
+        let end = (
+            origin.0 + (dir.x * length) as i32,
+            origin.1 - (dir.y * length) as i32,
+        );
+        canvas.line(origin, end, thickness, AXIS_COLORS[i]);
+
+        // the label past the end of the axis, shortened to the type of the coordinate
+        let name = names[i].split('-').next().unwrap_or_default();
+        let label_pos = (
+            origin.0 + (dir.x * (length + 3.0 * unit)) as i32 - text_width(name, text_scale) / 2,
+            origin.1 - (dir.y * (length + 3.0 * unit)) as i32 - 7 * text_scale / 2,
+        );
+        canvas.text(label_pos.0, label_pos.1, name, text_scale, AXIS_COLORS[i]);
+    }
+}
+
+/// Color of the colormap of the shaders at `x` in [0, 1]
+pub(crate) fn colormap(x: f32, srgb: bool) -> [u8; 3] {
+    // same as colormap() in cube.frag
+    let red = if x < 0.7 {
+        4.0 * x - 1.5
+    } else {
+        -4.0 * x + 4.5
+    };
+    let green = if x < 0.5 {
+        4.0 * x - 0.5
+    } else {
+        -4.0 * x + 3.5
+    };
+    let blue = if x < 0.3 {
+        4.0 * x + 0.5
+    } else {
+        -4.0 * x + 2.5
+    };
+
+    [red, green, blue].map(|c| encode(c, srgb))
+}
+
+/// Color of the velocity coded rendering at `t` in [0, 1] of the channels rendered,
+/// from blue to red
+pub(crate) fn velocity_color(t: f32, srgb: bool) -> [u8; 3] {
+    // same as velocity_color() in cube.frag
+    let h = 4.0 * (1.0 - t.clamp(0.0, 1.0));
+    [0.0, 4.0, 2.0].map(|offset| {
+        let c = ((h + offset).rem_euclid(6.0) - 3.0).abs() - 1.0;
+        encode(c, srgb)
+    })
+}
+
+// 8 bits value of a linear color channel, sRGB encoded for an sRGB frame
+fn encode(c: f32, srgb: bool) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if !srgb {
+        c
+    } else if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+/// Short label of a data value
+pub(crate) fn format_value(value: f32) -> String {
+    let magnitude = value.abs();
+    if magnitude == 0.0 || (1e-2..1e4).contains(&magnitude) {
+        let label = format!("{:.3}", value);
+        label
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        format!("{:.2e}", value)
+    }
+}
+
+/// Lines of text on a dark background, as an RGBA image of the size returned
+pub(crate) fn text_box(lines: &[String], scale: i32) -> (Vec<u8>, (u32, u32)) {
+    let margin = 3 * scale;
+    // 7 pixels per glyph and 3 between the lines
+    let line_height = 10 * scale;
+    // the shadow of the text is offset by one pixel of the font
+    let width = lines
+        .iter()
+        .map(|line| text_width(line, scale))
+        .max()
+        .unwrap_or(0)
+        + 2 * margin
+        + scale;
+    let height = (lines.len() as i32 * line_height - 3 * scale).max(0) + 2 * margin + scale;
+
+    let mut rgba = vec![0; 4 * (width * height) as usize];
+    let mut canvas = Canvas {
+        rgba: &mut rgba,
+        width,
+        height,
+    };
+    canvas.fill_rect(0, 0, width, height, BACKGROUND);
+    for (i, line) in lines.iter().enumerate() {
+        canvas.text(margin, margin + i as i32 * line_height, line, scale, WHITE);
+    }
+    (rgba, (width as u32, height as u32))
+}
+
+/// Legend of the velocity coded rendering on a dark background, as an RGBA image of
+/// the size returned: the hues of the channels rendered with the spectral coordinates
+/// of the first and last ones below, under the name of the spectral axis
+pub(crate) fn velocity_legend(
+    label: &str,
+    ends: [f64; 2],
+    scale: i32,
+    srgb: bool,
+) -> (Vec<u8>, (u32, u32)) {
+    let margin = 3 * scale;
+    let char_height = 7 * scale;
+    let [first, last] = ends.map(|end| format_value(end as f32));
+    let bar_width = (80 * scale)
+        .max(text_width(label, scale))
+        .max(text_width(&first, scale) + text_width(&last, scale) + 6 * scale);
+    let bar_height = 4 * scale;
+    let width = bar_width + 2 * margin + scale;
+    let height = 2 * char_height + bar_height + 4 * scale + 2 * margin + scale;
+
+    let mut rgba = vec![0; 4 * (width * height) as usize];
+    let mut canvas = Canvas {
+        rgba: &mut rgba,
+        width,
+        height,
+    };
+    canvas.fill_rect(0, 0, width, height, BACKGROUND);
+    canvas.text(margin, margin, label, scale, WHITE);
+    let y_bar = margin + char_height + 2 * scale;
+    for x in 0..bar_width {
+        let t = x as f32 / (bar_width - 1).max(1) as f32;
+        let color = velocity_color(t, srgb);
+        canvas.fill_rect(margin + x, y_bar, 1, bar_height, color);
+    }
+    let y_text = y_bar + bar_height + 2 * scale;
+    canvas.text(margin, y_text, &first, scale, WHITE);
+    canvas.text(
+        margin + bar_width - text_width(&last, scale),
+        y_text,
+        &last,
+        scale,
+        WHITE,
+    );
+    (rgba, (width as u32, height as u32))
+}
+
+/// Line plot of `values` against `coords` on a dark background, as an RGBA image of `size`
+///
+/// The title and the unit of the values are written above the plot, the ends of the
+/// value range on its left and the ends of the coordinates below it with `x_label`.
+/// The line is broken at the blank values.
+pub(crate) fn line_plot(
+    title: &str,
+    x_label: &str,
+    y_label: &str,
+    coords: &[f64],
+    values: &[f32],
+    size: (u32, u32),
+    scale: i32,
+) -> Vec<u8> {
+    let (width, height) = (size.0 as i32, size.1 as i32);
+    let mut rgba = vec![0; 4 * (width * height).max(0) as usize];
+    let mut canvas = Canvas {
+        rgba: &mut rgba,
+        width,
+        height,
+    };
+    canvas.fill_rect(0, 0, width, height, BACKGROUND);
+
+    let margin = 4 * scale;
+    let char_height = 7 * scale;
+    canvas.text(margin, margin, title, scale, WHITE);
+    canvas.text(
+        width - margin - text_width(y_label, scale),
+        margin,
+        y_label,
+        scale,
+        WHITE,
+    );
+
+    let finite = values.iter().copied().filter(|v| v.is_finite());
+    let (min, max) = finite.fold((f32::MAX, f32::MIN), |(min, max), v| {
+        (min.min(v), max.max(v))
+    });
+    let (min, max) = if min > max {
+        (0.0, 1.0)
+    } else if min == max {
+        (min - 0.5, max + 0.5)
+    } else {
+        (min, max)
+    };
+    let (first, last) = match (coords.first(), coords.last()) {
+        (Some(&first), Some(&last)) if first != last => (first, last),
+        _ => (0.0, 1.0),
+    };
+
+    // frame of the plot, between the labels of the values and the ones of the coordinates
+    let min_label = format_value(min);
+    let max_label = format_value(max);
+    let label_width = text_width(&min_label, scale).max(text_width(&max_label, scale));
+    let x0 = margin + label_width + 2 * scale;
+    let y0 = 2 * margin + char_height;
+    let x1 = width - margin;
+    let y1 = height - 2 * margin - char_height;
+    if x1 - x0 < 2 || y1 - y0 < 2 {
+        return rgba;
+    }
+    canvas.fill_rect(x0, y0, x1 - x0, scale, WHITE);
+    canvas.fill_rect(x0, y1 - scale, x1 - x0, scale, WHITE);
+    canvas.fill_rect(x0, y0, scale, y1 - y0, WHITE);
+    canvas.fill_rect(x1 - scale, y0, scale, y1 - y0, WHITE);
+
+    canvas.text(
+        x0 - 2 * scale - text_width(&max_label, scale),
+        y0,
+        &max_label,
+        scale,
+        WHITE,
+    );
+    canvas.text(
+        x0 - 2 * scale - text_width(&min_label, scale),
+        y1 - char_height,
+        &min_label,
+        scale,
+        WHITE,
+    );
+    let y_text = y1 + margin;
+    canvas.text(x0, y_text, &format_value(first as f32), scale, WHITE);
+    let last_label = format_value(last as f32);
+    canvas.text(
+        x1 - text_width(&last_label, scale),
+        y_text,
+        &last_label,
+        scale,
+        WHITE,
+    );
+    canvas.text(
+        (x0 + x1 - text_width(x_label, scale)) / 2,
+        y_text,
+        x_label,
+        scale,
+        WHITE,
+    );
+
+    // inside of the frame
+    let inner = (
+        x0 + 2 * scale,
+        y0 + 2 * scale,
+        x1 - 2 * scale,
+        y1 - 2 * scale,
+    );
+    let to_canvas = |coord: f64, value: f32| {
+        let fx = (coord - first) / (last - first);
+        let fy = (value - min) / (max - min);
+        (
+            inner.0 + (fx * (inner.2 - inner.0) as f64).round() as i32,
+            inner.3 - (fy * (inner.3 - inner.1) as f32).round() as i32,
+        )
+    };
+    let thickness = scale.max(1);
+    if min < 0.0 && max > 0.0 {
+        let (_, y) = to_canvas(first, 0.0);
+        for x in (inner.0..inner.2).step_by(4 * scale as usize) {
+            canvas.fill_rect(x, y, 2 * scale, 1, WHITE);
+        }
+    }
+    let mut previous = None;
+    for (&coord, &value) in coords.iter().zip(values.iter()) {
+        if !value.is_finite() {
+            previous = None;
+            continue;
+        }
+        let point = to_canvas(coord, value);
+        canvas.line(previous.unwrap_or(point), point, thickness, PLOT_COLOR);
+        previous = Some(point);
+    }
+
+    rgba
+}
+
+fn text_width(text: &str, scale: i32) -> i32 {
+    // 5 pixels per character and 1 between them
+    (6 * text.chars().count() as i32 - 1).max(0) * scale
+}
+
+/// RGBA image drawn into, clipping what falls outside
+struct Canvas<'a> {
+    rgba: &'a mut [u8],
+    width: i32,
+    height: i32,
+}
+
+impl Canvas<'_> {
+    fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [u8; 3]) {
+        let (x_start, x_end) = (x.max(0), (x + width).min(self.width));
+        let (y_start, y_end) = (y.max(0), (y + height).min(self.height));
+        for y in y_start..y_end {
+            for x in x_start..x_end {
+                let i = 4 * (y * self.width + x) as usize;
+                self.rgba[i..i + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+            }
+        }
+    }
+
+    /// Copy an RGBA image of `size` with its upper left corner at (x, y)
+    fn image(&mut self, x: i32, y: i32, rgba: &[u8], size: (i32, i32)) {
+        for row in 0..size.1 {
+            for col in 0..size.0 {
+                let (cx, cy) = (x + col, y + row);
+                if cx < 0 || cy < 0 || cx >= self.width || cy >= self.height {
+                    continue;
+                }
+                let i = 4 * (row * size.0 + col) as usize;
+                let j = 4 * (cy * self.width + cx) as usize;
+                self.rgba[j..j + 4].copy_from_slice(&rgba[i..i + 4]);
+            }
+        }
+    }
+
+    fn line(&mut self, from: (i32, i32), to: (i32, i32), thickness: i32, color: [u8; 3]) {
+        let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).max(1);
+        for i in 0..=steps {
+            let x = from.0 + (to.0 - from.0) * i / steps;
+            let y = from.1 + (to.1 - from.1) * i / steps;
+            self.fill_rect(
+                x - thickness / 2,
+                y - thickness / 2,
+                thickness,
+                thickness,
+                color,
+            );
+        }
+    }
+
+    /// Write `text` with its upper left corner at (x, y), with a shadow to stay readable
+    fn text(&mut self, x: i32, y: i32, text: &str, scale: i32, color: [u8; 3]) {
+        for (offset, color) in [(scale, SHADOW), (0, color)] {
+            for (i, c) in text.chars().enumerate() {
+                let x = x + offset + 6 * scale * i as i32;
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    for col in 0..5 {
+                        if bits & (0b10000 >> col) != 0 {
+                            self.fill_rect(
+                                x + col * scale,
+                                y + offset + row as i32 * scale,
+                                scale,
+                                scale,
+                                color,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a 5x7 bitmap font, the letters being drawn in upper case
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        '-' => [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+        '+' => [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+        '.' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ],
+        ':' => [
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+        ],
+        '/' => [
+            0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+        ],
+        '_' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111,
+        ],
+        _ => [0; 7],
+    }
+}
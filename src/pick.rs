@@ -0,0 +1,170 @@
+//! Voxel of the cube under the cursor
+
+use cgmath::InnerSpace;
+
+use crate::channels::ChannelRange;
+use crate::clip::Clipping;
+use crate::cube::Cube;
+use crate::labels::Labels;
+use crate::math::Vec3;
+
+/// How the voxel is chosen along the ray
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PickKind {
+    /// Voxel of the maximum along the ray, the one contributing the most to the
+    /// average mode
+    Maximum,
+    /// First voxel past a crossing of an iso level, as seen in the isosurface mode
+    FirstHit,
+}
+
+impl PickKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            PickKind::Maximum => "max",
+            PickKind::FirstHit => "first hit",
+        }
+    }
+}
+
+/// Voxel picked along the ray of a pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pick {
+    pub kind: PickKind,
+    /// 0-based indices along x, y and z
+    pub voxel: [u32; 3],
+    pub value: f32,
+    /// World coordinates of the center of the voxel, if the cube has a WCS
+    pub world: Option<[f64; 3]>,
+    /// ID of the object the voxel belongs to, if labels are loaded
+    pub label: Option<u32>,
+}
+
+impl Pick {
+    /// Lines describing the voxel, with the axis types and units of the cube
+    pub(crate) fn readout(&self, cube: &Cube) -> Vec<String> {
+        let [x, y, z] = self.voxel;
+        let mut lines = vec![
+            format!("{} at voxel {} {} {}", self.kind.name(), x, y, z),
+            match &cube.bunit {
+                Some(bunit) => format!("value {} {}", self.value, bunit),
+                None => format!("value {}", self.value),
+            },
+        ];
+        if let (Some(world), Some(wcs)) = (self.world, cube.wcs()) {
+            for (w, axis) in world.iter().zip(wcs.axes.iter()) {
+                // the type of the coordinate, without the projection
+                let name = axis.ctype.split('-').next().unwrap_or_default();
+                lines.push(format!("{} {} {}", name, format_world(*w), axis.cunit));
+            }
+        }
+        if let Some(id) = self.label {
+            lines.push(format!("object {}", id));
+        }
+        lines
+    }
+}
+
+// coordinate with 6 decimals at most, the trailing zeros being dropped
+fn format_world(w: f64) -> String {
+    let s = format!("{:.6}", w);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Pick the voxel along a ray in texture coordinates, within the part of the cube
+/// rendered: the crop box, the channel range and the clip planes
+///
+/// The ray is sampled once per voxel crossed. With `FirstHit`, the first sample past
+/// a crossing of one of `iso_values` is picked. With `labels`, the voxel is labeled
+/// and, if the flag is set, only the voxels of the objects shown are picked.
+pub(crate) fn pick(
+    cube: &Cube,
+    labels: Option<(&Labels, bool)>,
+    clipping: &Clipping,
+    channels: ChannelRange,
+    kind: PickKind,
+    iso_values: &[f32],
+    (origin, dir): (Vec3<f32>, Vec3<f32>),
+) -> Option<Pick> {
+    let (w, h, d) = cube.dim;
+    let dim = Vec3::new(w as f32, h as f32, d as f32);
+    let [z_min, z_max, _, _] = channels.uniform(d);
+    let lo = Vec3::new(
+        clipping.box_min[0],
+        clipping.box_min[1],
+        clipping.box_min[2].max(z_min),
+    );
+    let hi = Vec3::new(
+        clipping.box_max[0],
+        clipping.box_max[1],
+        clipping.box_max[2].min(z_max),
+    );
+
+    // segment of the ray in the crop box, as in cube.frag
+    let (mut t_near, mut t_far) = (0.0_f32, f32::MAX);
+    for axis in 0..3 {
+        let t_lo = (lo[axis] - origin[axis]) / dir[axis];
+        let t_hi = (hi[axis] - origin[axis]) / dir[axis];
+        t_near = t_near.max(t_lo.min(t_hi));
+        t_far = t_far.min(t_lo.max(t_hi));
+    }
+    for plane in &clipping.planes {
+        let n = Vec3::from(plane.normal);
+        let denom = n.dot(dir);
+        let num = plane.offset - n.dot(origin);
+        if denom > 0.0 {
+            t_far = t_far.min(num / denom);
+        } else if denom < 0.0 {
+            t_near = t_near.max(num / denom);
+        } else if num < 0.0 {
+            return None;
+        }
+    }
+    if t_far <= t_near {
+        return None;
+    }
+
+    // length of the ray crossing one voxel
+    let step = 1.0 / Vec3::new(dir.x * dim.x, dir.y * dim.y, dir.z * dim.z).magnitude();
+    let num_samples = ((t_far - t_near) / step).ceil() as u32;
+
+    let mut picked: Option<([u32; 3], f32)> = None;
+    let mut prev: Option<f32> = None;
+    for i in 0..num_samples {
+        let p = origin + dir * (t_near + (i as f32 + 0.5) * step);
+        let voxel = [
+            ((p.x * dim.x) as u32).min(w - 1),
+            ((p.y * dim.y) as u32).min(h - 1),
+            ((p.z * dim.z) as u32).min(d - 1),
+        ];
+        let v = cube.value(voxel[0], voxel[1], voxel[2]);
+        if !v.is_finite() || labels.is_some_and(|(labels, hide)| hide && !labels.shown_at(voxel)) {
+            continue;
+        }
+
+        match kind {
+            PickKind::Maximum => {
+                if picked.is_none_or(|(_, max)| v > max) {
+                    picked = Some((voxel, v));
+                }
+            }
+            PickKind::FirstHit => {
+                if let Some(prev) = prev {
+                    if iso_values.iter().any(|iso| (prev - iso) * (v - iso) < 0.0) {
+                        picked = Some((voxel, v));
+                        break;
+                    }
+                }
+                prev = Some(v);
+            }
+        }
+    }
+
+    picked.map(|(voxel, value)| Pick {
+        kind,
+        voxel,
+        value,
+        world: cube.wcs().map(|wcs| wcs.pix2world(voxel.map(|i| i as f64))),
+        label: labels.and_then(|(labels, _)| labels.id_at(voxel)),
+    })
+}
@@ -0,0 +1,475 @@
+//! Position-velocity diagrams along a path across the spatial plane of the cube
+
+use std::ops::Range;
+
+use crate::cube::Cube;
+use crate::spectrum::format_coord;
+use crate::texture::Texture;
+
+/// Vertices of a path drawn over a map at most
+pub(crate) const MAX_PATH_VERTICES: usize = 32;
+
+// point along a path, in 0-based pixels, with the unit normal of its segment
+type PathSample = ([f64; 2], [f64; 2]);
+
+/// Path of a position-velocity diagram across the spatial plane of the cube
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PvPath {
+    /// Vertices of the polyline, in 0-based pixels
+    pub vertices: Vec<[f64; 2]>,
+    /// Width of the slit across the path in pixels, the values across it being averaged.
+    /// Up to 1, the path itself is sampled.
+    pub width: f32,
+}
+
+impl PvPath {
+    /// Length of the polyline in pixels
+    pub fn length(&self) -> f64 {
+        self.vertices
+            .windows(2)
+            .map(|pair| (pair[1][0] - pair[0][0]).hypot(pair[1][1] - pair[0][1]))
+            .sum()
+    }
+
+    /// Points along the path one pixel apart from its first vertex, with the unit
+    /// normal of the segment they are on
+    fn samples(&self) -> Vec<PathSample> {
+        let mut samples = vec![];
+        // distance of the next point from the start of the segment
+        let mut s = 0.0;
+        for pair in self.vertices.windows(2) {
+            let ([ax, ay], [bx, by]) = (pair[0], pair[1]);
+            let length = (bx - ax).hypot(by - ay);
+            if length == 0.0 {
+                continue;
+            }
+            let (ux, uy) = ((bx - ax) / length, (by - ay) / length);
+            while s <= length {
+                samples.push(([ax + ux * s, ay + uy * s], [-uy, ux]));
+                s += 1.0;
+            }
+            s -= length;
+        }
+        samples
+    }
+
+    /// Offsets of the samples across the slit, one pixel apart around the path
+    fn offsets(&self) -> Vec<f64> {
+        let n = (self.width.round() as u32).max(1);
+        (0..n).map(|k| k as f64 - (n - 1) as f64 / 2.0).collect()
+    }
+
+    /// Text of the HISTORY cards of the files written from the diagram
+    pub(crate) fn description(&self) -> String {
+        let vertices: Vec<String> = self
+            .vertices
+            .iter()
+            .map(|[x, y]| format!("{} {}", format_coord(*x), format_coord(*y)))
+            .collect();
+        format!(
+            "Position-velocity diagram along the 0-based pixels {}, slit of {} pixels",
+            vertices.join(" to "),
+            (self.width.round() as u32).max(1)
+        )
+    }
+}
+
+/// Values of the cube along a path, one row per channel
+#[derive(Clone, Debug, PartialEq)]
+pub struct PvDiagram {
+    /// Number of positions along the path, one pixel apart, and number of channels
+    pub size: (u32, u32),
+    /// Values with the position varying the fastest, NaN where no sample is finite
+    pub data: Vec<f32>,
+    pub path: PvPath,
+}
+
+impl PvDiagram {
+    /// Minimum and maximum of the values, the blank ones being ignored
+    pub fn value_range(&self) -> Option<Range<f32>> {
+        let mut values = self.data.iter().copied().filter(|v| v.is_finite());
+        let first = values.next()?;
+        Some(values.fold(first..first, |range, v| {
+            range.start.min(v)..range.end.max(v)
+        }))
+    }
+
+    /// Compute the diagram on the CPU, for the devices without compute shaders
+    ///
+    /// The spatial plane is interpolated bilinearly between the centers of the pixels,
+    /// leaving out the blank ones, and the samples across the slit are averaged.
+    pub(crate) fn from_cube(cube: &Cube, path: &PvPath) -> Result<Self, &'static str> {
+        let (w, h, d) = cube.dim;
+        let samples = path_samples(path)?;
+        let offsets = path.offsets();
+
+        let mut data = Vec::with_capacity(samples.len() * d as usize);
+        for z in 0..d {
+            for ([x, y], [nx, ny]) in &samples {
+                let (mut sum, mut num_samples) = (0.0, 0);
+                for offset in &offsets {
+                    let (px, py) = (x + nx * offset, y + ny * offset);
+                    let (x0, y0) = (px.floor(), py.floor());
+                    let (fx, fy) = (px - x0, py - y0);
+                    let (mut value, mut weight) = (0.0, 0.0);
+                    for (dx, dy, wc) in [
+                        (0, 0, (1.0 - fx) * (1.0 - fy)),
+                        (1, 0, fx * (1.0 - fy)),
+                        (0, 1, (1.0 - fx) * fy),
+                        (1, 1, fx * fy),
+                    ] {
+                        let (cx, cy) = (x0 as i64 + dx, y0 as i64 + dy);
+                        if wc == 0.0 || cx < 0 || cy < 0 || cx >= w as i64 || cy >= h as i64 {
+                            continue;
+                        }
+                        let v = cube.value(cx as u32, cy as u32, z);
+                        if v.is_finite() {
+                            value += v as f64 * wc;
+                            weight += wc;
+                        }
+                    }
+                    if weight > 0.0 {
+                        sum += value / weight;
+                        num_samples += 1;
+                    }
+                }
+                data.push(if num_samples > 0 {
+                    (sum / num_samples as f64) as f32
+                } else {
+                    f32::NAN
+                });
+            }
+        }
+
+        Ok(Self {
+            size: (samples.len() as u32, d),
+            data,
+            path: path.clone(),
+        })
+    }
+}
+
+// points along a path with two distinct vertices at least
+fn path_samples(path: &PvPath) -> Result<Vec<PathSample>, &'static str> {
+    let samples = path.samples();
+    if samples.is_empty() {
+        Err("The path needs two distinct vertices at least")
+    } else {
+        Ok(samples)
+    }
+}
+
+/// std140 layout of the `Pv` uniform block of pv.comp
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PvUniform {
+    // x: number of positions, y: number of offsets across the slit,
+    // z: offset of the first sample across the slit
+    params: [f32; 4],
+}
+
+// positions computed by a workgroup of pv.comp, along the path and along the channels
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Compute pass sampling the cube texture along a path
+pub(crate) struct PvPipeline {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buf: wgpu::Buffer,
+}
+
+impl PvPipeline {
+    pub(crate) fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("pv comp shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/pv.comp").into(),
+                stage: wgpu::naga::ShaderStage::Compute,
+                defines: Default::default(),
+            },
+        });
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<PvUniform>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+                storage(3, true),
+                storage(4, false),
+            ],
+            label: Some("pv_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PV Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("PV Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pv"),
+            size: std::mem::size_of::<PvUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            uniform_buf,
+        }
+    }
+
+    /// Compute the diagram of `cube` from its texture, waiting for the GPU
+    pub(crate) fn compute(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &Texture,
+        cube: &Cube,
+        path: &PvPath,
+    ) -> Result<PvDiagram, &'static str> {
+        let d = cube.dim.2;
+        let samples = path_samples(path)?;
+        let offsets = path.offsets();
+        let n = samples.len() as u32;
+
+        let size = (n as wgpu::BufferAddress) * (d as wgpu::BufferAddress) * 4;
+        if size > device.limits().max_storage_buffer_binding_size as wgpu::BufferAddress {
+            return Err("The path is too long for the GPU");
+        }
+
+        queue.write_buffer(
+            &self.uniform_buf,
+            0,
+            bytemuck::bytes_of(&PvUniform {
+                params: [n as f32, offsets.len() as f32, offsets[0] as f32, 0.0],
+            }),
+        );
+        // position and normal of each point of the path
+        let points: Vec<[f32; 4]> = samples
+            .iter()
+            .map(|([x, y], [nx, ny])| [*x as f32, *y as f32, *nx as f32, *ny as f32])
+            .collect();
+        let points_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pv path"),
+            size: (points.len() * std::mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&points_buf, 0, bytemuck::cast_slice(&points));
+
+        let pv_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pv diagram"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pv diagram read back"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: points_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: pv_buf.as_entire_binding(),
+                },
+            ],
+            label: Some("pv_bind_group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("PV Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("PV Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(n.div_ceil(WORKGROUP_SIZE), d.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        encoder.copy_buffer_to_buffer(&pv_buf, 0, &read_buf, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, mapped) = futures::channel::oneshot::channel();
+        read_buf
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        device
+            .poll(wgpu::PollType::Wait)
+            .map_err(|_| "The GPU did not finish computing the position-velocity diagram")?;
+        pollster::block_on(mapped)
+            .map_err(|_| "The position-velocity diagram could not be read back")?
+            .map_err(|_| "The position-velocity diagram could not be read back")?;
+
+        let data = bytemuck::cast_slice(&read_buf.slice(..).get_mapped_range()).to_vec();
+        read_buf.unmap();
+
+        Ok(PvDiagram {
+            size: (n, d),
+            data,
+            path: path.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cube of 4x4 spaxels and 2 channels whose values grow linearly along each axis,
+    // which the bilinear interpolation gives back exactly
+    fn ramp() -> Cube {
+        let mut data = vec![];
+        for z in 0..2 {
+            for y in 0..4 {
+                for x in 0..4 {
+                    data.push((x + 10 * y + 100 * z) as f32);
+                }
+            }
+        }
+        Cube {
+            data,
+            dim: (4, 4, 2),
+            datamin: None,
+            datamax: None,
+            bunit: None,
+            wcs: None,
+            name: None,
+        }
+    }
+
+    fn diagram(cube: &Cube, vertices: &[[f64; 2]], width: f32) -> PvDiagram {
+        let path = PvPath {
+            vertices: vertices.to_vec(),
+            width,
+        };
+        PvDiagram::from_cube(cube, &path).unwrap()
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn values_along_a_path() {
+        let cube = ramp();
+        let pv = diagram(&cube, &[[0.0, 1.0], [3.0, 1.0]], 1.0);
+        assert_eq!(pv.size, (4, 2));
+        assert_close(
+            &pv.data,
+            &[10.0, 11.0, 12.0, 13.0, 110.0, 111.0, 112.0, 113.0],
+        );
+
+        // one pixel apart along the diagonal, between the pixels
+        let pv = diagram(&cube, &[[0.0, 0.0], [3.0, 3.0]], 1.0);
+        assert_eq!(pv.size, (5, 2));
+        let step = 11.0 / 2.0_f32.sqrt();
+        assert_close(&pv.data[..4], &[0.0, step, 2.0 * step, 3.0 * step]);
+        // the last sample is beyond the cube, its neighbours inside being kept
+        assert!(pv.data[4].is_finite());
+
+        // a polyline is sampled on from its corner
+        let pv = diagram(&cube, &[[0.0, 0.0], [1.5, 0.0], [1.5, 2.0]], 1.0);
+        assert_close(&pv.data[..4], &[0.0, 1.0, 6.5, 16.5]);
+    }
+
+    #[test]
+    fn slit_averages_across_the_path() {
+        let mut cube = ramp();
+        // the slit of 3 pixels averages 0, 10 and 20 at the middle of the cube
+        let pv = diagram(&cube, &[[0.0, 1.0], [3.0, 1.0]], 3.0);
+        assert_close(&pv.data[..4], &[10.0, 11.0, 12.0, 13.0]);
+
+        // the samples outside of the cube are left out
+        let pv = diagram(&cube, &[[0.0, 0.0], [3.0, 0.0]], 3.0);
+        assert_close(&pv.data[..4], &[5.0, 6.0, 7.0, 8.0]);
+
+        // and so are the blank pixels, here the one at x = 0 and y = 1
+        cube.data[4] = f32::NAN;
+        let pv = diagram(&cube, &[[0.0, 0.0], [3.0, 0.0]], 3.0);
+        assert_close(&pv.data[..2], &[0.0, 6.0]);
+    }
+
+    #[test]
+    fn paths_need_two_distinct_vertices() {
+        let cube = ramp();
+        for vertices in [vec![], vec![[1.0, 1.0]], vec![[1.0, 1.0], [1.0, 1.0]]] {
+            let path = PvPath {
+                vertices,
+                width: 1.0,
+            };
+            assert!(PvDiagram::from_cube(&cube, &path).is_err());
+        }
+    }
+}
@@ -0,0 +1,297 @@
+// delay after the last change of the view before rendering at full quality, in seconds
+pub(crate) const REST_DELAY: f32 = 0.25;
+// resolution of the volume while the view changes, relative to its panel
+pub(crate) const INTERACTIVE_SCALE: f32 = 0.5;
+
+// number of jittered frames averaged in the history before it is considered converged
+pub(crate) const MAX_ACCUMULATED_FRAMES: u32 = 64;
+
+/// Format of the offscreen targets of the volume
+///
+/// A float format keeps the precision of the averaged frames, falling back
+/// to the surface format where it cannot be rendered to and blended.
+pub(crate) fn target_format(
+    adapter: &wgpu::Adapter,
+    surface_format: wgpu::TextureFormat,
+) -> wgpu::TextureFormat {
+    let format = wgpu::TextureFormat::Rgba16Float;
+    let features = adapter.get_texture_format_features(format);
+    if features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        && features.flags.contains(
+            wgpu::TextureFormatFeatureFlags::BLENDABLE
+                | wgpu::TextureFormatFeatureFlags::FILTERABLE,
+        )
+    {
+        format
+    } else {
+        surface_format
+    }
+}
+
+/// Blending of a new frame into an offscreen target with the weight given by the blend constant
+pub(crate) const ACCUMULATE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Constant,
+        dst_factor: wgpu::BlendFactor::OneMinusConstant,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Constant,
+        dst_factor: wgpu::BlendFactor::OneMinusConstant,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Offscreen texture the volume is drawn into before being copied to its panel
+pub(crate) struct OffscreenTarget {
+    size: (u32, u32),
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl OffscreenTarget {
+    pub(crate) fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub(crate) fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Resize the target, returning whether it has been recreated
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, blit: &Blit, size: (u32, u32)) -> bool {
+        let size = (size.0.max(1), size.1.max(1));
+        if size != self.size {
+            *self = blit.create_target(device, size);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Pass copying an offscreen target to the viewport of a render pass,
+/// upscaling it with a bilinear filtering
+pub(crate) struct Blit {
+    target_format: wgpu::TextureFormat,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Blit {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        surface_format: wgpu::TextureFormat,
+    ) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("blit_bind_group_layout"),
+        });
+
+        let pipeline = create_blit_pipeline(device, &bind_group_layout, surface_format);
+
+        Self {
+            target_format,
+            sampler,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    pub(crate) fn create_target(&self, device: &wgpu::Device, size: (u32, u32)) -> OffscreenTarget {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("volume target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.target_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        self.bind_texture(device, &texture, size)
+    }
+
+    /// Upload sRGB encoded RGBA pixels drawn like the targets, e.g. the text overlays
+    pub(crate) fn create_image(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[u8],
+        size: (u32, u32),
+    ) -> OffscreenTarget {
+        let extent = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("overlay image"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            rgba,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size.0),
+                rows_per_image: Some(size.1),
+            },
+            extent,
+        );
+        self.bind_texture(device, &texture, size)
+    }
+
+    fn bind_texture(
+        &self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        size: (u32, u32),
+    ) -> OffscreenTarget {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("blit_bind_group"),
+        });
+
+        OffscreenTarget {
+            size,
+            view,
+            bind_group,
+        }
+    }
+
+    /// Bind the pipeline drawing a target on the viewport of the pass
+    pub(crate) fn bind<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        target: &'a OffscreenTarget,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &target.bind_group, &[]);
+    }
+}
+
+fn create_blit_pipeline(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("blit vert shader"),
+        source: wgpu::ShaderSource::Glsl {
+            shader: include_str!("shaders/blit.vert").into(),
+            stage: wgpu::naga::ShaderStage::Vertex,
+            defines: Default::default(),
+        },
+    });
+    let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("blit frag shader"),
+        source: wgpu::ShaderSource::Glsl {
+            shader: include_str!("shaders/blit.frag").into(),
+            stage: wgpu::naga::ShaderStage::Fragment,
+            defines: Default::default(),
+        },
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Blit Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Blit Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &vs_shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            buffers: &[crate::vertex::Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &fs_shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
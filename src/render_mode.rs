@@ -0,0 +1,185 @@
+/// The way samples are combined along a ray
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    /// Mean of the samples along the ray
+    Average,
+    /// First crossing(s) of the iso levels, shaded with the gradient
+    Isosurface,
+    /// Mean of the samples along the ray as the luminance, with a hue going from blue
+    /// to red along the channels rendered
+    Velocity,
+}
+
+impl RenderMode {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            RenderMode::Average => RenderMode::Isosurface,
+            RenderMode::Isosurface => RenderMode::Velocity,
+            RenderMode::Velocity => RenderMode::Average,
+        }
+    }
+
+    fn as_f32(self) -> f32 {
+        match self {
+            RenderMode::Average => 0.0,
+            RenderMode::Isosurface => 1.0,
+            RenderMode::Velocity => 2.0,
+        }
+    }
+}
+
+impl std::str::FromStr for RenderMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "average" => Ok(RenderMode::Average),
+            "iso" | "isosurface" => Ok(RenderMode::Isosurface),
+            "velocity" => Ok(RenderMode::Velocity),
+            _ => Err("Unknown render mode"),
+        }
+    }
+}
+
+/// Sampling of the rays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quality {
+    /// Number of samples taken per voxel crossed
+    pub samples_per_voxel: f32,
+    /// Cap on the number of samples along a ray, the step growing beyond
+    pub max_steps: u32,
+}
+
+impl Quality {
+    pub const DRAFT: Quality = Quality {
+        samples_per_voxel: 0.5,
+        max_steps: 256,
+    };
+    pub const NORMAL: Quality = Quality {
+        samples_per_voxel: 1.0,
+        max_steps: 1024,
+    };
+    pub const HIGH: Quality = Quality {
+        samples_per_voxel: 2.0,
+        max_steps: 4096,
+    };
+
+    pub(crate) fn next(self) -> Self {
+        if self == Quality::DRAFT {
+            Quality::NORMAL
+        } else if self == Quality::NORMAL {
+            Quality::HIGH
+        } else {
+            Quality::DRAFT
+        }
+    }
+}
+
+impl Default for Quality {
+    fn default() -> Self {
+        Quality::NORMAL
+    }
+}
+
+impl std::str::FromStr for Quality {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(Quality::DRAFT),
+            "normal" => Ok(Quality::NORMAL),
+            "high" => Ok(Quality::HIGH),
+            _ => Err("Unknown quality, expected 'draft', 'normal' or 'high'"),
+        }
+    }
+}
+
+// must match the size of the iso arrays in cube.frag
+pub(crate) const MAX_ISO_LEVELS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IsoLevel {
+    /// Iso value, expressed in the data unit
+    pub value: f32,
+    pub color: [f32; 3],
+    pub opacity: f32,
+}
+
+// default colors given to the levels, from the lowest to the highest
+const ISO_COLORS: [[f32; 3]; MAX_ISO_LEVELS] = [
+    [0.2, 0.4, 1.0],
+    [0.2, 0.9, 0.4],
+    [1.0, 0.8, 0.2],
+    [1.0, 0.3, 0.2],
+];
+
+/// Spread `num` levels evenly inside the data range.
+/// Low levels are kept transparent so that the inner ones stay visible.
+pub(crate) fn spread_iso_levels(num: usize, min: f32, max: f32) -> Vec<IsoLevel> {
+    let num = num.clamp(1, MAX_ISO_LEVELS);
+    (0..num)
+        .map(|i| {
+            let t = (i + 1) as f32 / (num + 1) as f32;
+            IsoLevel {
+                value: min + t * (max - min),
+                color: ISO_COLORS[i],
+                opacity: if i + 1 == num { 1.0 } else { 0.35 },
+            }
+        })
+        .collect()
+}
+
+/// std140 layout of the `Render` uniform block of cube.frag
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct RenderUniform {
+    // x: mode, y: number of iso levels, z: 1 if the values below the lower cut are hidden
+    mode: [f32; 4],
+    iso_values: [f32; MAX_ISO_LEVELS],
+    // rgb + opacity
+    iso_colors: [[f32; 4]; MAX_ISO_LEVELS],
+    // x: samples per voxel, y: max number of steps
+    quality: [f32; 4],
+}
+
+impl RenderUniform {
+    /// * `hide_below_cut` - in the average and velocity coded modes, the samples below
+    ///   the lower cut give nothing so that the parts of the cube below it are skipped
+    pub(crate) fn new(
+        mode: RenderMode,
+        levels: &[IsoLevel],
+        hide_below_cut: bool,
+        quality: Quality,
+    ) -> Self {
+        let mut iso_values = [0.0; MAX_ISO_LEVELS];
+        let mut iso_colors = [[0.0; 4]; MAX_ISO_LEVELS];
+
+        let num_levels = levels.len().min(MAX_ISO_LEVELS);
+        for (i, level) in levels.iter().take(num_levels).enumerate() {
+            iso_values[i] = level.value;
+            iso_colors[i] = [
+                level.color[0],
+                level.color[1],
+                level.color[2],
+                level.opacity,
+            ];
+        }
+
+        Self {
+            mode: [
+                mode.as_f32(),
+                num_levels as f32,
+                if hide_below_cut { 1.0 } else { 0.0 },
+                0.0,
+            ],
+            iso_values,
+            iso_colors,
+            quality: [
+                quality.samples_per_voxel.max(1e-3),
+                quality.max_steps.max(1) as f32,
+                0.0,
+                0.0,
+            ],
+        }
+    }
+}
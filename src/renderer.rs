@@ -0,0 +1,1181 @@
+//! Rendering of the volume into textures, with or without a window
+
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, CameraUniform};
+use crate::capture;
+use crate::channels::ChannelRange;
+use crate::clip::{ClipUniform, Clipping};
+use crate::colormap::{Colormap, Stretch};
+use crate::cube::Cube;
+use crate::grid::MinMaxGrid;
+use crate::labels::{self, LabelDisplay, Labels};
+use crate::layers::{Blend, Layer, LayersUniform, MAX_LAYERS};
+use crate::math::Vec4;
+use crate::moments::{MomentMap, MomentPipeline, MomentSettings};
+use crate::pv::{PvDiagram, PvPath, PvPipeline};
+use crate::refine::{self, Blit};
+use crate::render_mode::{self, IsoLevel, Quality, RenderMode, RenderUniform};
+use crate::texture::Texture;
+use crate::vertex::Vertex;
+
+/// What is rendered and how, for a rendering without a window
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub camera: Camera,
+    /// Data values mapped to 0 and 1 before the cuts
+    pub minmax: Range<f32>,
+    /// Contrast cuts (scale, offset) applied to the normalized values
+    pub cuts: [f32; 2],
+    pub stretch: Stretch,
+    pub colormap: Colormap,
+    pub render_mode: RenderMode,
+    pub iso_levels: Vec<IsoLevel>,
+    /// In the average and velocity coded modes, average the values after the transfer
+    /// function, the ones below the lower cut giving nothing, so that the parts of the
+    /// cube below it are skipped
+    pub hide_below_cut: bool,
+    pub quality: Quality,
+    pub clipping: Clipping,
+    /// Channels rendered, all of them if `None`
+    pub channels: Option<ChannelRange>,
+    /// Number of jittered renderings averaged
+    pub samples: u32,
+    /// Cubes overlaid, each one drawn with the texture of the same index added
+    /// with [`Renderer::add_layer`]
+    pub layers: Vec<Layer>,
+    pub blend: Blend,
+    /// Drawing of the objects labeled with [`Renderer::load_labels`]
+    pub label_display: LabelDisplay,
+    /// Hide the data outside of the objects labeled and shown
+    pub hide_unlabeled: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            camera: Camera::default(),
+            minmax: 0.0..1.0,
+            cuts: [1.0, 0.0],
+            stretch: Stretch::Linear,
+            colormap: Colormap::Jet,
+            render_mode: RenderMode::Average,
+            iso_levels: render_mode::spread_iso_levels(1, 0.0, 1.0),
+            hide_below_cut: false,
+            quality: Quality::HIGH,
+            clipping: Clipping::default(),
+            channels: None,
+            samples: 16,
+            layers: vec![],
+            blend: Blend::default(),
+            label_display: LabelDisplay::default(),
+            hide_unlabeled: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Default settings with the data range of a cube, from its header if given
+    pub fn for_cube(cube: &Cube) -> Self {
+        let range = cube.value_range().unwrap_or(0.0..1.0);
+        let minmax = cube.datamin.unwrap_or(range.start)..cube.datamax.unwrap_or(range.end);
+        Self {
+            iso_levels: render_mode::spread_iso_levels(1, minmax.start, minmax.end),
+            minmax,
+            ..Default::default()
+        }
+    }
+}
+
+/// GPU resources of the ray marching of a cube
+///
+/// The viewer draws with it into its window. On its own, it renders into
+/// textures or RGBA buffers without any window, see [`Renderer::headless`].
+pub struct Renderer {
+    pub(crate) device: wgpu::Device,
+    pub(crate) queue: wgpu::Queue,
+    // format of the frames drawn from the offscreen targets
+    output_format: wgpu::TextureFormat,
+
+    pub(crate) bind_group_layout: wgpu::BindGroupLayout,
+    pub(crate) bind_group: wgpu::BindGroup,
+    // texture of the cube loaded, also read by the compute passes
+    cube: Texture,
+    grid: Texture,
+    // textures of the cubes overlaid, the blank one being bound in place of the missing ones
+    layers: Vec<Texture>,
+    blank: Texture,
+    // labels of the objects in the cube loaded and the table of their colors
+    labels: Option<(Texture, Texture)>,
+    blank_table: Texture,
+    // `None` when the device has no compute shaders, e.g. on WebGL
+    moments: Option<MomentPipeline>,
+    pv: Option<PvPipeline>,
+    pipeline: wgpu::RenderPipeline,
+    // the volume is drawn offscreen and copied to the frames
+    pub(crate) blit: Blit,
+    pub(crate) vertex_buffer: wgpu::Buffer,
+    pub(crate) index_buffer: wgpu::Buffer,
+
+    // uniforms
+    time_buf: wgpu::Buffer,
+    camera_buf: wgpu::Buffer,
+    cuts_buf: wgpu::Buffer,
+    minmax_buf: wgpu::Buffer,
+    render_buf: wgpu::Buffer,
+    clip_buf: wgpu::Buffer,
+    channels_buf: wgpu::Buffer,
+    layers_buf: wgpu::Buffer,
+    labels_buf: wgpu::Buffer,
+
+    // number of channels of the cube loaded
+    depth: u32,
+}
+
+impl Renderer {
+    /// Create the pipelines drawing frames of `output_format` with a device of `adapter`
+    pub fn new(
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let cube =
+            Texture::from_raw_bytes::<f32>(&device, &queue, None, (1, 1, 1), 4, "cube").unwrap();
+        let grid = MinMaxGrid::empty().texture(&device, &queue).unwrap();
+        let blank =
+            Texture::from_raw_bytes::<f32>(&device, &queue, None, (1, 1, 1), 4, "blank").unwrap();
+        let blank_table =
+            Texture::from_raw_bytes::<[u8; 4]>(&device, &queue, None, (1, 1, 1), 4, "blank table")
+                .unwrap();
+
+        // uniform buffers
+        let time_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("time in secs since starting"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let minmax_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("minmax"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let render_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("render mode"),
+            size: std::mem::size_of::<RenderUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let clip_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("clipping"),
+            size: std::mem::size_of::<ClipUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let channels_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("channels"),
+            size: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let camera_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("camera"),
+            size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let cuts_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cuts"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layers_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("layers"),
+            size: std::mem::size_of::<LayersUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let labels_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("labels"),
+            size: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let texture_binding = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D3,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                // time uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+                // camera uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // cuts uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+                // minmax uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Vec4<f32>>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+                // render mode uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<RenderUniform>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // clipping uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<ClipUniform>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+                // spectral range, used by the volume and the slices
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+                // min/max grid for skipping the empty space
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                // cuts, colormaps and alignment of the cubes overlaid
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<LayersUniform>() as wgpu::BufferAddress,
+                        ),
+                    },
+                    count: None,
+                },
+                // textures of the cubes overlaid
+                texture_binding(14),
+                texture_binding(15),
+                texture_binding(16),
+                // display of the objects labeled
+                wgpu::BindGroupLayoutEntry {
+                    binding: 17,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress
+                        ),
+                    },
+                    count: None,
+                },
+                // labels of the voxels and table of the colors of the objects
+                texture_binding(18),
+                texture_binding(19),
+            ],
+            label: Some("texture_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(
+            &device,
+            &bind_group_layout,
+            [&cube, &grid, &blank, &blank_table],
+            [&blank; MAX_LAYERS],
+            [
+                &time_buf,
+                &camera_buf,
+                &cuts_buf,
+                &minmax_buf,
+                &render_buf,
+                &clip_buf,
+                &channels_buf,
+                &layers_buf,
+                &labels_buf,
+            ],
+        );
+
+        let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("cube vert shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/cube.vert").into(),
+                stage: wgpu::naga::ShaderStage::Vertex,
+                defines: Default::default(),
+            },
+        });
+        let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("cube frag shader"),
+            source: wgpu::ShaderSource::Glsl {
+                shader: include_str!("shaders/cube.frag").into(),
+                stage: wgpu::naga::ShaderStage::Fragment,
+                defines: Default::default(),
+            },
+        });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let target_format = refine::target_format(adapter, output_format);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_shader,
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_shader,
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    // the frame is averaged with the previous ones
+                    blend: Some(refine::ACCUMULATE_BLEND),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                polygon_mode: wgpu::PolygonMode::Fill,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None, // 5.
+            cache: None,     // 6.
+        });
+        let blit = Blit::new(&device, target_format, output_format);
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(&[
+                Vertex { ndc: [-1.0, -1.0] },
+                Vertex { ndc: [1.0, -1.0] },
+                Vertex { ndc: [1.0, 1.0] },
+                Vertex { ndc: [-1.0, 1.0] },
+            ]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(&[0, 1, 2, 0, 2, 3]),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let compute = cfg!(not(target_arch = "wasm32"))
+            && adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        let moments = compute.then(|| MomentPipeline::new(&device));
+        let pv = compute.then(|| PvPipeline::new(&device));
+
+        let renderer = Self {
+            device,
+            queue,
+            output_format,
+            bind_group_layout,
+            bind_group,
+            cube,
+            grid,
+            layers: vec![],
+            blank,
+            labels: None,
+            blank_table,
+            moments,
+            pv,
+            pipeline,
+            blit,
+            vertex_buffer,
+            index_buffer,
+            time_buf,
+            camera_buf,
+            cuts_buf,
+            minmax_buf,
+            render_buf,
+            clip_buf,
+            channels_buf,
+            layers_buf,
+            labels_buf,
+            depth: 1,
+        };
+        renderer.write_settings(&RenderSettings {
+            quality: Quality::default(),
+            ..Default::default()
+        });
+        renderer
+    }
+
+    /// Renderer without any window, on a GPU if there is one, else on a software adapter
+    ///
+    /// The backends can be chosen with the `WGPU_BACKEND` environment variable.
+    pub async fn headless() -> Result<Self, &'static str> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
+        let options = |force_fallback_adapter| wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter,
+        };
+        let adapter = match instance.request_adapter(&options(false)).await {
+            Ok(adapter) => adapter,
+            Err(_) => instance
+                .request_adapter(&options(true))
+                .await
+                .map_err(|_| "No GPU adapter found, not even a software one")?,
+        };
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                required_features: wgpu::Features::empty(),
+                // as large cubes as the adapter can take
+                required_limits: adapter.limits(),
+                memory_hints: Default::default(),
+                label: None,
+                trace: wgpu::Trace::Off,
+            })
+            .await
+            .map_err(|_| "Cannot create the GPU device")?;
+
+        Ok(Self::new(
+            &adapter,
+            device,
+            queue,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        ))
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Format of the textures rendered
+    pub fn output_format(&self) -> wgpu::TextureFormat {
+        self.output_format
+    }
+
+    /// Upload a cube to the GPU, replacing the previous one
+    pub fn load_cube(&mut self, cube: &Cube) -> Result<(), &'static str> {
+        // the shaders read the values in the big endian order of the FITS files
+        let bytes: Vec<u8> = cube.data.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let texture = Texture::from_raw_bytes::<f32>(
+            &self.device,
+            &self.queue,
+            Some(&bytes),
+            cube.dim,
+            4,
+            "cube",
+        )?;
+        let grid = MinMaxGrid::from_cube(cube).texture(&self.device, &self.queue)?;
+        self.set_textures(texture, grid, cube.dim.2);
+        Ok(())
+    }
+
+    /// Bind the textures of a cube and of its min/max grid, removing the layers
+    /// aligned on the previous cube and its labels
+    pub(crate) fn set_textures(&mut self, cube: Texture, grid: Texture, depth: u32) {
+        self.depth = depth;
+        self.cube = cube;
+        self.grid = grid;
+        self.layers.clear();
+        self.labels = None;
+        self.bind_textures();
+    }
+
+    /// Upload a cube overlaid on the one loaded, returning its index in the
+    /// layers of the settings
+    pub fn add_layer(&mut self, cube: &Cube) -> Result<usize, &'static str> {
+        if self.layers.len() >= MAX_LAYERS {
+            return Err("Too many cubes overlaid");
+        }
+        let bytes: Vec<u8> = cube.data.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let texture = Texture::from_raw_bytes::<f32>(
+            &self.device,
+            &self.queue,
+            Some(&bytes),
+            cube.dim,
+            4,
+            "layer",
+        )?;
+        self.layers.push(texture);
+        self.bind_textures();
+        Ok(self.layers.len() - 1)
+    }
+
+    /// Remove a cube overlaid, the next ones moving down by one index
+    pub fn remove_layer(&mut self, index: usize) {
+        if index < self.layers.len() {
+            self.layers.remove(index);
+            self.bind_textures();
+        }
+    }
+
+    /// Upload the labels of the objects in the cube loaded, replacing the previous ones
+    pub fn load_labels(&mut self, labels: &Labels) -> Result<(), &'static str> {
+        // unlike the cubes, the indices are uploaded in the native byte order
+        let indices: Vec<f32> = labels.indices.iter().map(|&index| index as f32).collect();
+        let texture = Texture::from_raw_bytes::<f32>(
+            &self.device,
+            &self.queue,
+            Some(bytemuck::cast_slice(&indices)),
+            labels.dim,
+            4,
+            "labels",
+        )?;
+        let (rgba, size) = labels.table();
+        let table = Texture::from_raw_bytes::<[u8; 4]>(
+            &self.device,
+            &self.queue,
+            Some(&rgba),
+            size,
+            4,
+            "label table",
+        )?;
+        self.labels = Some((texture, table));
+        self.bind_textures();
+        Ok(())
+    }
+
+    /// Remove the labels of the objects
+    pub fn clear_labels(&mut self) {
+        self.labels = None;
+        self.bind_textures();
+    }
+
+    /// Colors of the objects labeled, to be written again once some are hidden or shown
+    pub fn write_label_table(&self, labels: &Labels) {
+        if let Some((_, table)) = &self.labels {
+            let (rgba, size) = labels.table();
+            table.write_data(&self.queue, (0, 0, 0), &rgba, size);
+        }
+    }
+
+    fn bind_textures(&mut self) {
+        let layers: [&Texture; MAX_LAYERS] =
+            std::array::from_fn(|i| self.layers.get(i).unwrap_or(&self.blank));
+        let (labels, table) = match &self.labels {
+            Some((labels, table)) => (labels, table),
+            None => (&self.blank, &self.blank_table),
+        };
+        self.bind_group = create_bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            [&self.cube, &self.grid, labels, table],
+            layers,
+            [
+                &self.time_buf,
+                &self.camera_buf,
+                &self.cuts_buf,
+                &self.minmax_buf,
+                &self.render_buf,
+                &self.clip_buf,
+                &self.channels_buf,
+                &self.layers_buf,
+                &self.labels_buf,
+            ],
+        );
+    }
+
+    /// Compute a moment map of `cube`, which must be the one loaded
+    ///
+    /// This runs a compute pass when the device has compute shaders, else the
+    /// map is computed on the CPU.
+    pub fn moment_map(
+        &self,
+        cube: &Cube,
+        settings: &MomentSettings,
+    ) -> Result<MomentMap, &'static str> {
+        match &self.moments {
+            Some(pipeline) => {
+                pipeline.compute(&self.device, &self.queue, &self.cube, cube, settings)
+            }
+            None => Ok(MomentMap::from_cube(cube, settings)),
+        }
+    }
+
+    /// Compute the position-velocity diagram of `cube` along `path`, the cube
+    /// being the one loaded
+    ///
+    /// Like the moment maps, this runs a compute pass when the device has compute
+    /// shaders, else the diagram is computed on the CPU.
+    pub fn pv_diagram(&self, cube: &Cube, path: &PvPath) -> Result<PvDiagram, &'static str> {
+        match &self.pv {
+            Some(pipeline) => pipeline.compute(&self.device, &self.queue, &self.cube, cube, path),
+            None => PvDiagram::from_cube(cube, path),
+        }
+    }
+
+    /// Render the cube loaded into a texture of the output format
+    pub fn render(
+        &self,
+        settings: &RenderSettings,
+        size: (u32, u32),
+    ) -> Result<wgpu::Texture, &'static str> {
+        self.write_settings(settings);
+        self.write_camera(&settings.camera, size.0 as f32 / size.1.max(1) as f32);
+        self.draw_frame(size, settings.samples, 0.0)
+    }
+
+    /// Render the cube loaded into tightly packed RGBA rows of 8 bits per channel
+    pub async fn render_rgba(
+        &self,
+        settings: &RenderSettings,
+        size: (u32, u32),
+    ) -> Result<Vec<u8>, &'static str> {
+        let texture = self.render(settings, size)?;
+        let encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Frame Encoder"),
+            });
+        capture::read_back(&self.device, &self.queue, encoder, &texture)?
+            .rgba()
+            .await
+    }
+
+    // every uniform but the camera and the time
+    fn write_settings(&self, settings: &RenderSettings) {
+        self.write_minmax(&settings.minmax);
+        self.write_cuts(settings.cuts[0], settings.cuts[1]);
+        self.write_transfer(settings.stretch, settings.colormap);
+        self.write_render(
+            settings.render_mode,
+            &settings.iso_levels,
+            settings.hide_below_cut,
+            settings.quality,
+        );
+        self.write_clipping(&settings.clipping);
+        self.write_channels(
+            settings
+                .channels
+                .unwrap_or_else(|| ChannelRange::all(self.depth)),
+        );
+        self.write_layers(&settings.layers, settings.blend);
+        self.write_labels(settings.label_display, settings.hide_unlabeled);
+    }
+
+    /// Time in seconds and index of the frame averaged, driving the jitter of the rays
+    pub(crate) fn write_time(&self, elapsed: f32, frame: u32) {
+        self.queue.write_buffer(
+            &self.time_buf,
+            0,
+            bytemuck::bytes_of(&[elapsed, frame as f32, 0.0, 0.0]),
+        );
+    }
+
+    /// Camera seen from a panel of width / height `aspect`
+    pub(crate) fn write_camera(&self, camera: &Camera, aspect: f32) {
+        self.queue.write_buffer(
+            &self.camera_buf,
+            0,
+            bytemuck::bytes_of(&camera.uniform(aspect)),
+        );
+    }
+
+    pub(crate) fn write_cuts(&self, scale: f32, offset: f32) {
+        self.queue
+            .write_buffer(&self.cuts_buf, 0, bytemuck::bytes_of(&[scale, offset]));
+    }
+
+    /// Stretch and colormap, sharing the uniform of the cuts
+    pub(crate) fn write_transfer(&self, stretch: Stretch, colormap: Colormap) {
+        self.queue.write_buffer(
+            &self.cuts_buf,
+            8,
+            bytemuck::bytes_of(&[stretch.as_f32(), colormap.as_f32()]),
+        );
+    }
+
+    pub(crate) fn write_minmax(&self, minmax: &Range<f32>) {
+        self.queue.write_buffer(
+            &self.minmax_buf,
+            0,
+            bytemuck::bytes_of(&[minmax.start, minmax.end, 0.0_f32, 0.0_f32]),
+        );
+    }
+
+    pub(crate) fn write_render(
+        &self,
+        mode: RenderMode,
+        iso_levels: &[IsoLevel],
+        hide_below_cut: bool,
+        quality: Quality,
+    ) {
+        self.queue.write_buffer(
+            &self.render_buf,
+            0,
+            bytemuck::bytes_of(&RenderUniform::new(
+                mode,
+                iso_levels,
+                hide_below_cut,
+                quality,
+            )),
+        );
+    }
+
+    pub(crate) fn write_clipping(&self, clipping: &Clipping) {
+        self.queue
+            .write_buffer(&self.clip_buf, 0, bytemuck::bytes_of(&clipping.uniform()));
+    }
+
+    pub(crate) fn write_channels(&self, range: ChannelRange) {
+        self.queue.write_buffer(
+            &self.channels_buf,
+            0,
+            bytemuck::bytes_of(&range.uniform(self.depth)),
+        );
+    }
+
+    /// Layers drawn, the ones without a texture being left out
+    pub(crate) fn write_layers(&self, layers: &[Layer], blend: Blend) {
+        let layers = &layers[..layers.len().min(self.layers.len())];
+        self.queue.write_buffer(
+            &self.layers_buf,
+            0,
+            bytemuck::bytes_of(&LayersUniform::new(layers, blend)),
+        );
+    }
+
+    /// Display of the objects labeled, nothing being drawn nor hidden without labels
+    pub(crate) fn write_labels(&self, display: LabelDisplay, hide_unlabeled: bool) {
+        let uniform = match self.labels {
+            Some(_) => labels::labels_uniform(display, hide_unlabeled),
+            None => labels::labels_uniform(LabelDisplay::Off, false),
+        };
+        self.queue
+            .write_buffer(&self.labels_buf, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Draw the volume into an offscreen target, averaged with what it holds with
+    /// the weight given, or replacing it for a weight of 1
+    pub(crate) fn draw_volume(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        weight: f64,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Volume Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: if weight == 1.0 {
+                        wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 0.01,
+                            g: 0.01,
+                            b: 0.01,
+                            a: 1.0,
+                        })
+                    } else {
+                        wgpu::LoadOp::Load
+                    },
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_blend_constant(wgpu::Color {
+            r: weight,
+            g: weight,
+            b: weight,
+            a: weight,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..6, 0, 0..1);
+    }
+
+    /// Draw the volume at a size of its own, averaging jittered samples, into
+    /// a texture of the output format
+    pub(crate) fn draw_frame(
+        &self,
+        size: (u32, u32),
+        samples: u32,
+        elapsed: f32,
+    ) -> Result<wgpu::Texture, &'static str> {
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        if size.0 == 0 || size.1 == 0 || size.0 > max_size || size.1 > max_size {
+            return Err("The frame size is not supported by the GPU");
+        }
+
+        let target = self.blit.create_target(&self.device, size);
+        for i in 0..samples.max(1) {
+            self.write_time(elapsed, i);
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Frame Encoder"),
+                });
+            self.draw_volume(&mut encoder, target.view(), 1.0 / (i + 1) as f64);
+            self.queue.submit(std::iter::once(encoder.finish()));
+        }
+
+        let texture = capture::create_frame_texture(&self.device, self.output_format, size);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Frame Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Frame Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.blit.bind(&mut render_pass, &target);
+            render_pass.draw_indexed(0..6, 0, 0..1);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(texture)
+    }
+}
+
+// bind group of the cube, its grid, its labels with their colors, the layers and
+// the uniform buffers, in the order of their bindings
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    [cube, grid, labels, table]: [&Texture; 4],
+    layers: [&Texture; MAX_LAYERS],
+    [time_buf, camera_buf, cuts_buf, minmax_buf, render_buf, clip_buf, channels_buf, layers_buf, labels_buf]: [&wgpu::Buffer;
+        9],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&cube.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&cube.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: time_buf,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(16),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: camera_buf,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(
+                        std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress
+                    ),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: cuts_buf,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(16),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: minmax_buf,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(16),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: render_buf,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: clip_buf,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: channels_buf,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: wgpu::BindingResource::TextureView(&grid.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: layers_buf,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: wgpu::BindingResource::TextureView(&layers[0].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 15,
+                resource: wgpu::BindingResource::TextureView(&layers[1].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 16,
+                resource: wgpu::BindingResource::TextureView(&layers[2].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 17,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: labels_buf,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 18,
+                resource: wgpu::BindingResource::TextureView(&labels.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 19,
+                resource: wgpu::BindingResource::TextureView(&table.view),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // noise over the whole cube, below 0.1, with a bright ball in its middle
+    fn sparse_cube(n: u32) -> Cube {
+        let c = (n - 1) as f32 / 2.0;
+        let mut seed = 1_u32;
+        let mut data = vec![];
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    let noise = 0.1 * (seed >> 8) as f32 / (1 << 24) as f32;
+                    let (dx, dy, dz) = (x as f32 - c, y as f32 - c, z as f32 - c);
+                    let ball = (-(dx * dx + dy * dy + dz * dz) / 50.0).exp();
+                    data.push(noise + ball);
+                }
+            }
+        }
+        Cube {
+            data,
+            dim: (n, n, n),
+            datamin: None,
+            datamax: None,
+            bunit: None,
+            wcs: None,
+            name: None,
+        }
+    }
+
+    // bind the cube with a grid of which no cell can be skipped
+    fn disable_skipping(renderer: &mut Renderer, cube: &Cube) {
+        let bytes: Vec<u8> = cube.data.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let texture = Texture::from_raw_bytes::<f32>(
+            &renderer.device,
+            &renderer.queue,
+            Some(&bytes),
+            cube.dim,
+            4,
+            "cube",
+        )
+        .unwrap();
+        let grid = MinMaxGrid::empty()
+            .texture(&renderer.device, &renderer.queue)
+            .unwrap();
+        renderer.set_textures(texture, grid, cube.dim.2);
+    }
+
+    #[test]
+    fn hiding_below_cut_skips_without_changing_the_image() {
+        // without any adapter, not even a software one, there is nothing to test
+        let mut renderer = match pollster::block_on(Renderer::headless()) {
+            Ok(renderer) => renderer,
+            Err(_) => return,
+        };
+        let cube = sparse_cube(64);
+        renderer.load_cube(&cube).unwrap();
+
+        for render_mode in [RenderMode::Average, RenderMode::Velocity] {
+            let settings = RenderSettings {
+                // the noise below the lower cut
+                minmax: 0.1..1.0,
+                render_mode,
+                hide_below_cut: true,
+                samples: 1,
+                ..Default::default()
+            };
+            renderer.load_cube(&cube).unwrap();
+            let skipped = pollster::block_on(renderer.render_rgba(&settings, (64, 64))).unwrap();
+            disable_skipping(&mut renderer, &cube);
+            let marched = pollster::block_on(renderer.render_rgba(&settings, (64, 64))).unwrap();
+            assert_eq!(skipped, marched);
+            // the ball is drawn, and nothing around it
+            assert!(skipped
+                .chunks_exact(4)
+                .any(|rgba| rgba[..3] != skipped[..3]));
+        }
+    }
+}
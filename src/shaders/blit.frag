@@ -0,0 +1,12 @@
+// blit.frag
+#version 440
+
+layout(location=0) in vec2 uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_source;
+layout(set = 0, binding = 1) uniform sampler s_source;
+
+void main() {
+    f_color = texture(sampler2D(t_source, s_source), uv);
+}
\ No newline at end of file
@@ -0,0 +1,14 @@
+// blit.vert
+#version 440
+precision highp int;
+precision highp float;
+
+layout(location=0) in vec2 a_ndc;
+
+layout(location=0) out vec2 uv;
+
+void main() {
+    gl_Position = vec4(a_ndc.xy, 0.0, 1.0);
+    // the rows of a texture go downward
+    uv = vec2(a_ndc.x * 0.5 + 0.5, 0.5 - a_ndc.y * 0.5);
+}
\ No newline at end of file
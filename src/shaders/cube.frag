@@ -6,39 +6,95 @@ layout(location=0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform texture3D t_map;
 layout(set = 0, binding = 1) uniform sampler s_map;
-layout(set = 0, binding = 2)
-uniform RotationMatrix {
-    mat4 rot;
-};
 layout(set = 0, binding = 4)
 uniform Time {
     vec4 time;
 };
 layout(set = 0, binding = 5)
-uniform Origin {
-    vec4 origin;
+uniform Camera {
+    // world to camera frame
+    mat4 view;
+    mat4 proj;
+    // clip space to world, to unproject the pixels
+    mat4 inv_view_proj;
 };
 layout(set = 0, binding = 6)
 uniform Cut {
+    // x: scale, y: offset of the normalized values,
+    // z: stretch (0 = linear, 1 = sqrt, 2 = log, 3 = asinh),
+    // w: colormap (0 = jet, 1 = grey, 2 = viridis, 3 = turbo)
     vec4 cut;
 };
-layout(set = 0, binding = 7)
-uniform Perspective {
-    vec4 perspective;
-};
 layout(set = 0, binding = 8)
 uniform MinMax {
     vec4 minmax;
 };
+layout(set = 0, binding = 9)
+uniform Render {
+    // x: mode (0 = average, 1 = isosurface, 2 = velocity coded),
+    // y: number of iso levels, z: 1 if the values below the lower cut are hidden
+    vec4 render_mode;
+    vec4 iso_values;
+    // rgb + opacity of each iso level
+    vec4 iso_colors[4];
+    // x: samples per voxel crossed, y: max number of steps along a ray
+    vec4 quality;
+};
+layout(set = 0, binding = 10)
+uniform Clip {
+    // crop box in texture coordinates, w: 1 to render the cut faces
+    vec4 box_min;
+    // w: number of clipping planes
+    vec4 box_max;
+    // x: crop box face under the cursor (-1 if none), y: 1 to show the box handles
+    vec4 handles;
+    // a plane keeps the points p verifying dot(xyz, p) <= w
+    vec4 planes[4];
+};
+layout(set = 0, binding = 11)
+uniform Channels {
+    // xy: spectral range rendered, in texture coordinates
+    vec4 channels;
+};
+// min and max of the data over macro cells of the cube
+layout(set = 0, binding = 12) uniform texture3D t_grid;
+
+// cube overlaid, with its own colors
+struct Layer {
+    // as in Cut: x: scale, y: offset, z: stretch, w: colormap
+    vec4 cut;
+    // xy: values mapped to 0 and 1 before the cuts, z: opacity
+    vec4 minmax;
+    // texture coordinates in the layer of the point p of the cube: scale * p + offset
+    vec4 scale;
+    vec4 offset;
+};
+// must match layers::MAX_LAYERS
+const int MAX_LAYERS = 3;
+layout(set = 0, binding = 13)
+uniform Layers {
+    // x: number of layers, y: blend (0 = additive, 1 = maximum, 2 = alpha)
+    vec4 layers_params;
+    Layer layers[MAX_LAYERS];
+};
+layout(set = 0, binding = 14) uniform texture3D t_layer0;
+layout(set = 0, binding = 15) uniform texture3D t_layer1;
+layout(set = 0, binding = 16) uniform texture3D t_layer2;
 
-vec3 lonlat2xyz(float lon, float lat) {
-    float lat_s = sin(lat);
-    float lat_c = cos(lat);
-    float lon_s = sin(lon);
-    float lon_c = cos(lon);
+// objects found in the cube by a source finder
+layout(set = 0, binding = 17)
+uniform Labels {
+    // x: display (0 = off, 1 = outlines, 2 = tint),
+    // y: 1 to hide the data outside of the objects shown
+    vec4 labels_params;
+};
+// index of the object of each voxel in the table plus one, 0 outside of the objects
+layout(set = 0, binding = 18) uniform texture3D t_labels;
+// color of each object, with an alpha of 0 once hidden
+layout(set = 0, binding = 19) uniform texture3D t_label_table;
+// must match labels::TABLE_WIDTH
+const int TABLE_WIDTH = 256;
 
-    return vec3(lat_c * lon_s, lat_s, lat_c * lon_c);
-}
 float colormap_red(float x) {
     if (x < 0.7) {
         return 4.0 * x - 1.5;
@@ -111,13 +167,33 @@ vec3 colormap_turbo(in float x) {
     );
 }
 
-vec4 colormap(float x) {
+vec4 colormap_of(float x, float map) {
+    if (map == 1.0) {
+        return vec4(vec3(clamp(x, 0.0, 1.0)), 1.0);
+    } else if (map == 2.0) {
+        return vec4(colormap_viridis(clamp(x, 0.0, 1.0)), 1.0);
+    } else if (map == 3.0) {
+        return vec4(colormap_turbo(x), 1.0);
+    }
+
     float r = clamp(colormap_red(x), 0.0, 1.0);
     float g = clamp(colormap_green(x), 0.0, 1.0);
     float b = clamp(colormap_blue(x), 0.0, 1.0);
     return vec4(r, g, b, 1.0);
 }
 
+vec4 colormap(float x) {
+    return colormap_of(x, cut.w);
+}
+
+// Hue of the velocity coded rendering, from blue at t = 0 to red at t = 1.
+// Must match overlay::velocity_color.
+vec3 velocity_color(float t) {
+    // hue in sixths of a turn, 4 being blue and 0 red
+    float h = 4.0 * (1.0 - clamp(t, 0.0, 1.0));
+    return clamp(abs(mod(h + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+}
+
 float to_l_endian(float x) {
     uint y = floatBitsToUint(x);
 
@@ -142,8 +218,335 @@ float asinhStretch(float x, float scale, float nonlinearity) {
     return asinh(scale * x) / asinh(scale * nonlinearity);
 }
 
-const float fov = 0.523333;
-const float camera_near = 1.0;
+// Data value to the colormap: normalized with the min and max (range.xy),
+// stretched then cut (c as in Cut)
+float transfer_of(float v, vec4 range, vec4 c) {
+    float x = (v - range.x) / (range.y - range.x);
+    if (c.z == 1.0) {
+        x = sqrt(clamp(x, 0.0, 1.0));
+    } else if (c.z == 2.0) {
+        x = log(1000.0 * clamp(x, 0.0, 1.0) + 1.0) / log(1001.0);
+    } else if (c.z == 3.0) {
+        x = asinhStretch(clamp(x, 0.0, 1.0), 10.0, 1.0);
+    }
+    return x * c.x + c.y;
+}
+
+float transfer(float v) {
+    return transfer_of(v, minmax, cut);
+}
+
+// Index of the object at the point p of the cube plus one, 0 outside of the objects
+int label_at(vec3 p) {
+    ivec3 dim = textureSize(sampler3D(t_labels, s_map), 0);
+    ivec3 voxel = ivec3(floor(p * vec3(dim)));
+    if (any(lessThan(voxel, ivec3(0))) || any(greaterThanEqual(voxel, dim))) {
+        return 0;
+    }
+    return int(texelFetch(sampler3D(t_labels, s_map), voxel, 0).r);
+}
+
+// Color of an object, with an alpha of 0 if it is hidden
+vec4 label_color(int label) {
+    int index = label - 1;
+    ivec3 texel = ivec3(index % TABLE_WIDTH, index / TABLE_WIDTH, 0);
+    return texelFetch(sampler3D(t_label_table, s_map), texel, 0);
+}
+
+bool label_shown(int label) {
+    return label > 0 && label_color(label).a > 0.5;
+}
+
+float sample_cube(vec3 p) {
+    // the data outside of the objects shown is hidden as the lowest value
+    if (labels_params.y == 1.0 && !label_shown(label_at(p))) {
+        return minmax.x;
+    }
+    return to_l_endian(texture(sampler3D(t_map, s_map), p).r);
+}
+
+float sample_layer(int k, vec3 q) {
+    float v;
+    if (k == 0) {
+        v = texture(sampler3D(t_layer0, s_map), q).r;
+    } else if (k == 1) {
+        v = texture(sampler3D(t_layer1, s_map), q).r;
+    } else {
+        v = texture(sampler3D(t_layer2, s_map), q).r;
+    }
+    return to_l_endian(v);
+}
+
+// Mean of the samples of a layer along the ray. False if the ray misses the layer
+// or only crosses blank values.
+bool project_layer(int k, vec3 p, vec3 dr, int num_sampling, out float projection) {
+    vec3 q = layers[k].scale.xyz * p + layers[k].offset.xyz;
+    vec3 dq = layers[k].scale.xyz * dr;
+    float sum = 0.0;
+    int n = 0;
+    for (int i = 0; i < num_sampling; i++) {
+        if (all(greaterThanEqual(q, vec3(0.0))) && all(lessThan(q, vec3(1.0)))) {
+            float v = sample_layer(k, q);
+            if (!isnan(v) && !isinf(v)) {
+                sum += v;
+                n++;
+            }
+        }
+        q += dq;
+    }
+    projection = sum / float(max(n, 1));
+    return n > 0;
+}
+
+// Combine the colors of the layers with the one of the cube along the ray
+vec3 blend_layers(vec3 color, vec3 p, vec3 dr, int num_sampling) {
+    for (int k = 0; k < int(layers_params.x); k++) {
+        float v;
+        if (!project_layer(k, p, dr, num_sampling, v)) {
+            continue;
+        }
+        float x = transfer_of(v, layers[k].minmax, layers[k].cut);
+        vec3 c = colormap_of(x, layers[k].cut.w).rgb;
+        // the faint values weigh less, so that the cube shows through them
+        float a = clamp(x, 0.0, 1.0) * layers[k].minmax.z;
+        if (layers_params.y == 0.0) {
+            color += a * c;
+        } else if (layers_params.y == 1.0) {
+            color = max(color, a * c);
+        } else {
+            color = mix(color, c, a);
+        }
+    }
+    return clamp(color, 0.0, 1.0);
+}
+
+float in_object(vec3 p, int label) {
+    return label_at(p) == label ? 1.0 : 0.0;
+}
+
+// Composite front to back the objects shown along the ray over the color of the data,
+// either all their voxels for the tint or, for the outlines, the ones near their
+// boundaries where the ray grazes them
+vec3 draw_labels(vec3 color, vec3 p, vec3 dr, vec3 r, int num_sampling, float samples_per_voxel) {
+    if (labels_params.x == 0.0) {
+        return color;
+    }
+    bool outlines = labels_params.x == 1.0;
+    // opacity per voxel crossed, spread over its samples
+    float alpha = 1.0 - pow(outlines ? 0.1 : 0.92, 1.0 / samples_per_voxel);
+    vec3 d = 1.0 / vec3(textureSize(sampler3D(t_labels, s_map), 0));
+
+    vec4 acc = vec4(0.0);
+    for (int i = 0; i < num_sampling; i++) {
+        int label = label_at(p);
+        if (label_shown(label)) {
+            float a = alpha;
+            if (outlines) {
+                // normal of the boundary from the voxels of the object within two voxels
+                vec3 n = vec3(0.0);
+                for (int k = 1; k <= 2; k++) {
+                    vec3 dk = float(k) * d;
+                    n += vec3(
+                        in_object(p - vec3(dk.x, 0.0, 0.0), label) - in_object(p + vec3(dk.x, 0.0, 0.0), label),
+                        in_object(p - vec3(0.0, dk.y, 0.0), label) - in_object(p + vec3(0.0, dk.y, 0.0), label),
+                        in_object(p - vec3(0.0, 0.0, dk.z), label) - in_object(p + vec3(0.0, 0.0, dk.z), label)
+                    ) / d;
+                }
+                a = dot(n, n) > 0.0 ? alpha * pow(1.0 - abs(dot(normalize(n), r)), 4.0) : 0.0;
+            }
+            acc.rgb += (1.0 - acc.a) * a * label_color(label).rgb;
+            acc.a += (1.0 - acc.a) * a;
+            if (acc.a > 0.99) {
+                break;
+            }
+        }
+        p += dr;
+    }
+    return acc.rgb + (1.0 - acc.a) * color;
+}
+
+// (t_near, t_far) of the intersection of a ray with the box [lo, hi]
+vec2 intersect_box(vec3 o, vec3 r, vec3 lo, vec3 hi) {
+    vec3 t_low = (lo - o) / r;
+    vec3 t_high = (hi - o) / r;
+
+    vec3 t_close = min(t_low, t_high);
+    vec3 t_far = max(t_low, t_high);
+
+    return vec2(
+        max(t_close.x, max(t_close.y, t_close.z)),
+        min(t_far.x, min(t_far.y, t_far.z))
+    );
+}
+
+// edge of a macro cell in voxels, must match grid::CELL_SIZE
+const float GRID_CELL = 8.0;
+
+vec3 grid_cell_size() {
+    return GRID_CELL / vec3(textureSize(sampler3D(t_map, s_map), 0));
+}
+
+// Macro cell containing the point p of the cube
+ivec3 grid_cell(vec3 p) {
+    ivec3 num_cells = textureSize(sampler3D(t_grid, s_map), 0);
+    return clamp(ivec3(floor(p / grid_cell_size())), ivec3(0), num_cells - 1);
+}
+
+// Min and max of the data over a macro cell
+vec2 cell_minmax(ivec3 cell) {
+    return texelFetch(sampler3D(t_grid, s_map), cell, 0).rg;
+}
+
+// Whether a macro cell can be skipped at p. Samples out of the cube wrap
+// around it so they are never skipped, nor any once the data outside of the
+// objects is hidden since the grid holds the data of the whole cube.
+bool can_skip(vec3 p) {
+    return labels_params.y != 1.0 && all(greaterThanEqual(p, vec3(0.0))) && all(lessThan(p, vec3(1.0)));
+}
+
+// Whether all the values of a macro cell are below the lower cut, the transfer
+// function being monotonic
+bool below_cut(vec2 range) {
+    return max(transfer(range.x), transfer(range.y)) <= 0.0;
+}
+
+// Number of samples p + j * dr, j >= 0, lying in a macro cell
+int samples_in_cell(vec3 p, vec3 dr, ivec3 cell) {
+    vec3 lo = vec3(cell) * grid_cell_size();
+    float t_exit = intersect_box(p, dr, lo, lo + grid_cell_size()).y;
+    return max(int(ceil(t_exit)), 1);
+}
+
+// Whether no iso level can be crossed from a sample of value v
+// to samples of values inside range
+bool crosses_no_level(vec2 range, float v, int num_levels) {
+    for (int k = 0; k < num_levels; k++) {
+        float iso = iso_values[k];
+        bool above = range.x >= iso && v >= iso;
+        bool below = range.y <= iso && v <= iso;
+        if (!above && !below) {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Normal of the isosurface from a central difference of the data.
+// It points toward decreasing values, i.e. out of the emission.
+vec3 iso_normal(vec3 p, vec3 r) {
+    vec3 d = 1.0 / vec3(textureSize(sampler3D(t_map, s_map), 0));
+    vec3 g = vec3(
+        sample_cube(p + vec3(d.x, 0.0, 0.0)) - sample_cube(p - vec3(d.x, 0.0, 0.0)),
+        sample_cube(p + vec3(0.0, d.y, 0.0)) - sample_cube(p - vec3(0.0, d.y, 0.0)),
+        sample_cube(p + vec3(0.0, 0.0, d.z)) - sample_cube(p - vec3(0.0, 0.0, d.z))
+    );
+
+    if (dot(g, g) > 0.0) {
+        return -normalize(g);
+    } else {
+        return -r;
+    }
+}
+
+// Phong model with a headlight, i.e. the light comes from the eye
+vec3 shade(vec3 color, vec3 n, vec3 r) {
+    float diffuse = abs(dot(n, -r));
+    // with a headlight, dot(reflect(r, n), -r) = 2 * dot(n, r)^2 - 1
+    float specular = pow(max(2.0 * diffuse * diffuse - 1.0, 0.0), 32.0);
+
+    return color * (0.2 + 0.8 * diffuse) + vec3(0.3 * specular);
+}
+
+// Composite front to back the crossings of the iso levels along the ray
+vec4 render_isosurface(vec3 p, vec3 dr, vec3 r, int num_sampling) {
+    int num_levels = int(render_mode.y);
+
+    vec4 acc = vec4(0.0);
+    float prev = sample_cube(p);
+    // last macro cell found to be crossed by a level
+    ivec3 dense_cell = ivec3(-1);
+    int i = 0;
+    while (i < num_sampling) {
+        vec3 q = p + dr;
+
+        ivec3 cell = grid_cell(q);
+        if (any(notEqual(cell, dense_cell)) && can_skip(q)) {
+            if (crosses_no_level(cell_minmax(cell), prev, num_levels)) {
+                // jump to the last sample of the cell
+                int n = min(samples_in_cell(q, dr, cell), num_sampling - i);
+                p = q + float(n - 1) * dr;
+                prev = sample_cube(p);
+                i += n;
+                continue;
+            }
+            dense_cell = cell;
+        }
+
+        float v = sample_cube(q);
+
+        for (int k = 0; k < num_levels; k++) {
+            float iso = iso_values[k];
+            if ((prev - iso) * (v - iso) < 0.0) {
+                // refine the crossing by bisection between p and q
+                vec3 a = p;
+                vec3 b = q;
+                float va = prev;
+                for (int j = 0; j < 6; j++) {
+                    vec3 m = 0.5 * (a + b);
+                    float vm = sample_cube(m);
+                    if ((va - iso) * (vm - iso) <= 0.0) {
+                        b = m;
+                    } else {
+                        a = m;
+                        va = vm;
+                    }
+                }
+                vec3 hit = 0.5 * (a + b);
+
+                vec3 color = shade(iso_colors[k].rgb, iso_normal(hit, r), r);
+                float alpha = iso_colors[k].a;
+                acc.rgb += (1.0 - acc.a) * alpha * color;
+                acc.a += (1.0 - acc.a) * alpha;
+            }
+        }
+
+        if (acc.a > 0.99) {
+            break;
+        }
+
+        prev = v;
+        p = q;
+        i++;
+    }
+
+    return acc;
+}
+
+const vec3 background = vec3(0.01);
+
+// Index of the face of the box [lo, hi] the point p lies on,
+// in the order -x, +x, -y, +y, -z, +z
+int box_face(vec3 p, vec3 lo, vec3 hi) {
+    vec3 d_lo = abs(p - lo);
+    vec3 d_hi = abs(p - hi);
+
+    int face = 0;
+    float d = d_lo.x;
+    if (d_hi.x < d) { d = d_hi.x; face = 1; }
+    if (d_lo.y < d) { d = d_lo.y; face = 2; }
+    if (d_hi.y < d) { d = d_hi.y; face = 3; }
+    if (d_lo.z < d) { d = d_lo.z; face = 4; }
+    if (d_hi.z < d) { d = d_hi.z; face = 5; }
+    return face;
+}
+
+// Whether the point p of the surface of the box [lo, hi] lies on one of its edges
+bool on_box_edge(vec3 p, vec3 lo, vec3 hi) {
+    vec3 d = min(abs(p - lo), abs(hi - p));
+    vec3 near_face = vec3(lessThan(d, vec3(0.004)));
+    return near_face.x + near_face.y + near_face.z >= 2.0;
+}
+
 //const float dmin = -2.451346722E-03;
 //const float dmax = 1.179221552E-02;
 
@@ -151,77 +554,195 @@ const float camera_near = 1.0;
 const vec3 l = vec3(-0.5, -0.5, -0.5);
 const vec3 h = vec3(0.5, 0.5, 0.5);
 void main() {
-    vec3 cam_origin = 1.5 * lonlat2xyz(origin.x, origin.y);
-
-    // vector from camera origin to the look
-    vec3 cam_dir = normalize(-cam_origin);
-    // origin of the screen in world space
-    vec3 o_cam = cam_origin + cam_dir * camera_near;
-
-    // find a vector belonging to the plane of screen oriented with y
-    vec3 ox = normalize(vec3(cam_dir.z, 0.0, -cam_dir.x));
-    vec3 oy = -cross(ox, cam_dir);
-
-    vec3 p_cam = o_cam + ox * ndc.x + oy * ndc.y;
-
-    // vector director from the cam origin to the pixel on screen
-    // traditional perspective director vector
-    // orthographic perspective
-    vec3 r;
-    if (perspective.x == 1.0) {
-        // perspective
-        r = normalize(p_cam - cam_origin);
-    } else {
-        r = cam_dir;
-    }
+    // unproject the pixel on the near and far planes, the ray starting on the near one
+    vec4 p_near = inv_view_proj * vec4(ndc, -1.0, 1.0);
+    vec4 p_far = inv_view_proj * vec4(ndc, 1.0, 1.0);
+    vec3 p_cam = p_near.xyz / p_near.w;
+    // vector director of the ray, shared by all the pixels in orthographic
+    vec3 r = normalize(p_far.xyz / p_far.w - p_cam);
 
-    vec3 t_low = (l - p_cam) / r;
-    vec3 t_high = (h - p_cam) / r;
+    // the whole cube and the crop box inside it, restricted to the channel range
+    vec2 t_cube = intersect_box(p_cam, r, l, h);
+    vec3 crop_lo = l + vec3(box_min.xy, max(box_min.z, channels.x));
+    vec3 crop_hi = l + vec3(box_max.xy, min(box_max.z, channels.y));
+    vec2 t_crop = intersect_box(p_cam, r, crop_lo, crop_hi);
 
-    vec3 t_close = min(t_low, t_high);
-    vec3 t_far = max(t_low, t_high);
+    // the camera may be inside the cube
+    float t_c = max(t_crop.x, 0.0);
+    float t_f = t_crop.y;
 
-    float t_c = max(t_close.x, max(t_close.y, t_close.z));
-    float t_f = min(t_far.x, min(t_far.y, t_far.z));
+    // clip the marched segment with the planes
+    int num_planes = int(box_max.w);
+    for (int k = 0; k < num_planes; k++) {
+        vec3 n = planes[k].xyz;
+        float denom = dot(n, r);
+        float num = planes[k].w - dot(n, p_cam - l);
+        if (denom > 0.0) {
+            t_f = min(t_f, num / denom);
+        } else if (denom < 0.0) {
+            t_c = max(t_c, num / denom);
+        } else if (num < 0.0) {
+            // parallel to the plane, on the clipped side
+            t_f = t_c - 1.0;
+        }
+    }
+
+    // outline of the crop box and highlight of the face under the cursor
+    vec4 handle = vec4(0.0);
+    if (handles.y == 1.0 && t_crop.y >= t_crop.x) {
+        vec3 p_in = p_cam + r * t_crop.x;
+        vec3 p_out = p_cam + r * t_crop.y;
+        if (on_box_edge(p_in, crop_lo, crop_hi) || on_box_edge(p_out, crop_lo, crop_hi)) {
+            handle = vec4(1.0, 1.0, 1.0, 0.8);
+        } else if (box_face(p_in, crop_lo, crop_hi) == int(handles.x)) {
+            handle = vec4(1.0, 0.8, 0.2, 0.25);
+        }
+    }
 
     if (t_f < t_c) {
+        if (handle.a > 0.0) {
+            f_color = vec4(mix(background, handle.rgb, handle.a), 1.0);
+            return;
+        }
         discard;
     }
 
+    // the ray enters through a cut, render the face as a textured slice
+    if (box_min.w == 1.0 && t_c > t_cube.x + 1e-4) {
+        vec3 p_face = p_cam + r * t_c - l;
+        vec3 color = blend_layers(colormap(transfer(sample_cube(p_face))).rgb, p_face, vec3(0.0), 1);
+        color = draw_labels(color, p_face, vec3(0.0), r, 1, 1.0);
+        f_color = vec4(mix(color, handle.rgb, handle.a), 1.0);
+        return;
+    }
+
     float intensity = 0.0;
-    float step = 0.01;
+    // length of the ray crossing one voxel, given the voxel spacing along each axis
+    float voxel_step = 1.0 / length(r * vec3(textureSize(sampler3D(t_map, s_map), 0)));
+    float step = voxel_step / quality.x;
     int num_sampling = int((t_f - t_c) / step);
-    /*
-    int num_sampling = min(int((t_f - t_c) / step), 50);
-    if (num_sampling == 50) {
+    if (num_sampling > int(quality.y)) {
+        num_sampling = int(quality.y);
         step = (t_f - t_c) / float(num_sampling);
-    }*/
-    //float num_sampling = 100.0;
-    //float step = max((t_f - t_c) / num_sampling, 0.0005);
+    }
+    num_sampling = max(num_sampling, 1);
 
     vec3 dr = r * step;
 
+    // per pixel jitter of the ray start, moved by the golden ratio at each accumulated
+    // frame (time.y) so that the averaged frames cover the whole step
     float random = fract(sin(gl_FragCoord.x * 12.9898 + gl_FragCoord.y * 78.233) * 43758.5453);
+    random = fract(random + time.y * 0.61803398875);
     //float random = 0.0;
     float t_s = t_c + step * random;
     // absolute sampling point
     // scaled to the origin of the cube
     vec3 p = p_cam + r * t_s - l;
-    for (int i = 0; i < num_sampling; i++) {
-        intensity += to_l_endian(texture(sampler3D(t_map, s_map), p).r);
+    // start of the ray, the layers being sampled along it once the cube is
+    vec3 p_start = p;
+
+    if (render_mode.x == 1.0) {
+        vec4 acc = render_isosurface(p, dr, r, num_sampling);
+        vec3 color = acc.rgb + (1.0 - acc.a) * background;
+        color = blend_layers(color, p_start, dr, num_sampling);
+        color = draw_labels(color, p_start, dr, r, num_sampling, voxel_step / step);
+        f_color = vec4(mix(color, handle.rgb, handle.a), 1.0);
+        return;
+    }
+
+    // the samples below the lower cut give nothing once hidden, the values after the
+    // transfer function being averaged instead, so that the cells below it are skipped
+    bool hide_below_cut = render_mode.z == 1.0;
+
+    if (render_mode.x == 2.0) {
+        // velocity coded: the hue is the mean of the colors of the channels of the samples
+        // weighted by their intensity, and the luminance the mean intensity along the ray,
+        // or the mean of the weights once the values below the lower cut are hidden
+        float depth = float(textureSize(sampler3D(t_map, s_map), 0).z);
+        // first channel rendered and number of steps to the last one
+        float first = channels.x * depth;
+        float span = max(channels.y * depth - 1.0 - first, 1.0);
+        vec3 hue = vec3(0.0);
+        float weight = 0.0;
+        ivec3 dense_cell = ivec3(-1);
+        int i = 0;
+        while (i < num_sampling) {
+            ivec3 cell = grid_cell(p);
+            if (any(notEqual(cell, dense_cell)) && can_skip(p)) {
+                // the samples of a cell below the lower cut give no color, and only
+                // their value to the mean if the cell is uniform and they are shown
+                vec2 range = cell_minmax(cell);
+                if (below_cut(range) && (hide_below_cut || range.x == range.y)) {
+                    int n = min(samples_in_cell(p, dr, cell), num_sampling - i);
+                    if (!hide_below_cut) {
+                        intensity += float(n) * range.y;
+                    }
+                    p += float(n) * dr;
+                    i += n;
+                    continue;
+                }
+                dense_cell = cell;
+            }
+
+            float v = sample_cube(p);
+            float w = clamp(transfer(v), 0.0, 1.0);
+            hue += w * velocity_color((p.z * depth - 0.5 - first) / span);
+            weight += w;
+            intensity += hide_below_cut ? w : v;
+            p += dr;
+            i++;
+        }
+        intensity /= float(num_sampling);
+        if (!hide_below_cut) {
+            intensity = clamp(transfer(intensity), 0.0, 1.0);
+        }
+        vec3 color = weight > 0.0 ? hue / weight : vec3(1.0);
+        color = blend_layers(color * intensity, p_start, dr, num_sampling);
+        color = draw_labels(color, p_start, dr, r, num_sampling, voxel_step / step);
+        f_color = vec4(mix(color, handle.rgb, handle.a), 1.0);
+        return;
+    }
+
+    ivec3 dense_cell = ivec3(-1);
+    int i = 0;
+    while (i < num_sampling) {
+        ivec3 cell = grid_cell(p);
+        if (any(notEqual(cell, dense_cell)) && can_skip(p)) {
+            // every sample of a uniform cell adds its value to the mean, and the ones
+            // of a cell below the lower cut nothing once hidden
+            vec2 range = cell_minmax(cell);
+            bool hidden = hide_below_cut && below_cut(range);
+            if (hidden || range.x == range.y) {
+                int n = min(samples_in_cell(p, dr, cell), num_sampling - i);
+                if (!hidden) {
+                    intensity += float(n) * (hide_below_cut ? clamp(transfer(range.y), 0.0, 1.0) : range.y);
+                }
+                p += float(n) * dr;
+                i += n;
+                continue;
+            }
+            dense_cell = cell;
+        }
+        float v = sample_cube(p);
+        intensity += hide_below_cut ? clamp(transfer(v), 0.0, 1.0) : v;
         /*alpha *= 0.97;
         if (alpha < 1e-3) {
             break;
         }*/
         p += dr;
+        i++;
     }
     intensity /= num_sampling;
-    intensity = ((intensity - minmax.x) / (minmax.y - minmax.x)) * cut.x + cut.y;
+    if (!hide_below_cut) {
+        intensity = transfer(intensity);
+    }
 
     //intensity = asinhStretch(intensity, 2.0, 1.0);
 
     //f_color = vec4(colormap_turbo(intensity), 1.0);
-    f_color = colormap(intensity);
+    vec3 color = blend_layers(colormap(intensity).rgb, p_start, dr, num_sampling);
+    color = draw_labels(color, p_start, dr, r, num_sampling, voxel_step / step);
+    f_color = vec4(mix(color, handle.rgb, handle.a), 1.0);
     //f_color = vec4(intensity);
 }
  
\ No newline at end of file
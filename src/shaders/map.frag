@@ -0,0 +1,103 @@
+// map.frag
+#version 440
+
+layout(location=0) in vec2 uv;
+layout(location=0) out vec4 f_color;
+
+// 2D map stored as a texture of depth 1
+layout(set = 0, binding = 0) uniform texture3D t_map;
+layout(set = 0, binding = 1) uniform sampler s_map;
+layout(set = 0, binding = 2)
+uniform Map {
+    // xy: values at both ends of the colormap, zw: fraction of the panel covered by the map
+    vec4 map;
+    // x: number of vertices of the path drawn over the map, y: half width of its slit,
+    // zw: size of the map in pixels
+    vec4 path_params;
+    // vertices of the path in pixels of the map, two per vector
+    vec4 path[16];
+};
+
+const vec3 PATH_COLOR = vec3(1.0, 1.0, 1.0);
+
+vec2 path_vertex(int i) {
+    vec4 pair = path[i / 2];
+    return (i % 2 == 0) ? pair.xy : pair.zw;
+}
+
+// distance in pixels of the map from a point to the path
+float path_distance(vec2 p) {
+    float d = distance(p, path_vertex(0));
+    for (int i = 1; i < int(path_params.x); i++) {
+        vec2 a = path_vertex(i - 1);
+        vec2 ab = path_vertex(i) - a;
+        float t = clamp(dot(p - a, ab) / max(dot(ab, ab), 1e-6), 0.0, 1.0);
+        d = min(d, distance(p, a + t * ab));
+    }
+    return d;
+}
+
+float colormap_red(float x) {
+    if (x < 0.7) {
+        return 4.0 * x - 1.5;
+    } else {
+        return -4.0 * x + 4.5;
+    }
+}
+
+float colormap_green(float x) {
+    if (x < 0.5) {
+        return 4.0 * x - 0.5;
+    } else {
+        return -4.0 * x + 3.5;
+    }
+}
+
+float colormap_blue(float x) {
+    if (x < 0.3) {
+       return 4.0 * x + 0.5;
+    } else {
+       return -4.0 * x + 2.5;
+    }
+}
+
+vec4 colormap(float x) {
+    float r = clamp(colormap_red(x), 0.0, 1.0);
+    float g = clamp(colormap_green(x), 0.0, 1.0);
+    float b = clamp(colormap_blue(x), 0.0, 1.0);
+    return vec4(r, g, b, 1.0);
+}
+
+void main() {
+    vec2 p = uv * path_params.zw;
+    // pixels of the map per pixel of the window
+    float pixel = length(fwidth(p));
+
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        f_color = vec4(vec3(0.01), 1.0);
+        return;
+    }
+
+    float v = texture(sampler3D(t_map, s_map), vec3(uv, 0.5)).r;
+    // blank pixels
+    if (isnan(v)) {
+        f_color = vec4(vec3(0.05), 1.0);
+    } else {
+        f_color = colormap((v - map.x) / (map.y - map.x));
+    }
+
+    if (path_params.x > 0.0) {
+        float d = path_distance(p);
+        // the slit, then a line of about 1.5 pixels of the window along the path
+        if (path_params.x > 1.0 && d < path_params.y) {
+            f_color.rgb = mix(f_color.rgb, PATH_COLOR, 0.2);
+        }
+        float line = 1.0 - smoothstep(0.75 * pixel, 1.5 * pixel, d);
+        // the vertices as dots
+        for (int i = 0; i < int(path_params.x); i++) {
+            float r = distance(p, path_vertex(i));
+            line = max(line, 1.0 - smoothstep(2.5 * pixel, 3.5 * pixel, r));
+        }
+        f_color.rgb = mix(f_color.rgb, PATH_COLOR, line);
+    }
+}
@@ -0,0 +1,20 @@
+// map.vert
+#version 440
+precision highp int;
+precision highp float;
+
+layout(location=0) in vec2 a_ndc;
+
+layout(location=0) out vec2 uv;
+
+layout(set = 0, binding = 2)
+uniform Map {
+    // xy: values at both ends of the colormap, zw: fraction of the panel covered by the map
+    vec4 map;
+};
+
+void main() {
+    gl_Position = vec4(a_ndc.xy, 0.0, 1.0);
+    // letterbox the map so that the pixels stay square
+    uv = (a_ndc / map.zw) * 0.5 + 0.5;
+}
@@ -0,0 +1,99 @@
+// slice.frag
+#version 440
+
+layout(location=0) in vec2 uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture3D t_map;
+layout(set = 0, binding = 1) uniform sampler s_map;
+layout(set = 0, binding = 6)
+uniform Cut {
+    vec4 cut;
+};
+layout(set = 0, binding = 8)
+uniform MinMax {
+    vec4 minmax;
+};
+layout(set = 0, binding = 11)
+uniform Channels {
+    // xy: spectral range rendered, in texture coordinates
+    vec4 channels;
+};
+
+layout(set = 1, binding = 0)
+uniform Slice {
+    // x: axis, y: slice position, zw: fraction of the panel covered by the plane
+    vec4 slice;
+};
+
+float colormap_red(float x) {
+    if (x < 0.7) {
+        return 4.0 * x - 1.5;
+    } else {
+        return -4.0 * x + 4.5;
+    }
+}
+
+float colormap_green(float x) {
+    if (x < 0.5) {
+        return 4.0 * x - 0.5;
+    } else {
+        return -4.0 * x + 3.5;
+    }
+}
+
+float colormap_blue(float x) {
+    if (x < 0.3) {
+       return 4.0 * x + 0.5;
+    } else {
+       return -4.0 * x + 2.5;
+    }
+}
+
+vec4 colormap(float x) {
+    float r = clamp(colormap_red(x), 0.0, 1.0);
+    float g = clamp(colormap_green(x), 0.0, 1.0);
+    float b = clamp(colormap_blue(x), 0.0, 1.0);
+    return vec4(r, g, b, 1.0);
+}
+
+float to_l_endian(float x) {
+    uint y = floatBitsToUint(x);
+
+    uint a = y & 0xff;
+    uint b = (y >> 8) & 0xff;
+    uint c = (y >> 16) & 0xff;
+    uint d = y >> 24;
+
+    uint w = (a << 24) | (b << 16) | (c << 8) | d;
+
+    return uintBitsToFloat(w);
+}
+
+void main() {
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        f_color = vec4(vec3(0.01), 1.0);
+        return;
+    }
+
+    vec3 p;
+    if (slice.x == 0.0) {
+        // XY plane
+        p = vec3(uv, slice.y);
+    } else if (slice.x == 1.0) {
+        // XZ plane
+        p = vec3(uv.x, slice.y, uv.y);
+    } else {
+        // YZ plane
+        p = vec3(slice.y, uv);
+    }
+
+    float intensity = to_l_endian(texture(sampler3D(t_map, s_map), p).r);
+    intensity = ((intensity - minmax.x) / (minmax.y - minmax.x)) * cut.x + cut.y;
+
+    f_color = colormap(intensity);
+    // dim the channels outside of the spectral range
+    if (p.z < channels.x || p.z > channels.y) {
+        f_color.rgb *= 0.3;
+    }
+}
@@ -0,0 +1,20 @@
+// slice.vert
+#version 440
+precision highp int;
+precision highp float;
+
+layout(location=0) in vec2 a_ndc;
+
+layout(location=0) out vec2 uv;
+
+layout(set = 1, binding = 0)
+uniform Slice {
+    // x: axis, y: slice position, zw: fraction of the panel covered by the plane
+    vec4 slice;
+};
+
+void main() {
+    gl_Position = vec4(a_ndc.xy, 0.0, 1.0);
+    // letterbox the plane so that the voxels stay square
+    uv = (a_ndc / slice.zw) * 0.5 + 0.5;
+}
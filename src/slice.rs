@@ -0,0 +1,315 @@
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+/// Axis normal to a slice
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SliceAxis {
+    /// XY plane, stepping along the third (spectral) axis
+    Z,
+    /// XZ plane
+    Y,
+    /// YZ plane
+    X,
+}
+
+impl SliceAxis {
+    pub(crate) const ALL: [SliceAxis; 3] = [SliceAxis::Z, SliceAxis::Y, SliceAxis::X];
+
+    pub(crate) fn index(self) -> usize {
+        match self {
+            SliceAxis::Z => 0,
+            SliceAxis::Y => 1,
+            SliceAxis::X => 2,
+        }
+    }
+
+    /// Number of slices along the axis
+    pub(crate) fn num_slices(self, dim: (u32, u32, u32)) -> u32 {
+        match self {
+            SliceAxis::Z => dim.2,
+            SliceAxis::Y => dim.1,
+            SliceAxis::X => dim.0,
+        }
+    }
+
+    /// Size in voxels of the plane displayed
+    fn plane_size(self, dim: (u32, u32, u32)) -> (u32, u32) {
+        match self {
+            SliceAxis::Z => (dim.0, dim.1),
+            SliceAxis::Y => (dim.0, dim.2),
+            SliceAxis::X => (dim.1, dim.2),
+        }
+    }
+}
+
+impl std::str::FromStr for SliceAxis {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xy" | "z" => Ok(SliceAxis::Z),
+            "xz" | "y" => Ok(SliceAxis::Y),
+            "yz" | "x" => Ok(SliceAxis::X),
+            _ => Err("Unknown slice axis, expected 'xy', 'xz' or 'yz'"),
+        }
+    }
+}
+
+/// Content of a part of the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Panel {
+    Volume,
+    Slice(SliceAxis),
+    /// 2D map computed from the cube
+    Map,
+    /// Position-velocity diagram along the path drawn over the map
+    Pv,
+}
+
+/// Arrangement of the panels in the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewLayout {
+    /// Ray marched volume only
+    Volume,
+    /// A single slice
+    Slice(SliceAxis),
+    /// The three orthogonal slices plus the volume
+    Quad,
+    /// The map computed last, e.g. a moment map
+    Map,
+    /// The position-velocity diagram computed last
+    Pv,
+}
+
+impl ViewLayout {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            ViewLayout::Volume => ViewLayout::Slice(SliceAxis::Z),
+            ViewLayout::Slice(SliceAxis::Z) => ViewLayout::Slice(SliceAxis::Y),
+            ViewLayout::Slice(SliceAxis::Y) => ViewLayout::Slice(SliceAxis::X),
+            ViewLayout::Slice(SliceAxis::X) => ViewLayout::Quad,
+            ViewLayout::Quad | ViewLayout::Map | ViewLayout::Pv => ViewLayout::Volume,
+        }
+    }
+
+    /// Panels and their viewport (x, y, width, height) in pixels
+    pub(crate) fn panels(self, size: PhysicalSize<u32>) -> Vec<(Panel, [f32; 4])> {
+        let (w, h) = (size.width as f32, size.height as f32);
+        match self {
+            ViewLayout::Volume => vec![(Panel::Volume, [0.0, 0.0, w, h])],
+            ViewLayout::Slice(axis) => vec![(Panel::Slice(axis), [0.0, 0.0, w, h])],
+            ViewLayout::Map => vec![(Panel::Map, [0.0, 0.0, w, h])],
+            ViewLayout::Pv => vec![(Panel::Pv, [0.0, 0.0, w, h])],
+            ViewLayout::Quad => {
+                let (hw, hh) = ((w * 0.5).floor(), (h * 0.5).floor());
+                vec![
+                    (Panel::Slice(SliceAxis::Z), [0.0, 0.0, hw, hh]),
+                    (Panel::Volume, [hw, 0.0, w - hw, hh]),
+                    (Panel::Slice(SliceAxis::Y), [0.0, hh, hw, h - hh]),
+                    (Panel::Slice(SliceAxis::X), [hw, hh, w - hw, h - hh]),
+                ]
+            }
+        }
+    }
+
+    /// Panel under a position of the window
+    pub(crate) fn panel_at(
+        self,
+        pos: PhysicalPosition<f64>,
+        size: PhysicalSize<u32>,
+    ) -> Option<(Panel, [f32; 4])> {
+        let (x, y) = (pos.x as f32, pos.y as f32);
+        self.panels(size)
+            .into_iter()
+            .find(|(_, [px, py, pw, ph])| x >= *px && x < px + pw && y >= *py && y < py + ph)
+    }
+}
+
+impl std::str::FromStr for ViewLayout {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "volume" => Ok(ViewLayout::Volume),
+            "quad" => Ok(ViewLayout::Quad),
+            _ => s
+                .parse::<SliceAxis>()
+                .map(ViewLayout::Slice)
+                .map_err(|_| "Unknown layout, expected 'volume', 'xy', 'xz', 'yz' or 'quad'"),
+        }
+    }
+}
+
+/// std140 layout of the `Slice` uniform block of slice.vert/slice.frag
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SliceUniform {
+    // x: axis, y: position of the slice in texture coordinates,
+    // zw: fraction of the panel covered by the plane
+    params: [f32; 4],
+}
+
+/// GPU state of one of the three orthogonal slices
+pub(crate) struct Slice {
+    pub axis: SliceAxis,
+    /// Index of the slice along `axis`
+    pub index: u32,
+
+    buf: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Slice {
+    pub(crate) fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(
+                        std::mem::size_of::<SliceUniform>() as wgpu::BufferAddress
+                    ),
+                },
+                count: None,
+            }],
+            label: Some("slice_bind_group_layout"),
+        })
+    }
+
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        axis: SliceAxis,
+    ) -> Self {
+        let buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("slice"),
+            size: std::mem::size_of::<SliceUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buf.as_entire_binding(),
+            }],
+            label: Some("slice_bind_group"),
+        });
+
+        Self {
+            axis,
+            index: 0,
+            buf,
+            bind_group,
+        }
+    }
+
+    /// Move the slice by `delta` slices, clamped to the cube
+    pub(crate) fn step(&mut self, delta: i32, dim: (u32, u32, u32)) {
+        let num_slices = self.axis.num_slices(dim).max(1) as i64;
+        self.index = (self.index as i64 + delta as i64).clamp(0, num_slices - 1) as u32;
+    }
+
+    /// Write the uniform for a given cube and a panel of `viewport` size
+    pub(crate) fn update(&self, queue: &wgpu::Queue, dim: (u32, u32, u32), viewport: [f32; 4]) {
+        let num_slices = self.axis.num_slices(dim).max(1);
+        // sample the center of the voxels
+        let pos = (self.index as f32 + 0.5) / num_slices as f32;
+
+        let (sx, sy) = letterbox(self.axis.plane_size(dim), viewport);
+
+        queue.write_buffer(
+            &self.buf,
+            0,
+            bytemuck::bytes_of(&SliceUniform {
+                params: [self.axis.index() as f32, pos, sx, sy],
+            }),
+        );
+    }
+}
+
+/// Fraction of a panel of `viewport` size covered by a plane of `size` pixels,
+/// letterboxed inside it to keep the pixels square
+pub(crate) fn letterbox(size: (u32, u32), viewport: [f32; 4]) -> (f32, f32) {
+    let plane_aspect = size.0.max(1) as f32 / size.1.max(1) as f32;
+    let panel_aspect = viewport[2] / viewport[3].max(1.0);
+    if panel_aspect > plane_aspect {
+        (plane_aspect / panel_aspect, 1.0)
+    } else {
+        (1.0, panel_aspect / plane_aspect)
+    }
+}
+
+/// Pipeline drawing a slice of the cube with the colormap and cuts of the volume
+///
+/// The group 0 is the bind group of the volume, giving the cube texture and
+/// the minmax/cuts uniforms, the group 1 is the bind group of a `Slice`
+pub(crate) fn create_pipeline(
+    device: &wgpu::Device,
+    texture_bind_group_layout: &wgpu::BindGroupLayout,
+    slice_bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let vs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("slice vert shader"),
+        source: wgpu::ShaderSource::Glsl {
+            shader: include_str!("shaders/slice.vert").into(),
+            stage: wgpu::naga::ShaderStage::Vertex,
+            defines: Default::default(),
+        },
+    });
+    let fs_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("slice frag shader"),
+        source: wgpu::ShaderSource::Glsl {
+            shader: include_str!("shaders/slice.frag").into(),
+            stage: wgpu::naga::ShaderStage::Fragment,
+            defines: Default::default(),
+        },
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Slice Pipeline Layout"),
+        bind_group_layouts: &[texture_bind_group_layout, slice_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Slice Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &vs_shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            buffers: &[crate::vertex::Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &fs_shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,398 @@
+//! Spectra extracted along the spectral axis of the cube
+
+use std::ops::Range;
+
+use crate::cube::Cube;
+
+/// Spaxels summed into a spectrum, around the center of the aperture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aperture {
+    /// The spaxel at the center only
+    Spaxel,
+    /// The spaxels whose center is within `radius` pixels of the center of the aperture
+    Circle { radius: f32 },
+    /// A box of `width` by `height` spaxels around the center of the aperture
+    Box { width: u32, height: u32 },
+}
+
+impl Aperture {
+    /// Short description, as drawn over the plot
+    pub fn name(&self) -> String {
+        match self {
+            Aperture::Spaxel => "spaxel".to_string(),
+            Aperture::Circle { radius } => format!("circle r {}", radius),
+            Aperture::Box { width, height } => format!("box {}x{}", width, height),
+        }
+    }
+
+    /// Spaxels of a `size` spatial plane in the aperture centered on `center`,
+    /// given in 0-based pixels
+    fn spaxels(&self, center: [f64; 2], size: (u32, u32)) -> Vec<[u32; 2]> {
+        // bounds of the aperture along an axis, cut to the plane
+        let span = |first: f64, last: f64, len: u32| {
+            first.max(0.0) as i64..=last.min(len as f64 - 1.0) as i64
+        };
+        let [cx, cy] = center;
+        let mut spaxels = vec![];
+        if !(cx.is_finite() && cy.is_finite()) {
+            return spaxels;
+        }
+        match *self {
+            Aperture::Spaxel => {
+                let (x, y) = (cx.round(), cy.round());
+                if x >= 0.0 && y >= 0.0 && x < size.0 as f64 && y < size.1 as f64 {
+                    spaxels.push([x as u32, y as u32]);
+                }
+            }
+            Aperture::Circle { radius } => {
+                let r = radius as f64;
+                for y in span((cy - r).ceil(), (cy + r).floor(), size.1) {
+                    for x in span((cx - r).ceil(), (cx + r).floor(), size.0) {
+                        let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+                        if dx * dx + dy * dy <= r * r {
+                            spaxels.push([x as u32, y as u32]);
+                        }
+                    }
+                }
+            }
+            Aperture::Box { width, height } => {
+                // the box is centered on the center when it spans an odd number of spaxels
+                let x0 = (cx - (width as f64 - 1.0) / 2.0).round();
+                let y0 = (cy - (height as f64 - 1.0) / 2.0).round();
+                for y in span(y0, y0 + height as f64 - 1.0, size.1) {
+                    for x in span(x0, x0 + width as f64 - 1.0, size.0) {
+                        spaxels.push([x as u32, y as u32]);
+                    }
+                }
+            }
+        }
+        spaxels
+    }
+}
+
+impl std::str::FromStr for Aperture {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str =
+            "Expected 'spaxel', 'circle:<radius>', 'box:<width>x<height>' or 'box:<size>'";
+
+        match s.split_once(':') {
+            None if s == "spaxel" => Ok(Aperture::Spaxel),
+            Some(("circle", radius)) => match radius.trim().parse::<f32>() {
+                Ok(radius) if radius.is_finite() && radius >= 0.0 => {
+                    Ok(Aperture::Circle { radius })
+                }
+                Ok(_) => Err("The radius must be positive"),
+                Err(_) => Err(EXPECTED),
+            },
+            Some(("box", size)) => {
+                let (width, height) = size.split_once('x').unwrap_or((size, size));
+                match (width.trim().parse::<u32>(), height.trim().parse::<u32>()) {
+                    (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+                        Ok(Aperture::Box { width, height })
+                    }
+                    (Ok(_), Ok(_)) => Err("The size of the box must be positive"),
+                    _ => Err(EXPECTED),
+                }
+            }
+            _ => Err(EXPECTED),
+        }
+    }
+}
+
+/// File format of a spectrum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpectrumFormat {
+    Csv,
+    /// FITS binary table
+    Fits,
+}
+
+impl SpectrumFormat {
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        ext.parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SpectrumFormat::Csv => "csv",
+            SpectrumFormat::Fits => "fits",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            SpectrumFormat::Csv => "text/csv",
+            SpectrumFormat::Fits => "application/fits",
+        }
+    }
+}
+
+impl std::str::FromStr for SpectrumFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(SpectrumFormat::Csv),
+            "fits" | "fit" => Ok(SpectrumFormat::Fits),
+            _ => Err("Unknown spectrum format, expected 'csv' or 'fits'"),
+        }
+    }
+}
+
+/// Spectrum of a spaxel, or summed over an aperture, along the whole spectral axis
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spectrum {
+    /// Center of the aperture, in 0-based pixels of the spatial plane
+    pub center: [f64; 2],
+    /// World coordinates of the center, if the cube has a WCS
+    pub world: Option<[f64; 2]>,
+    pub aperture: Aperture,
+    /// Number of spaxels of the cube in the aperture
+    pub num_spaxels: u32,
+    /// Spectral coordinate of each channel in the unit of CUNIT3, if the cube has a WCS
+    pub coords: Option<Vec<f64>>,
+    /// Sum over the aperture of the finite values of each channel, NaN if there is none
+    pub values: Vec<f32>,
+    /// Type and unit of the spectral axis, given by CTYPE3 and CUNIT3
+    pub ctype: String,
+    pub cunit: String,
+    /// Unit of the values, the one of the cube
+    pub bunit: Option<String>,
+}
+
+impl Spectrum {
+    /// Extract the spectrum of the aperture centered on `center`, in 0-based pixels
+    pub fn extract(
+        cube: &Cube,
+        center: [f64; 2],
+        aperture: Aperture,
+    ) -> Result<Self, &'static str> {
+        let (w, h, d) = cube.dim;
+        let spaxels = aperture.spaxels(center, (w, h));
+        if spaxels.is_empty() {
+            return Err("The aperture does not contain any spaxel of the cube");
+        }
+
+        let values = (0..d)
+            .map(|z| {
+                let mut finite = spaxels
+                    .iter()
+                    .map(|&[x, y]| cube.value(x, y, z))
+                    .filter(|v| v.is_finite())
+                    .peekable();
+                if finite.peek().is_none() {
+                    f32::NAN
+                } else {
+                    finite.sum()
+                }
+            })
+            .collect();
+
+        let wcs = cube.wcs();
+        let coords = wcs.map(|wcs| (0..d).map(|z| wcs.axes[2].pix2world(z as f64)).collect());
+        let (ctype, cunit) = match wcs {
+            Some(wcs) => (wcs.axes[2].ctype.clone(), wcs.axes[2].cunit.clone()),
+            None => (String::new(), String::new()),
+        };
+
+        Ok(Self {
+            center,
+            world: wcs.map(|wcs| {
+                let world = wcs.pix2world([center[0], center[1], 0.0]);
+                [world[0], world[1]]
+            }),
+            aperture,
+            num_spaxels: spaxels.len() as u32,
+            coords,
+            values,
+            ctype,
+            cunit,
+            bunit: cube.bunit.clone(),
+        })
+    }
+
+    /// Extract the spectrum of the aperture centered on the world coordinates of
+    /// the spatial axes, e.g. RA and DEC
+    pub fn extract_world(
+        cube: &Cube,
+        world: [f64; 2],
+        aperture: Aperture,
+    ) -> Result<Self, &'static str> {
+        let wcs = cube.wcs().ok_or("The cube has no WCS")?;
+        let p = wcs.world2pix([world[0], world[1], wcs.axes[2].crval]);
+        if !(p[0].is_finite() && p[1].is_finite()) {
+            return Err("The position is outside of the projection of the cube");
+        }
+        Self::extract(cube, [p[0], p[1]], aperture)
+    }
+
+    /// Minimum and maximum of the values, the blank ones being ignored
+    pub fn value_range(&self) -> Option<Range<f32>> {
+        let mut values = self.values.iter().copied().filter(|v| v.is_finite());
+        let first = values.next()?;
+        Some(values.fold(first..first, |range, v| {
+            range.start.min(v)..range.end.max(v)
+        }))
+    }
+
+    /// Name of the spectral coordinate, the channel index without WCS
+    pub(crate) fn coord_name(&self) -> &str {
+        if self.coords.is_none() {
+            return "channel";
+        }
+        // the type of the coordinate, without the algorithm code
+        match self.ctype.split('-').next().unwrap_or_default() {
+            "" => "spectral",
+            name => name,
+        }
+    }
+
+    /// Spectral coordinate of each channel, the channel index without WCS
+    pub(crate) fn coords_or_channels(&self) -> Vec<f64> {
+        match &self.coords {
+            Some(coords) => coords.clone(),
+            None => (0..self.values.len()).map(|z| z as f64).collect(),
+        }
+    }
+
+    /// Aperture and position the spectrum is extracted at
+    pub(crate) fn description(&self) -> String {
+        let [x, y] = self.center.map(format_coord);
+        let aperture = match self.aperture {
+            Aperture::Spaxel => String::new(),
+            Aperture::Circle { radius } => format!("circle of radius {} pixels", radius),
+            Aperture::Box { width, height } => format!("box of {}x{} pixels", width, height),
+        };
+        let mut description = if aperture.is_empty() {
+            format!("Spaxel at the pixel {} {}", x, y)
+        } else {
+            format!(
+                "Sum of the {} spaxels of a {} centered on the pixel {} {}",
+                self.num_spaxels, aperture, x, y
+            )
+        };
+        if let Some(world) = self.world {
+            let [a, b] = world.map(format_coord);
+            description.push_str(&format!(", world coordinates {} {}", a, b));
+        }
+        description
+    }
+
+    /// Title of the plot, in the characters of the overlay font
+    pub(crate) fn title(&self) -> String {
+        let [x, y] = self.center.map(|c| format!("{:.0}", c));
+        format!("{} at {} {}", self.aperture.name(), x, y)
+    }
+
+    /// Comma separated values, one row per channel, after comment lines starting with '#'
+    pub fn to_csv(&self) -> String {
+        let with_unit = |name: &str, unit: &str| {
+            if unit.is_empty() {
+                name.to_string()
+            } else {
+                format!("{} [{}]", name, unit)
+            }
+        };
+        let mut csv = format!("# {}\n", self.description());
+        let value = with_unit("value", self.bunit.as_deref().unwrap_or_default());
+        match &self.coords {
+            Some(coords) => {
+                let coord = with_unit(self.coord_name(), &self.cunit);
+                csv.push_str(&format!("channel,{},{}\n", coord, value));
+                for (z, (coord, value)) in coords.iter().zip(self.values.iter()).enumerate() {
+                    csv.push_str(&format!("{},{},{}\n", z, coord, value));
+                }
+            }
+            None => {
+                csv.push_str(&format!("channel,{}\n", value));
+                for (z, value) in self.values.iter().enumerate() {
+                    csv.push_str(&format!("{},{}\n", z, value));
+                }
+            }
+        }
+        csv
+    }
+}
+
+// pixel or world coordinate with 4 decimals at most, the trailing zeros being dropped
+pub(crate) fn format_coord(c: f64) -> String {
+    let s = format!("{:.4}", c);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cube of 4x3 spaxels of 2 channels, each value giving its voxel
+    fn cube() -> Cube {
+        let mut data = vec![];
+        for z in 0..2 {
+            for y in 0..3 {
+                for x in 0..4 {
+                    data.push((100 * z + 10 * y + x) as f32);
+                }
+            }
+        }
+        Cube {
+            data,
+            dim: (4, 3, 2),
+            datamin: None,
+            datamax: None,
+            bunit: None,
+            wcs: None,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn apertures_keep_the_spaxels_of_the_plane() {
+        assert_eq!(Aperture::Spaxel.spaxels([1.4, 1.6], (4, 3)), [[1, 2]]);
+        assert!(Aperture::Spaxel.spaxels([-0.6, 0.0], (4, 3)).is_empty());
+        assert!(Aperture::Spaxel.spaxels([f64::NAN, 0.0], (4, 3)).is_empty());
+
+        let circle = Aperture::Circle { radius: 1.0 };
+        assert_eq!(
+            circle.spaxels([1.0, 1.0], (4, 3)),
+            [[1, 0], [0, 1], [1, 1], [2, 1], [1, 2]]
+        );
+        assert_eq!(circle.spaxels([0.0, 0.0], (4, 3)), [[0, 0], [1, 0], [0, 1]]);
+
+        // an even box starts at the spaxel of the center
+        let box_ = Aperture::Box {
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(box_.spaxels([2.0, 0.0], (4, 3)), [[2, 0], [3, 0]]);
+        assert!(box_.spaxels([10.0, 10.0], (4, 3)).is_empty());
+    }
+
+    #[test]
+    fn huge_apertures_are_cut_to_the_plane() {
+        let circle = Aperture::Circle { radius: 1e7 };
+        assert_eq!(circle.spaxels([1.0, 1.0], (4, 3)).len(), 12);
+        let box_ = Aperture::Box {
+            width: 100_000,
+            height: 100_000,
+        };
+        assert_eq!(box_.spaxels([1e9, -1e9], (4, 3)).len(), 0);
+        assert_eq!(box_.spaxels([0.0, 0.0], (4, 3)).len(), 12);
+    }
+
+    #[test]
+    fn spectra_sum_the_spaxels_of_each_channel() {
+        let spectrum =
+            Spectrum::extract(&cube(), [3.0, 2.0], Aperture::Circle { radius: 1.0 }).unwrap();
+        assert_eq!(spectrum.num_spaxels, 3);
+        // (23 + 13 + 22) then the same plus 3 x 100
+        assert_eq!(spectrum.values, [58.0, 358.0]);
+        assert_eq!(spectrum.coords, None);
+        assert_eq!(spectrum.coord_name(), "channel");
+
+        let outside = Spectrum::extract(&cube(), [9.0, 9.0], Aperture::Spaxel);
+        assert!(outside.is_err());
+    }
+}
@@ -14,6 +14,9 @@ pub trait TextureFormat: ToByteSlice {
 impl TextureFormat for f32 {
     const WGPU_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
 }
+impl TextureFormat for [f32; 2] {
+    const WGPU_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+}
 impl TextureFormat for [u8; 4] {
     const WGPU_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 }
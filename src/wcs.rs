@@ -0,0 +1,578 @@
+use fitsrs::card::Value;
+
+/// Linear world coordinate description of one axis of the cube
+///
+/// The celestial projection and the PC matrix are not taken into account, so its
+/// conversions hold for the spectral axis, the ones of the spatial axes being done
+/// by [`Wcs::pix2world`] and [`Wcs::world2pix`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WcsAxis {
+    pub ctype: String,
+    pub cunit: String,
+    pub crval: f64,
+    /// 1-based reference pixel, as in the FITS header
+    pub crpix: f64,
+    pub cdelt: f64,
+}
+
+impl WcsAxis {
+    /// World coordinate of a 0-based pixel position
+    pub fn pix2world(&self, p: f64) -> f64 {
+        self.crval + (p + 1.0 - self.crpix) * self.cdelt
+    }
+
+    /// 0-based pixel position of a world coordinate
+    pub fn world2pix(&self, w: f64) -> f64 {
+        (w - self.crval) / self.cdelt + self.crpix - 1.0
+    }
+}
+
+/// Keywords of the header giving the frames of the world coordinates, carried as
+/// they are into the files derived from the cube
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WcsFrame {
+    /// Rest frequency of the line, from RESTFRQ (or RESTFREQ), in Hz
+    pub restfrq: Option<f64>,
+    /// Standard of rest of the spectral axis, from SPECSYS
+    pub specsys: Option<String>,
+    /// Reference frame of the celestial axes, from RADESYS (or RADECSYS)
+    pub radesys: Option<String>,
+    /// Equinox of the celestial axes, from EQUINOX (or EPOCH)
+    pub equinox: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wcs {
+    pub axes: [WcsAxis; 3],
+    /// Linear transformation of the pixel offsets applied before CDELT, the PCi_j
+    /// matrix indexed by the x, y and z axes of the cube. The identity unless the
+    /// axes are rotated, which the volume rendering does not take into account.
+    pub pc: [[f64; 3]; 3],
+    pub frame: WcsFrame,
+}
+
+const IDENTITY: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+fn get_f64(value: Option<&Value>) -> Option<f64> {
+    match value {
+        Some(Value::Float { value, .. }) => Some(*value),
+        Some(Value::Integer { value, .. }) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn get_string(value: Option<&Value>) -> Option<String> {
+    match value {
+        Some(Value::String { value, .. }) => Some(value.trim().to_string()),
+        _ => None,
+    }
+}
+
+impl Wcs {
+    /// Parse the WCS of the cube axes from its header
+    ///
+    /// * `get` - gives the value of a header keyword
+    /// * `axes` - the 1-based FITS axis numbers mapped to the x, y and z axes of the cube
+    pub(crate) fn parse<'a, F>(get: F, axes: [usize; 3]) -> Option<Self>
+    where
+        F: Fn(&str) -> Option<&'a Value>,
+    {
+        let cd = |i: usize, j: usize| get_f64(get(&format!("CD{}_{}", axes[i], axes[j])));
+        let parse_axis = |i: usize| -> Option<WcsAxis> {
+            let n = axes[i];
+            // CDELT may be given through the CD matrix, as the scale of its row
+            let cdelt = get_f64(get(&format!("CDELT{}", n))).or_else(|| {
+                let row: Vec<f64> = (0..3).filter_map(|j| cd(i, j)).collect();
+                let scale = row.iter().map(|v| v * v).sum::<f64>().sqrt();
+                (scale > 0.0).then(|| scale.copysign(cd(i, i).unwrap_or(1.0)))
+            })?;
+
+            Some(WcsAxis {
+                ctype: get_string(get(&format!("CTYPE{}", n))).unwrap_or_default(),
+                cunit: get_string(get(&format!("CUNIT{}", n))).unwrap_or_default(),
+                crval: get_f64(get(&format!("CRVAL{}", n))).unwrap_or(0.0),
+                crpix: get_f64(get(&format!("CRPIX{}", n))).unwrap_or(0.0),
+                cdelt,
+            })
+        };
+        let axes_wcs = [parse_axis(0)?, parse_axis(1)?, parse_axis(2)?];
+
+        let mut pc = IDENTITY;
+        for (i, row) in pc.iter_mut().enumerate() {
+            // the rows of the CD matrix are the ones of PC scaled by CDELT
+            let from_cd = get(&format!("CDELT{}", axes[i])).is_none();
+            for (j, v) in row.iter_mut().enumerate() {
+                if from_cd {
+                    *v = cd(i, j).unwrap_or(0.0) / axes_wcs[i].cdelt;
+                } else if let Some(value) = get_f64(get(&format!("PC{}_{}", axes[i], axes[j]))) {
+                    *v = value;
+                }
+            }
+        }
+        // rotation of the latitude axis in the AIPS convention, without any matrix
+        let crota = get_f64(get(&format!("CROTA{}", axes[1]))).filter(|&angle| angle != 0.0);
+        if let (Some(angle), true) = (crota, pc == IDENTITY) {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let ratio = axes_wcs[1].cdelt / axes_wcs[0].cdelt;
+            pc[0] = [cos, -sin * ratio, 0.0];
+            pc[1] = [sin / ratio, cos, 0.0];
+        }
+
+        let frame = WcsFrame {
+            restfrq: get_f64(get("RESTFRQ")).or_else(|| get_f64(get("RESTFREQ"))),
+            specsys: get_string(get("SPECSYS")),
+            radesys: get_string(get("RADESYS")).or_else(|| get_string(get("RADECSYS"))),
+            equinox: get_f64(get("EQUINOX")).or_else(|| get_f64(get("EPOCH"))),
+        };
+
+        Some(Self {
+            axes: axes_wcs,
+            pc,
+            frame,
+        })
+    }
+
+    /// World coordinates of a 0-based voxel position
+    ///
+    /// The offsets from CRPIX go through PC and CDELT, then the celestial axes through
+    /// their projection: exactly for the zenithal ones (TAN, SIN, ARC, STG and ZEA)
+    /// with the default LONPOLE, and as offsets on the sky around the reference point
+    /// for the others. Celestial axes without projection code are linear.
+    pub fn pix2world(&self, p: [f64; 3]) -> [f64; 3] {
+        let x = self.intermediate(p);
+        let mut w = [0, 1, 2].map(|i| self.axes[i].crval + x[i]);
+        if let Some((lon, lat, projection)) = self.celestial() {
+            let (lon_axis, lat_axis) = (&self.axes[lon], &self.axes[lat]);
+            let (lon_scale, lat_scale) = (degrees(lon_axis), degrees(lat_axis));
+            let reference = (lon_axis.crval * lon_scale, lat_axis.crval * lat_scale);
+            let (x, y) = (x[lon] * lon_scale, x[lat] * lat_scale);
+            let (a, d) = match projection {
+                Some(projection) => {
+                    let (phi, theta) = projection.deproject(x, y);
+                    native_to_celestial(phi, theta, reference)
+                }
+                None => (
+                    reference.0 + x / reference.1.to_radians().cos(),
+                    reference.1 + y,
+                ),
+            };
+            w[lon] = a.rem_euclid(360.0) / lon_scale;
+            w[lat] = d / lat_scale;
+        }
+        w
+    }
+
+    /// 0-based voxel position of world coordinates, the inverse of `pix2world`
+    pub fn world2pix(&self, w: [f64; 3]) -> [f64; 3] {
+        let mut x = [0, 1, 2].map(|i| w[i] - self.axes[i].crval);
+        if let Some((lon, lat, projection)) = self.celestial() {
+            let (lon_axis, lat_axis) = (&self.axes[lon], &self.axes[lat]);
+            let (lon_scale, lat_scale) = (degrees(lon_axis), degrees(lat_axis));
+            let reference = (lon_axis.crval * lon_scale, lat_axis.crval * lat_scale);
+            let (a, d) = (w[lon] * lon_scale, w[lat] * lat_scale);
+            let (x_lon, x_lat) = match projection {
+                Some(projection) => {
+                    let (phi, theta) = celestial_to_native(a, d, reference);
+                    projection.project(phi, theta)
+                }
+                None => (
+                    ((a - reference.0 + 180.0).rem_euclid(360.0) - 180.0)
+                        * reference.1.to_radians().cos(),
+                    d - reference.1,
+                ),
+            };
+            x[lon] = x_lon / lon_scale;
+            x[lat] = x_lat / lat_scale;
+        }
+
+        // back through CDELT and PC
+        let q = [0, 1, 2].map(|i| x[i] / self.axes[i].cdelt);
+        let inverse = invert(&self.pc).unwrap_or(IDENTITY);
+        [0, 1, 2].map(|i| {
+            let offset: f64 = (0..3).map(|j| inverse[i][j] * q[j]).sum();
+            offset + self.axes[i].crpix - 1.0
+        })
+    }
+
+    /// Intermediate world coordinates of a 0-based voxel position, the offsets from
+    /// CRPIX multiplied by PC then CDELT
+    pub(crate) fn intermediate(&self, p: [f64; 3]) -> [f64; 3] {
+        let offsets = [0, 1, 2].map(|j| p[j] + 1.0 - self.axes[j].crpix);
+        let matrix = self.linear_matrix();
+        [0, 1, 2].map(|i| (0..3).map(|j| matrix[i][j] * offsets[j]).sum())
+    }
+
+    /// Matrix of the intermediate world coordinates of the pixel offsets, the rows of
+    /// PC multiplied by CDELT
+    pub(crate) fn linear_matrix(&self) -> [[f64; 3]; 3] {
+        [0, 1, 2].map(|i| self.pc[i].map(|v| self.axes[i].cdelt * v))
+    }
+
+    // longitude and latitude axes with the projection of the longitude one, `None`
+    // for the linear axes and for the projections approximated
+    fn celestial(&self) -> Option<(usize, usize, Option<Zenithal>)> {
+        let types = self.axes.clone().map(|axis| axis.ctype);
+        let lon = types.iter().position(|ctype| {
+            matches!(axis_type(ctype), "RA") || axis_type(ctype).ends_with("LON")
+        })?;
+        let lat = types.iter().position(|ctype| {
+            matches!(axis_type(ctype), "DEC") || axis_type(ctype).ends_with("LAT")
+        })?;
+        let code = types[lon].get(5..).unwrap_or_default().trim();
+        if code.is_empty() {
+            return None;
+        }
+        Some((lon, lat, code.parse().ok()))
+    }
+}
+
+// type of an axis without its projection code, e.g. RA for RA---SIN
+fn axis_type(ctype: &str) -> &str {
+    ctype.split('-').next().unwrap_or_default().trim()
+}
+
+// factor from the unit of a celestial axis to the degree
+fn degrees(axis: &WcsAxis) -> f64 {
+    match axis.cunit.as_str() {
+        "arcmin" => 1.0 / 60.0,
+        "arcsec" => 1.0 / 3600.0,
+        "mas" => 1.0 / 3.6e6,
+        "rad" => 180.0 / std::f64::consts::PI,
+        _ => 1.0,
+    }
+}
+
+/// Zenithal projections of the FITS standard, whose native pole is the reference
+/// point of the celestial axes
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Zenithal {
+    /// Gnomonic
+    Tan,
+    /// Orthographic, without the obliquity parameters
+    Sin,
+    /// Zenithal equidistant
+    Arc,
+    /// Stereographic
+    Stg,
+    /// Zenithal equal area
+    Zea,
+}
+
+impl std::str::FromStr for Zenithal {
+    type Err = ();
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code {
+            "TAN" => Ok(Zenithal::Tan),
+            "SIN" => Ok(Zenithal::Sin),
+            "ARC" => Ok(Zenithal::Arc),
+            "STG" => Ok(Zenithal::Stg),
+            "ZEA" => Ok(Zenithal::Zea),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Zenithal {
+    /// Native longitude and latitude of the intermediate coordinates (x, y), in
+    /// degrees, NaN outside of the projection
+    fn deproject(self, x: f64, y: f64) -> (f64, f64) {
+        let r = x.hypot(y);
+        let phi = if r == 0.0 {
+            0.0
+        } else {
+            x.atan2(-y).to_degrees()
+        };
+        let r_rad = r.to_radians();
+        let theta = match self {
+            Zenithal::Tan => 1.0_f64.atan2(r_rad).to_degrees(),
+            Zenithal::Sin => r_rad.acos().to_degrees(),
+            Zenithal::Arc => 90.0 - r,
+            Zenithal::Stg => 90.0 - 2.0 * (r_rad / 2.0).atan().to_degrees(),
+            Zenithal::Zea => 90.0 - 2.0 * (r_rad / 2.0).asin().to_degrees(),
+        };
+        (phi, theta)
+    }
+
+    /// Intermediate coordinates (x, y) of the native longitude and latitude, in
+    /// degrees
+    fn project(self, phi: f64, theta: f64) -> (f64, f64) {
+        let colatitude = (90.0 - theta).to_radians();
+        let r = match self {
+            Zenithal::Tan => theta.to_radians().tan().recip().to_degrees(),
+            Zenithal::Sin => theta.to_radians().cos().to_degrees(),
+            Zenithal::Arc => 90.0 - theta,
+            Zenithal::Stg => (2.0 * (colatitude / 2.0).tan()).to_degrees(),
+            Zenithal::Zea => (2.0 * (colatitude / 2.0).sin()).to_degrees(),
+        };
+        let (sin, cos) = phi.to_radians().sin_cos();
+        (r * sin, -r * cos)
+    }
+}
+
+// longitude of the celestial pole in the native frame, LONPOLE by default for the
+// zenithal projections
+fn native_pole(reference_lat: f64) -> f64 {
+    if reference_lat >= 90.0 {
+        0.0
+    } else {
+        180.0
+    }
+}
+
+/// Celestial longitude and latitude of native spherical coordinates, the native pole
+/// being at the reference point, in degrees
+fn native_to_celestial(phi: f64, theta: f64, (a0, d0): (f64, f64)) -> (f64, f64) {
+    let (sin_t, cos_t) = theta.to_radians().sin_cos();
+    let (sin_d0, cos_d0) = d0.to_radians().sin_cos();
+    let (sin_p, cos_p) = (phi - native_pole(d0)).to_radians().sin_cos();
+    let a = a0
+        + (-cos_t * sin_p)
+            .atan2(sin_t * cos_d0 - cos_t * sin_d0 * cos_p)
+            .to_degrees();
+    let d = (sin_t * sin_d0 + cos_t * cos_d0 * cos_p)
+        .clamp(-1.0, 1.0)
+        .asin()
+        .to_degrees();
+    (a, d)
+}
+
+/// Native spherical coordinates of a celestial longitude and latitude, the inverse
+/// of `native_to_celestial`
+fn celestial_to_native(a: f64, d: f64, (a0, d0): (f64, f64)) -> (f64, f64) {
+    let (sin_d, cos_d) = d.to_radians().sin_cos();
+    let (sin_d0, cos_d0) = d0.to_radians().sin_cos();
+    let (sin_a, cos_a) = (a - a0).to_radians().sin_cos();
+    let phi = native_pole(d0)
+        + (-cos_d * sin_a)
+            .atan2(sin_d * cos_d0 - cos_d * sin_d0 * cos_a)
+            .to_degrees();
+    let theta = (sin_d * sin_d0 + cos_d * cos_d0 * cos_a)
+        .clamp(-1.0, 1.0)
+        .asin()
+        .to_degrees();
+    (phi, theta)
+}
+
+// cofactor of the element (i, j) of a 3x3 matrix, signed by the cyclic order
+fn cofactor(m: &[[f64; 3]; 3], i: usize, j: usize) -> f64 {
+    let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+    let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+    m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+}
+
+pub(crate) fn determinant(m: &[[f64; 3]; 3]) -> f64 {
+    (0..3).map(|j| m[0][j] * cofactor(m, 0, j)).sum()
+}
+
+// inverse of a 3x3 matrix, `None` if it is singular
+fn invert(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let det = determinant(m);
+    if det == 0.0 {
+        return None;
+    }
+    // the transpose of the cofactors over the determinant
+    Some([0, 1, 2].map(|i| [0, 1, 2].map(|j| cofactor(m, j, i) / det)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn axis(ctype: &str, crval: f64, cdelt: f64) -> WcsAxis {
+        WcsAxis {
+            ctype: ctype.to_string(),
+            cunit: String::new(),
+            crval,
+            crpix: 1.0,
+            cdelt,
+        }
+    }
+
+    // celestial axes of the projection `code` centered on (ra, dec), and a velocity axis
+    fn sky(code: &str, (ra, dec): (f64, f64), cdelt: f64) -> Wcs {
+        Wcs {
+            axes: [
+                axis(&format!("RA---{}", code), ra, -cdelt),
+                axis(&format!("DEC--{}", code), dec, cdelt),
+                axis("VRAD", 1000.0, 10.0),
+            ],
+            pc: IDENTITY,
+            frame: WcsFrame::default(),
+        }
+    }
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() < tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn gnomonic_and_orthographic_on_the_equator() {
+        // 45 and 30 degrees east of the reference point along the equator
+        let tan = sky("TAN", (0.0, 0.0), 1.0).pix2world([-45.0, 0.0, 0.0]);
+        assert_close(tan[0], 45.0_f64.to_radians().atan().to_degrees(), 1e-9);
+        assert_close(tan[1], 0.0, 1e-9);
+        let sin = sky("SIN", (0.0, 0.0), 1.0).pix2world([-30.0, 0.0, 0.0]);
+        assert_close(sin[0], 30.0_f64.to_radians().asin().to_degrees(), 1e-9);
+        // the spectral axis stays linear
+        assert_close(sin[2], 1000.0, 1e-9);
+
+        // west of 0 wraps around to 360
+        let west = sky("TAN", (0.0, 0.0), 1.0).pix2world([1.0, 0.0, 0.0]);
+        assert_close(west[0], 359.0, 1e-3);
+    }
+
+    #[test]
+    fn right_ascension_offsets_grow_with_the_declination() {
+        let cdelt = 1.0 / 3600.0;
+        for dec in [0.0, 60.0, -75.0] {
+            let world = sky("SIN", (150.0, dec), cdelt).pix2world([-10.0, 0.0, 0.0]);
+            let offset = 10.0 * cdelt / dec.to_radians().cos();
+            assert_close(world[0], 150.0 + offset, 1e-3 * offset);
+            assert_close(world[1], dec, 1e-6);
+        }
+    }
+
+    #[test]
+    fn pixels_come_back_from_their_world_coordinates() {
+        let (sin, cos) = 30.0_f64.to_radians().sin_cos();
+        for code in ["TAN", "SIN", "ARC", "STG", "ZEA", "GLS", ""] {
+            let mut wcs = sky(code, (210.0, -40.0), 0.01);
+            wcs.axes[0].crpix = 50.5;
+            wcs.axes[1].crpix = 60.0;
+            wcs.pc[0] = [cos, -sin, 0.0];
+            wcs.pc[1] = [sin, cos, 0.0];
+            for p in [[0.0, 0.0, 3.0], [99.0, 20.0, 0.0], [50.5, 120.0, 7.5]] {
+                let q = wcs.world2pix(wcs.pix2world(p));
+                for i in 0..3 {
+                    assert_close(q[i], p[i], 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn axes_without_projection_code_are_linear() {
+        let mut wcs = sky("", (150.0, 60.0), 0.5);
+        wcs.axes[0].ctype = "RA".to_string();
+        wcs.axes[1].ctype = "DEC".to_string();
+        let world = wcs.pix2world([-4.0, 2.0, 1.0]);
+        assert_eq!(world, [152.0, 61.0, 1010.0]);
+    }
+
+    // header of the keywords of numbers and strings
+    fn header(cards: &[(&str, &str)]) -> HashMap<String, Value> {
+        cards
+            .iter()
+            .map(|&(key, value)| {
+                let value_card = [b' '; 80];
+                let value = match value.parse() {
+                    Ok(value) => Value::Float { value, value_card },
+                    Err(_) => Value::String {
+                        value: value.to_string(),
+                        value_card,
+                    },
+                };
+                (key.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn parse(cards: &[(&str, &str)]) -> Wcs {
+        let header = header(cards);
+        Wcs::parse(|key| header.get(key), [1, 2, 3]).unwrap()
+    }
+
+    fn assert_matrix(a: [[f64; 3]; 3], b: [[f64; 3]; 3]) {
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_close(a[i][j], b[i][j], 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn cd_matrix_gives_cdelt_and_pc() {
+        let (sin, cos) = 30.0_f64.to_radians().sin_cos();
+        let scale = 0.01;
+        let (cd1_1, cd1_2) = ((-scale * cos).to_string(), (scale * sin).to_string());
+        let (cd2_1, cd2_2) = ((scale * sin).to_string(), (scale * cos).to_string());
+        let wcs = parse(&[
+            ("CTYPE1", "RA---SIN"),
+            ("CTYPE2", "DEC--SIN"),
+            ("CTYPE3", "VRAD"),
+            ("CRVAL1", "150"),
+            ("CRPIX1", "32"),
+            ("CD1_1", &cd1_1),
+            ("CD1_2", &cd1_2),
+            ("CD2_1", &cd2_1),
+            ("CD2_2", &cd2_2),
+            ("CDELT3", "5000"),
+            ("PC3_3", "1"),
+        ]);
+        assert_eq!(wcs.axes[0].ctype, "RA---SIN");
+        assert_eq!((wcs.axes[0].crval, wcs.axes[0].crpix), (150.0, 32.0));
+        assert_close(wcs.axes[0].cdelt, -scale, 1e-15);
+        assert_close(wcs.axes[1].cdelt, scale, 1e-15);
+        assert_eq!(wcs.axes[2].cdelt, 5000.0);
+        assert_matrix(wcs.pc, [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]]);
+
+        // CDELT and PC multiplied give back the CD matrix
+        let matrix = wcs.linear_matrix();
+        assert_close(matrix[0][1], scale * sin, 1e-15);
+        assert_close(matrix[1][0], scale * sin, 1e-15);
+    }
+
+    #[test]
+    fn crota_rotates_the_celestial_axes() {
+        let (sin, cos) = 30.0_f64.to_radians().sin_cos();
+        let cards = [
+            ("CDELT1", "-0.01"),
+            ("CDELT2", "0.02"),
+            ("CDELT3", "1"),
+            ("CROTA2", "30"),
+        ];
+        let wcs = parse(&cards);
+        assert_eq!(wcs.axes[0].cdelt, -0.01);
+        assert_matrix(
+            wcs.pc,
+            [
+                [cos, 2.0 * sin, 0.0],
+                [-0.5 * sin, cos, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+        );
+        // the same rotation as the CD matrix of the angle
+        let matrix = wcs.linear_matrix();
+        assert_matrix(
+            matrix,
+            [
+                [-0.01 * cos, -0.02 * sin, 0.0],
+                [-0.01 * sin, 0.02 * cos, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+        );
+
+        // a PC matrix takes precedence
+        let wcs = parse(&[cards[0], cards[1], cards[2], cards[3], ("PC1_1", "2")]);
+        assert_matrix(wcs.pc, [[2.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn axes_of_the_cube_are_picked_from_the_header() {
+        let header = header(&[
+            ("CDELT1", "1"),
+            ("CDELT2", "2"),
+            ("CDELT3", "3"),
+            ("CDELT4", "4"),
+            ("PC2_4", "0.5"),
+        ]);
+        let wcs = Wcs::parse(|key| header.get(key), [2, 4, 3]).unwrap();
+        assert_eq!(wcs.axes.clone().map(|axis| axis.cdelt), [2.0, 4.0, 3.0]);
+        assert_eq!(wcs.pc[0], [1.0, 0.5, 0.0]);
+        // an axis without any scale has no WCS
+        assert_eq!(Wcs::parse(|key| header.get(key), [1, 2, 5]), None);
+    }
+}
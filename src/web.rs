@@ -0,0 +1,94 @@
+use wasm_bindgen::JsCast;
+
+/// Make the browser download `bytes` as a file named `filename`
+pub(crate) fn download(bytes: &[u8], filename: &str, mime_type: &str) -> Result<(), &'static str> {
+    let array = js_sys::Uint8Array::from(bytes);
+    let parts = js_sys::Array::of1(&array);
+
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|_| "Cannot create the blob to download")?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|_| "Cannot create an url for the blob")?;
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let anchor = document
+        .create_element("a")
+        .map_err(|_| "Cannot create the download link")?
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .map_err(|_| "Cannot create the download link")?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+
+    let _ = web_sys::Url::revoke_object_url(&url);
+
+    Ok(())
+}
+
+/// Voxel picked under the cursor as given to the callback of the page, `null` if none
+pub(crate) fn pick_to_js(pick: Option<&crate::Pick>) -> wasm_bindgen::JsValue {
+    use wasm_bindgen::JsValue;
+
+    let pick = match pick {
+        Some(pick) => pick,
+        None => return JsValue::NULL,
+    };
+    let [x, y, z] = pick.voxel.map(|i| JsValue::from_f64(i as f64));
+    let world = match pick.world {
+        Some([a, b, c]) => js_sys::Array::of3(&a.into(), &b.into(), &c.into()).into(),
+        None => JsValue::NULL,
+    };
+
+    let object = js_sys::Object::new();
+    for (key, value) in [
+        ("kind", JsValue::from_str(pick.kind.name())),
+        ("voxel", js_sys::Array::of3(&x, &y, &z).into()),
+        ("value", JsValue::from_f64(pick.value as f64)),
+        ("world", world),
+        (
+            "label",
+            pick.label
+                .map_or(JsValue::NULL, |id| JsValue::from_f64(id as f64)),
+        ),
+    ] {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from_str(key), &value);
+    }
+    object.into()
+}
+
+/// Objects of the catalog as given to the callback of the page, `null` if none
+pub(crate) fn catalog_to_js(catalog: Option<&crate::Catalog>) -> wasm_bindgen::JsValue {
+    use wasm_bindgen::JsValue;
+
+    let catalog = match catalog {
+        Some(catalog) => catalog,
+        None => return JsValue::NULL,
+    };
+    let objects = js_sys::Array::new();
+    for object in &catalog.objects {
+        let [x, y, z] = object.centroid;
+        let world = match object.world {
+            Some([a, b, c]) => js_sys::Array::of3(&a.into(), &b.into(), &c.into()).into(),
+            None => JsValue::NULL,
+        };
+
+        let js_object = js_sys::Object::new();
+        for (key, value) in [
+            ("id", JsValue::from_f64(object.id as f64)),
+            (
+                "centroid",
+                js_sys::Array::of3(&x.into(), &y.into(), &z.into()).into(),
+            ),
+            ("world", world),
+            ("voxels", JsValue::from_f64(object.num_voxels as f64)),
+            ("flux", JsValue::from_f64(object.flux)),
+            ("peak", JsValue::from_f64(object.peak as f64)),
+        ] {
+            let _ = js_sys::Reflect::set(&js_object, &JsValue::from_str(key), &value);
+        }
+        objects.push(&js_object);
+    }
+    objects.into()
+}